@@ -0,0 +1,355 @@
+use bevy::prelude::*;
+
+use bevy_matchbox::prelude::*;
+use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+use sqlx::{MySqlConnection, MySqlPool, Error};
+use std::collections::{HashMap, HashSet};
+use std::{env, fs};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    AchievementCriteria,
+    AchievementDefinition,
+    AchievementProgress,
+    Achievements,
+    ClientRequestEvent,
+    ConnectedPlayers,
+    DatabasePool,
+    GameResult,
+    GameResultRecordedEvent,
+    PacketAchievementUnlocked,
+    PacketAchievements,
+    PacketAchievementsRequest,
+};
+
+use crate::handlers::signaling_server_handler::{send_peer_message, send_player_message};
+
+const DEFAULT_ACHIEVEMENTS_FILE: &str = "assets/achievements.json";
+const MAX_ACHIEVEMENT_ID_LEN: usize = 64;
+
+fn map_set_matches(filter: &Option<String>, map_set_name: Option<&str>) -> bool {
+    filter.as_deref().is_none_or(|name| Some(name) == map_set_name)
+}
+
+impl AchievementCriteria {
+    // Progress after one more finished game
+    fn advance(&self, result: &GameResult, map_set_name: Option<&str>, progress: i32) -> i32 {
+        match self {
+            AchievementCriteria::GamesPlayed => progress + 1,
+            AchievementCriteria::GamesWon => {
+                if result.player_count > 1 && result.placement == 1 {
+                    progress + 1
+                } else {
+                    progress
+                }
+            }
+            AchievementCriteria::HoleInOne { level, map_set_name: filter } => {
+                if !map_set_matches(filter, map_set_name) {
+                    return progress;
+                }
+                let aces = result
+                    .hole_strokes
+                    .iter()
+                    .filter(|(hole, strokes)| *strokes == 1 && level.is_none_or(|level| level == *hole))
+                    .count() as i32;
+                progress + aces
+            }
+            AchievementCriteria::PlayedWith { players } => {
+                if result.player_count >= *players {
+                    progress + 1
+                } else {
+                    progress
+                }
+            }
+            AchievementCriteria::RoundUnder { map_set_name: filter, strokes } => {
+                if map_set_matches(filter, map_set_name) && result.total_strokes < *strokes {
+                    progress + 1
+                } else {
+                    progress
+                }
+            }
+            // Rounds on holes without a par have no score to par and never count
+            AchievementCriteria::RoundToPar { map_set_name: filter, to_par } => {
+                if map_set_matches(filter, map_set_name) && result.to_par().is_some_and(|score| score <= *to_par) {
+                    progress + 1
+                } else {
+                    progress
+                }
+            }
+            // Solo games neither extend nor break a streak
+            AchievementCriteria::WinStreak => match (result.player_count > 1, result.placement == 1) {
+                (false, _) => progress,
+                (true, true) => progress + 1,
+                (true, false) => 0,
+            },
+        }
+    }
+}
+
+impl AchievementProgress {
+    pub fn new(definition: &AchievementDefinition, progress: i32, unlocked: Option<OffsetDateTime>) -> Self {
+        Self {
+            achievement_id: definition.achievement_id.clone(),
+            name: definition.name.clone(),
+            description: definition.description.clone(),
+            progress,
+            target: definition.target,
+            unlocked,
+        }
+    }
+}
+
+impl Achievements {
+    // ACHIEVEMENTS_FILE points at a JSON list of definitions, broken entries are skipped
+    pub fn load() -> Self {
+        let path = env::var("ACHIEVEMENTS_FILE").unwrap_or_else(|_| String::from(DEFAULT_ACHIEVEMENTS_FILE));
+        let definitions: Vec<AchievementDefinition> = match fs::read_to_string(&path) {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(definitions) => definitions,
+                Err(err) => {
+                    error!("Failed to parse achievements file {:?}: {:?}", path, err);
+                    Vec::new()
+                }
+            },
+            Err(err) => {
+                warn!("Failed to read achievements file {:?}: {}", path, err);
+                Vec::new()
+            }
+        };
+
+        let mut seen = HashSet::new();
+        let definitions: Vec<AchievementDefinition> = definitions
+            .into_iter()
+            .filter(|definition| {
+                let id = &definition.achievement_id;
+                if id.is_empty() || id.len() > MAX_ACHIEVEMENT_ID_LEN {
+                    warn!("Ignoring achievement with invalid id {:?}", id);
+                    false
+                } else if definition.target < 1 {
+                    warn!("Ignoring achievement {:?} with target {}", id, definition.target);
+                    false
+                } else if !seen.insert(id.clone()) {
+                    warn!("Ignoring duplicate achievement {:?}", id);
+                    false
+                } else {
+                    true
+                }
+            })
+            .collect();
+        info!("Loaded {} achievement(s) from {:?}", definitions.len(), path);
+        Self {
+            definitions,
+        }
+    }
+}
+
+// Stored progress keyed by achievement id; `for_update` locks the rows for the surrounding transaction
+async fn fetch_achievement_progress(
+    conn: &mut MySqlConnection,
+    player_id: &Uuid,
+    for_update: bool,
+) -> Result<HashMap<String, (i32, Option<OffsetDateTime>)>, Error> {
+    let lock = if for_update { " FOR UPDATE" } else { "" };
+    let rows: Vec<(String, i32, Option<OffsetDateTime>)> = sqlx::query_as(&format!(
+        "SELECT achievement_id, progress, unlocked FROM player_achievement WHERE player_id = UUID_TO_BIN(?){}",
+        lock,
+    ))
+    .bind(player_id.to_string())
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(achievement_id, progress, unlocked)| (achievement_id, (progress, unlocked)))
+        .collect())
+}
+
+// Applies one game to the player's progress and returns the achievements it unlocked
+async fn record_achievements(
+    pool: &MySqlPool,
+    definitions: &[AchievementDefinition],
+    result: &GameResult,
+) -> Result<Vec<AchievementProgress>, Error> {
+    let mut tx = pool.begin().await?;
+    let stored = fetch_achievement_progress(&mut tx, &result.player_id, true).await?;
+    let map_set_name: Option<String> = sqlx::query_scalar(
+        "SELECT map_set_name FROM map_set_table WHERE map_set_id = UUID_TO_BIN(?)",
+    )
+    .bind(result.map_set_id.to_string())
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let now = OffsetDateTime::now_utc();
+    let mut unlocked = Vec::new();
+    for definition in definitions.iter() {
+        let (progress, unlocked_at) = stored.get(&definition.achievement_id).copied().unwrap_or((0, None));
+        if unlocked_at.is_some() {
+            continue;
+        }
+        let updated = definition
+            .criteria
+            .advance(result, map_set_name.as_deref(), progress)
+            .min(definition.target);
+        if updated == progress {
+            continue;
+        }
+        let unlocked_at = (updated >= definition.target).then_some(now);
+        sqlx::query(
+            "INSERT INTO player_achievement (player_id, achievement_id, progress, unlocked)
+             VALUES (UUID_TO_BIN(?), ?, ?, ?)
+             ON DUPLICATE KEY UPDATE progress = VALUES(progress), unlocked = VALUES(unlocked)",
+        )
+        .bind(result.player_id.to_string())
+        .bind(&definition.achievement_id)
+        .bind(updated)
+        .bind(unlocked_at)
+        .execute(&mut *tx)
+        .await?;
+        if unlocked_at.is_some() {
+            unlocked.push(AchievementProgress::new(definition, updated, unlocked_at));
+        }
+    }
+    tx.commit().await?;
+    Ok(unlocked)
+}
+
+pub fn achievement_record_system(
+    mut event_reader: EventReader<GameResultRecordedEvent>,
+    achievements: Res<Achievements>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    for event in event_reader.read() {
+        // Client submitted games are not checked by the server, so they cannot unlock anything
+        let results: Vec<GameResult> = event.results.iter().filter(|result| result.validated).cloned().collect();
+        if results.is_empty() || achievements.definitions.is_empty() {
+            continue;
+        }
+        let definitions = achievements.definitions.clone();
+        let pool = pool.0.clone();
+        // Spawn the background task using bevy_tokio_tasks
+        runtime.spawn_background_task(move |ctx| {
+            record_achievements_async(definitions, results, pool, ctx)
+        });
+    }
+}
+
+pub async fn record_achievements_async(
+    definitions: Vec<AchievementDefinition>,
+    results: Vec<GameResult>,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let mut unlocked = Vec::new();
+    for result in results.iter() {
+        match record_achievements(&pool, &definitions, result).await {
+            Ok(achievements) if !achievements.is_empty() => unlocked.push((result.player_id, achievements)),
+            Ok(_) => {}
+            Err(err) => {
+                let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+                eprintln!("Failed to update achievements: {:?}", err_for_ctx);
+                ctx.run_on_main_thread(move |_ctx| {
+                    info!("Failed to update achievements in the task: {:?}", err_for_ctx);
+                })
+                .await;
+            }
+        }
+    }
+    if unlocked.is_empty() {
+        return;
+    }
+
+    ctx.run_on_main_thread(move |ctx| {
+        let Some(connected_players) = ctx.world.get_resource::<ConnectedPlayers>().cloned() else {
+            return;
+        };
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            for (player_id, achievements) in unlocked {
+                for achievement in achievements {
+                    info!("Player {} unlocked {:?}", player_id, achievement.achievement_id);
+                    let packet = PacketAchievementUnlocked { achievement };
+                    send_player_message(&mut socket, &connected_players, &player_id, "AchievementUnlocked", &packet);
+                }
+            }
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+    })
+    .await;
+}
+
+pub async fn send_achievements_async(
+    definitions: Vec<AchievementDefinition>,
+    player_id: Uuid,
+    peer: PeerId,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let stored = match pool.acquire().await {
+        Ok(mut conn) => fetch_achievement_progress(&mut conn, &player_id, false).await,
+        Err(err) => Err(err),
+    };
+    let stored = match stored {
+        Ok(stored) => stored,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to execute query: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to execute query in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            return;
+        }
+    };
+
+    // Progress for achievements that were since removed from the file is left out
+    let achievements = definitions
+        .iter()
+        .map(|definition| {
+            let (progress, unlocked) = stored.get(&definition.achievement_id).copied().unwrap_or((0, None));
+            AchievementProgress::new(definition, progress, unlocked)
+        })
+        .collect();
+    let packet = PacketAchievements {
+        player_id: player_id.to_string(),
+        achievements,
+    };
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            send_peer_message(&mut socket, peer, &player_id, "Achievements", &packet);
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+    })
+    .await;
+}
+
+pub fn achievement_request_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    achievements: Res<Achievements>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    for event in event_reader.read() {
+        if event.command != "AchievementsRequest" {
+            continue;
+        }
+        let packet = match serde_json::from_str::<PacketAchievementsRequest>(&event.payload) {
+            Ok(packet) => packet,
+            Err(err) => {
+                error!("Failed to deserialize PacketAchievementsRequest from JSON: {:?}", err);
+                continue;
+            }
+        };
+        let Ok(player_id) = Uuid::parse_str(&packet.player_id) else {
+            warn!("AchievementsRequest carried an invalid player_id {:?}", packet.player_id);
+            continue;
+        };
+        let definitions = achievements.definitions.clone();
+        let pool = pool.0.clone();
+        let peer = event.peer;
+        runtime.spawn_background_task(move |ctx| {
+            send_achievements_async(definitions, player_id, peer, pool, ctx)
+        });
+    }
+}
@@ -0,0 +1,103 @@
+use bevy::prelude::*;
+
+use bevy_matchbox::prelude::*;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use uuid::Uuid;
+
+use crate::{
+    Admins,
+    ClientRequestEvent,
+    ConnectedPlayers,
+    PacketAdminLogin,
+    PlayerDisconnectedEvent,
+};
+
+use crate::handlers::map_set_handler::send_admin_result;
+
+impl Admins {
+    // ADMIN_PLAYER_IDS is a comma separated list of player ids, usually set in .env next to ADMIN_TOKEN
+    pub fn from_env() -> Self {
+        let mut player_ids = HashSet::new();
+        if let Ok(value) = env::var("ADMIN_PLAYER_IDS") {
+            for id in value.split(',').map(str::trim).filter(|id| !id.is_empty()) {
+                match Uuid::parse_str(id) {
+                    Ok(player_id) => {
+                        player_ids.insert(player_id);
+                    }
+                    Err(err) => warn!("Ignoring invalid admin player id {:?}: {}", id, err),
+                }
+            }
+        }
+        let token = env::var("ADMIN_TOKEN").ok().filter(|token| !token.trim().is_empty());
+        if token.is_none() && !player_ids.is_empty() {
+            warn!("ADMIN_TOKEN is not set, admin commands are disabled");
+        }
+        info!("Loaded {} admin player id(s)", player_ids.len());
+        Self {
+            player_ids,
+            token,
+            sessions: HashMap::new(),
+        }
+    }
+
+    // Player ids are public, so only an admin who logged in from this very peer counts
+    pub fn is_admin(&self, player_id: &Uuid, peer: PeerId) -> bool {
+        self.player_ids.contains(player_id) && self.sessions.get(player_id) == Some(&peer)
+    }
+
+    fn login(&mut self, player_id: Uuid, peer: PeerId, token: &str) -> Result<(), String> {
+        let Some(expected) = self.token.as_ref() else {
+            return Err(String::from("Admin login is disabled"));
+        };
+        // Comparing digests keeps the time taken independent of how much of the token matched
+        let matches = Sha256::digest(expected.as_bytes())
+            .iter()
+            .zip(Sha256::digest(token.as_bytes()).iter())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0;
+        if !self.player_ids.contains(&player_id) || !matches {
+            return Err(String::from("Admin login refused"));
+        }
+        self.sessions.insert(player_id, peer);
+        Ok(())
+    }
+}
+
+pub fn admin_request_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    mut admins: ResMut<Admins>,
+) {
+    for event in event_reader.read() {
+        if event.command != "AdminLogin" {
+            continue;
+        }
+        let packet = match serde_json::from_str::<PacketAdminLogin>(&event.payload) {
+            Ok(packet) => packet,
+            Err(err) => {
+                error!("Failed to deserialize PacketAdminLogin from JSON: {:?}", err);
+                continue;
+            }
+        };
+        let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+            continue;
+        };
+        let result = admins.login(player_id, event.peer, &packet.token);
+        if result.is_ok() {
+            info!("Admin {} logged in from peer {}", player_id, event.peer);
+        }
+        send_admin_result(&mut socket, event.peer, &player_id, &event.command, None, result);
+    }
+}
+
+pub fn admin_disconnect_system(
+    mut event_reader: EventReader<PlayerDisconnectedEvent>,
+    mut admins: ResMut<Admins>,
+) {
+    for event in event_reader.read() {
+        admins.sessions.remove(&event.player_id);
+    }
+}
@@ -0,0 +1,669 @@
+use bevy::prelude::*;
+
+use bevy_matchbox::prelude::*;
+use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+use sqlx::{query_as, MySqlPool, Error};
+use std::collections::{HashMap, HashSet};
+use time::{Date, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::{
+    Challenge,
+    ChallengeHistoryEntry,
+    ChallengeHole,
+    ChallengePeriod,
+    Challenges,
+    ClientRequestEvent,
+    ConnectedPlayers,
+    DatabasePool,
+    LeaderBoardEntry,
+    MapSet,
+    PacketChallengeHistory,
+    PacketChallengeHistoryRequest,
+    PacketChallengeLeaderBoard,
+    PacketChallengeLeaderBoardRequest,
+    PacketChallengeRequest,
+    PacketChallengeStatus,
+    PacketChallengeSubmit,
+    PacketChallenges,
+};
+
+use crate::handlers::{
+    leader_board_handler::MAX_STROKES_PER_HOLE,
+    signaling_server_handler::{send_peer_message, send_player_message},
+};
+
+const STROKE_LIMIT_CHOICES: [Option<i32>; 3] = [None, Some(4), Some(6)];
+const HISTORY_LIMIT: i64 = 14;
+const DEFAULT_PAGE_SIZE: u32 = 10;
+const MAX_PAGE_SIZE: u32 = 50;
+
+type ChallengeRow = (Uuid, Date, Date, u64, String, Option<i32>);
+type ChallengeHistoryRow = (Uuid, Date, Date, u64, String, Option<i32>, i64, Option<i32>, Option<i32>);
+
+impl ChallengePeriod {
+    pub const ALL: [ChallengePeriod; 2] = [ChallengePeriod::Daily, ChallengePeriod::Weekly];
+
+    fn key(&self) -> &'static str {
+        match self {
+            ChallengePeriod::Daily => "daily",
+            ChallengePeriod::Weekly => "weekly",
+        }
+    }
+
+    fn hole_count(&self) -> usize {
+        match self {
+            ChallengePeriod::Daily => 6,
+            ChallengePeriod::Weekly => 9,
+        }
+    }
+
+    // [starts, ends) of the period containing the given UTC day; weeks start Monday
+    pub fn window(&self, today: Date) -> (Date, Date) {
+        match self {
+            ChallengePeriod::Daily => (today, today + time::Duration::days(1)),
+            ChallengePeriod::Weekly => {
+                let starts = today - time::Duration::days(today.weekday().number_days_from_monday() as i64);
+                (starts, starts + time::Duration::days(7))
+            }
+        }
+    }
+}
+
+// FNV-1a over the period and its start day, so every server picks the same challenge
+fn challenge_seed(period: ChallengePeriod, starts: Date) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in format!("{}-{}", period.key(), starts).bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// splitmix64, enough to shuffle holes deterministically without pulling in a rand crate
+struct SeededRng(u64);
+
+impl SeededRng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+impl Challenge {
+    // Picks the challenge holes out of every stored map set; the map sets are ordered by id
+    // first so the selection only depends on the seed and the table contents. Sets share
+    // files (Whole Course holds both nines), so each file enters the pool once.
+    pub fn generate(period: ChallengePeriod, starts: Date, map_sets: &[MapSet]) -> Result<Challenge, String> {
+        let mut sorted: Vec<&MapSet> = map_sets.iter().collect();
+        sorted.sort_by_key(|map_set| map_set.map_set_id);
+        let mut holes: Vec<ChallengeHole> = sorted
+            .iter()
+            .flat_map(|map_set| {
+                map_set.levels().into_iter().map(|(level, file_path)| ChallengeHole {
+                    map_set_id: map_set.map_set_id,
+                    level,
+                    file_path,
+                })
+            })
+            .collect();
+        let mut seen_files = HashSet::new();
+        holes.retain(|hole| seen_files.insert(hole.file_path.clone()));
+        if holes.is_empty() {
+            return Err(String::from("No playable holes in map_set_table"));
+        }
+
+        let seed = challenge_seed(period, starts);
+        let mut rng = SeededRng(seed);
+        for idx in (1..holes.len()).rev() {
+            let other = rng.below(idx + 1);
+            holes.swap(idx, other);
+        }
+        holes.truncate(period.hole_count());
+        let stroke_limit = STROKE_LIMIT_CHOICES[rng.below(STROKE_LIMIT_CHOICES.len())];
+
+        let (starts, ends) = period.window(starts);
+        Ok(Challenge {
+            challenge_id: Uuid::now_v7(),
+            period,
+            starts,
+            ends,
+            seed,
+            holes,
+            stroke_limit,
+        })
+    }
+
+    pub fn is_open(&self, today: Date) -> bool {
+        self.starts <= today && today < self.ends
+    }
+
+    fn from_row(period: ChallengePeriod, row: ChallengeRow) -> Result<Challenge, Error> {
+        let (challenge_id, starts, ends, seed, holes, stroke_limit) = row;
+        let holes: Vec<ChallengeHole> = serde_json::from_str(&holes).map_err(|err| Error::Decode(Box::new(err)))?;
+        Ok(Challenge {
+            challenge_id,
+            period,
+            starts,
+            ends,
+            seed,
+            holes,
+            stroke_limit,
+        })
+    }
+
+    // Scores an attempt hole by hole; like a game session, a hole ends at the stroke limit,
+    // so anything above it counts as the limit
+    fn score_attempt(&self, strokes: &[i32]) -> Result<Vec<i32>, String> {
+        if strokes.len() != self.holes.len() {
+            return Err(String::from("Stroke count does not match the challenge holes"));
+        }
+        if strokes.iter().any(|strokes| *strokes < 1) {
+            return Err(String::from("Every hole takes at least one stroke"));
+        }
+        let limit = self.stroke_limit.unwrap_or(MAX_STROKES_PER_HOLE).min(MAX_STROKES_PER_HOLE);
+        Ok(strokes.iter().map(|strokes| (*strokes).min(limit)).collect())
+    }
+}
+
+impl Challenges {
+    pub fn new() -> Self {
+        Self {
+            current: HashMap::new(),
+            loading: HashSet::new(),
+        }
+    }
+
+    pub fn all(&self) -> Vec<Challenge> {
+        ChallengePeriod::ALL
+            .iter()
+            .filter_map(|period| self.current.get(period).cloned())
+            .collect()
+    }
+
+    pub fn find(&self, challenge_id: &Uuid) -> Option<&Challenge> {
+        self.current.values().find(|challenge| &challenge.challenge_id == challenge_id)
+    }
+}
+
+impl Default for Challenges {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn fetch_challenge(
+    pool: &MySqlPool,
+    period: ChallengePeriod,
+    starts: Date,
+) -> Result<Option<Challenge>, Error> {
+    let row: Option<ChallengeRow> = sqlx::query_as(
+        "SELECT challenge_id, starts, ends, seed, holes, stroke_limit
+         FROM challenge WHERE period = ? AND starts = ?",
+    )
+    .bind(period.key())
+    .bind(starts)
+    .fetch_optional(pool)
+    .await?;
+    row.map(|row| Challenge::from_row(period, row)).transpose()
+}
+
+// Loads the period's challenge, generating and storing it first if no server has yet
+async fn ensure_challenge(
+    pool: &MySqlPool,
+    period: ChallengePeriod,
+    starts: Date,
+) -> Result<Result<Challenge, String>, Error> {
+    if let Some(challenge) = fetch_challenge(pool, period, starts).await? {
+        return Ok(Ok(challenge));
+    }
+
+    // Retired sets are left out of new challenges
+    let map_sets: Vec<MapSet> = query_as::<_, MapSet>(
+        "SELECT * FROM map_set_table WHERE map_set_id NOT IN (SELECT map_set_id FROM map_set_retired)",
+    )
+    .fetch_all(pool)
+    .await?;
+    let challenge = match Challenge::generate(period, starts, &map_sets) {
+        Ok(challenge) => challenge,
+        Err(reason) => return Ok(Err(reason)),
+    };
+    let holes = serde_json::to_string(&challenge.holes).map_err(|err| Error::Decode(Box::new(err)))?;
+
+    // A concurrent insert for the same day wins through the unique key, so re-read afterwards
+    sqlx::query(
+        "INSERT IGNORE INTO challenge (challenge_id, period, starts, ends, seed, holes, stroke_limit)
+         VALUES (UUID_TO_BIN(?), ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(challenge.challenge_id.to_string())
+    .bind(period.key())
+    .bind(challenge.starts)
+    .bind(challenge.ends)
+    .bind(challenge.seed)
+    .bind(holes)
+    .bind(challenge.stroke_limit)
+    .execute(pool)
+    .await?;
+    Ok(fetch_challenge(pool, period, starts).await?.ok_or(String::from("Challenge vanished after insert")))
+}
+
+pub fn challenge_rollover_system(
+    mut challenges: ResMut<Challenges>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    let today = OffsetDateTime::now_utc().date();
+    for period in ChallengePeriod::ALL {
+        let (starts, _) = period.window(today);
+        let is_current = challenges.current.get(&period).is_some_and(|challenge| challenge.starts == starts);
+        if is_current || challenges.loading.contains(&period) {
+            continue;
+        }
+        challenges.loading.insert(period);
+        let pool = pool.0.clone();
+        // Spawn the background task using bevy_tokio_tasks
+        runtime.spawn_background_task(move |ctx| {
+            challenge_rollover_async(period, starts, pool, ctx)
+        });
+    }
+}
+
+pub async fn challenge_rollover_async(
+    period: ChallengePeriod,
+    starts: Date,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let challenge = match ensure_challenge(&pool, period, starts).await {
+        Ok(Ok(challenge)) => Some(challenge),
+        Ok(Err(reason)) => {
+            warn!("No {} challenge for {}: {}", period.key(), starts, reason);
+            None
+        }
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to set up challenge: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to set up challenge in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            None
+        }
+    };
+
+    ctx.run_on_main_thread(move |ctx| {
+        // Clearing the flag lets the rollover system retry on its next tick after a failure
+        if let Some(mut challenges) = ctx.world.get_resource_mut::<Challenges>() {
+            challenges.loading.remove(&period);
+            if let Some(challenge) = challenge.clone() {
+                challenges.current.insert(period, challenge);
+            }
+        }
+        let Some(challenge) = challenge else {
+            return;
+        };
+        info!("Publishing {} challenge [{}] with {} holes", period.key(), challenge.challenge_id, challenge.holes.len());
+        let Some(connected_players) = ctx.world.get_resource::<ConnectedPlayers>().cloned() else {
+            return;
+        };
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            let packet = PacketChallenges {
+                challenges: vec![challenge],
+            };
+            for player_id in connected_players.player_ids() {
+                send_player_message(&mut socket, &connected_players, &player_id, "Challenges", &packet);
+            }
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+    })
+    .await;
+}
+
+pub async fn submit_challenge_attempt_async(
+    challenge_id: Uuid,
+    player_id: Uuid,
+    strokes: Vec<i32>,
+    peer: PeerId,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let hole_strokes = serde_json::to_string(&strokes).unwrap_or_default();
+    let result = sqlx::query(
+        "INSERT IGNORE INTO challenge_attempt (challenge_id, player_id, total_strokes, hole_strokes)
+         VALUES (UUID_TO_BIN(?), UUID_TO_BIN(?), ?, ?)",
+    )
+    .bind(challenge_id.to_string())
+    .bind(player_id.to_string())
+    .bind(strokes.iter().sum::<i32>())
+    .bind(hole_strokes)
+    .execute(&pool)
+    .await;
+
+    let status = match result {
+        Ok(done) if done.rows_affected() == 0 => PacketChallengeStatus {
+            challenge_id: challenge_id.to_string(),
+            recorded: false,
+            reason: Some(String::from("Challenge already attempted")),
+        },
+        Ok(_) => PacketChallengeStatus {
+            challenge_id: challenge_id.to_string(),
+            recorded: true,
+            reason: None,
+        },
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to record challenge attempt: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to record challenge attempt in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            PacketChallengeStatus {
+                challenge_id: challenge_id.to_string(),
+                recorded: false,
+                reason: Some(String::from("Failed to record attempt")),
+            }
+        }
+    };
+
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            send_peer_message(&mut socket, peer, &player_id, "ChallengeStatus", &status);
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+    })
+    .await;
+}
+
+async fn fetch_challenge_leader_board(
+    pool: &MySqlPool,
+    challenge_id: &Uuid,
+    page: u32,
+    page_size: u32,
+) -> Result<(i64, Vec<LeaderBoardEntry>), Error> {
+    let (total_entries,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM challenge_attempt WHERE challenge_id = UUID_TO_BIN(?)",
+    )
+    .bind(challenge_id.to_string())
+    .fetch_one(pool)
+    .await?;
+
+    let offset = page as i64 * page_size as i64;
+    let rows: Vec<(Uuid, String, i32)> = sqlx::query_as(
+        "SELECT a.player_id, p.username, a.total_strokes
+         FROM challenge_attempt a JOIN player_table p ON p.player_id = a.player_id
+         WHERE a.challenge_id = UUID_TO_BIN(?)
+         ORDER BY a.total_strokes ASC, a.submitted ASC
+         LIMIT ? OFFSET ?",
+    )
+    .bind(challenge_id.to_string())
+    .bind(page_size as i64)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+
+    let entries = rows
+        .into_iter()
+        .enumerate()
+        .map(|(idx, (player_id, username, strokes))| LeaderBoardEntry {
+            rank: offset + idx as i64 + 1,
+            player_id: player_id.to_string(),
+            username,
+            strokes: strokes as i64,
+            games: 1,
+            to_par: None, // Challenges mix holes from several sets, they are scored on strokes alone
+        })
+        .collect();
+    Ok((total_entries, entries))
+}
+
+pub async fn send_challenge_leader_board_async(
+    challenge_id: Uuid,
+    page: u32,
+    page_size: u32,
+    peer: PeerId,
+    player_id: Uuid,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let (total_entries, entries) = match fetch_challenge_leader_board(&pool, &challenge_id, page, page_size).await {
+        Ok(board) => board,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to execute query: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to execute query in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            return;
+        }
+    };
+
+    let leader_board = PacketChallengeLeaderBoard {
+        challenge_id: challenge_id.to_string(),
+        page,
+        page_size,
+        total_entries,
+        entries,
+        validated: false,
+    };
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            send_peer_message(&mut socket, peer, &player_id, "ChallengeLeaderBoard", &leader_board);
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+    })
+    .await;
+}
+
+async fn fetch_challenge_history(
+    pool: &MySqlPool,
+    period: ChallengePeriod,
+    player_id: &Uuid,
+) -> Result<Vec<ChallengeHistoryEntry>, Error> {
+    let rows: Vec<ChallengeHistoryRow> = sqlx::query_as(
+        "SELECT c.challenge_id, c.starts, c.ends, c.seed, c.holes, c.stroke_limit,
+            (SELECT COUNT(*) FROM challenge_attempt a WHERE a.challenge_id = c.challenge_id),
+            (SELECT MIN(a.total_strokes) FROM challenge_attempt a WHERE a.challenge_id = c.challenge_id),
+            (SELECT a.total_strokes FROM challenge_attempt a
+             WHERE a.challenge_id = c.challenge_id AND a.player_id = UUID_TO_BIN(?))
+         FROM challenge c
+         WHERE c.period = ?
+         ORDER BY c.starts DESC
+         LIMIT ?",
+    )
+    .bind(player_id.to_string())
+    .bind(period.key())
+    .bind(HISTORY_LIMIT)
+    .fetch_all(pool)
+    .await?;
+
+    let mut entries = Vec::new();
+    for (challenge_id, starts, ends, seed, holes, stroke_limit, attempts, best_strokes, player_strokes) in rows {
+        entries.push(ChallengeHistoryEntry {
+            challenge: Challenge::from_row(period, (challenge_id, starts, ends, seed, holes, stroke_limit))?,
+            attempts,
+            best_strokes,
+            player_strokes,
+        });
+    }
+    Ok(entries)
+}
+
+pub async fn send_challenge_history_async(
+    period: ChallengePeriod,
+    peer: PeerId,
+    player_id: Uuid,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let entries = match fetch_challenge_history(&pool, period, &player_id).await {
+        Ok(entries) => entries,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to execute query: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to execute query in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            return;
+        }
+    };
+
+    let history = PacketChallengeHistory {
+        period,
+        entries,
+    };
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            send_peer_message(&mut socket, peer, &player_id, "ChallengeHistory", &history);
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+    })
+    .await;
+}
+
+fn send_challenge_rejection(
+    socket: &mut MatchboxSocket<SingleChannel>,
+    peer: PeerId,
+    player_id: &Uuid,
+    challenge_id: String,
+    reason: String,
+) {
+    warn!("Rejected challenge attempt from {}: {}", player_id, reason);
+    let status = PacketChallengeStatus {
+        challenge_id,
+        recorded: false,
+        reason: Some(reason),
+    };
+    send_peer_message(socket, peer, player_id, "ChallengeStatus", &status);
+}
+
+pub fn challenge_request_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    challenges: Res<Challenges>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    for event in event_reader.read() {
+        match event.command.as_str() {
+            "ChallengeRequest" => {
+                let packet = match serde_json::from_str::<PacketChallengeRequest>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketChallengeRequest from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let packet = PacketChallenges {
+                    challenges: challenges.all(),
+                };
+                send_peer_message(&mut socket, event.peer, &player_id, "Challenges", &packet);
+            }
+            "ChallengeSubmit" => {
+                let packet = match serde_json::from_str::<PacketChallengeSubmit>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketChallengeSubmit from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let challenge = Uuid::parse_str(&packet.challenge_id)
+                    .ok()
+                    .and_then(|challenge_id| challenges.find(&challenge_id))
+                    .filter(|challenge| challenge.is_open(OffsetDateTime::now_utc().date()));
+                let Some(challenge) = challenge else {
+                    let reason = String::from("Challenge is not open");
+                    send_challenge_rejection(&mut socket, event.peer, &player_id, packet.challenge_id, reason);
+                    continue;
+                };
+                let strokes = match challenge.score_attempt(&packet.strokes) {
+                    Ok(strokes) => strokes,
+                    Err(reason) => {
+                        send_challenge_rejection(&mut socket, event.peer, &player_id, packet.challenge_id, reason);
+                        continue;
+                    }
+                };
+                let challenge_id = challenge.challenge_id;
+                let pool = pool.0.clone();
+                let peer = event.peer;
+                runtime.spawn_background_task(move |ctx| {
+                    submit_challenge_attempt_async(challenge_id, player_id, strokes, peer, pool, ctx)
+                });
+            }
+            "ChallengeLeaderBoardRequest" => {
+                let packet = match serde_json::from_str::<PacketChallengeLeaderBoardRequest>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketChallengeLeaderBoardRequest from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let challenge_id = match packet.challenge_id.as_deref().map(Uuid::parse_str) {
+                    Some(Ok(challenge_id)) => challenge_id,
+                    Some(Err(_)) => {
+                        warn!("ChallengeLeaderBoardRequest from {} carried a malformed challenge id", player_id);
+                        continue;
+                    }
+                    None => match challenges.current.get(&packet.period) {
+                        Some(challenge) => challenge.challenge_id,
+                        None => {
+                            warn!("ChallengeLeaderBoardRequest from {}: no current challenge", player_id);
+                            continue;
+                        }
+                    },
+                };
+                let page_size = match packet.page_size {
+                    0 => DEFAULT_PAGE_SIZE,
+                    page_size => page_size.min(MAX_PAGE_SIZE),
+                };
+                let page = packet.page;
+                let pool = pool.0.clone();
+                let peer = event.peer;
+                runtime.spawn_background_task(move |ctx| {
+                    send_challenge_leader_board_async(challenge_id, page, page_size, peer, player_id, pool, ctx)
+                });
+            }
+            "ChallengeHistoryRequest" => {
+                let packet = match serde_json::from_str::<PacketChallengeHistoryRequest>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketChallengeHistoryRequest from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let period = packet.period;
+                let pool = pool.0.clone();
+                let peer = event.peer;
+                runtime.spawn_background_task(move |ctx| {
+                    send_challenge_history_async(period, peer, player_id, pool, ctx)
+                });
+            }
+            _ => {}
+        }
+    }
+}
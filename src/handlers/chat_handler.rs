@@ -0,0 +1,371 @@
+use bevy::prelude::*;
+
+use bevy_matchbox::prelude::*;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use std::{env, fs};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    Admins,
+    Chat,
+    ChatChannel,
+    ChatMessage,
+    ChatScope,
+    ClientRequestEvent,
+    ConnectedPlayers,
+    Friends,
+    PacketChatHistory,
+    PacketChatMute,
+    PacketChatRejected,
+    PacketChatSend,
+    PacketChatSilence,
+    Parties,
+    PlayerDisconnectedEvent,
+    Rooms,
+};
+
+use crate::handlers::{
+    room_handler::DEFAULT_ROOM_ID,
+    signaling_server_handler::send_player_message,
+};
+
+const DEFAULT_WORD_FILTER_FILE: &str = "assets/chat_word_filter.txt";
+const MAX_MESSAGE_LENGTH: usize = 200; // In characters
+const RATE_LIMIT_MESSAGES: usize = 5;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(10);
+const HISTORY_LENGTH: usize = 50;
+
+impl ChatChannel {
+    pub fn scope(&self) -> ChatScope {
+        match self {
+            ChatChannel::Party(_) => ChatScope::Party,
+            ChatChannel::Room(_) => ChatScope::Room,
+        }
+    }
+
+    pub fn channel_id(&self) -> String {
+        match self {
+            ChatChannel::Party(party_id) => party_id.to_string(),
+            ChatChannel::Room(room_id) => room_id.clone(),
+        }
+    }
+
+    // The sender's current party or room for the requested scope. The lobby is not a room, chatting
+    // there would reach every connected player
+    fn for_player(scope: ChatScope, player_id: &Uuid, parties: &Parties, rooms: &Rooms) -> Option<Self> {
+        match scope {
+            ChatScope::Party => parties.party_for_player(player_id).map(|party| ChatChannel::Party(party.party_id)),
+            ChatScope::Room => match rooms.room_of(player_id) {
+                DEFAULT_ROOM_ID => None,
+                room_id => Some(ChatChannel::Room(String::from(room_id))),
+            },
+        }
+    }
+
+    // None once the party was disbanded or the room removed
+    fn members(&self, parties: &Parties, rooms: &Rooms, connected_players: &ConnectedPlayers) -> Option<Vec<Uuid>> {
+        match self {
+            ChatChannel::Party(party_id) => parties.get(party_id).map(|party| party.members.clone()),
+            ChatChannel::Room(room_id) => {
+                if !rooms.rooms.contains_key(room_id) {
+                    return None;
+                }
+                Some(rooms.members_of(room_id, connected_players))
+            }
+        }
+    }
+}
+
+impl Chat {
+    // CHAT_WORD_FILTER_FILE holds one filtered word or phrase per line, '#' starts a comment
+    pub fn load() -> Self {
+        let path = env::var("CHAT_WORD_FILTER_FILE").unwrap_or_else(|_| String::from(DEFAULT_WORD_FILTER_FILE));
+        let words: Vec<String> = match fs::read_to_string(&path) {
+            Ok(contents) => contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(regex::escape)
+                .collect(),
+            Err(err) => {
+                warn!("Failed to read chat word filter {:?}: {}", path, err);
+                Vec::new()
+            }
+        };
+        let word_filter = if words.is_empty() {
+            None
+        } else {
+            match Regex::new(&format!(r"(?i)\b(?:{})\b", words.join("|"))) {
+                Ok(word_filter) => Some(word_filter),
+                Err(err) => {
+                    error!("Failed to build chat word filter: {:?}", err);
+                    None
+                }
+            }
+        };
+        info!("Loaded {} chat word filter entries from {:?}", words.len(), path);
+        Self {
+            history: HashMap::new(),
+            seen_members: HashMap::new(),
+            recent_sends: HashMap::new(),
+            muted_by: HashMap::new(),
+            silenced: HashMap::new(),
+            word_filter,
+        }
+    }
+
+    // Filtered words are masked rather than rejecting the whole message
+    pub fn filter(&self, text: &str) -> String {
+        match self.word_filter.as_ref() {
+            Some(word_filter) => word_filter
+                .replace_all(text, |caps: &regex::Captures| "*".repeat(caps[0].chars().count()))
+                .into_owned(),
+            None => String::from(text),
+        }
+    }
+
+    fn check_send(&mut self, player_id: &Uuid, text: &str, now: Instant) -> Result<(), String> {
+        if self.silenced.get(player_id).is_some_and(|until| *until > now) {
+            return Err(String::from("You are muted"));
+        }
+        let length = text.chars().count();
+        if length == 0 {
+            return Err(String::from("Message is empty"));
+        }
+        if length > MAX_MESSAGE_LENGTH {
+            return Err(format!("Message is longer than {} characters", MAX_MESSAGE_LENGTH));
+        }
+        let sends = self.recent_sends.entry(*player_id).or_default();
+        while sends.front().is_some_and(|sent| now.duration_since(*sent) > RATE_LIMIT_WINDOW) {
+            sends.pop_front();
+        }
+        if sends.len() >= RATE_LIMIT_MESSAGES {
+            return Err(String::from("Sending messages too quickly"));
+        }
+        sends.push_back(now);
+        Ok(())
+    }
+
+    fn is_muted_by(&self, player_id: &Uuid, sender_id: &Uuid) -> bool {
+        self.muted_by.get(player_id).is_some_and(|muted| muted.contains(sender_id))
+    }
+
+    fn push_history(&mut self, channel: &ChatChannel, message: ChatMessage) {
+        let history = self.history.entry(channel.clone()).or_default();
+        history.push_back(message);
+        while history.len() > HISTORY_LENGTH {
+            history.pop_front();
+        }
+    }
+}
+
+// Messages from muted or blocked senders never reach the player, live or from history
+fn visible_to(chat: &Chat, friends: &Friends, player_id: &Uuid, sender_id: &str) -> bool {
+    match Uuid::parse_str(sender_id) {
+        Ok(sender_id) => !chat.is_muted_by(player_id, &sender_id) && !friends.is_blocked(player_id, &sender_id),
+        Err(_) => false,
+    }
+}
+
+fn send_chat_rejection(
+    socket: &mut MatchboxSocket<SingleChannel>,
+    connected_players: &ConnectedPlayers,
+    player_id: &Uuid,
+    scope: ChatScope,
+    reason: String,
+) {
+    info!("Chat message from {} rejected: {}", player_id, reason);
+    let rejection = PacketChatRejected {
+        scope,
+        reason,
+    };
+    send_player_message(socket, connected_players, player_id, "ChatRejected", &rejection);
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn chat_request_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    mut chat: ResMut<Chat>,
+    friends: Res<Friends>,
+    parties: Res<Parties>,
+    rooms: Res<Rooms>,
+    admins: Res<Admins>,
+) {
+    for event in event_reader.read() {
+        match event.command.as_str() {
+            "ChatSend" => {
+                let packet = match serde_json::from_str::<PacketChatSend>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketChatSend from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let Some(channel) = ChatChannel::for_player(packet.scope, &player_id, &parties, &rooms) else {
+                    let reason = match packet.scope {
+                        ChatScope::Party => String::from("Not in a party"),
+                        ChatScope::Room => String::from("Not in a room"),
+                    };
+                    send_chat_rejection(&mut socket, &connected_players, &player_id, packet.scope, reason);
+                    continue;
+                };
+                let text = packet.text.trim();
+                if let Err(reason) = chat.check_send(&player_id, text, Instant::now()) {
+                    send_chat_rejection(&mut socket, &connected_players, &player_id, packet.scope, reason);
+                    continue;
+                }
+                let Some(members) = channel.members(&parties, &rooms, &connected_players) else {
+                    continue;
+                };
+
+                let message = ChatMessage {
+                    message_id: Uuid::now_v7(),
+                    scope: channel.scope(),
+                    channel_id: channel.channel_id(),
+                    sender_id: player_id.to_string(),
+                    text: chat.filter(text),
+                    sent: OffsetDateTime::now_utc(),
+                };
+                // The sender gets the filtered message back as confirmation
+                for member in members.iter() {
+                    if visible_to(&chat, &friends, member, &message.sender_id) {
+                        send_player_message(&mut socket, &connected_players, member, "ChatMessage", &message);
+                    }
+                }
+                chat.seen_members.insert(channel.clone(), members.into_iter().collect());
+                chat.push_history(&channel, message);
+            }
+            "ChatMute" | "ChatUnmute" => {
+                let packet = match serde_json::from_str::<PacketChatMute>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketChatMute from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let Ok(target_id) = Uuid::parse_str(&packet.target_id) else {
+                    warn!("{} carried an invalid target_id {:?}", event.command, packet.target_id);
+                    continue;
+                };
+                let muted = chat.muted_by.entry(player_id).or_default();
+                if event.command == "ChatMute" {
+                    muted.insert(target_id);
+                } else {
+                    muted.remove(&target_id);
+                }
+            }
+            "ChatSilence" => {
+                let packet = match serde_json::from_str::<PacketChatSilence>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketChatSilence from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                if !admins.is_admin(&player_id, event.peer) {
+                    warn!("Player {} is not an admin, ignoring ChatSilence", player_id);
+                    continue;
+                }
+                let Ok(target_id) = Uuid::parse_str(&packet.target_id) else {
+                    warn!("ChatSilence carried an invalid target_id {:?}", packet.target_id);
+                    continue;
+                };
+                if packet.minutes == 0 {
+                    chat.silenced.remove(&target_id);
+                    info!("Admin {} lifted the chat mute on {}", player_id, target_id);
+                } else {
+                    let until = Instant::now() + Duration::from_secs(packet.minutes as u64 * 60);
+                    chat.silenced.insert(target_id, until);
+                    info!("Admin {} muted {} in chat for {} minutes", player_id, target_id, packet.minutes);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Hands the channel history to players who joined since the last message, and forgets channels
+// whose party or room is gone
+pub fn chat_history_system(
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    mut chat: ResMut<Chat>,
+    friends: Res<Friends>,
+    parties: Res<Parties>,
+    rooms: Res<Rooms>,
+) {
+    let now = Instant::now();
+    chat.silenced.retain(|_, until| *until > now);
+
+    let channels: Vec<ChatChannel> = chat.history.keys().cloned().collect();
+    for channel in channels {
+        let Some(members) = channel.members(&parties, &rooms, &connected_players) else {
+            chat.history.remove(&channel);
+            chat.seen_members.remove(&channel);
+            continue;
+        };
+        let members: HashSet<Uuid> = members.into_iter().collect();
+        let joined: Vec<Uuid> = match chat.seen_members.get(&channel) {
+            Some(seen) => members.difference(seen).copied().collect(),
+            None => members.iter().copied().collect(),
+        };
+        for player_id in joined.iter() {
+            let messages: Vec<ChatMessage> = chat.history[&channel]
+                .iter()
+                .filter(|message| visible_to(&chat, &friends, player_id, &message.sender_id))
+                .cloned()
+                .collect();
+            let history = PacketChatHistory {
+                scope: channel.scope(),
+                channel_id: channel.channel_id(),
+                messages,
+            };
+            send_player_message(&mut socket, &connected_players, player_id, "ChatHistory", &history);
+        }
+        chat.seen_members.insert(channel, members);
+    }
+}
+
+pub fn chat_disconnect_system(
+    mut event_reader: EventReader<PlayerDisconnectedEvent>,
+    mut chat: ResMut<Chat>,
+) {
+    for event in event_reader.read() {
+        chat.recent_sends.remove(&event.player_id);
+        chat.muted_by.remove(&event.player_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lobby_players_have_no_room_channel() {
+        let parties = Parties::new(4);
+        let mut rooms = Rooms::new(Duration::from_secs(60));
+        let player_id = Uuid::now_v7();
+
+        assert!(ChatChannel::for_player(ChatScope::Room, &player_id, &parties, &rooms).is_none());
+
+        let room = rooms.create(None, player_id).unwrap();
+        assert_eq!(
+            ChatChannel::for_player(ChatScope::Room, &player_id, &parties, &rooms),
+            Some(ChatChannel::Room(room.room_id)),
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use bevy::prelude::*;
+
+use bevy_matchbox::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::{
+    ClientStateQuery,
+    ClientStateQueryCompleteEvent,
+    ClientStateQueryEvent,
+    ClientStateQueryResult,
+    ConnectedPlayers,
+    PacketAllStates,
+    PacketStateRequest,
+    RunTrigger,
+};
+
+use crate::handlers::signaling_server_handler::send_player_message;
+
+impl ClientStateQuery {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            request_id: None,
+            started: None,
+            timeout,
+            pending: HashSet::new(),
+            responses: HashMap::new(),
+            last_result: None,
+        }
+    }
+
+    pub fn begin(&mut self, player_ids: Vec<Uuid>) -> Uuid {
+        let request_id = Uuid::now_v7();
+        self.request_id = Some(request_id);
+        self.started = Some(Instant::now());
+        self.pending = player_ids.into_iter().collect();
+        self.responses.clear();
+        request_id
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.request_id.is_some()
+    }
+
+    // Returns true when the reply belonged to the query in flight
+    pub fn record_response(&mut self, all_states: PacketAllStates) -> bool {
+        let player_id = match Uuid::parse_str(&all_states.player_id) {
+            Ok(player_id) => player_id,
+            Err(err) => {
+                warn!("PacketAllStates carried an invalid player_id {:?}: {:?}", all_states.player_id, err);
+                return false;
+            }
+        };
+        let request_id = match self.request_id {
+            Some(request_id) => request_id,
+            None => {
+                info!("PacketAllStates from player {} with no query in flight", player_id);
+                return false;
+            }
+        };
+        if all_states.request_id != request_id.to_string() {
+            info!(
+                "Dropping PacketAllStates from player {} for request [{}], query in flight is [{}]",
+                player_id, all_states.request_id, request_id,
+            );
+            return false;
+        }
+        if self.pending.remove(&player_id) {
+            self.responses.insert(player_id, all_states);
+            true
+        } else {
+            info!("Unsolicited PacketAllStates from player {}", player_id);
+            false
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        match self.started {
+            Some(started) => self.pending.is_empty() || started.elapsed() >= self.timeout,
+            None => false,
+        }
+    }
+
+    pub fn finish(&mut self) -> Option<ClientStateQueryResult> {
+        let request_id = self.request_id.take()?;
+        self.started = None;
+        let mut responses: Vec<(Uuid, PacketAllStates)> = self.responses.drain().collect();
+        responses.sort_by_key(|(player_id, _)| *player_id);
+        let mut non_responders: Vec<Uuid> = self.pending.drain().collect();
+        non_responders.sort();
+        let result = ClientStateQueryResult {
+            request_id,
+            responses,
+            non_responders,
+        };
+        self.last_result = Some(result.clone());
+        Some(result)
+    }
+
+    pub fn get_last_result(&self) -> Option<&ClientStateQueryResult> {
+        self.last_result.as_ref()
+    }
+}
+
+pub fn network_get_client_state_game(
+    mut event_writer: EventWriter<ClientStateQueryEvent>,
+    mut run_trigger: ResMut<RunTrigger>,
+) {
+    event_writer.send(ClientStateQueryEvent {
+        player_ids: Vec::new(),
+    });
+    run_trigger.set_target("network_get_client_state_game", false);
+}
+
+pub fn client_state_query_request_system(
+    mut event_reader: EventReader<ClientStateQueryEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    mut client_state_query: ResMut<ClientStateQuery>,
+) {
+    for event in event_reader.read() {
+        if client_state_query.is_active() {
+            warn!("client_state_query_request_system: query already in flight, ignoring request");
+            continue;
+        }
+
+        let player_ids = if event.player_ids.is_empty() {
+            connected_players.player_ids()
+        } else {
+            event.player_ids.clone()
+        };
+        if player_ids.is_empty() {
+            info!("client_state_query_request_system: no players to query");
+            continue;
+        }
+
+        let request_id = client_state_query.begin(player_ids.clone());
+        info!("client_state_query_request_system: request [{}] to {:?}", request_id, player_ids);
+
+        for player_id in player_ids {
+            // Players without a known peer stay pending and are reported as non-responders
+            let request = PacketStateRequest {
+                request_id: request_id.to_string(),
+                player_id: player_id.to_string(),
+            };
+            send_player_message(&mut socket, &connected_players, &player_id, "RequestAllStates", &request);
+        }
+    }
+}
+
+pub fn client_state_query_timeout_system(
+    mut client_state_query: ResMut<ClientStateQuery>,
+    mut event_writer: EventWriter<ClientStateQueryCompleteEvent>,
+) {
+    if !client_state_query.is_complete() {
+        return;
+    }
+    if let Some(result) = client_state_query.finish() {
+        info!(
+            "Client state query [{}] finished: {} responded, {} did not respond",
+            result.request_id,
+            result.responses.len(),
+            result.non_responders.len(),
+        );
+        event_writer.send(ClientStateQueryCompleteEvent { result });
+    }
+}
@@ -71,7 +71,7 @@ pub async fn db_pipeline_player_init_async(
         Some((db_player_id, _)) => {
             // Player email exists, so we need to sync the ID with the client
 
-            if let Some(db_player_id) = db_player_id.clone() {
+            if let Some(db_player_id) = *db_player_id {
                 // Send the correct ID (from the database) to the client
                 ctx.run_on_main_thread(move |ctx| { 
                     let event_writer = ctx.world.get_resource_mut::<Events<SyncPlayerIdEvent>>();
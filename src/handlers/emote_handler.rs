@@ -0,0 +1,188 @@
+use bevy::prelude::*;
+
+use bevy_matchbox::prelude::*;
+use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+use sqlx::{MySqlPool, Error};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::{
+    ClientRequestEvent,
+    ConnectedPlayers,
+    DatabasePool,
+    Emotes,
+    Friends,
+    GameSessions,
+    PacketEmote,
+    PacketEmoteRejected,
+    PacketEmoteSend,
+};
+
+use crate::handlers::signaling_server_handler::send_player_message;
+
+// Clients map these ids to their own text, sounds and animations
+pub const EMOTE_IDS: [&str; 8] = [
+    "nice_shot",
+    "oops",
+    "so_close",
+    "wow",
+    "good_game",
+    "hurry_up",
+    "thanks",
+    "unlucky",
+];
+const EMOTE_COOLDOWN: Duration = Duration::from_secs(2);
+
+impl Emotes {
+    pub fn new() -> Self {
+        Self {
+            last_sent: HashMap::new(),
+            pending: HashMap::new(),
+            totals: HashMap::new(),
+            flushing: false,
+        }
+    }
+
+    fn check_send(&mut self, player_id: &Uuid, emote_id: &str, now: Instant) -> Result<(), &'static str> {
+        if !EMOTE_IDS.contains(&emote_id) {
+            return Err("Unknown emote");
+        }
+        if self.last_sent.get(player_id).is_some_and(|sent| now.duration_since(*sent) < EMOTE_COOLDOWN) {
+            return Err("Sending emotes too quickly");
+        }
+        self.last_sent.insert(*player_id, now);
+        Ok(())
+    }
+
+    fn count(&mut self, player_id: Uuid, emote_id: &str, uses: i32) {
+        *self.pending.entry((player_id, String::from(emote_id))).or_default() += uses;
+    }
+}
+
+impl Default for Emotes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn emote_request_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    mut emotes: ResMut<Emotes>,
+    friends: Res<Friends>,
+    game_sessions: Res<GameSessions>,
+) {
+    for event in event_reader.read() {
+        if event.command != "EmoteSend" {
+            continue;
+        }
+        let packet = match serde_json::from_str::<PacketEmoteSend>(&event.payload) {
+            Ok(packet) => packet,
+            Err(err) => {
+                error!("Failed to deserialize PacketEmoteSend from JSON: {:?}", err);
+                continue;
+            }
+        };
+        let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+            continue;
+        };
+        let result = match game_sessions.session_for_player(&player_id) {
+            Some(session) => emotes.check_send(&player_id, &packet.emote_id, Instant::now()).map(|_| session),
+            None => Err("Not in a game session"),
+        };
+        let session = match result {
+            Ok(session) => session,
+            Err(reason) => {
+                let rejection = PacketEmoteRejected {
+                    emote_id: packet.emote_id,
+                    reason: String::from(reason),
+                };
+                send_player_message(&mut socket, &connected_players, &player_id, "EmoteRejected", &rejection);
+                continue;
+            }
+        };
+
+        let emote = PacketEmote {
+            session_id: session.session_id.to_string(),
+            sender_id: player_id.to_string(),
+            emote_id: packet.emote_id,
+        };
+        // Players who blocked the sender, or were blocked by them, do not see the emote
+        for member in session.player_order.iter() {
+            if *member != player_id && !friends.is_blocked(member, &player_id) {
+                send_player_message(&mut socket, &connected_players, member, "Emote", &emote);
+            }
+        }
+        emotes.count(player_id, &emote.emote_id, 1);
+        *emotes.totals.entry(emote.emote_id).or_default() += 1;
+    }
+}
+
+// Usage is written in batches, emotes are too frequent for a write each
+pub fn emote_persist_system(
+    mut emotes: ResMut<Emotes>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    let now = Instant::now();
+    emotes.last_sent.retain(|_, sent| now.duration_since(*sent) < EMOTE_COOLDOWN);
+    if emotes.flushing || emotes.pending.is_empty() {
+        return;
+    }
+    emotes.flushing = true;
+    let pending = std::mem::take(&mut emotes.pending);
+    let pool = pool.0.clone();
+    // Spawn the background task using bevy_tokio_tasks
+    runtime.spawn_background_task(move |ctx| {
+        save_emote_stats_async(pending, pool, ctx)
+    });
+}
+
+async fn save_emote_stats(pool: &MySqlPool, pending: &HashMap<(Uuid, String), i32>) -> Result<(), Error> {
+    let mut tx = pool.begin().await?;
+    for ((player_id, emote_id), uses) in pending.iter() {
+        sqlx::query(
+            "INSERT INTO player_emote_stats (player_id, emote_id, uses) VALUES (UUID_TO_BIN(?), ?, ?)
+             ON DUPLICATE KEY UPDATE uses = uses + VALUES(uses)",
+        )
+        .bind(player_id.to_string())
+        .bind(emote_id)
+        .bind(uses)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await
+}
+
+pub async fn save_emote_stats_async(
+    pending: HashMap<(Uuid, String), i32>,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let result = save_emote_stats(&pool, &pending).await;
+    if let Err(err) = result.as_ref() {
+        let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+        eprintln!("Failed to save emote stats: {:?}", err_for_ctx);
+        ctx.run_on_main_thread(move |_ctx| {
+            info!("Failed to save emote stats in the task: {:?}", err_for_ctx);
+        })
+        .await;
+    }
+
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some(mut emotes) = ctx.world.get_resource_mut::<Emotes>() {
+            emotes.flushing = false;
+            // Put the counts back so the next flush retries them
+            if result.is_err() {
+                for ((player_id, emote_id), uses) in pending {
+                    emotes.count(player_id, &emote_id, uses);
+                }
+            }
+        } else {
+            info!("Failed to access emotes resource");
+        }
+    })
+    .await;
+}
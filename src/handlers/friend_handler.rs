@@ -0,0 +1,622 @@
+use bevy::prelude::*;
+
+use bevy_matchbox::prelude::*;
+use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+use sqlx::{MySqlConnection, MySqlPool, Error};
+use std::collections::{HashMap, HashSet};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    ClientRequestEvent,
+    ConnectedPlayers,
+    DatabasePool,
+    FriendAction,
+    FriendEntry,
+    FriendRequestEntry,
+    Friends,
+    PacketFriendAction,
+    PacketFriendList,
+    PacketFriendListRequest,
+    PacketFriendPresence,
+    PacketFriendStatus,
+    Presence,
+    PresenceStatus,
+};
+
+use crate::handlers::signaling_server_handler::{send_peer_message, send_player_message};
+
+// The client's game state outside of a game, anything else counts as playing
+const NOT_IN_GAME: &str = "NotInGame";
+
+impl FriendAction {
+    pub fn from_command(command: &str) -> Option<Self> {
+        match command {
+            "FriendRequest" => Some(FriendAction::Request),
+            "FriendAccept" => Some(FriendAction::Accept),
+            "FriendDecline" => Some(FriendAction::Decline),
+            "FriendRemove" => Some(FriendAction::Remove),
+            "PlayerBlock" => Some(FriendAction::Block),
+            "PlayerUnblock" => Some(FriendAction::Unblock),
+            _ => None,
+        }
+    }
+}
+
+impl Presence {
+    pub fn offline() -> Self {
+        Self {
+            status: PresenceStatus::Offline,
+            menu: None,
+            map_set: None,
+        }
+    }
+
+    // Derived from the connection and the last PacketAllStates the client reported
+    pub fn of(connected_players: &ConnectedPlayers, player_id: &Uuid) -> Self {
+        if !connected_players.is_connected(player_id) {
+            return Presence::offline();
+        }
+        match connected_players.get_states(player_id) {
+            Some(all_states) if !all_states.state_game.is_empty() && all_states.state_game != NOT_IN_GAME => Self {
+                status: PresenceStatus::InGame,
+                menu: None,
+                map_set: Some(all_states.state_map_set),
+            },
+            Some(all_states) => Self {
+                status: PresenceStatus::InMenu,
+                menu: Some(all_states.state_menu),
+                map_set: None,
+            },
+            None => Self {
+                status: PresenceStatus::InMenu,
+                menu: None,
+                map_set: None,
+            },
+        }
+    }
+}
+
+impl Friends {
+    pub fn new() -> Self {
+        Self {
+            friends: HashMap::new(),
+            blocked: HashMap::new(),
+            loading: HashSet::new(),
+            presence: HashMap::new(),
+        }
+    }
+
+    // True when either player blocked the other
+    pub fn is_blocked(&self, player_id: &Uuid, other_id: &Uuid) -> bool {
+        let blocks = |by: &Uuid, target: &Uuid| self.blocked.get(by).is_some_and(|blocked| blocked.contains(target));
+        blocks(player_id, other_id) || blocks(other_id, player_id)
+    }
+
+    pub fn presence_of(&self, player_id: &Uuid) -> Presence {
+        self.presence.get(player_id).cloned().unwrap_or_else(Presence::offline)
+    }
+
+    // Connected players whose friend list holds the player
+    pub fn online_friends_of(&self, player_id: &Uuid) -> Vec<Uuid> {
+        self.friends
+            .iter()
+            .filter(|(_, friends)| friends.contains(player_id))
+            .map(|(friend_id, _)| *friend_id)
+            .collect()
+    }
+
+    fn set_lists(&mut self, player_id: Uuid, friends: HashSet<Uuid>, blocked: HashSet<Uuid>) {
+        self.friends.insert(player_id, friends);
+        self.blocked.insert(player_id, blocked);
+    }
+}
+
+impl Default for Friends {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn fetch_friend_sets(
+    conn: &mut MySqlConnection,
+    player_id: &Uuid,
+) -> Result<(HashSet<Uuid>, HashSet<Uuid>), Error> {
+    let friends: Vec<(Uuid,)> = sqlx::query_as("SELECT friend_id FROM player_friend WHERE player_id = UUID_TO_BIN(?)")
+        .bind(player_id.to_string())
+        .fetch_all(&mut *conn)
+        .await?;
+    let blocked: Vec<(Uuid,)> = sqlx::query_as("SELECT blocked_id FROM player_block WHERE player_id = UUID_TO_BIN(?)")
+        .bind(player_id.to_string())
+        .fetch_all(&mut *conn)
+        .await?;
+    Ok((
+        friends.into_iter().map(|(friend_id,)| friend_id).collect(),
+        blocked.into_iter().map(|(blocked_id,)| blocked_id).collect(),
+    ))
+}
+
+async fn count(conn: &mut MySqlConnection, sql: &str, player_id: &Uuid, other_id: &Uuid) -> Result<i64, Error> {
+    let (count,): (i64,) = sqlx::query_as(sql)
+        .bind(player_id.to_string())
+        .bind(other_id.to_string())
+        .bind(other_id.to_string())
+        .bind(player_id.to_string())
+        .fetch_one(&mut *conn)
+        .await?;
+    Ok(count)
+}
+
+async fn insert_friendship(conn: &mut MySqlConnection, player_id: &Uuid, friend_id: &Uuid) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT IGNORE INTO player_friend (player_id, friend_id)
+         VALUES (UUID_TO_BIN(?), UUID_TO_BIN(?)), (UUID_TO_BIN(?), UUID_TO_BIN(?))",
+    )
+    .bind(player_id.to_string())
+    .bind(friend_id.to_string())
+    .bind(friend_id.to_string())
+    .bind(player_id.to_string())
+    .execute(&mut *conn)
+    .await
+    .map(|_| ())
+}
+
+// Drops the friendship and any pending request between the two players, returns the rows removed
+async fn delete_friendship(conn: &mut MySqlConnection, player_id: &Uuid, friend_id: &Uuid) -> Result<u64, Error> {
+    let mut removed = 0;
+    for sql in [
+        "DELETE FROM player_friend
+         WHERE (player_id = UUID_TO_BIN(?) AND friend_id = UUID_TO_BIN(?))
+            OR (player_id = UUID_TO_BIN(?) AND friend_id = UUID_TO_BIN(?))",
+        "DELETE FROM player_friend_request
+         WHERE (from_player_id = UUID_TO_BIN(?) AND to_player_id = UUID_TO_BIN(?))
+            OR (from_player_id = UUID_TO_BIN(?) AND to_player_id = UUID_TO_BIN(?))",
+    ] {
+        removed += sqlx::query(sql)
+            .bind(player_id.to_string())
+            .bind(friend_id.to_string())
+            .bind(friend_id.to_string())
+            .bind(player_id.to_string())
+            .execute(&mut *conn)
+            .await?
+            .rows_affected();
+    }
+    Ok(removed)
+}
+
+async fn take_request(conn: &mut MySqlConnection, from_player_id: &Uuid, to_player_id: &Uuid) -> Result<bool, Error> {
+    let result = sqlx::query(
+        "DELETE FROM player_friend_request WHERE from_player_id = UUID_TO_BIN(?) AND to_player_id = UUID_TO_BIN(?)",
+    )
+    .bind(from_player_id.to_string())
+    .bind(to_player_id.to_string())
+    .execute(&mut *conn)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+// Ok(Some(reason)) when the action was refused, nothing is written in that case
+async fn apply_friend_action(
+    pool: &MySqlPool,
+    action: FriendAction,
+    player_id: &Uuid,
+    target_id: &Uuid,
+) -> Result<Option<&'static str>, Error> {
+    if player_id == target_id {
+        return Ok(Some("Cannot target yourself"));
+    }
+    let mut tx = pool.begin().await?;
+    let (exists,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM player_table WHERE player_id = UUID_TO_BIN(?)")
+        .bind(target_id.to_string())
+        .fetch_one(&mut *tx)
+        .await?;
+    if exists == 0 {
+        return Ok(Some("Unknown player"));
+    }
+
+    let refused = match action {
+        FriendAction::Request => {
+            let blocked = count(
+                &mut tx,
+                "SELECT COUNT(*) FROM player_block
+                 WHERE (player_id = UUID_TO_BIN(?) AND blocked_id = UUID_TO_BIN(?))
+                    OR (player_id = UUID_TO_BIN(?) AND blocked_id = UUID_TO_BIN(?))",
+                player_id,
+                target_id,
+            )
+            .await?;
+            let friends = count(
+                &mut tx,
+                "SELECT COUNT(*) FROM player_friend
+                 WHERE (player_id = UUID_TO_BIN(?) AND friend_id = UUID_TO_BIN(?))
+                    OR (player_id = UUID_TO_BIN(?) AND friend_id = UUID_TO_BIN(?))",
+                player_id,
+                target_id,
+            )
+            .await?;
+            if blocked > 0 {
+                Some("Player is unavailable")
+            } else if friends > 0 {
+                Some("Already friends")
+            } else if take_request(&mut tx, target_id, player_id).await? {
+                // Both asked each other, so this doubles as accepting
+                insert_friendship(&mut tx, player_id, target_id).await?;
+                None
+            } else {
+                let result = sqlx::query(
+                    "INSERT IGNORE INTO player_friend_request (from_player_id, to_player_id)
+                     VALUES (UUID_TO_BIN(?), UUID_TO_BIN(?))",
+                )
+                .bind(player_id.to_string())
+                .bind(target_id.to_string())
+                .execute(&mut *tx)
+                .await?;
+                (result.rows_affected() == 0).then_some("Request already sent")
+            }
+        }
+        FriendAction::Accept => {
+            if take_request(&mut tx, target_id, player_id).await? {
+                insert_friendship(&mut tx, player_id, target_id).await?;
+                None
+            } else {
+                Some("No pending request from this player")
+            }
+        }
+        FriendAction::Decline => {
+            (!take_request(&mut tx, target_id, player_id).await?).then_some("No pending request from this player")
+        }
+        FriendAction::Remove => {
+            (delete_friendship(&mut tx, player_id, target_id).await? == 0).then_some("Not friends with this player")
+        }
+        FriendAction::Block => {
+            delete_friendship(&mut tx, player_id, target_id).await?;
+            let result = sqlx::query(
+                "INSERT IGNORE INTO player_block (player_id, blocked_id) VALUES (UUID_TO_BIN(?), UUID_TO_BIN(?))",
+            )
+            .bind(player_id.to_string())
+            .bind(target_id.to_string())
+            .execute(&mut *tx)
+            .await?;
+            (result.rows_affected() == 0).then_some("Player is already blocked")
+        }
+        FriendAction::Unblock => {
+            let result = sqlx::query(
+                "DELETE FROM player_block WHERE player_id = UUID_TO_BIN(?) AND blocked_id = UUID_TO_BIN(?)",
+            )
+            .bind(player_id.to_string())
+            .bind(target_id.to_string())
+            .execute(&mut *tx)
+            .await?;
+            (result.rows_affected() == 0).then_some("Player is not blocked")
+        }
+    };
+    if refused.is_none() {
+        tx.commit().await?;
+    }
+    Ok(refused)
+}
+
+// Friend presence is filled in on the main thread, everything else comes from the database
+async fn fetch_friend_list(pool: &MySqlPool, player_id: &Uuid) -> Result<PacketFriendList, Error> {
+    let friends: Vec<(Uuid, String)> = sqlx::query_as(
+        "SELECT f.friend_id, p.username FROM player_friend f
+         JOIN player_table p ON p.player_id = f.friend_id
+         WHERE f.player_id = UUID_TO_BIN(?)
+         ORDER BY p.username ASC",
+    )
+    .bind(player_id.to_string())
+    .fetch_all(pool)
+    .await?;
+    let incoming: Vec<(Uuid, String, OffsetDateTime)> = sqlx::query_as(
+        "SELECT r.from_player_id, p.username, r.created FROM player_friend_request r
+         JOIN player_table p ON p.player_id = r.from_player_id
+         WHERE r.to_player_id = UUID_TO_BIN(?)
+         ORDER BY r.created ASC",
+    )
+    .bind(player_id.to_string())
+    .fetch_all(pool)
+    .await?;
+    let outgoing: Vec<(Uuid, String, OffsetDateTime)> = sqlx::query_as(
+        "SELECT r.to_player_id, p.username, r.created FROM player_friend_request r
+         JOIN player_table p ON p.player_id = r.to_player_id
+         WHERE r.from_player_id = UUID_TO_BIN(?)
+         ORDER BY r.created ASC",
+    )
+    .bind(player_id.to_string())
+    .fetch_all(pool)
+    .await?;
+    let blocked: Vec<(Uuid,)> = sqlx::query_as(
+        "SELECT blocked_id FROM player_block WHERE player_id = UUID_TO_BIN(?) ORDER BY created ASC",
+    )
+    .bind(player_id.to_string())
+    .fetch_all(pool)
+    .await?;
+
+    let to_requests = |rows: Vec<(Uuid, String, OffsetDateTime)>| -> Vec<FriendRequestEntry> {
+        rows.into_iter()
+            .map(|(player_id, username, created)| FriendRequestEntry {
+                player_id: player_id.to_string(),
+                username,
+                created,
+            })
+            .collect()
+    };
+    Ok(PacketFriendList {
+        player_id: player_id.to_string(),
+        friends: friends
+            .into_iter()
+            .map(|(friend_id, username)| FriendEntry {
+                player_id: friend_id.to_string(),
+                username,
+                presence: Presence::offline(),
+            })
+            .collect(),
+        incoming: to_requests(incoming),
+        outgoing: to_requests(outgoing),
+        blocked: blocked.into_iter().map(|(blocked_id,)| blocked_id.to_string()).collect(),
+    })
+}
+
+fn send_friend_list(world: &mut World, player_id: &Uuid, mut list: PacketFriendList) {
+    let Some(connected_players) = world.get_resource::<ConnectedPlayers>().cloned() else {
+        return;
+    };
+    if !connected_players.is_connected(player_id) {
+        return;
+    }
+    if let Some(friends) = world.get_resource::<Friends>() {
+        for entry in list.friends.iter_mut() {
+            if let Ok(friend_id) = Uuid::parse_str(&entry.player_id) {
+                entry.presence = friends.presence_of(&friend_id);
+            }
+        }
+    }
+    if let Some(mut socket) = world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+        send_player_message(&mut socket, &connected_players, player_id, "FriendList", &list);
+    } else {
+        info!("Failed to access matchbox resource");
+    }
+}
+
+pub fn friends_load_system(
+    mut friends: ResMut<Friends>,
+    connected_players: Res<ConnectedPlayers>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    // Lists of players who left are dropped, they are loaded again on reconnect
+    let connected: HashSet<Uuid> = connected_players.player_ids().into_iter().collect();
+    friends.friends.retain(|player_id, _| connected.contains(player_id));
+    friends.blocked.retain(|player_id, _| connected.contains(player_id));
+
+    let player_ids: Vec<Uuid> = connected
+        .into_iter()
+        .filter(|player_id| !friends.friends.contains_key(player_id) && !friends.loading.contains(player_id))
+        .collect();
+    if player_ids.is_empty() {
+        return;
+    }
+    friends.loading.extend(player_ids.iter().copied());
+    let pool = pool.0.clone();
+    // Spawn the background task using bevy_tokio_tasks
+    runtime.spawn_background_task(move |ctx| {
+        load_friends_async(player_ids, pool, ctx)
+    });
+}
+
+pub async fn load_friends_async(
+    player_ids: Vec<Uuid>,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let mut loaded = Vec::new();
+    for player_id in player_ids.iter() {
+        let sets = match pool.acquire().await {
+            Ok(mut conn) => fetch_friend_sets(&mut conn, player_id).await,
+            Err(err) => Err(err),
+        };
+        match sets {
+            Ok((friends, blocked)) => loaded.push((*player_id, friends, blocked)),
+            Err(err) => {
+                let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+                eprintln!("Failed to load friends: {:?}", err_for_ctx);
+                ctx.run_on_main_thread(move |_ctx| {
+                    info!("Failed to load friends in the task: {:?}", err_for_ctx);
+                })
+                .await;
+            }
+        }
+    }
+
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some(mut friends) = ctx.world.get_resource_mut::<Friends>() {
+            for player_id in player_ids.iter() {
+                friends.loading.remove(player_id);
+            }
+            for (player_id, friend_ids, blocked) in loaded {
+                friends.set_lists(player_id, friend_ids, blocked);
+            }
+        } else {
+            info!("Failed to access friends resource");
+        }
+    })
+    .await;
+}
+
+pub fn friends_presence_system(
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    mut friends: ResMut<Friends>,
+    connected_players: Res<ConnectedPlayers>,
+) {
+    let mut changed: Vec<(Uuid, Presence)> = Vec::new();
+    for player_id in connected_players.player_ids() {
+        let presence = Presence::of(&connected_players, &player_id);
+        if friends.presence.get(&player_id) != Some(&presence) {
+            changed.push((player_id, presence));
+        }
+    }
+    let gone: Vec<Uuid> = friends
+        .presence
+        .keys()
+        .filter(|player_id| !connected_players.is_connected(player_id))
+        .copied()
+        .collect();
+    changed.extend(gone.into_iter().map(|player_id| (player_id, Presence::offline())));
+
+    for (player_id, presence) in changed {
+        if presence.status == PresenceStatus::Offline {
+            friends.presence.remove(&player_id);
+        } else {
+            friends.presence.insert(player_id, presence.clone());
+        }
+        let packet = PacketFriendPresence {
+            player_id: player_id.to_string(),
+            presence,
+        };
+        for friend_id in friends.online_friends_of(&player_id) {
+            send_player_message(&mut socket, &connected_players, &friend_id, "FriendPresence", &packet);
+        }
+    }
+}
+
+pub async fn friend_action_async(
+    command: String,
+    action: FriendAction,
+    player_id: Uuid,
+    target_id: Uuid,
+    peer: PeerId,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let refused = match apply_friend_action(&pool, action, &player_id, &target_id).await {
+        Ok(refused) => refused,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to apply friend action: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to apply friend action in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            Some("Server error")
+        }
+    };
+
+    // Both players get fresh lists, for the target this is the notice of the request or change
+    let mut updates = Vec::new();
+    if refused.is_none() {
+        for id in [player_id, target_id] {
+            let sets = match pool.acquire().await {
+                Ok(mut conn) => fetch_friend_sets(&mut conn, &id).await,
+                Err(err) => Err(err),
+            };
+            match (sets, fetch_friend_list(&pool, &id).await) {
+                (Ok((friend_ids, blocked)), Ok(list)) => updates.push((id, friend_ids, blocked, list)),
+                (Err(err), _) | (_, Err(err)) => {
+                    let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+                    eprintln!("Failed to execute query: {:?}", err_for_ctx);
+                    ctx.run_on_main_thread(move |_ctx| {
+                        info!("Failed to execute query in the task: {:?}", err_for_ctx);
+                    })
+                    .await;
+                }
+            }
+        }
+    }
+
+    let status = PacketFriendStatus {
+        command,
+        target_id: target_id.to_string(),
+        ok: refused.is_none(),
+        reason: refused.map(String::from),
+    };
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            send_peer_message(&mut socket, peer, &player_id, "FriendStatus", &status);
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+        for (id, friend_ids, blocked, list) in updates {
+            if let Some(mut friends) = ctx.world.get_resource_mut::<Friends>() {
+                if friends.friends.contains_key(&id) {
+                    friends.set_lists(id, friend_ids, blocked);
+                }
+            }
+            send_friend_list(ctx.world, &id, list);
+        }
+    })
+    .await;
+}
+
+pub async fn send_friend_list_async(
+    player_id: Uuid,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let list = match fetch_friend_list(&pool, &player_id).await {
+        Ok(list) => list,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to execute query: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to execute query in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            return;
+        }
+    };
+    ctx.run_on_main_thread(move |ctx| {
+        send_friend_list(ctx.world, &player_id, list);
+    })
+    .await;
+}
+
+pub fn friend_request_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    connected_players: Res<ConnectedPlayers>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    for event in event_reader.read() {
+        if event.command == "FriendListRequest" {
+            let packet = match serde_json::from_str::<PacketFriendListRequest>(&event.payload) {
+                Ok(packet) => packet,
+                Err(err) => {
+                    error!("Failed to deserialize PacketFriendListRequest from JSON: {:?}", err);
+                    continue;
+                }
+            };
+            let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                continue;
+            };
+            let pool = pool.0.clone();
+            runtime.spawn_background_task(move |ctx| {
+                send_friend_list_async(player_id, pool, ctx)
+            });
+            continue;
+        }
+
+        let Some(action) = FriendAction::from_command(&event.command) else {
+            continue;
+        };
+        let packet = match serde_json::from_str::<PacketFriendAction>(&event.payload) {
+            Ok(packet) => packet,
+            Err(err) => {
+                error!("Failed to deserialize PacketFriendAction from JSON: {:?}", err);
+                continue;
+            }
+        };
+        let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+            continue;
+        };
+        let Ok(target_id) = Uuid::parse_str(&packet.target_id) else {
+            warn!("{} carried an invalid target_id {:?}", event.command, packet.target_id);
+            continue;
+        };
+        let command = event.command.clone();
+        let peer = event.peer;
+        let pool = pool.0.clone();
+        runtime.spawn_background_task(move |ctx| {
+            friend_action_async(command, action, player_id, target_id, peer, pool, ctx)
+        });
+    }
+}
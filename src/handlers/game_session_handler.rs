@@ -0,0 +1,517 @@
+use bevy::prelude::*;
+
+use bevy_matchbox::prelude::*;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::{
+    ClientRequestEvent,
+    ConnectedPlayers,
+    GameSession,
+    GameSessionFinishedEvent,
+    GameSessions,
+    GameSessionState,
+    MapSet,
+    MapSets,
+    PacketGameSessionLeave,
+    PacketGameSessionRejected,
+    PacketGameSessionStart,
+    PacketGameSessionStateRequest,
+    PacketGameSessionStroke,
+    PlayerDisconnectedEvent,
+    Rooms,
+};
+
+use crate::handlers::{
+    room_handler::DEFAULT_ROOM_ID,
+    signaling_server_handler::send_player_message,
+};
+
+const RECENTLY_FINISHED_CAPACITY: usize = 64;
+
+impl GameSession {
+    pub fn new(map_set: &MapSet, player_order: Vec<Uuid>, room_id: String, play_style: Option<String>) -> Result<Self, String> {
+        let levels: Vec<i32> = map_set.levels().into_iter().map(|(level, _)| level).collect();
+        if levels.is_empty() {
+            return Err(format!("Map set {} has no playable levels", map_set.map_set_id));
+        }
+        if player_order.is_empty() {
+            return Err(String::from("A game session needs at least one player"));
+        }
+        let unique: HashSet<&Uuid> = player_order.iter().collect();
+        if unique.len() != player_order.len() {
+            return Err(String::from("Player order contains duplicate players"));
+        }
+
+        let mut strokes = HashMap::new();
+        let mut hole_completed = HashMap::new();
+        for player_id in player_order.iter() {
+            strokes.insert(*player_id, vec![0; levels.len()]);
+            hole_completed.insert(*player_id, vec![false; levels.len()]);
+        }
+
+        let pars = levels.iter().map(|level| map_set.par(*level)).collect();
+        let stroke_limits = levels.iter().map(|level| map_set.stroke_limit(*level)).collect();
+
+        Ok(Self {
+            session_id: Uuid::now_v7(),
+            room_id,
+            map_set_id: map_set.map_set_id,
+            map_set_revision: map_set.revision,
+            levels,
+            current_level: 0,
+            player_order,
+            departed: Vec::new(),
+            play_style,
+            turn: 0,
+            strokes,
+            hole_completed,
+            pars,
+            stroke_limits,
+            playlist: Vec::new(),
+            state: GameSessionState::InProgress,
+            turn_started: Some(Instant::now()),
+        })
+    }
+
+    pub fn active_player(&self) -> Option<Uuid> {
+        self.player_order.get(self.turn).copied()
+    }
+
+    pub fn current_level_number(&self) -> Option<i32> {
+        self.levels.get(self.current_level).copied()
+    }
+
+    pub fn is_member(&self, player_id: &Uuid) -> bool {
+        self.player_order.contains(player_id)
+    }
+
+    pub fn total_strokes(&self, player_id: &Uuid) -> i32 {
+        self.strokes
+            .get(player_id)
+            .map(|strokes| strokes.iter().sum())
+            .unwrap_or(0)
+    }
+
+    pub fn apply_stroke(&mut self, player_id: &Uuid, mut hole_completed: bool) -> Result<(), String> {
+        if self.state != GameSessionState::InProgress {
+            return Err(String::from("Game session is not in progress"));
+        }
+        if self.active_player() != Some(*player_id) {
+            return Err(String::from("It is not this player's turn"));
+        }
+        let level = self.current_level;
+        if self.hole_completed[player_id][level] {
+            return Err(String::from("Player has already completed this hole"));
+        }
+
+        if let Some(strokes) = self.strokes.get_mut(player_id) {
+            strokes[level] += 1;
+            // Players who reach the stroke limit pick up and move on
+            hole_completed |= strokes[level] >= self.stroke_limits[level];
+        }
+        if hole_completed {
+            if let Some(completed) = self.hole_completed.get_mut(player_id) {
+                completed[level] = true;
+            }
+        }
+        self.advance_turn();
+        Ok(())
+    }
+
+    pub fn remove_player(&mut self, player_id: &Uuid) -> Result<(), String> {
+        let Some(idx) = self.player_order.iter().position(|id| id == player_id) else {
+            return Err(String::from("Player is not part of this game session"));
+        };
+        let active_player = self.active_player();
+        self.player_order.remove(idx);
+        if self.state == GameSessionState::InProgress {
+            self.departed.push(*player_id);
+        }
+        self.strokes.remove(player_id);
+        self.hole_completed.remove(player_id);
+        if self.player_order.is_empty() || self.state != GameSessionState::InProgress {
+            return Ok(());
+        }
+
+        if idx < self.turn {
+            self.turn -= 1;
+        } else if self.turn >= self.player_order.len() {
+            self.turn = 0;
+        }
+        if let Some(active_player) = self.active_player() {
+            if self.hole_completed[&active_player][self.current_level] {
+                self.advance_turn();
+            }
+        }
+        if self.active_player() != active_player {
+            self.turn_started = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    pub fn is_turn_expired(&self, timeout: Duration) -> bool {
+        self.state == GameSessionState::InProgress
+            && self.turn_started.is_some_and(|turn_started| turn_started.elapsed() >= timeout)
+    }
+
+    // Charges the idle active player a stroke and passes the turn on, so the stroke limit
+    // eventually ends the hole for a player who never comes back.
+    pub fn expire_turn(&mut self) -> Result<(), String> {
+        let Some(player_id) = self.active_player() else {
+            return Err(String::from("Game session has no active player"));
+        };
+        self.apply_stroke(&player_id, false)
+    }
+
+    // Hands the turn to the next player still playing the hole, moving on to the next level
+    // once everyone has holed out and finishing the session after the last level.
+    fn advance_turn(&mut self) {
+        self.turn_started = Some(Instant::now());
+        let player_count = self.player_order.len();
+        for offset in 1..=player_count {
+            let idx = (self.turn + offset) % player_count;
+            let player_id = self.player_order[idx];
+            if !self.hole_completed[&player_id][self.current_level] {
+                self.turn = idx;
+                return;
+            }
+        }
+
+        if self.current_level + 1 < self.levels.len() {
+            self.current_level += 1;
+            self.turn = 0;
+        } else {
+            self.state = GameSessionState::Finished;
+        }
+    }
+}
+
+impl GameSessions {
+    pub fn new(turn_timeout: Duration) -> Self {
+        Self {
+            sessions: HashMap::new(),
+            recently_finished: VecDeque::new(),
+            turn_timeout,
+        }
+    }
+
+    // Removes a session that has ended, remembering it for late result submissions
+    pub fn finish(&mut self, session_id: &Uuid) -> Option<GameSession> {
+        let session = self.sessions.remove(session_id)?;
+        if self.recently_finished.len() >= RECENTLY_FINISHED_CAPACITY {
+            self.recently_finished.pop_front();
+        }
+        self.recently_finished.push_back(session.clone());
+        Some(session)
+    }
+
+    pub fn get_recently_finished(&self, session_id: &Uuid) -> Option<&GameSession> {
+        self.recently_finished.iter().find(|session| &session.session_id == session_id)
+    }
+
+    pub fn get(&self, session_id: &Uuid) -> Option<&GameSession> {
+        self.sessions.get(session_id)
+    }
+
+    pub fn session_for_player(&self, player_id: &Uuid) -> Option<&GameSession> {
+        self.sessions
+            .values()
+            .find(|session| session.state == GameSessionState::InProgress && session.is_member(player_id))
+    }
+
+    pub fn start(&mut self, map_set: &MapSet, player_order: Vec<Uuid>, room_id: String, play_style: Option<String>) -> Result<GameSession, String> {
+        let session = GameSession::new(map_set, player_order, room_id, play_style)?;
+        self.insert(session)
+    }
+
+    // Registers a session built by the caller, e.g. one with a playlist attached
+    pub fn insert(&mut self, session: GameSession) -> Result<GameSession, String> {
+        self.check_players_free(&session.player_order)?;
+        self.sessions.insert(session.session_id, session.clone());
+        Ok(session)
+    }
+
+    // Registers a session in a fresh room for its players; the room is only created once
+    // the session has been accepted, so a rejected start leaves no room behind.
+    pub fn insert_in_new_room(&mut self, mut session: GameSession, rooms: &mut Rooms) -> Result<GameSession, String> {
+        self.check_players_free(&session.player_order)?;
+        session.room_id = rooms.create_for_players(&session.player_order).room_id;
+        self.insert(session)
+    }
+
+    pub fn check_players_free(&self, player_order: &[Uuid]) -> Result<(), String> {
+        match player_order.iter().find(|player_id| self.session_for_player(player_id).is_some()) {
+            Some(player_id) => Err(format!("Player {} is already in a game session", player_id)),
+            None => Ok(()),
+        }
+    }
+
+    // Drops a player from their session, ending it when it empties or their departure
+    // completes the last hole. Returns the updated session and whether it finished.
+    fn remove_player(&mut self, session_id: &Uuid, player_id: &Uuid) -> Result<Option<(GameSession, bool)>, String> {
+        let Some(session) = self.sessions.get_mut(session_id) else {
+            return Err(String::from("Unknown game session"));
+        };
+        session.remove_player(player_id)?;
+        info!("Player {} left game session [{}]", player_id, session_id);
+        if session.player_order.is_empty() {
+            info!("Game session [{}] abandoned", session_id);
+            self.sessions.remove(session_id);
+            return Ok(None);
+        }
+        let session = session.clone();
+        let finished = session.state == GameSessionState::Finished;
+        if finished {
+            self.finish(session_id);
+        }
+        Ok(Some((session, finished)))
+    }
+}
+
+pub fn broadcast_session_state(
+    socket: &mut MatchboxSocket<SingleChannel>,
+    connected_players: &ConnectedPlayers,
+    session: &GameSession,
+) {
+    for player_id in session.player_order.iter() {
+        send_player_message(socket, connected_players, player_id, "GameSessionState", session);
+    }
+}
+
+fn send_session_rejection(
+    socket: &mut MatchboxSocket<SingleChannel>,
+    connected_players: &ConnectedPlayers,
+    player_id: &Uuid,
+    session_id: Option<Uuid>,
+    reason: String,
+) {
+    warn!("Rejected game session request from {}: {}", player_id, reason);
+    let rejection = PacketGameSessionRejected {
+        session_id: session_id.map(|session_id| session_id.to_string()),
+        reason,
+    };
+    send_player_message(socket, connected_players, player_id, "GameSessionRejected", &rejection);
+}
+
+pub fn game_session_request_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    map_sets: Res<MapSets>,
+    mut game_sessions: ResMut<GameSessions>,
+    mut rooms: ResMut<Rooms>,
+    mut finished_writer: EventWriter<GameSessionFinishedEvent>,
+) {
+    for event in event_reader.read() {
+        match event.command.as_str() {
+            "GameSessionStart" => {
+                let packet = match serde_json::from_str::<PacketGameSessionStart>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketGameSessionStart from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+
+                let player_order: Result<Vec<Uuid>, _> = packet.player_ids.iter().map(|id| Uuid::parse_str(id)).collect();
+                let result = match (player_order, Uuid::parse_str(&packet.map_set_id)) {
+                    (Ok(player_order), Ok(map_set_id)) => {
+                        if !player_order.contains(&player_id) {
+                            Err(String::from("Requesting player must be part of the game session"))
+                        } else if let Some(missing) = player_order.iter().find(|id| connected_players.get_peer(id).is_none()) {
+                            Err(format!("Player {} is not connected", missing))
+                        } else if !rooms.same_room(&player_order) {
+                            Err(String::from("All players must be in the same room"))
+                        } else {
+                            match map_sets.playable(&map_set_id) {
+                                Some(map_set) => {
+                                    // Games never run in the shared lobby, so lobby players get a room of their own
+                                    let room_id = rooms.room_of(&player_id).to_string();
+                                    let play_style = connected_players.get_play_style(&player_id);
+                                    if room_id == DEFAULT_ROOM_ID {
+                                        GameSession::new(map_set, player_order, room_id, play_style)
+                                            .and_then(|session| game_sessions.insert_in_new_room(session, &mut rooms))
+                                    } else {
+                                        game_sessions.start(map_set, player_order, room_id, play_style)
+                                    }
+                                }
+                                None => Err(format!("Unknown map set {}", map_set_id)),
+                            }
+                        }
+                    }
+                    _ => Err(String::from("Malformed player or map set id")),
+                };
+
+                match result {
+                    Ok(session) => {
+                        info!("Started game session [{}] on map set [{}] in room [{}]", session.session_id, session.map_set_id, session.room_id);
+                        broadcast_session_state(&mut socket, &connected_players, &session);
+                    }
+                    Err(reason) => {
+                        send_session_rejection(&mut socket, &connected_players, &player_id, None, reason);
+                    }
+                }
+            }
+            "GameSessionStroke" => {
+                let packet = match serde_json::from_str::<PacketGameSessionStroke>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketGameSessionStroke from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let Ok(session_id) = Uuid::parse_str(&packet.session_id) else {
+                    send_session_rejection(&mut socket, &connected_players, &player_id, None, String::from("Malformed session id"));
+                    continue;
+                };
+                let Some(session) = game_sessions.sessions.get_mut(&session_id) else {
+                    send_session_rejection(&mut socket, &connected_players, &player_id, Some(session_id), String::from("Unknown game session"));
+                    continue;
+                };
+
+                match session.apply_stroke(&player_id, packet.hole_completed) {
+                    Ok(()) => {
+                        let session = session.clone();
+                        broadcast_session_state(&mut socket, &connected_players, &session);
+                        if session.state == GameSessionState::Finished {
+                            info!("Game session [{}] finished", session_id);
+                            if let Some(session) = game_sessions.finish(&session_id) {
+                                finished_writer.send(GameSessionFinishedEvent { session });
+                            }
+                        }
+                    }
+                    Err(reason) => {
+                        // Resync the offending client with the authoritative state
+                        let session = session.clone();
+                        send_session_rejection(&mut socket, &connected_players, &player_id, Some(session_id), reason);
+                        send_player_message(&mut socket, &connected_players, &player_id, "GameSessionState", &session);
+                    }
+                }
+            }
+            "GameSessionLeave" => {
+                let packet = match serde_json::from_str::<PacketGameSessionLeave>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketGameSessionLeave from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let Ok(session_id) = Uuid::parse_str(&packet.session_id) else {
+                    continue;
+                };
+                if !game_sessions.sessions.contains_key(&session_id) {
+                    continue;
+                }
+
+                match game_sessions.remove_player(&session_id, &player_id) {
+                    Ok(Some((session, finished))) => {
+                        broadcast_session_state(&mut socket, &connected_players, &session);
+                        if finished {
+                            finished_writer.send(GameSessionFinishedEvent { session });
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(reason) => {
+                        send_session_rejection(&mut socket, &connected_players, &player_id, Some(session_id), reason);
+                    }
+                }
+            }
+            "GameSessionStateRequest" => {
+                let packet = match serde_json::from_str::<PacketGameSessionStateRequest>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketGameSessionStateRequest from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let session = Uuid::parse_str(&packet.session_id)
+                    .ok()
+                    .and_then(|session_id| game_sessions.get(&session_id))
+                    .filter(|session| session.is_member(&player_id));
+                match session {
+                    Some(session) => {
+                        send_player_message(&mut socket, &connected_players, &player_id, "GameSessionState", session);
+                    }
+                    None => {
+                        send_session_rejection(&mut socket, &connected_players, &player_id, None, String::from("Unknown game session"));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn game_session_disconnect_system(
+    mut event_reader: EventReader<PlayerDisconnectedEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    mut game_sessions: ResMut<GameSessions>,
+    mut finished_writer: EventWriter<GameSessionFinishedEvent>,
+) {
+    for event in event_reader.read() {
+        let Some(session_id) = game_sessions.session_for_player(&event.player_id).map(|session| session.session_id) else {
+            continue;
+        };
+        match game_sessions.remove_player(&session_id, &event.player_id) {
+            Ok(Some((session, finished))) => {
+                broadcast_session_state(&mut socket, &connected_players, &session);
+                if finished {
+                    finished_writer.send(GameSessionFinishedEvent { session });
+                }
+            }
+            Ok(None) => {}
+            Err(reason) => {
+                warn!("Failed to drop disconnected player {} from game session [{}]: {}", event.player_id, session_id, reason);
+            }
+        }
+    }
+}
+
+pub fn game_session_turn_timeout_system(
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    mut game_sessions: ResMut<GameSessions>,
+    mut finished_writer: EventWriter<GameSessionFinishedEvent>,
+) {
+    let turn_timeout = game_sessions.turn_timeout;
+    let expired: Vec<Uuid> = game_sessions
+        .sessions
+        .values()
+        .filter(|session| session.is_turn_expired(turn_timeout))
+        .map(|session| session.session_id)
+        .collect();
+    for session_id in expired {
+        let Some(session) = game_sessions.sessions.get_mut(&session_id) else {
+            continue;
+        };
+        let player_id = session.active_player();
+        if let Err(reason) = session.expire_turn() {
+            warn!("Failed to expire the turn in game session [{}]: {}", session_id, reason);
+            continue;
+        }
+        info!("Turn of player {:?} in game session [{}] timed out", player_id, session_id);
+        let session = session.clone();
+        broadcast_session_state(&mut socket, &connected_players, &session);
+        if session.state == GameSessionState::Finished {
+            info!("Game session [{}] finished", session_id);
+            if let Some(session) = game_sessions.finish(&session_id) {
+                finished_writer.send(GameSessionFinishedEvent { session });
+            }
+        }
+    }
+}
@@ -8,12 +8,14 @@ use std::time::{
 use crate::{
     ConnectedPlayers,
     HeartBeatMonitorTimer,
+    PlayerDisconnectedEvent,
 };
 
 pub fn heartbeat_monitor_system(
     time: Res<Time>,
     mut timer: ResMut<HeartBeatMonitorTimer>,
     connected_players: ResMut<ConnectedPlayers>,
+    mut event_writer: EventWriter<PlayerDisconnectedEvent>,
 ) {
     // Check if the timer has finished
     if timer.0.tick(time.delta()).finished() {
@@ -21,14 +23,20 @@ pub fn heartbeat_monitor_system(
         let timeout_duration = Duration::from_secs(15);
         let mut players = connected_players.players.lock().unwrap();
         let now = Instant::now();
+        let mut timed_out = Vec::new();
 
         // Find and remove players who have not sent a heartbeat in the last `timeout_duration`
         players.retain(|player_id, player_status| {
             let is_active = now.duration_since(player_status.last_heartbeat) < timeout_duration;
             if !is_active {
                 warn!("Removing player {} due to timeout.", player_id);
+                timed_out.push(*player_id);
             }
             is_active
         });
+
+        for player_id in timed_out {
+            event_writer.send(PlayerDisconnectedEvent { player_id });
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,736 @@
+use bevy::prelude::*;
+
+use bevy_matchbox::prelude::*;
+use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+use sqlx::{MySqlPool, Error};
+use std::time::{Duration, Instant};
+use time::{OffsetDateTime, Time};
+use uuid::Uuid;
+
+use crate::{
+    ClientRequestEvent,
+    ConnectedPlayers,
+    DatabasePool,
+    GameResult,
+    GameResultRecordedEvent,
+    GameResultSubmissions,
+    GameSession,
+    GameSessionFinishedEvent,
+    GameSessions,
+    LeaderBoardEntry,
+    LeaderBoardPeriod,
+    MapSets,
+    PacketGameResultStatus,
+    PacketGameResultSubmit,
+    PacketLastGame,
+    PacketLastGameRequest,
+    PacketLeaderBoard,
+    PacketLeaderBoardRequest,
+    Seasons,
+};
+
+use crate::handlers::signaling_server_handler::send_peer_message;
+
+pub const MAX_STROKES_PER_HOLE: i32 = 20;
+const DEFAULT_PAGE_SIZE: u32 = 10;
+const MAX_PAGE_SIZE: u32 = 50;
+const MAX_UNVALIDATED_PER_HOUR: usize = 10; // Per player, games the server did not run
+const UNVALIDATED_DEDUPE_WINDOW: Duration = Duration::from_secs(300); // One per player and map set
+const UNVALIDATED_WINDOW: Duration = Duration::from_secs(3600);
+
+// A friends board holds the requesting player and everyone on their friend list; binds the id twice
+const FRIENDS_FILTER: &str = "(r.player_id = UUID_TO_BIN(?)
+    OR r.player_id IN (SELECT f.friend_id FROM player_friend f WHERE f.player_id = UUID_TO_BIN(?)))";
+
+impl GameResult {
+    // One result per player still in the session, placed by total strokes with ties sharing a place
+    pub fn from_session(session: &GameSession) -> Vec<GameResult> {
+        let hole_pars: Vec<(i32, i32)> = session
+            .levels
+            .iter()
+            .zip(session.pars.iter())
+            .filter_map(|(level, par)| par.map(|par| (*level, par)))
+            .collect();
+        let totals: Vec<(Uuid, i32)> = session
+            .player_order
+            .iter()
+            .map(|player_id| (*player_id, session.total_strokes(player_id)))
+            .collect();
+        totals
+            .iter()
+            .map(|(player_id, total_strokes)| {
+                let placement = 1 + totals.iter().filter(|(_, other)| other < total_strokes).count() as i32;
+                let hole_strokes = session
+                    .levels
+                    .iter()
+                    .zip(session.strokes[player_id].iter())
+                    .map(|(level, strokes)| (*level, *strokes))
+                    .collect();
+                GameResult {
+                    game_result_id: Uuid::now_v7(),
+                    session_id: Some(session.session_id),
+                    map_set_id: session.map_set_id,
+                    map_set_revision: session.map_set_revision,
+                    player_id: *player_id,
+                    total_strokes: *total_strokes,
+                    hole_strokes,
+                    hole_pars: hole_pars.clone(),
+                    placement,
+                    player_count: totals.len() as i32,
+                    validated: true,
+                }
+            })
+            .collect()
+    }
+
+    pub fn par(&self, level: i32) -> Option<i32> {
+        self.hole_pars.iter().find(|(hole, _)| *hole == level).map(|(_, par)| *par)
+    }
+
+    // Total relative to par, only when every hole played had a par
+    pub fn to_par(&self) -> Option<i32> {
+        self.hole_strokes
+            .iter()
+            .map(|(level, strokes)| self.par(*level).map(|par| strokes - par))
+            .sum()
+    }
+}
+
+impl GameResultSubmissions {
+    // Unvalidated results are stored as sent, so each player gets only a few and no repeats of one set
+    fn check(&mut self, player_id: &Uuid, map_set_id: &Uuid, now: Instant) -> Result<(), &'static str> {
+        self.recent.retain(|_, submitted| {
+            submitted.retain(|(_, at)| now.duration_since(*at) < UNVALIDATED_WINDOW);
+            !submitted.is_empty()
+        });
+        let submitted = self.recent.entry(*player_id).or_default();
+        if submitted.len() >= MAX_UNVALIDATED_PER_HOUR {
+            return Err("Too many game results submitted, try again later");
+        }
+        let repeat = submitted
+            .iter()
+            .any(|(submitted_set, at)| submitted_set == map_set_id && now.duration_since(*at) < UNVALIDATED_DEDUPE_WINDOW);
+        if repeat {
+            return Err("A result for this map set was submitted moments ago");
+        }
+        submitted.push((*map_set_id, now));
+        Ok(())
+    }
+}
+
+impl LeaderBoardPeriod {
+    pub fn start(&self, seasons: &Seasons) -> OffsetDateTime {
+        match self {
+            LeaderBoardPeriod::AllTime => OffsetDateTime::UNIX_EPOCH,
+            LeaderBoardPeriod::Season => seasons
+                .current
+                .as_ref()
+                .map_or(OffsetDateTime::UNIX_EPOCH, |season| season.starts),
+            LeaderBoardPeriod::Weekly => {
+                // Weeks start Monday 00:00 UTC
+                let now = OffsetDateTime::now_utc();
+                let days_into_week = now.weekday().number_days_from_monday() as i64;
+                (now - time::Duration::days(days_into_week)).replace_time(Time::MIDNIGHT)
+            }
+        }
+    }
+}
+
+pub fn leader_board_log_game(
+    mut event_reader: EventReader<GameSessionFinishedEvent>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    for event in event_reader.read() {
+        // Playlists are throwaway sets with their own level numbering, so their games stay off the
+        // boards, and with no recorded result they feed no ratings, stats or achievements either
+        if !event.session.playlist.is_empty() {
+            continue;
+        }
+        let results = GameResult::from_session(&event.session);
+        if results.is_empty() {
+            continue;
+        }
+        let pool = pool.0.clone();
+        // Spawn the background task using bevy_tokio_tasks
+        runtime.spawn_background_task(move |ctx| {
+            log_game_results_async(results, pool, None, ctx)
+        });
+    }
+}
+
+// Stores the results, then announces them to the rest of the app and optionally acknowledges a submitter
+pub async fn log_game_results_async(
+    results: Vec<GameResult>,
+    pool: MySqlPool,
+    reply_to: Option<(PeerId, Uuid)>,
+    mut ctx: TaskContext,
+) {
+    let status = match insert_game_results(&pool, &results).await {
+        Ok(()) => {
+            println!("Inserted {} game results", results.len());
+            PacketGameResultStatus {
+                recorded: true,
+                validated: results.iter().all(|result| result.validated),
+                reason: None,
+            }
+        }
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to insert game results: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to insert game results in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            PacketGameResultStatus {
+                recorded: false,
+                validated: false,
+                reason: Some(String::from("Failed to store game result")),
+            }
+        }
+    };
+
+    let recorded = status.recorded;
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some((peer, player_id)) = reply_to {
+            if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+                send_peer_message(&mut socket, peer, &player_id, "GameResultStatus", &status);
+            }
+        }
+        if recorded {
+            if let Some(mut writer) = ctx.world.get_resource_mut::<Events<GameResultRecordedEvent>>() {
+                writer.send(GameResultRecordedEvent { results });
+            }
+        }
+    })
+    .await;
+}
+
+async fn insert_game_results(
+    pool: &MySqlPool,
+    results: &[GameResult],
+) -> Result<(), Error> {
+    let mut tx = pool.begin().await?;
+    for result in results.iter() {
+        sqlx::query(
+            "INSERT INTO game_result (game_result_id, session_id, map_set_id, map_set_revision, player_id,
+                total_strokes, to_par, holes_played, placement, player_count, validated, completed)
+             VALUES (UUID_TO_BIN(?), UUID_TO_BIN(?), UUID_TO_BIN(?), ?, UUID_TO_BIN(?), ?, ?, ?, ?, ?, ?, NOW())",
+        )
+        .bind(result.game_result_id.to_string())
+        .bind(result.session_id.map(|session_id| session_id.to_string()))
+        .bind(result.map_set_id.to_string())
+        .bind(result.map_set_revision)
+        .bind(result.player_id.to_string())
+        .bind(result.total_strokes)
+        .bind(result.to_par())
+        .bind(result.hole_strokes.len() as i32)
+        .bind(result.placement)
+        .bind(result.player_count)
+        .bind(result.validated)
+        .execute(&mut *tx)
+        .await?;
+
+        for (level, strokes) in result.hole_strokes.iter() {
+            sqlx::query(
+                "INSERT INTO game_result_hole (game_result_id, level, strokes, par)
+                 VALUES (UUID_TO_BIN(?), ?, ?, ?)",
+            )
+            .bind(result.game_result_id.to_string())
+            .bind(*level)
+            .bind(*strokes)
+            .bind(result.par(*level))
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+    tx.commit().await
+}
+
+pub struct LeaderBoardQuery {
+    pub map_set_id: Option<Uuid>,
+    pub level: Option<i32>,
+    pub map_set_revision: Option<i32>,
+    pub since: OffsetDateTime,
+    pub friends_of: Option<Uuid>,
+    pub page: u32,
+    pub page_size: u32,
+}
+
+pub async fn fetch_leader_board(
+    pool: &MySqlPool,
+    query: &LeaderBoardQuery,
+) -> Result<(i64, Vec<LeaderBoardEntry>), Error> {
+    // A round only has a score to par if every hole in it had a par, stored with the result
+    let (from_sql, strokes_sql, to_par_sql) = match query.level {
+        Some(_) => (
+            "game_result_hole h JOIN game_result r ON r.game_result_id = h.game_result_id",
+            "h.strokes",
+            "h.strokes - h.par",
+        ),
+        None => (
+            "game_result r",
+            "r.total_strokes",
+            "r.to_par",
+        ),
+    };
+    // Only results the server can vouch for reach the boards
+    let mut filters = vec!["r.validated = 1", "r.completed >= ?"];
+    if query.map_set_id.is_some() {
+        filters.push("r.map_set_id = UUID_TO_BIN(?)");
+    }
+    if query.level.is_some() {
+        filters.push("h.level = ?");
+    }
+    if query.map_set_revision.is_some() {
+        filters.push("r.map_set_revision = ?");
+    }
+    if query.friends_of.is_some() {
+        filters.push(FRIENDS_FILTER);
+    }
+    let where_sql = filters.join(" AND ");
+
+    // Both statements share the same filter placeholders, bound in the order they were pushed
+    macro_rules! bind_filters {
+        ($statement:expr) => {{
+            let mut statement = $statement.bind(query.since);
+            if let Some(map_set_id) = query.map_set_id {
+                statement = statement.bind(map_set_id.to_string());
+            }
+            if let Some(level) = query.level {
+                statement = statement.bind(level);
+            }
+            if let Some(map_set_revision) = query.map_set_revision {
+                statement = statement.bind(map_set_revision);
+            }
+            if let Some(player_id) = query.friends_of {
+                statement = statement.bind(player_id.to_string()).bind(player_id.to_string());
+            }
+            statement
+        }};
+    }
+
+    let count_sql = format!("SELECT COUNT(DISTINCT r.player_id) FROM {} WHERE {}", from_sql, where_sql);
+    let (total_entries,): (i64,) = bind_filters!(sqlx::query_as::<_, (i64,)>(&count_sql))
+        .fetch_one(pool)
+        .await?;
+
+    // Each player is listed with their single best game, ranked on par where it is known so
+    // strokes and to_par always come from the same game; games without a par fall in after
+    let entries_sql = format!(
+        "SELECT b.player_id, p.username, CAST(b.strokes AS SIGNED), b.games, CAST(b.to_par AS SIGNED)
+         FROM (
+            SELECT r.player_id, {strokes} AS strokes, {to_par} AS to_par,
+                COUNT(*) OVER (PARTITION BY r.player_id) AS games,
+                ROW_NUMBER() OVER (PARTITION BY r.player_id ORDER BY {to_par} IS NULL, {to_par} ASC, {strokes} ASC) AS game_rank
+            FROM {from} WHERE {filters}
+         ) b JOIN player_table p ON p.player_id = b.player_id
+         WHERE b.game_rank = 1
+         ORDER BY b.to_par IS NULL, b.to_par ASC, b.strokes ASC, b.games DESC, p.username ASC
+         LIMIT ? OFFSET ?",
+        strokes = strokes_sql,
+        to_par = to_par_sql,
+        from = from_sql,
+        filters = where_sql,
+    );
+    let offset = query.page as i64 * query.page_size as i64;
+    let rows: Vec<(Uuid, String, i64, i64, Option<i64>)> = bind_filters!(sqlx::query_as::<_, (Uuid, String, i64, i64, Option<i64>)>(&entries_sql))
+        .bind(query.page_size as i64)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
+
+    let entries = rows
+        .into_iter()
+        .enumerate()
+        .map(|(idx, (player_id, username, strokes, games, to_par))| LeaderBoardEntry {
+            rank: offset + idx as i64 + 1,
+            player_id: player_id.to_string(),
+            username,
+            strokes,
+            games,
+            to_par,
+        })
+        .collect();
+    Ok((total_entries, entries))
+}
+
+pub async fn send_leader_board_async(
+    request: PacketLeaderBoardRequest,
+    query: LeaderBoardQuery,
+    peer: PeerId,
+    player_id: Uuid,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let (total_entries, entries) = match fetch_leader_board(&pool, &query).await {
+        Ok(board) => board,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to execute query: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to execute query in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            return;
+        }
+    };
+
+    let leader_board = PacketLeaderBoard {
+        map_set_id: request.map_set_id,
+        level: request.level,
+        map_set_revision: request.map_set_revision,
+        period: request.period,
+        friends_only: request.friends_only,
+        page: query.page,
+        page_size: query.page_size,
+        total_entries,
+        entries,
+    };
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            send_peer_message(&mut socket, peer, &player_id, "LeaderBoard", &leader_board);
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+    })
+    .await;
+}
+
+async fn fetch_last_game(
+    pool: &MySqlPool,
+    player_id: &Uuid,
+) -> Result<Vec<GameResult>, Error> {
+    let last: Option<(Uuid, Option<Uuid>)> = sqlx::query_as(
+        "SELECT game_result_id, session_id FROM game_result
+         WHERE player_id = UUID_TO_BIN(?)
+         ORDER BY completed DESC
+         LIMIT 1",
+    )
+    .bind(player_id.to_string())
+    .fetch_optional(pool)
+    .await?;
+    let Some((game_result_id, session_id)) = last else {
+        return Ok(Vec::new());
+    };
+
+    // Games run by the server pull in every player's result from the same session
+    type LastGameRow = (Uuid, Option<Uuid>, Uuid, Option<i32>, Uuid, i32, i32, i32, bool);
+    let rows: Vec<LastGameRow> = match session_id {
+        Some(session_id) => {
+            sqlx::query_as(
+                "SELECT game_result_id, session_id, map_set_id, map_set_revision, player_id, total_strokes, placement,
+                    player_count, validated
+                 FROM game_result WHERE session_id = UUID_TO_BIN(?) ORDER BY placement ASC",
+            )
+            .bind(session_id.to_string())
+            .fetch_all(pool)
+            .await?
+        }
+        None => {
+            sqlx::query_as(
+                "SELECT game_result_id, session_id, map_set_id, map_set_revision, player_id, total_strokes, placement,
+                    player_count, validated
+                 FROM game_result WHERE game_result_id = UUID_TO_BIN(?)",
+            )
+            .bind(game_result_id.to_string())
+            .fetch_all(pool)
+            .await?
+        }
+    };
+
+    let mut results = Vec::new();
+    for (game_result_id, session_id, map_set_id, map_set_revision, player_id, total_strokes, placement, player_count, validated) in rows {
+        let holes: Vec<(i32, i32, Option<i32>)> = sqlx::query_as(
+            "SELECT level, strokes, par FROM game_result_hole WHERE game_result_id = UUID_TO_BIN(?) ORDER BY level ASC",
+        )
+        .bind(game_result_id.to_string())
+        .fetch_all(pool)
+        .await?;
+        results.push(GameResult {
+            game_result_id,
+            session_id,
+            map_set_id,
+            map_set_revision,
+            player_id,
+            total_strokes,
+            hole_strokes: holes.iter().map(|(level, strokes, _)| (*level, *strokes)).collect(),
+            hole_pars: holes.iter().filter_map(|(level, _, par)| par.map(|par| (*level, par))).collect(),
+            placement,
+            player_count,
+            validated,
+        });
+    }
+    Ok(results)
+}
+
+pub async fn leader_board_review_last_game_async(
+    peer: PeerId,
+    player_id: Uuid,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let results = match fetch_last_game(&pool, &player_id).await {
+        Ok(results) => results,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to execute query: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to execute query in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            return;
+        }
+    };
+
+    let last_game = PacketLastGame { results };
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            send_peer_message(&mut socket, peer, &player_id, "LastGame", &last_game);
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+    })
+    .await;
+}
+
+async fn session_has_results(
+    pool: &MySqlPool,
+    session_id: &Uuid,
+) -> Result<bool, Error> {
+    let stored: Option<(i32,)> = sqlx::query_as(
+        "SELECT 1 FROM game_result WHERE session_id = UUID_TO_BIN(?) LIMIT 1",
+    )
+    .bind(session_id.to_string())
+    .fetch_optional(pool)
+    .await?;
+    Ok(stored.is_some())
+}
+
+// Stores a client submitted result unless it claims a session whose results are already stored,
+// which happens once the session has dropped out of GameSessions::recently_finished
+pub async fn submit_game_result_async(
+    result: GameResult,
+    claimed_session_id: Option<Uuid>,
+    pool: MySqlPool,
+    peer: PeerId,
+    mut ctx: TaskContext,
+) {
+    let player_id = result.player_id;
+    let rejection = match claimed_session_id {
+        Some(session_id) => match session_has_results(&pool, &session_id).await {
+            Ok(false) => None,
+            Ok(true) => Some("Results for this game session are already recorded"),
+            Err(err) => {
+                eprintln!("Failed to look up game session results: {:?}", err.to_string());
+                Some("Failed to store game result")
+            }
+        },
+        None => None,
+    };
+    let Some(reason) = rejection else {
+        log_game_results_async(vec![result], pool, Some((peer, player_id)), ctx).await;
+        return;
+    };
+
+    let status = PacketGameResultStatus {
+        recorded: false,
+        validated: false,
+        reason: Some(String::from(reason)),
+    };
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            send_peer_message(&mut socket, peer, &player_id, "GameResultStatus", &status);
+        }
+    })
+    .await;
+}
+
+// Checks a client submitted game. Games the server ran were already recorded when their session
+// finished, so those are only compared against the session; anything else is stored unvalidated.
+fn validate_submission(
+    packet: &PacketGameResultSubmit,
+    player_id: Uuid,
+    map_sets: &MapSets,
+    game_sessions: &GameSessions,
+) -> Result<GameResult, PacketGameResultStatus> {
+    let reject = |reason: &str| PacketGameResultStatus {
+        recorded: false,
+        validated: false,
+        reason: Some(String::from(reason)),
+    };
+
+    if let Some(session_id) = packet.session_id.as_deref().and_then(|id| Uuid::parse_str(id).ok()) {
+        if game_sessions.get(&session_id).is_some() {
+            return Err(reject("Game session is still in progress"));
+        }
+        if let Some(session) = game_sessions.get_recently_finished(&session_id) {
+            if !session.playlist.is_empty() {
+                return Err(reject("Playlist games are not recorded"));
+            }
+            if !session.strokes.contains_key(&player_id) {
+                return Err(reject("Player did not finish this game session"));
+            }
+            let reason = if session.strokes[&player_id] == packet.strokes {
+                None
+            } else {
+                Some(String::from("Submitted strokes differ from the server's record"))
+            };
+            return Err(PacketGameResultStatus {
+                recorded: true,
+                validated: true,
+                reason,
+            });
+        }
+    }
+
+    let Ok(map_set_id) = Uuid::parse_str(&packet.map_set_id) else {
+        return Err(reject("Malformed map set id"));
+    };
+    let Some(map_set) = map_sets.get(&map_set_id) else {
+        return Err(reject("Unknown map set"));
+    };
+    let levels = map_set.levels();
+    if packet.strokes.len() != levels.len() {
+        return Err(reject("Stroke count does not match the map set's levels"));
+    }
+    let out_of_range = levels
+        .iter()
+        .zip(packet.strokes.iter())
+        .any(|((level, _), strokes)| *strokes < 1 || *strokes > map_set.stroke_limit(*level));
+    if out_of_range {
+        return Err(reject("Strokes per hole out of range"));
+    }
+
+    Ok(GameResult {
+        game_result_id: Uuid::now_v7(),
+        session_id: None,
+        map_set_id,
+        map_set_revision: map_set.revision,
+        player_id,
+        total_strokes: packet.strokes.iter().sum(),
+        hole_strokes: levels.iter().map(|(level, _)| *level).zip(packet.strokes.iter().copied()).collect(),
+        hole_pars: levels.iter().filter_map(|(level, _)| map_set.par(*level).map(|par| (*level, par))).collect(),
+        placement: 1,
+        player_count: 1,
+        validated: false,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn leader_board_request_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    map_sets: Res<MapSets>,
+    game_sessions: Res<GameSessions>,
+    mut submissions: ResMut<GameResultSubmissions>,
+    seasons: Res<Seasons>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    for event in event_reader.read() {
+        match event.command.as_str() {
+            "GameResultSubmit" => {
+                let packet = match serde_json::from_str::<PacketGameResultSubmit>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketGameResultSubmit from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let result = validate_submission(&packet, player_id, &map_sets, &game_sessions).and_then(|result| {
+                    match submissions.check(&player_id, &result.map_set_id, Instant::now()) {
+                        Ok(()) => Ok(result),
+                        Err(reason) => Err(PacketGameResultStatus {
+                            recorded: false,
+                            validated: false,
+                            reason: Some(String::from(reason)),
+                        }),
+                    }
+                });
+                match result {
+                    Ok(result) => {
+                        let claimed_session_id = packet.session_id.as_deref().and_then(|id| Uuid::parse_str(id).ok());
+                        let pool = pool.0.clone();
+                        let peer = event.peer;
+                        runtime.spawn_background_task(move |ctx| {
+                            submit_game_result_async(result, claimed_session_id, pool, peer, ctx)
+                        });
+                    }
+                    Err(status) => {
+                        send_peer_message(&mut socket, event.peer, &player_id, "GameResultStatus", &status);
+                    }
+                }
+            }
+            "LeaderBoardRequest" => {
+                let packet = match serde_json::from_str::<PacketLeaderBoardRequest>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketLeaderBoardRequest from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let map_set_id = match packet.map_set_id.as_deref().map(Uuid::parse_str) {
+                    Some(Ok(map_set_id)) => Some(map_set_id),
+                    Some(Err(_)) => {
+                        warn!("LeaderBoardRequest from {} carried a malformed map set id", player_id);
+                        continue;
+                    }
+                    None => None,
+                };
+                if map_set_id.is_none() && packet.level.is_none() {
+                    warn!("LeaderBoardRequest from {} needs a map set or a level", player_id);
+                    continue;
+                }
+
+                let page_size = match packet.page_size {
+                    0 => DEFAULT_PAGE_SIZE,
+                    page_size => page_size.min(MAX_PAGE_SIZE),
+                };
+                // A set's board ranks its current course unless an older revision is asked for
+                let map_set_revision = packet.map_set_revision.or_else(|| {
+                    map_set_id.and_then(|map_set_id| map_sets.get(&map_set_id)).and_then(|map_set| map_set.revision)
+                });
+                let query = LeaderBoardQuery {
+                    map_set_id,
+                    level: packet.level,
+                    map_set_revision,
+                    since: packet.period.start(&seasons),
+                    friends_of: packet.friends_only.then_some(player_id),
+                    page: packet.page,
+                    page_size,
+                };
+                let pool = pool.0.clone();
+                let peer = event.peer;
+                runtime.spawn_background_task(move |ctx| {
+                    send_leader_board_async(packet, query, peer, player_id, pool, ctx)
+                });
+            }
+            "LastGameRequest" => {
+                let packet = match serde_json::from_str::<PacketLastGameRequest>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketLastGameRequest from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let pool = pool.0.clone();
+                let peer = event.peer;
+                runtime.spawn_background_task(move |ctx| {
+                    leader_board_review_last_game_async(peer, player_id, pool, ctx)
+                });
+            }
+            _ => {}
+        }
+    }
+}
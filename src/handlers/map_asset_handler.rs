@@ -0,0 +1,435 @@
+use bevy::prelude::*;
+use bevy_matchbox::prelude::*;
+use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha2::{Digest, Sha256};
+use sqlx::{MySqlPool, Error};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    ClientRequestEvent,
+    ConnectedPlayers,
+    DatabasePool,
+    MapAssetDownloads,
+    MapAssetEntry,
+    MapAssetTransfer,
+    MapSet,
+    MapSets,
+    PacketMapAssetCancel,
+    PacketMapAssetChunk,
+    PacketMapAssetRejected,
+    PacketMapAssetRequest,
+    PlayerDisconnectedEvent,
+};
+
+use crate::handlers::{
+    map_set_handler::{fetch_map_sets, send_existing_map_sets_async},
+    map_set_revision_handler::record_revision,
+    signaling_server_handler::send_peer_message,
+};
+
+const DEFAULT_MAP_ASSETS_DIR: &str = "assets";
+pub const CHUNK_SIZE: usize = 16 * 1024; // Stays well under data channel message limits once base64 encoded
+const CHUNKS_PER_TICK: usize = 8; // Shared by all downloads so gameplay messages keep flowing
+const MAX_TRANSFERS_PER_PLAYER: usize = 4;
+
+pub fn map_assets_dir() -> PathBuf {
+    PathBuf::from(env::var("MAP_ASSETS_DIR").unwrap_or_else(|_| String::from(DEFAULT_MAP_ASSETS_DIR)))
+}
+
+// Map set file paths are relative to the assets directory and may not leave it
+pub fn resolve_asset_path(root: &Path, file_path: &str) -> Option<PathBuf> {
+    let relative = Path::new(file_path);
+    if relative.components().all(|component| matches!(component, Component::Normal(_))) {
+        Some(root.join(relative))
+    } else {
+        None
+    }
+}
+
+pub fn hex_sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+// Size and lowercase hex SHA-256 of the file contents
+pub async fn hash_file(path: &Path) -> std::io::Result<(u64, String)> {
+    let contents = tokio::fs::read(path).await?;
+    Ok((contents.len() as u64, hex_sha256(&contents)))
+}
+
+// Levels whose file is missing or unreadable are left out of the manifest
+pub async fn scan_map_set(root: &Path, map_set: &MapSet) -> Vec<MapAssetEntry> {
+    let mut entries = Vec::new();
+    for (level, file_path) in map_set.levels() {
+        let Some(path) = resolve_asset_path(root, &file_path) else {
+            warn!("Map set {:?} level {} has an unsafe file path {:?}", map_set.map_set_name, level, file_path);
+            continue;
+        };
+        match hash_file(&path).await {
+            Ok((size, hash)) => entries.push(MapAssetEntry {
+                level,
+                file_path,
+                size,
+                hash,
+            }),
+            Err(err) => warn!("Map set {:?} level {} file {:?} could not be read: {}", map_set.map_set_name, level, path, err),
+        }
+    }
+    entries
+}
+
+// Replaces the stored manifest and bumps last_updated so clients notice the change
+pub async fn store_manifest(pool: &MySqlPool, map_set_id: &Uuid, entries: &[MapAssetEntry]) -> Result<(), Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM map_set_asset WHERE map_set_id = UUID_TO_BIN(?)")
+        .bind(map_set_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+    for entry in entries.iter() {
+        sqlx::query(
+            "INSERT INTO map_set_asset (map_set_id, level, file_path, size, hash)
+             VALUES (UUID_TO_BIN(?), ?, ?, ?, ?)",
+        )
+        .bind(map_set_id.to_string())
+        .bind(entry.level)
+        .bind(&entry.file_path)
+        .bind(entry.size)
+        .bind(&entry.hash)
+        .execute(&mut *tx)
+        .await?;
+    }
+    sqlx::query("UPDATE map_set_table SET last_updated = NOW() WHERE map_set_id = UUID_TO_BIN(?)")
+        .bind(map_set_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await
+}
+
+// Returns how many map sets had a changed manifest
+async fn scan_map_assets(pool: &MySqlPool, root: &Path, ctx: &mut TaskContext) -> Result<usize, Error> {
+    let mut changed = 0;
+    for map_set in fetch_map_sets(pool).await? {
+        let entries = scan_map_set(root, &map_set).await;
+        if entries != map_set.assets {
+            store_manifest(pool, &map_set.map_set_id, &entries).await?;
+            info!("Map set {:?} manifest now lists {} file(s)", map_set.map_set_name, entries.len());
+            changed += 1;
+        }
+        // Changed file hashes make a new revision, sets from before revisions get their first one
+        let revision = record_revision(pool, &map_set, &entries).await?;
+        if map_set.revision != Some(revision) {
+            let map_set_id = map_set.map_set_id;
+            ctx.run_on_main_thread(move |ctx| {
+                if let Some(mut map_sets) = ctx.world.get_resource_mut::<MapSets>() {
+                    map_sets.set_revision(&map_set_id, revision);
+                }
+            })
+            .await;
+        }
+    }
+    Ok(changed)
+}
+
+pub fn map_asset_scan_system(
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    let root = map_assets_dir();
+    let pool = pool.0.clone();
+    // Spawn the background task using bevy_tokio_tasks
+    runtime.spawn_background_task(move |ctx| {
+        scan_map_assets_async(root, pool, ctx)
+    });
+}
+
+// Ends by reloading the catalog, so synced clients get any new manifests
+pub async fn scan_map_assets_async(
+    root: PathBuf,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let result = if root.is_dir() {
+        scan_map_assets(&pool, &root, &mut ctx).await
+    } else {
+        ctx.run_on_main_thread(move |_ctx| {
+            warn!("Map assets directory {:?} not found, skipping the asset scan", root);
+        })
+        .await;
+        Ok(0)
+    };
+    match result {
+        Ok(0) => {}
+        Ok(changed) => {
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Updated the asset manifest of {} map set(s)", changed);
+            })
+            .await;
+        }
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to scan map assets: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to scan map assets in the task: {:?}", err_for_ctx);
+            })
+            .await;
+        }
+    }
+    send_existing_map_sets_async(pool, ctx).await;
+}
+
+impl MapAssetDownloads {
+    pub fn new() -> Self {
+        Self {
+            transfers: VecDeque::new(),
+            files: HashMap::new(),
+            loading: HashSet::new(),
+        }
+    }
+
+    fn remove(&mut self, player_id: &Uuid, map_set_id: &Uuid, level: i32) {
+        self.transfers.retain(|transfer| {
+            transfer.player_id != *player_id || transfer.map_set_id != *map_set_id || transfer.asset.level != level
+        });
+    }
+
+    // A repeated request replaces the running one, e.g. after the client lost chunks
+    pub fn queue(&mut self, transfer: MapAssetTransfer) -> Result<(), &'static str> {
+        self.remove(&transfer.player_id, &transfer.map_set_id, transfer.asset.level);
+        let running = self.transfers.iter().filter(|running| running.player_id == transfer.player_id).count();
+        if running >= MAX_TRANSFERS_PER_PLAYER {
+            return Err("Too many downloads in progress");
+        }
+        info!("Player {} downloading {:?} from byte {}", transfer.player_id, transfer.asset.file_path, transfer.offset);
+        self.transfers.push_back(transfer);
+        Ok(())
+    }
+}
+
+impl Default for MapAssetDownloads {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn send_asset_rejection(
+    socket: &mut MatchboxSocket<SingleChannel>,
+    peer: PeerId,
+    player_id: &Uuid,
+    map_set_id: String,
+    level: i32,
+    reason: &str,
+) {
+    info!("Map asset download of {} level {} for {} rejected: {}", map_set_id, level, player_id, reason);
+    let rejection = PacketMapAssetRejected {
+        map_set_id,
+        level,
+        reason: String::from(reason),
+    };
+    send_peer_message(socket, peer, player_id, "MapAssetRejected", &rejection);
+}
+
+// Resuming only makes sense if the file did not change since the partial download
+pub fn resume_offset(asset: &MapAssetEntry, hash: Option<&str>, offset: u64) -> u64 {
+    if hash == Some(asset.hash.as_str()) && offset <= asset.size {
+        offset
+    } else {
+        0
+    }
+}
+
+pub fn map_asset_request_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    map_sets: Res<MapSets>,
+    mut downloads: ResMut<MapAssetDownloads>,
+) {
+    for event in event_reader.read() {
+        match event.command.as_str() {
+            "MapAssetRequest" => {
+                let packet = match serde_json::from_str::<PacketMapAssetRequest>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketMapAssetRequest from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let map_set = Uuid::parse_str(&packet.map_set_id)
+                    .ok()
+                    .and_then(|map_set_id| map_sets.map_sets.iter().find(|map_set| map_set.map_set_id == map_set_id));
+                let Some(map_set) = map_set else {
+                    send_asset_rejection(&mut socket, event.peer, &player_id, packet.map_set_id, packet.level, "Unknown map set");
+                    continue;
+                };
+                let Some(asset) = map_set.assets.iter().find(|asset| asset.level == packet.level) else {
+                    send_asset_rejection(&mut socket, event.peer, &player_id, packet.map_set_id, packet.level, "Level is not in the map set manifest");
+                    continue;
+                };
+
+                let transfer = MapAssetTransfer {
+                    player_id,
+                    peer: event.peer,
+                    map_set_id: map_set.map_set_id,
+                    asset: asset.clone(),
+                    offset: resume_offset(asset, packet.hash.as_deref(), packet.offset),
+                };
+                if let Err(reason) = downloads.queue(transfer) {
+                    send_asset_rejection(&mut socket, event.peer, &player_id, packet.map_set_id, packet.level, reason);
+                }
+            }
+            "MapAssetCancel" => {
+                let packet = match serde_json::from_str::<PacketMapAssetCancel>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketMapAssetCancel from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                if let Ok(map_set_id) = Uuid::parse_str(&packet.map_set_id) {
+                    downloads.remove(&player_id, &map_set_id, packet.level);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Sends at most CHUNKS_PER_TICK chunks per run, taking turns between downloads
+pub fn map_asset_transfer_system(
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    mut downloads: ResMut<MapAssetDownloads>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    let downloads = &mut *downloads;
+    let missing: Vec<MapAssetEntry> = downloads
+        .transfers
+        .iter()
+        .filter(|transfer| !downloads.files.contains_key(&transfer.asset.hash))
+        .map(|transfer| transfer.asset.clone())
+        .collect();
+    for asset in missing {
+        if downloads.loading.insert(asset.hash.clone()) {
+            let root = map_assets_dir();
+            // Spawn the background task using bevy_tokio_tasks
+            runtime.spawn_background_task(move |ctx| {
+                load_map_asset_async(root, asset, ctx)
+            });
+        }
+    }
+
+    let mut sent = 0;
+    let mut waiting = 0;
+    while sent < CHUNKS_PER_TICK && waiting < downloads.transfers.len() {
+        let Some(mut transfer) = downloads.transfers.pop_front() else {
+            break;
+        };
+        let Some(file) = downloads.files.get(&transfer.asset.hash) else {
+            downloads.transfers.push_back(transfer);
+            waiting += 1;
+            continue;
+        };
+        waiting = 0;
+
+        let start = transfer.offset as usize;
+        let end = (start + CHUNK_SIZE).min(file.len());
+        let data = &file[start..end];
+        let chunk = PacketMapAssetChunk {
+            map_set_id: transfer.map_set_id.to_string(),
+            level: transfer.asset.level,
+            hash: transfer.asset.hash.clone(),
+            size: transfer.asset.size,
+            offset: transfer.offset,
+            data: STANDARD.encode(data),
+            chunk_hash: hex_sha256(data),
+        };
+        send_peer_message(&mut socket, transfer.peer, &transfer.player_id, "MapAssetChunk", &chunk);
+        sent += 1;
+
+        transfer.offset = end as u64;
+        if transfer.offset < transfer.asset.size {
+            downloads.transfers.push_back(transfer);
+        } else {
+            info!("Player {} finished downloading {:?}", transfer.player_id, transfer.asset.file_path);
+        }
+    }
+
+    // Drop file contents no download needs anymore
+    let transfers = &downloads.transfers;
+    downloads.files.retain(|hash, _| transfers.iter().any(|transfer| transfer.asset.hash == *hash));
+}
+
+pub async fn load_map_asset_async(
+    root: PathBuf,
+    asset: MapAssetEntry,
+    mut ctx: TaskContext,
+) {
+    let result = match resolve_asset_path(&root, &asset.file_path) {
+        Some(path) => tokio::fs::read(&path).await.map_err(|err| err.to_string()),
+        None => Err(String::from("unsafe file path")),
+    };
+    // The manifest is only refreshed by a scan, so the file on disk may have moved on
+    let result = result.and_then(|contents| {
+        if contents.len() as u64 == asset.size && hex_sha256(&contents) == asset.hash {
+            Ok(contents)
+        } else {
+            Err(String::from("file no longer matches the manifest"))
+        }
+    });
+    if let Err(err) = result.as_ref() {
+        let err_for_ctx = err.clone();
+        let file_path = asset.file_path.clone();
+        eprintln!("Failed to load map asset {:?}: {:?}", file_path, err_for_ctx);
+        ctx.run_on_main_thread(move |_ctx| {
+            info!("Failed to load map asset {:?} in the task: {:?}", file_path, err_for_ctx);
+        })
+        .await;
+    }
+
+    ctx.run_on_main_thread(move |ctx| {
+        let Some(mut downloads) = ctx.world.get_resource_mut::<MapAssetDownloads>() else {
+            info!("Failed to access map asset downloads resource");
+            return;
+        };
+        downloads.loading.remove(&asset.hash);
+        let failed: VecDeque<MapAssetTransfer> = match result {
+            Ok(contents) => {
+                downloads.files.insert(asset.hash.clone(), Arc::new(contents));
+                return;
+            }
+            Err(_) => {
+                let (failed, kept) = downloads.transfers.drain(..).partition(|transfer| transfer.asset.hash == asset.hash);
+                downloads.transfers = kept;
+                failed
+            }
+        };
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            for transfer in failed {
+                let map_set_id = transfer.map_set_id.to_string();
+                send_asset_rejection(&mut socket, transfer.peer, &transfer.player_id, map_set_id, transfer.asset.level, "Level file is unavailable");
+            }
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+    })
+    .await;
+}
+
+pub fn map_asset_disconnect_system(
+    mut event_reader: EventReader<PlayerDisconnectedEvent>,
+    mut downloads: ResMut<MapAssetDownloads>,
+) {
+    for event in event_reader.read() {
+        downloads.transfers.retain(|transfer| transfer.player_id != event.player_id);
+    }
+}
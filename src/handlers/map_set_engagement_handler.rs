@@ -0,0 +1,412 @@
+use bevy::prelude::*;
+
+use bevy_matchbox::prelude::*;
+use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+use sqlx::{MySqlPool, Error};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+use crate::{
+    ClientRequestEvent,
+    ConnectedPlayers,
+    DatabasePool,
+    GameSessionFinishedEvent,
+    GameSessions,
+    HoleRating,
+    MapSet,
+    MapSetEngagement,
+    MapSetPlays,
+    MapSets,
+    PacketMapSetRate,
+    PacketMapSetRateResult,
+};
+
+use crate::handlers::signaling_server_handler::send_peer_message;
+
+const MIN_STARS: i32 = 1;
+const MAX_STARS: i32 = 5;
+const SET_RATING_LEVEL: i32 = 0; // Stored level for ratings of the whole set
+
+// (average stars, count) per hole file path
+type HoleRatings = HashMap<String, (f64, i64)>;
+
+impl MapSetEngagement {
+    pub fn set_plays(&mut self, plays: i64, completions: i64) {
+        self.plays = plays;
+        self.completions = completions;
+        self.completion_rate = (plays > 0).then(|| completions as f64 / plays as f64);
+    }
+
+    // Holes are rated by file, so a hole keeps its ratings when it moves to another level or set
+    fn set_ratings(&mut self, levels: &[(i32, String)], set_rating: Option<(f64, i64)>, hole_ratings: &HoleRatings) {
+        self.rating = set_rating.map(|(rating, _)| rating);
+        self.rating_count = set_rating.map_or(0, |(_, rating_count)| rating_count);
+        self.holes = levels
+            .iter()
+            .filter_map(|(level, file_path)| {
+                hole_ratings.get(file_path).map(|(rating, rating_count)| HoleRating {
+                    level: *level,
+                    rating: *rating,
+                    rating_count: *rating_count,
+                })
+            })
+            .collect();
+    }
+}
+
+impl MapSetPlays {
+    pub fn new() -> Self {
+        Self {
+            seen_sessions: HashSet::new(),
+            pending: HashMap::new(),
+            flushing: false,
+        }
+    }
+
+    // Sessions on sets outside the catalog, like party playlists, are not counted
+    fn count(&mut self, map_sets: &mut MapSets, map_set_id: Uuid, plays: i64, completions: i64) {
+        let Some(map_set) = map_sets.map_sets.iter_mut().find(|map_set| map_set.map_set_id == map_set_id) else {
+            return;
+        };
+        let engagement = &mut map_set.engagement;
+        engagement.set_plays(engagement.plays + plays, engagement.completions + completions);
+        let pending = self.pending.entry(map_set_id).or_default();
+        pending.0 += plays;
+        pending.1 += completions;
+    }
+}
+
+impl Default for MapSetPlays {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Play counts and ratings of the given map sets, keyed by map_set_id
+pub async fn fetch_engagement(pool: &MySqlPool, map_sets: &[MapSet]) -> Result<HashMap<Uuid, MapSetEngagement>, Error> {
+    let plays: HashMap<Uuid, (i64, i64)> = sqlx::query_as::<_, (Uuid, i64, i64)>("SELECT map_set_id, plays, completions FROM map_set_play")
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|(map_set_id, plays, completions)| (map_set_id, (plays, completions)))
+        .collect();
+    let set_ratings = fetch_set_ratings(pool, None).await?;
+    let hole_ratings = fetch_hole_ratings(pool, None).await?;
+    Ok(map_sets
+        .iter()
+        .map(|map_set| {
+            let mut engagement = MapSetEngagement::default();
+            if let Some((plays, completions)) = plays.get(&map_set.map_set_id) {
+                engagement.set_plays(*plays, *completions);
+            }
+            engagement.set_ratings(&map_set.levels(), set_ratings.get(&map_set.map_set_id).copied(), &hole_ratings);
+            (map_set.map_set_id, engagement)
+        })
+        .collect())
+}
+
+// (average stars, count) of whole set ratings, for one set or all of them
+async fn fetch_set_ratings(pool: &MySqlPool, map_set_id: Option<&Uuid>) -> Result<HashMap<Uuid, (f64, i64)>, Error> {
+    let map_set_id = map_set_id.map(|map_set_id| map_set_id.to_string());
+    let rows: Vec<(Uuid, f64, i64)> = sqlx::query_as(
+        "SELECT map_set_id, CAST(AVG(stars) AS DOUBLE), COUNT(*) FROM map_set_rating
+         WHERE level = ? AND (? IS NULL OR map_set_id = UUID_TO_BIN(?))
+         GROUP BY map_set_id",
+    )
+    .bind(SET_RATING_LEVEL)
+    .bind(&map_set_id)
+    .bind(&map_set_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|(map_set_id, rating, rating_count)| (map_set_id, (rating, rating_count))).collect())
+}
+
+// Ratings of the given hole files, or of every rated hole
+async fn fetch_hole_ratings(pool: &MySqlPool, file_paths: Option<&[String]>) -> Result<HoleRatings, Error> {
+    let filter = match file_paths {
+        Some([]) => return Ok(HashMap::new()),
+        Some(file_paths) => format!("WHERE file_path IN ({})", vec!["?"; file_paths.len()].join(", ")),
+        None => String::new(),
+    };
+    let sql = format!(
+        "SELECT file_path, CAST(AVG(stars) AS DOUBLE), COUNT(*) FROM map_set_hole_rating {} GROUP BY file_path",
+        filter,
+    );
+    let mut statement = sqlx::query_as::<_, (String, f64, i64)>(&sql);
+    for file_path in file_paths.unwrap_or_default() {
+        statement = statement.bind(file_path);
+    }
+    let rows = statement.fetch_all(pool).await?;
+    Ok(rows.into_iter().map(|(file_path, rating, rating_count)| (file_path, (rating, rating_count))).collect())
+}
+
+// Counts a play for every player a session starts with and a completion for every player still in
+// it when it finishes, so the completion rate reflects players who quit part way
+pub fn map_set_play_system(
+    mut finished_reader: EventReader<GameSessionFinishedEvent>,
+    game_sessions: Res<GameSessions>,
+    mut map_set_plays: ResMut<MapSetPlays>,
+    mut map_sets: ResMut<MapSets>,
+) {
+    for event in finished_reader.read() {
+        let session = &event.session;
+        let players = session.player_order.len() as i64;
+        let plays = if map_set_plays.seen_sessions.remove(&session.session_id) { 0 } else { players };
+        map_set_plays.count(&mut map_sets, session.map_set_id, plays, players);
+    }
+    for session in game_sessions.sessions.values() {
+        if map_set_plays.seen_sessions.insert(session.session_id) {
+            map_set_plays.count(&mut map_sets, session.map_set_id, session.player_order.len() as i64, 0);
+        }
+    }
+    // Abandoned sessions are dropped without a finished event
+    map_set_plays.seen_sessions.retain(|session_id| game_sessions.sessions.contains_key(session_id));
+}
+
+// Counts are written in batches like emote usage
+pub fn map_set_play_persist_system(
+    mut map_set_plays: ResMut<MapSetPlays>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    if map_set_plays.flushing || map_set_plays.pending.is_empty() {
+        return;
+    }
+    map_set_plays.flushing = true;
+    let pending = std::mem::take(&mut map_set_plays.pending);
+    let pool = pool.0.clone();
+    // Spawn the background task using bevy_tokio_tasks
+    runtime.spawn_background_task(move |ctx| {
+        save_map_set_plays_async(pending, pool, ctx)
+    });
+}
+
+async fn save_map_set_plays(pool: &MySqlPool, pending: &HashMap<Uuid, (i64, i64)>) -> Result<(), Error> {
+    let mut tx = pool.begin().await?;
+    for (map_set_id, (plays, completions)) in pending.iter() {
+        sqlx::query(
+            "INSERT INTO map_set_play (map_set_id, plays, completions) VALUES (UUID_TO_BIN(?), ?, ?)
+             ON DUPLICATE KEY UPDATE plays = plays + VALUES(plays), completions = completions + VALUES(completions)",
+        )
+        .bind(map_set_id.to_string())
+        .bind(plays)
+        .bind(completions)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await
+}
+
+pub async fn save_map_set_plays_async(
+    pending: HashMap<Uuid, (i64, i64)>,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let result = save_map_set_plays(&pool, &pending).await;
+    if let Err(err) = result.as_ref() {
+        let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+        eprintln!("Failed to save map set plays: {:?}", err_for_ctx);
+        ctx.run_on_main_thread(move |_ctx| {
+            info!("Failed to save map set plays in the task: {:?}", err_for_ctx);
+        })
+        .await;
+    }
+
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some(mut map_set_plays) = ctx.world.get_resource_mut::<MapSetPlays>() {
+            map_set_plays.flushing = false;
+            // Put the counts back so the next flush retries them, they are already in MapSets
+            if result.is_err() {
+                for (map_set_id, (plays, completions)) in pending {
+                    let current = map_set_plays.pending.entry(map_set_id).or_default();
+                    current.0 += plays;
+                    current.1 += completions;
+                }
+            }
+        } else {
+            info!("Failed to access map set plays resource");
+        }
+    })
+    .await;
+}
+
+fn send_rate_result(
+    socket: &mut MatchboxSocket<SingleChannel>,
+    peer: PeerId,
+    player_id: &Uuid,
+    packet: PacketMapSetRate,
+    result: Result<MapSetEngagement, String>,
+) {
+    if let Err(reason) = result.as_ref() {
+        info!("Rating from player {} rejected: {}", player_id, reason);
+    }
+    let reply = PacketMapSetRateResult {
+        map_set_id: packet.map_set_id,
+        level: packet.level,
+        stars: packet.stars,
+        accepted: result.is_ok(),
+        reason: result.as_ref().err().cloned(),
+        engagement: result.ok(),
+    };
+    send_peer_message(socket, peer, player_id, "MapSetRateResult", &reply);
+}
+
+pub fn map_set_rating_request_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    map_sets: Res<MapSets>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    for event in event_reader.read() {
+        if event.command != "MapSetRate" {
+            continue;
+        }
+        let packet = match serde_json::from_str::<PacketMapSetRate>(&event.payload) {
+            Ok(packet) => packet,
+            Err(err) => {
+                error!("Failed to deserialize PacketMapSetRate from JSON: {:?}", err);
+                continue;
+            }
+        };
+        let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+            continue;
+        };
+        let map_set = Uuid::parse_str(&packet.map_set_id).ok().and_then(|map_set_id| map_sets.get(&map_set_id));
+        let result = match map_set {
+            None => Err(String::from("Unknown map set")),
+            Some(_) if !(MIN_STARS..=MAX_STARS).contains(&packet.stars) => {
+                Err(format!("Ratings are {} to {} stars", MIN_STARS, MAX_STARS))
+            }
+            Some(map_set) if packet.level.is_some_and(|level| !map_set.levels().iter().any(|(hole, _)| *hole == level)) => {
+                Err(String::from("Level is not part of the map set"))
+            }
+            Some(map_set) => Ok(map_set.clone()),
+        };
+        match result {
+            Ok(map_set) => {
+                let pool = pool.0.clone();
+                let peer = event.peer;
+                // Spawn the background task using bevy_tokio_tasks
+                runtime.spawn_background_task(move |ctx| {
+                    rate_map_set_async(packet, map_set, player_id, peer, pool, ctx)
+                });
+            }
+            Err(reason) => send_rate_result(&mut socket, event.peer, &player_id, packet, Err(reason)),
+        }
+    }
+}
+
+// Ok(Err(reason)) when the player has not finished the course yet, in a game the server vouched for;
+// otherwise the set's rating and the ratings of its hole files afterwards
+async fn rate_map_set(
+    pool: &MySqlPool,
+    packet: &PacketMapSetRate,
+    map_set: &MapSet,
+    player_id: &Uuid,
+) -> Result<Result<(Option<(f64, i64)>, HoleRatings), String>, Error> {
+    let hole_count = map_set.hole_range_end - map_set.hole_range_start + 1;
+    let (finished,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM game_result
+         WHERE map_set_id = UUID_TO_BIN(?) AND player_id = UUID_TO_BIN(?) AND holes_played >= ? AND validated = 1",
+    )
+    .bind(map_set.map_set_id.to_string())
+    .bind(player_id.to_string())
+    .bind(hole_count)
+    .fetch_one(pool)
+    .await?;
+    if finished == 0 {
+        return Ok(Err(String::from("Finish the course before rating it")));
+    }
+
+    let levels = map_set.levels();
+    match packet.level {
+        Some(level) => {
+            let Some((_, file_path)) = levels.iter().find(|(hole, _)| *hole == level) else {
+                return Ok(Err(String::from("Level is not part of the map set")));
+            };
+            sqlx::query(
+                "INSERT INTO map_set_hole_rating (file_path, player_id, stars) VALUES (?, UUID_TO_BIN(?), ?)
+                 ON DUPLICATE KEY UPDATE stars = VALUES(stars)",
+            )
+            .bind(file_path)
+            .bind(player_id.to_string())
+            .bind(packet.stars)
+            .execute(pool)
+            .await?;
+        }
+        None => {
+            sqlx::query(
+                "INSERT INTO map_set_rating (map_set_id, level, player_id, stars) VALUES (UUID_TO_BIN(?), ?, UUID_TO_BIN(?), ?)
+                 ON DUPLICATE KEY UPDATE stars = VALUES(stars)",
+            )
+            .bind(map_set.map_set_id.to_string())
+            .bind(SET_RATING_LEVEL)
+            .bind(player_id.to_string())
+            .bind(packet.stars)
+            .execute(pool)
+            .await?;
+        }
+    }
+    let set_rating = fetch_set_ratings(pool, Some(&map_set.map_set_id)).await?.remove(&map_set.map_set_id);
+    let file_paths: Vec<String> = levels.into_iter().map(|(_, file_path)| file_path).collect();
+    let hole_ratings = fetch_hole_ratings(pool, Some(&file_paths)).await?;
+    Ok(Ok((set_rating, hole_ratings)))
+}
+
+pub async fn rate_map_set_async(
+    packet: PacketMapSetRate,
+    map_set: MapSet,
+    player_id: Uuid,
+    peer: PeerId,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let result = match rate_map_set(&pool, &packet, &map_set, &player_id).await {
+        Ok(result) => result,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to save rating: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to save rating in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            Err(String::from("Failed to save the rating"))
+        }
+    };
+
+    ctx.run_on_main_thread(move |ctx| {
+        // Play counts in MapSets may be ahead of the database, so only the ratings are replaced
+        let result = match result {
+            Ok((set_rating, hole_ratings)) => match ctx.world.get_resource_mut::<MapSets>() {
+                Some(mut map_sets) => {
+                    let current = map_sets.map_sets.iter_mut().find(|current| current.map_set_id == map_set.map_set_id);
+                    match current {
+                        Some(current) => {
+                            let levels = current.levels();
+                            current.engagement.set_ratings(&levels, set_rating, &hole_ratings);
+                            Ok(current.engagement.clone())
+                        }
+                        None => {
+                            let mut engagement = map_set.engagement.clone();
+                            engagement.set_ratings(&map_set.levels(), set_rating, &hole_ratings);
+                            Ok(engagement)
+                        }
+                    }
+                }
+                None => {
+                    info!("Failed to access map_sets_resource");
+                    return;
+                }
+            },
+            Err(reason) => Err(reason),
+        };
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            send_rate_result(&mut socket, peer, &player_id, packet, result);
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+    })
+    .await;
+}
@@ -1,24 +1,308 @@
 use bevy::prelude::*;
-// use bevy_matchbox::prelude::*;
+use bevy_matchbox::prelude::*;
 use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
-// use rmp_serde::{
-//     encode, 
-//     decode, 
-//     to_vec_named,
-// };
-
-use sqlx::{MySqlPool,
-    // query_as,
+
+use sqlx::{MySqlConnection, MySqlPool, Error,
+    query_as,
 };
-use time::macros::datetime;
-    // OffsetDateTime,
-// };
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use time::OffsetDateTime;
 use uuid::Uuid;
 
-use crate::DatabasePool;
-    // MapSet,
-    // MapSets,
-// };
+use crate::{
+    Admins,
+    ClientRequestEvent,
+    ConnectedPlayers,
+    DatabasePool,
+    HoleMetadata,
+    MapAssetEntry,
+    MapSet,
+    MapSetAuthor,
+    MapSetEngagement,
+    MapSets,
+    PacketMapSetAdminResult,
+    PacketMapSetCreate,
+    PacketMapSetReorder,
+    PacketMapSetRequest,
+    PacketMapSetRetire,
+    PacketMapSetSummary,
+    PacketMapSetSummaryRequest,
+    PacketMapSetUpdate,
+    PacketMapSets,
+};
+
+use crate::handlers::{
+    leader_board_handler::MAX_STROKES_PER_HOLE,
+    map_asset_handler::{map_assets_dir, resolve_asset_path, scan_map_assets_async, scan_map_set, store_manifest},
+    map_set_engagement_handler::fetch_engagement,
+    map_set_revision_handler::{fetch_current_revisions, record_revision},
+    map_set_seed_handler::{apply_map_set_seeds, load_map_set_seeds, map_set_seeds_dir},
+    signaling_server_handler::{send_peer_message, send_player_message},
+};
+
+const MAX_MAP_SET_NAME_LEN: usize = 64;
+const LEVEL_COUNT: usize = 18;
+const MAX_PAR: i32 = 10;
+const MAX_DIFFICULTY: i32 = 5;
+const MAX_HOLE_TEXT_LEN: usize = 64; // Display names, authors and tags
+const MAX_HOLE_TAGS: usize = 8;
+
+impl MapSet {
+    // An empty set without level files, callers fill in the paths before saving it
+    pub fn new(map_set_id: Uuid, map_set_name: String, hole_range_start: i32, hole_range_end: i32) -> Self {
+        let now = OffsetDateTime::now_utc();
+        Self {
+            map_set_id,
+            map_set_name,
+            created: now,
+            last_updated: now,
+            hole_range_start,
+            hole_range_end,
+            file_path_level_1: None,
+            file_path_level_2: None,
+            file_path_level_3: None,
+            file_path_level_4: None,
+            file_path_level_5: None,
+            file_path_level_6: None,
+            file_path_level_7: None,
+            file_path_level_8: None,
+            file_path_level_9: None,
+            file_path_level_10: None,
+            file_path_level_11: None,
+            file_path_level_12: None,
+            file_path_level_13: None,
+            file_path_level_14: None,
+            file_path_level_15: None,
+            file_path_level_16: None,
+            file_path_level_17: None,
+            file_path_level_18: None,
+            assets: Vec::new(),
+            holes: Vec::new(),
+            retired: None,
+            community_author: None,
+            revision: None,
+            engagement: MapSetEngagement::default(),
+        }
+    }
+
+    pub fn file_paths(&self) -> [&Option<String>; 18] {
+        [
+            &self.file_path_level_1,
+            &self.file_path_level_2,
+            &self.file_path_level_3,
+            &self.file_path_level_4,
+            &self.file_path_level_5,
+            &self.file_path_level_6,
+            &self.file_path_level_7,
+            &self.file_path_level_8,
+            &self.file_path_level_9,
+            &self.file_path_level_10,
+            &self.file_path_level_11,
+            &self.file_path_level_12,
+            &self.file_path_level_13,
+            &self.file_path_level_14,
+            &self.file_path_level_15,
+            &self.file_path_level_16,
+            &self.file_path_level_17,
+            &self.file_path_level_18,
+        ]
+    }
+
+    // Playable levels as (level number, file path), limited to the hole range
+    pub fn levels(&self) -> Vec<(i32, String)> {
+        self.file_paths()
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, file_path)| {
+                let level = idx as i32 + 1;
+                if level < self.hole_range_start || level > self.hole_range_end {
+                    return None;
+                }
+                file_path.as_ref().map(|path| (level, path.clone()))
+            })
+            .collect()
+    }
+
+    pub fn file_paths_mut(&mut self) -> [&mut Option<String>; 18] {
+        [
+            &mut self.file_path_level_1,
+            &mut self.file_path_level_2,
+            &mut self.file_path_level_3,
+            &mut self.file_path_level_4,
+            &mut self.file_path_level_5,
+            &mut self.file_path_level_6,
+            &mut self.file_path_level_7,
+            &mut self.file_path_level_8,
+            &mut self.file_path_level_9,
+            &mut self.file_path_level_10,
+            &mut self.file_path_level_11,
+            &mut self.file_path_level_12,
+            &mut self.file_path_level_13,
+            &mut self.file_path_level_14,
+            &mut self.file_path_level_15,
+            &mut self.file_path_level_16,
+            &mut self.file_path_level_17,
+            &mut self.file_path_level_18,
+        ]
+    }
+
+    // Requests always carry one entry per level, blank paths count as no file
+    pub fn set_file_paths(&mut self, file_paths: &[Option<String>]) -> Result<(), String> {
+        if file_paths.len() != LEVEL_COUNT {
+            return Err(format!("Expected {} file paths, got {}", LEVEL_COUNT, file_paths.len()));
+        }
+        for (slot, file_path) in self.file_paths_mut().into_iter().zip(file_paths) {
+            *slot = file_path
+                .as_deref()
+                .map(str::trim)
+                .filter(|path| !path.is_empty())
+                .map(String::from);
+        }
+        Ok(())
+    }
+
+    pub fn hole(&self, level: i32) -> Option<&HoleMetadata> {
+        self.holes.iter().find(|hole| hole.level == level)
+    }
+
+    pub fn par(&self, level: i32) -> Option<i32> {
+        self.hole(level).map(|hole| hole.par)
+    }
+
+    pub fn stroke_limit(&self, level: i32) -> i32 {
+        self.hole(level)
+            .and_then(|hole| hole.stroke_limit)
+            .unwrap_or(MAX_STROKES_PER_HOLE)
+    }
+
+    // Trims the text fields and keeps the holes in level order, blank text counts as unset
+    pub fn set_holes(&mut self, holes: Vec<HoleMetadata>) {
+        let trimmed = |text: Option<String>| {
+            text.map(|text| text.trim().to_string()).filter(|text| !text.is_empty())
+        };
+        self.holes = holes
+            .into_iter()
+            .map(|hole| HoleMetadata {
+                display_name: trimmed(hole.display_name),
+                author: trimmed(hole.author),
+                tags: hole
+                    .tags
+                    .iter()
+                    .map(|tag| tag.trim().to_lowercase())
+                    .filter(|tag| !tag.is_empty())
+                    .collect(),
+                ..hole
+            })
+            .collect();
+        self.holes.sort_by_key(|hole| hole.level);
+    }
+
+    // `order` lists the current levels of the hole range in their new positions
+    pub fn reorder(&mut self, order: &[i32]) -> Result<(), String> {
+        let range: Vec<i32> = (self.hole_range_start..=self.hole_range_end).collect();
+        let mut sorted = order.to_vec();
+        sorted.sort_unstable();
+        if sorted != range {
+            return Err(format!(
+                "Order must list levels {} to {} exactly once",
+                self.hole_range_start, self.hole_range_end
+            ));
+        }
+        let current: Vec<Option<String>> = self.file_paths().into_iter().cloned().collect();
+        for (level, from) in range.iter().zip(order) {
+            *self.file_paths_mut()[(*level - 1) as usize] = current[(*from - 1) as usize].clone();
+        }
+        // Hole metadata travels with its file
+        let holes = self
+            .holes
+            .iter()
+            .map(|hole| match order.iter().position(|from| *from == hole.level) {
+                Some(idx) => HoleMetadata {
+                    level: range[idx],
+                    ..hole.clone()
+                },
+                None => hole.clone(),
+            })
+            .collect();
+        self.set_holes(holes);
+        Ok(())
+    }
+
+    // Every hole in the range needs a .glb file path, levels outside the range must be empty
+    pub fn validate_levels(&self) -> Result<(), String> {
+        if self.hole_range_start < 1
+            || self.hole_range_end > LEVEL_COUNT as i32
+            || self.hole_range_start > self.hole_range_end
+        {
+            return Err(format!("Invalid hole range {} to {}", self.hole_range_start, self.hole_range_end));
+        }
+        for (idx, file_path) in self.file_paths().into_iter().enumerate() {
+            let level = idx as i32 + 1;
+            let in_range = level >= self.hole_range_start && level <= self.hole_range_end;
+            match (in_range, file_path) {
+                (true, None) => return Err(format!("Level {} has no file", level)),
+                (false, Some(_)) => return Err(format!("Level {} is outside the hole range but has a file", level)),
+                (true, Some(file_path)) if !file_path.ends_with(".glb") => {
+                    return Err(format!("Level {} file {:?} is not a .glb", level, file_path));
+                }
+                _ => {}
+            }
+        }
+        self.validate_holes()
+    }
+
+    fn validate_holes(&self) -> Result<(), String> {
+        let too_long = |text: &Option<String>| text.as_ref().is_some_and(|text| text.chars().count() > MAX_HOLE_TEXT_LEN);
+        for (idx, hole) in self.holes.iter().enumerate() {
+            let level = hole.level;
+            if level < self.hole_range_start || level > self.hole_range_end {
+                return Err(format!("Hole metadata for level {} is outside the hole range", level));
+            }
+            if idx > 0 && self.holes[idx - 1].level == level {
+                return Err(format!("Level {} has hole metadata twice", level));
+            }
+            if hole.par < 1 || hole.par > MAX_PAR {
+                return Err(format!("Level {} par must be 1 to {}", level, MAX_PAR));
+            }
+            if hole.difficulty.is_some_and(|difficulty| !(1..=MAX_DIFFICULTY).contains(&difficulty)) {
+                return Err(format!("Level {} difficulty must be 1 to {}", level, MAX_DIFFICULTY));
+            }
+            if hole.stroke_limit.is_some_and(|limit| limit < hole.par || limit > MAX_STROKES_PER_HOLE) {
+                return Err(format!("Level {} stroke limit must be between its par and {}", level, MAX_STROKES_PER_HOLE));
+            }
+            if too_long(&hole.display_name) || too_long(&hole.author) {
+                return Err(format!("Level {} display name and author are limited to {} characters", level, MAX_HOLE_TEXT_LEN));
+            }
+            if hole.tags.len() > MAX_HOLE_TAGS || hole.tags.iter().any(|tag| tag.chars().count() > MAX_HOLE_TEXT_LEN) {
+                return Err(format!("Level {} allows up to {} tags of {} characters", level, MAX_HOLE_TAGS, MAX_HOLE_TEXT_LEN));
+            }
+        }
+        Ok(())
+    }
+
+    // validate_levels, plus every level file has to exist under the assets directory
+    pub fn validate(&self, root: &Path) -> Result<(), String> {
+        self.validate_levels()?;
+        for (level, file_path) in self.levels() {
+            match resolve_asset_path(root, &file_path) {
+                Some(path) if path.is_file() => {}
+                Some(_) => return Err(format!("Level {} file {:?} does not exist", level, file_path)),
+                None => return Err(format!("Level {} file {:?} is not inside the assets directory", level, file_path)),
+            }
+        }
+        Ok(())
+    }
+
+    // Whether two versions agree on everything an admin or a seed file can set
+    pub fn same_definition(&self, other: &MapSet) -> bool {
+        self.map_set_name == other.map_set_name
+            && self.hole_range_start == other.hole_range_start
+            && self.hole_range_end == other.hole_range_end
+            && self.file_paths() == other.file_paths()
+            && self.holes == other.holes
+    }
+}
 
 pub fn first_time_boot_setup_map_set(
     pool: Res<DatabasePool>,
@@ -32,270 +316,129 @@ pub fn first_time_boot_setup_map_set(
     });
 }
 
+// Applies the seed files on every boot, safe to repeat since unchanged sets are left alone
 pub async fn first_time_boot_setup_map_set_async(
     pool: MySqlPool,
     mut ctx: TaskContext, 
 ) {
-    // Count how many players exist in the player_table
-    let res: (i64,) = match sqlx::query_as("SELECT COUNT(*) FROM map_set_table")
-        .fetch_one(&pool)
-        .await{
-            Ok(res) => res, 
-            Err(err) => {
-                eprintln!("Failed to execute query: {:?}", err);
-                // Run a callback on the main thread to handle the error properly
-                ctx.run_on_main_thread(move |_ctx| {
-                    info!("Failed to execute query in the task: {:?}", err);
-                })
-                .await;
-                return;
-            }
-        };
+    let seeds = load_map_set_seeds(&map_set_seeds_dir());
+    match apply_map_set_seeds(&pool, &seeds).await {
+        Ok((inserted, updated)) => {
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Map set seeds applied: {} inserted, {} updated", inserted, updated);
+            })
+            .await;
+        }
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to apply map set seeds: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to apply map set seeds in the task: {:?}", err_for_ctx);
+            })
+            .await;
+        }
+    }
 
-    println!("Number of res: {}", res.0);
-    if res.0 == 0 {
-        let _map_set_whole_couse = {
-            // No players exist, so let's create a new one
-            let map_set_id = Uuid::now_v7(); // Use the UUID directly, not as String
-            let map_set_name = String::from("Standard Maps: Whole Course");
-            let created = datetime!(2024-12-01 17:34:56); // Generated in UTC
-            let hole_range_start = 1;
-            let hole_range_end = 18;
-            let file_paths: [Option<&str>; 18] = [
-                Some("glb/map/level_1.glb"), 
-                Some("glb/map/level_2.glb"), 
-                Some("glb/map/level_3.glb"), 
-                Some("glb/map/level_4.glb"), 
-                Some("glb/map/level_5.glb"), 
-                Some("glb/map/level_6.glb"), 
-                Some("glb/map/level_7.glb"), 
-                Some("glb/map/level_8.glb"), 
-                Some("glb/map/level_9.glb"), 
-                Some("glb/map/level_10.glb"), 
-                Some("glb/map/level_11.glb"), 
-                Some("glb/map/level_12.glb"), 
-                Some("glb/map/level_13.glb"), 
-                Some("glb/map/level_14.glb"), 
-                Some("glb/map/level_15.glb"), 
-                Some("glb/map/level_16.glb"), 
-                Some("glb/map/level_17.glb"), 
-                Some("glb/map/level_18.glb"), 
-            ];
-
-            let insert_result = sqlx::query(
-            "INSERT INTO map_set_table (map_set_id, map_set_name, created, last_updated, 
-                    hole_range_start, hole_range_end, file_path_level_1, file_path_level_2, 
-                    file_path_level_3, file_path_level_4, file_path_level_5, file_path_level_6, 
-                    file_path_level_7, file_path_level_8, file_path_level_9, file_path_level_10, 
-                    file_path_level_11, file_path_level_12, file_path_level_13, file_path_level_14, 
-                    file_path_level_15, file_path_level_16, file_path_level_17, file_path_level_18
-                ) VALUES (
-                    UUID_TO_BIN(?), ?, ?, NOW(), ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?
-                )"
-                )
-                .bind(map_set_id.to_string())
-                .bind(map_set_name)
-                .bind(created)
-                .bind(hole_range_start)
-                .bind(hole_range_end)
-                .bind(file_paths[0])
-                .bind(file_paths[1])
-                .bind(file_paths[2])
-                .bind(file_paths[3])
-                .bind(file_paths[4])
-                .bind(file_paths[5])
-                .bind(file_paths[6])
-                .bind(file_paths[7])
-                .bind(file_paths[8])
-                .bind(file_paths[9])
-                .bind(file_paths[10])
-                .bind(file_paths[11])
-                .bind(file_paths[12])
-                .bind(file_paths[13])
-                .bind(file_paths[14])
-                .bind(file_paths[15])
-                .bind(file_paths[16])
-                .bind(file_paths[17])
-                .execute(&pool)
-                .await;
-
-            match insert_result {
-                Ok(_) => {
-                    println!("Inserted new map set with ID: {:?}", map_set_id);
-                }
-                Err(err) => {
-                    eprintln!("Failed to insert new map set: {:?}", err);
-                    eprintln!("map set: {:?}", map_set_id);
-                    ctx.run_on_main_thread(move |_ctx| {
-                        info!("Failed to insert new map set in the task: {:?}", err);
-                    })
-                    .await;
-                }
-            }
-        };
-        
-        let _map_set_front_nine = {
-            // No players exist, so let's create a new one
-            let map_set_id = Uuid::now_v7(); // Use the UUID directly, not as String
-            let map_set_name = String::from("Standard Maps: Front Nine");
-            let created = datetime!(2024-12-01 17:34:56); // Generated in UTC
-            let hole_range_start = 1;
-            let hole_range_end = 9;
-            let file_paths: [Option<&str>; 18] = [
-                Some("glb/map/level_1.glb"), 
-                Some("glb/map/level_2.glb"), 
-                Some("glb/map/level_3.glb"), 
-                Some("glb/map/level_4.glb"), 
-                Some("glb/map/level_5.glb"), 
-                Some("glb/map/level_6.glb"), 
-                Some("glb/map/level_7.glb"), 
-                Some("glb/map/level_8.glb"), 
-                Some("glb/map/level_9.glb"), 
-                None, 
-                None, 
-                None, 
-                None, 
-                None, 
-                None, 
-                None, 
-                None, 
-                None, 
-            ];
-
-            let insert_result = sqlx::query(
-            "INSERT INTO map_set_table (map_set_id, map_set_name, created, last_updated, 
-                    hole_range_start, hole_range_end, file_path_level_1, file_path_level_2, 
-                    file_path_level_3, file_path_level_4, file_path_level_5, file_path_level_6, 
-                    file_path_level_7, file_path_level_8, file_path_level_9, file_path_level_10, 
-                    file_path_level_11, file_path_level_12, file_path_level_13, file_path_level_14, 
-                    file_path_level_15, file_path_level_16, file_path_level_17, file_path_level_18
-                ) VALUES (
-                    UUID_TO_BIN(?), ?, ?, NOW(), ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?
-                )"
-                )
-                .bind(map_set_id.to_string())
-                .bind(map_set_name)
-                .bind(created)
-                .bind(hole_range_start)
-                .bind(hole_range_end)
-                .bind(file_paths[0])
-                .bind(file_paths[1])
-                .bind(file_paths[2])
-                .bind(file_paths[3])
-                .bind(file_paths[4])
-                .bind(file_paths[5])
-                .bind(file_paths[6])
-                .bind(file_paths[7])
-                .bind(file_paths[8])
-                .bind(file_paths[9])
-                .bind(file_paths[10])
-                .bind(file_paths[11])
-                .bind(file_paths[12])
-                .bind(file_paths[13])
-                .bind(file_paths[14])
-                .bind(file_paths[15])
-                .bind(file_paths[16])
-                .bind(file_paths[17])
-                .execute(&pool)
-                .await;
-
-            match insert_result {
-                Ok(_) => {
-                    println!("Inserted new map set with ID: {:?}", map_set_id);
-                }
-                Err(err) => {
-                    eprintln!("Failed to insert new map set: {:?}", err);
-                    eprintln!("map set: {:?}", map_set_id);
-                    ctx.run_on_main_thread(move |_ctx| {
-                        info!("Failed to insert new map set in the task: {:?}", err);
-                    })
-                    .await;
-                }
-            }
-        };
-        
-        let _map_set_back_nine = {
-            // No players exist, so let's create a new one
-            let map_set_id = Uuid::now_v7(); // Use the UUID directly, not as String
-            let map_set_name = String::from("Standard Maps: Back Nine");
-            let created = datetime!(2024-12-01 17:34:56); // Generated in UTC
-            let hole_range_start = 1;
-            let hole_range_end = 18;
-            let file_paths: [Option<&str>; 18] = [
-                None, 
-                None, 
-                None, 
-                None, 
-                None, 
-                None, 
-                None, 
-                None, 
-                None, 
-                Some("glb/map/level_10.glb"), 
-                Some("glb/map/level_11.glb"), 
-                Some("glb/map/level_12.glb"), 
-                Some("glb/map/level_13.glb"), 
-                Some("glb/map/level_14.glb"), 
-                Some("glb/map/level_15.glb"), 
-                Some("glb/map/level_16.glb"), 
-                Some("glb/map/level_17.glb"), 
-                Some("glb/map/level_18.glb"), 
-            ];
-
-            let insert_result = sqlx::query(
-            "INSERT INTO map_set_table (map_set_id, map_set_name, created, last_updated, 
-                    hole_range_start, hole_range_end, file_path_level_1, file_path_level_2, 
-                    file_path_level_3, file_path_level_4, file_path_level_5, file_path_level_6, 
-                    file_path_level_7, file_path_level_8, file_path_level_9, file_path_level_10, 
-                    file_path_level_11, file_path_level_12, file_path_level_13, file_path_level_14, 
-                    file_path_level_15, file_path_level_16, file_path_level_17, file_path_level_18
-                ) VALUES (
-                    UUID_TO_BIN(?), ?, ?, NOW(), ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?
-                )"
-                )
-                .bind(map_set_id.to_string())
-                .bind(map_set_name)
-                .bind(created)
-                .bind(hole_range_start)
-                .bind(hole_range_end)
-                .bind(file_paths[0])
-                .bind(file_paths[1])
-                .bind(file_paths[2])
-                .bind(file_paths[3])
-                .bind(file_paths[4])
-                .bind(file_paths[5])
-                .bind(file_paths[6])
-                .bind(file_paths[7])
-                .bind(file_paths[8])
-                .bind(file_paths[9])
-                .bind(file_paths[10])
-                .bind(file_paths[11])
-                .bind(file_paths[12])
-                .bind(file_paths[13])
-                .bind(file_paths[14])
-                .bind(file_paths[15])
-                .bind(file_paths[16])
-                .bind(file_paths[17])
-                .execute(&pool)
-                .await;
-
-            match insert_result {
-                Ok(_) => {
-                    println!("Inserted new map set with ID: {:?}", map_set_id);
-                }
-                Err(err) => {
-                    eprintln!("Failed to insert new map set: {:?}", err);
-                    eprintln!("map set: {:?}", map_set_id);
-                    ctx.run_on_main_thread(move |_ctx| {
-                        info!("Failed to insert new map set in the task: {:?}", err);
-                    })
-                    .await;
-                }
+    // Bring manifests up to date and load the catalog
+    scan_map_assets_async(map_assets_dir(), pool, ctx).await;
+}
+
+impl MapSets {
+    // (map_set_id, last_updated) for every set, clients compare it against their local copies
+    pub fn summary(&self) -> Vec<(Uuid, OffsetDateTime)> {
+        self.map_sets
+            .iter()
+            .map(|map_set| (map_set.map_set_id, map_set.last_updated))
+            .collect()
+    }
+
+    pub fn engagement(&self) -> Vec<(Uuid, MapSetEngagement)> {
+        self.map_sets
+            .iter()
+            .map(|map_set| (map_set.map_set_id, map_set.engagement.clone()))
+            .collect()
+    }
+
+    // Swaps in a fresh catalog, returning the sets that are new or changed and the ids of removed ones
+    pub fn replace(&mut self, mut map_sets: Vec<MapSet>) -> (Vec<MapSet>, Vec<Uuid>) {
+        // Plays are counted here first and written in batches, so the database may be behind
+        for map_set in map_sets.iter_mut() {
+            if let Some(current) = self.get(&map_set.map_set_id) {
+                map_set.engagement.set_plays(current.engagement.plays, current.engagement.completions);
             }
-        };
+        }
+        let changed: Vec<MapSet> = map_sets
+            .iter()
+            .filter(|map_set| {
+                self.get(&map_set.map_set_id)
+                    .is_none_or(|current| current.last_updated != map_set.last_updated)
+            })
+            .cloned()
+            .collect();
+        let ids: HashSet<Uuid> = map_sets.iter().map(|map_set| map_set.map_set_id).collect();
+        let removed: Vec<Uuid> = self
+            .map_sets
+            .iter()
+            .map(|map_set| map_set.map_set_id)
+            .filter(|map_set_id| !ids.contains(map_set_id))
+            .collect();
+        self.map_sets = map_sets;
+        self.loaded = true;
+        (changed, removed)
+    }
+
+    // Trimmed name if it is usable and not taken by another set
+    pub fn check_name(&self, map_set_name: &str, map_set_id: Option<&Uuid>) -> Result<String, String> {
+        let map_set_name = map_set_name.trim();
+        if map_set_name.is_empty() || map_set_name.chars().count() > MAX_MAP_SET_NAME_LEN {
+            return Err(format!("Map set names must be 1 to {} characters", MAX_MAP_SET_NAME_LEN));
+        }
+        let taken = self.map_sets.iter().any(|map_set| {
+            Some(&map_set.map_set_id) != map_set_id && map_set.map_set_name.eq_ignore_ascii_case(map_set_name)
+        });
+        if taken {
+            return Err(format!("A map set named {:?} already exists", map_set_name));
+        }
+        Ok(String::from(map_set_name))
+    }
+
+    // Adds or replaces a single set, for changes made by the server itself
+    pub fn upsert(&mut self, map_set: MapSet) {
+        match self.map_sets.iter_mut().find(|current| current.map_set_id == map_set.map_set_id) {
+            Some(current) => *current = map_set,
+            None => self.map_sets.push(map_set),
+        }
+    }
+
+    // New sessions pin the revision, so one recorded in the background must show up before the next reload
+    pub fn set_revision(&mut self, map_set_id: &Uuid, revision: i32) {
+        if let Some(map_set) = self.map_sets.iter_mut().find(|map_set| map_set.map_set_id == *map_set_id) {
+            map_set.revision = Some(revision);
+        }
+    }
+}
+
+// Incremental update for every player already holding the catalog
+pub fn broadcast_map_set_changes(
+    socket: &mut MatchboxSocket<SingleChannel>,
+    connected_players: &ConnectedPlayers,
+    map_sets: &MapSets,
+    changed: Vec<MapSet>,
+    removed: Vec<Uuid>,
+) {
+    if changed.is_empty() && removed.is_empty() {
+        return;
+    }
+    info!("Map set catalog changed: {} updated, {} removed", changed.len(), removed.len());
+    let packet = PacketMapSets {
+        map_sets: changed,
+        removed,
     };
+    for player_id in map_sets.synced.iter() {
+        send_player_message(socket, connected_players, player_id, "MapSetsUpdated", &packet);
+    }
 }
-/*
+
 pub fn client_sync_protocol_send_existing_map_sets(
     pool: Res<DatabasePool>,
     runtime: ResMut<TokioTasksRuntime>,
@@ -308,132 +451,567 @@ pub fn client_sync_protocol_send_existing_map_sets(
     });
 }
 
+// Every map set with its asset manifest and retirement attached
+pub async fn fetch_map_sets(pool: &MySqlPool) -> Result<Vec<MapSet>, Error> {
+    let mut map_sets = query_as::<_, MapSet>("SELECT * FROM map_set_table ORDER BY created ASC, map_set_name ASC")
+        .fetch_all(pool)
+        .await?;
+    attach_map_set_details(pool, &mut map_sets).await?;
+    Ok(map_sets)
+}
+
+pub async fn fetch_map_set(pool: &MySqlPool, map_set_id: &Uuid) -> Result<Option<MapSet>, Error> {
+    let mut map_sets = query_as::<_, MapSet>("SELECT * FROM map_set_table WHERE map_set_id = UUID_TO_BIN(?)")
+        .bind(map_set_id.to_string())
+        .fetch_all(pool)
+        .await?;
+    attach_map_set_details(pool, &mut map_sets).await?;
+    Ok(map_sets.pop())
+}
+
+async fn attach_map_set_details(pool: &MySqlPool, map_sets: &mut [MapSet]) -> Result<(), Error> {
+    let rows: Vec<(Uuid, i32, String, u64, String)> = query_as(
+        "SELECT map_set_id, level, file_path, size, hash FROM map_set_asset ORDER BY level ASC",
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut assets: HashMap<Uuid, Vec<MapAssetEntry>> = HashMap::new();
+    for (map_set_id, level, file_path, size, hash) in rows {
+        assets.entry(map_set_id).or_default().push(MapAssetEntry {
+            level,
+            file_path,
+            size,
+            hash,
+        });
+    }
+    type HoleRow = (Uuid, i32, Option<String>, i32, Option<i32>, Option<String>, String, Option<i32>);
+    let hole_rows: Vec<HoleRow> = query_as(
+        "SELECT map_set_id, level, display_name, par, difficulty, author, tags, stroke_limit
+         FROM map_set_hole ORDER BY level ASC",
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut holes: HashMap<Uuid, Vec<HoleMetadata>> = HashMap::new();
+    for (map_set_id, level, display_name, par, difficulty, author, tags, stroke_limit) in hole_rows {
+        holes.entry(map_set_id).or_default().push(HoleMetadata {
+            level,
+            display_name,
+            par,
+            difficulty,
+            author,
+            tags: serde_json::from_str(&tags).unwrap_or_default(),
+            stroke_limit,
+        });
+    }
+    let retired: HashMap<Uuid, OffsetDateTime> = query_as::<_, (Uuid, OffsetDateTime)>(
+        "SELECT map_set_id, retired FROM map_set_retired",
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .collect();
+    let mut authors: HashMap<Uuid, MapSetAuthor> = query_as::<_, (Uuid, Uuid, String)>(
+        "SELECT c.map_set_id, c.author_id, COALESCE(p.username, '')
+         FROM map_set_community c LEFT JOIN player_table p ON p.player_id = c.author_id",
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|(map_set_id, player_id, username)| (map_set_id, MapSetAuthor { player_id, username }))
+    .collect();
+    let mut engagement = fetch_engagement(pool, map_sets).await?;
+    let revisions = fetch_current_revisions(pool).await?;
+    for map_set in map_sets.iter_mut() {
+        map_set.revision = revisions.get(&map_set.map_set_id).copied();
+        map_set.engagement = engagement.remove(&map_set.map_set_id).unwrap_or_default();
+        map_set.assets = assets.remove(&map_set.map_set_id).unwrap_or_default();
+        map_set.holes = holes.remove(&map_set.map_set_id).unwrap_or_default();
+        map_set.retired = retired.get(&map_set.map_set_id).copied();
+        map_set.community_author = authors.remove(&map_set.map_set_id);
+    }
+    Ok(())
+}
+
+// Reloads the catalog into MapSets; players who already have it get only what changed, everyone
+// else receives the summary from map_set_sync_system
 pub async fn send_existing_map_sets_async(
     pool: MySqlPool,
     mut ctx: TaskContext
 ) {
-    let query = "SELECT * FROM map_set_table";
-
-    let map_sets: Vec<MapSet> = match query_as::<_, MapSet>(query).fetch_all(&pool).await {
-        Ok(map_sets) => {
-            let map_sets_clone = map_sets.clone();
-            ctx.run_on_main_thread(move |ctx| {
-                if let Some(mut map_sets_resource) = ctx.world.get_resource_mut::<MapSets>() {
-                    for map_set in map_sets_clone.iter() {
-                        map_sets_resource.map_sets.push(map_set.clone());
-                    }
-                } else {
-                    info!("Failed to access map_sets_resource");
-                }        
-            });
-            map_sets
-        },
+    let map_sets = match fetch_map_sets(&pool).await {
+        Ok(map_sets) => map_sets,
         Err(err) => {
-            error!("Failed to execute query: {:?}", err);
-
-            // Run a callback on the main thread to handle the error properly
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to execute query: {:?}", err_for_ctx);
             ctx.run_on_main_thread(move |_ctx| {
-                info!("Failed to execute query in the task: {:?}", err);
+                info!("Failed to execute query in the task: {:?}", err_for_ctx);
             })
             .await;
             return; // Exit early since the query failed.
-        },
+        }
     };
 
     ctx.run_on_main_thread(move |ctx| {
-        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
-            let peers: Vec<_> = socket.connected_peers().collect();
-
-            // Send the summary first
-            let summary: Vec<(Uuid, OffsetDateTime)> = map_sets
-                .iter()
-                .map(|ms| (ms.map_set_id.clone(), ms.last_updated))
-                .collect();
-
-            match encode::to_vec(&summary) {
-                Ok(serialized_summary) => {
-                    for peer in peers {
-                        socket.send(serialized_summary.clone().into(), peer);
+        let Some(connected_players) = ctx.world.get_resource::<ConnectedPlayers>().cloned() else {
+            return;
+        };
+        let Some(mut map_sets_resource) = ctx.world.remove_resource::<MapSets>() else {
+            info!("Failed to access map_sets_resource");
+            return;
+        };
+        let first_load = !map_sets_resource.loaded;
+        let (changed, removed) = map_sets_resource.replace(map_sets);
+        if first_load {
+            info!("Loaded {} map sets", map_sets_resource.map_sets.len());
+        } else if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            broadcast_map_set_changes(&mut socket, &connected_players, &map_sets_resource, changed, removed);
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+        ctx.world.insert_resource(map_sets_resource);
+    })
+    .await;
+}
+
+// Sends the catalog summary to players once after they connect
+pub fn map_set_sync_system(
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    mut map_sets: ResMut<MapSets>,
+) {
+    if !map_sets.loaded {
+        return;
+    }
+    let connected: HashSet<Uuid> = connected_players.player_ids().into_iter().collect();
+    map_sets.synced.retain(|player_id| connected.contains(player_id));
+    let summary = PacketMapSetSummary {
+        map_sets: map_sets.summary(),
+        engagement: map_sets.engagement(),
+    };
+    for player_id in connected {
+        // Players without a recorded peer yet are picked up on a later run
+        if map_sets.synced.contains(&player_id) || connected_players.get_peer(&player_id).is_none() {
+            continue;
+        }
+        send_player_message(&mut socket, &connected_players, &player_id, "MapSetSummary", &summary);
+        map_sets.synced.insert(player_id);
+    }
+}
+
+pub fn map_set_request_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    mut map_sets: ResMut<MapSets>,
+) {
+    for event in event_reader.read() {
+        match event.command.as_str() {
+            "MapSetSummaryRequest" => {
+                let packet = match serde_json::from_str::<PacketMapSetSummaryRequest>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketMapSetSummaryRequest from JSON: {:?}", err);
+                        continue;
                     }
-                    info!("Finished sending map set summary to peers.");
-                }
-                Err(ser_err) => {
-                    error!("Failed to serialize map sets summary for sending: {:?}", ser_err);
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let summary = PacketMapSetSummary {
+                    map_sets: map_sets.summary(),
+                    engagement: map_sets.engagement(),
+                };
+                send_peer_message(&mut socket, event.peer, &player_id, "MapSetSummary", &summary);
+                map_sets.synced.insert(player_id);
+            }
+            "MapSetRequest" => {
+                let packet = match serde_json::from_str::<PacketMapSetRequest>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketMapSetRequest from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                // An empty list asks for the full catalog, what REQUEST_FULL_MAP_SETS used to do
+                let requested: HashSet<Uuid> = packet
+                    .map_set_ids
+                    .iter()
+                    .filter_map(|map_set_id| Uuid::parse_str(map_set_id).ok())
+                    .collect();
+                let full = packet.map_set_ids.is_empty();
+                let reply = PacketMapSets {
+                    map_sets: map_sets
+                        .map_sets
+                        .iter()
+                        .filter(|map_set| full || requested.contains(&map_set.map_set_id))
+                        .cloned()
+                        .collect(),
+                    removed: Vec::new(),
+                };
+                info!("Sending {} map sets to player {}", reply.map_sets.len(), player_id);
+                send_peer_message(&mut socket, event.peer, &player_id, "MapSets", &reply);
+                map_sets.synced.insert(player_id);
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn send_admin_result(
+    socket: &mut MatchboxSocket<SingleChannel>,
+    peer: PeerId,
+    player_id: &Uuid,
+    command: &str,
+    map_set_id: Option<String>,
+    result: Result<(), String>,
+) {
+    if let Err(reason) = result.as_ref() {
+        info!("{} from admin {} rejected: {}", command, player_id, reason);
+    }
+    let packet = PacketMapSetAdminResult {
+        command: String::from(command),
+        map_set_id,
+        accepted: result.is_ok(),
+        reason: result.err(),
+    };
+    send_peer_message(socket, peer, player_id, "MapSetAdminResult", &packet);
+}
+
+// The requesting player if they are on the right peer and an admin
+pub fn verify_admin(
+    socket: &mut MatchboxSocket<SingleChannel>,
+    connected_players: &ConnectedPlayers,
+    admins: &Admins,
+    event: &ClientRequestEvent,
+    player_id: &str,
+) -> Option<Uuid> {
+    let player_id = connected_players.verify_peer(player_id, event.peer)?;
+    if !admins.is_admin(&player_id, event.peer) {
+        let reason = String::from("Only admins can change map sets");
+        send_admin_result(socket, event.peer, &player_id, &event.command, None, Err(reason));
+        return None;
+    }
+    Some(player_id)
+}
+
+pub fn existing_map_set(map_sets: &MapSets, map_set_id: &str) -> Result<MapSet, String> {
+    Uuid::parse_str(map_set_id)
+        .ok()
+        .and_then(|map_set_id| map_sets.get(&map_set_id))
+        .cloned()
+        .ok_or_else(|| String::from("Unknown map set"))
+}
+
+pub fn map_set_admin_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    admins: Res<Admins>,
+    map_sets: Res<MapSets>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    for event in event_reader.read() {
+        let command = event.command.clone();
+        // (player_id, map set to save and whether it is new) for create, update and reorder
+        let (player_id, result) = match event.command.as_str() {
+            "MapSetCreate" => {
+                let packet = match serde_json::from_str::<PacketMapSetCreate>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketMapSetCreate from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = verify_admin(&mut socket, &connected_players, &admins, event, &packet.player_id) else {
+                    continue;
+                };
+                let result = map_sets.check_name(&packet.map_set_name, None).and_then(|map_set_name| {
+                    let mut map_set = MapSet::new(Uuid::now_v7(), map_set_name, packet.hole_range_start, packet.hole_range_end);
+                    map_set.set_file_paths(&packet.file_paths)?;
+                    map_set.set_holes(packet.holes);
+                    Ok((map_set, true))
+                });
+                (player_id, result)
+            }
+            "MapSetUpdate" => {
+                let packet = match serde_json::from_str::<PacketMapSetUpdate>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketMapSetUpdate from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = verify_admin(&mut socket, &connected_players, &admins, event, &packet.player_id) else {
+                    continue;
+                };
+                let result = existing_map_set(&map_sets, &packet.map_set_id).and_then(|mut map_set| {
+                    if let Some(map_set_name) = packet.map_set_name.as_deref() {
+                        map_set.map_set_name = map_sets.check_name(map_set_name, Some(&map_set.map_set_id))?;
+                    }
+                    map_set.hole_range_start = packet.hole_range_start.unwrap_or(map_set.hole_range_start);
+                    map_set.hole_range_end = packet.hole_range_end.unwrap_or(map_set.hole_range_end);
+                    if let Some(file_paths) = packet.file_paths.as_deref() {
+                        map_set.set_file_paths(file_paths)?;
+                    }
+                    if let Some(holes) = packet.holes {
+                        map_set.set_holes(holes);
+                    }
+                    Ok((map_set, false))
+                });
+                (player_id, result)
+            }
+            "MapSetReorder" => {
+                let packet = match serde_json::from_str::<PacketMapSetReorder>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketMapSetReorder from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = verify_admin(&mut socket, &connected_players, &admins, event, &packet.player_id) else {
+                    continue;
+                };
+                let result = existing_map_set(&map_sets, &packet.map_set_id).and_then(|mut map_set| {
+                    map_set.reorder(&packet.order)?;
+                    Ok((map_set, false))
+                });
+                (player_id, result)
+            }
+            "MapSetRetire" | "MapSetRestore" => {
+                let packet = match serde_json::from_str::<PacketMapSetRetire>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketMapSetRetire from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = verify_admin(&mut socket, &connected_players, &admins, event, &packet.player_id) else {
+                    continue;
+                };
+                let retire = command == "MapSetRetire";
+                let result = existing_map_set(&map_sets, &packet.map_set_id).and_then(|map_set| {
+                    match (retire, map_set.retired.is_some()) {
+                        (true, true) => Err(String::from("Map set is already retired")),
+                        (false, false) => Err(String::from("Map set is not retired")),
+                        _ => Ok(map_set.map_set_id),
+                    }
+                });
+                match result {
+                    Ok(map_set_id) => {
+                        let pool = pool.0.clone();
+                        let peer = event.peer;
+                        // Spawn the background task using bevy_tokio_tasks
+                        runtime.spawn_background_task(move |ctx| {
+                            retire_map_set_async(command, map_set_id, retire, player_id, peer, pool, ctx)
+                        });
+                    }
+                    Err(reason) => send_admin_result(&mut socket, event.peer, &player_id, &command, Some(packet.map_set_id), Err(reason)),
                 }
+                continue;
+            }
+            _ => continue,
+        };
+
+        let root = map_assets_dir();
+        match result.and_then(|(map_set, insert)| map_set.validate(&root).map(|_| (map_set, insert))) {
+            Ok((map_set, insert)) => {
+                info!("Admin {} sent {} for map set {:?}", player_id, command, map_set.map_set_name);
+                let pool = pool.0.clone();
+                let peer = event.peer;
+                // Spawn the background task using bevy_tokio_tasks
+                runtime.spawn_background_task(move |ctx| {
+                    save_map_set_async(command, map_set, insert, player_id, peer, pool, ctx)
+                });
             }
+            Err(reason) => send_admin_result(&mut socket, event.peer, &player_id, &command, None, Err(reason)),
+        }
+    }
+}
+
+// Writes the set and its hole metadata with a fresh last_updated, then rebuilds its asset manifest and
+// records the result as a revision
+pub async fn save_map_set(pool: &MySqlPool, map_set: &MapSet, insert: bool) -> Result<(), Error> {
+    let mut tx = pool.begin().await?;
+    save_map_set_rows(&mut tx, map_set, insert).await?;
+    tx.commit().await?;
+    refresh_map_set_assets(pool, map_set).await
+}
+
+// The map_set_table and map_set_hole part of save_map_set, for callers running their own transaction
+pub async fn save_map_set_rows(conn: &mut MySqlConnection, map_set: &MapSet, insert: bool) -> Result<(), Error> {
+    let mut query = if insert {
+        sqlx::query(
+            "INSERT INTO map_set_table (map_set_id, map_set_name, created, last_updated,
+                hole_range_start, hole_range_end, file_path_level_1, file_path_level_2,
+                file_path_level_3, file_path_level_4, file_path_level_5, file_path_level_6,
+                file_path_level_7, file_path_level_8, file_path_level_9, file_path_level_10,
+                file_path_level_11, file_path_level_12, file_path_level_13, file_path_level_14,
+                file_path_level_15, file_path_level_16, file_path_level_17, file_path_level_18
+            ) VALUES (
+                UUID_TO_BIN(?), ?, ?, NOW(), ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?
+            )",
+        )
+        .bind(map_set.map_set_id.to_string())
+        .bind(map_set.map_set_name.clone())
+        .bind(map_set.created)
+    } else {
+        sqlx::query(
+            "UPDATE map_set_table SET map_set_name = ?, last_updated = NOW(),
+                hole_range_start = ?, hole_range_end = ?, file_path_level_1 = ?, file_path_level_2 = ?,
+                file_path_level_3 = ?, file_path_level_4 = ?, file_path_level_5 = ?, file_path_level_6 = ?,
+                file_path_level_7 = ?, file_path_level_8 = ?, file_path_level_9 = ?, file_path_level_10 = ?,
+                file_path_level_11 = ?, file_path_level_12 = ?, file_path_level_13 = ?, file_path_level_14 = ?,
+                file_path_level_15 = ?, file_path_level_16 = ?, file_path_level_17 = ?, file_path_level_18 = ?
+            WHERE map_set_id = UUID_TO_BIN(?)",
+        )
+        .bind(map_set.map_set_name.clone())
+    };
+    query = query.bind(map_set.hole_range_start).bind(map_set.hole_range_end);
+    for file_path in map_set.file_paths() {
+        query = query.bind(file_path.clone());
+    }
+    if !insert {
+        query = query.bind(map_set.map_set_id.to_string());
+    }
+    query.execute(&mut *conn).await?;
+
+    sqlx::query("DELETE FROM map_set_hole WHERE map_set_id = UUID_TO_BIN(?)")
+        .bind(map_set.map_set_id.to_string())
+        .execute(&mut *conn)
+        .await?;
+    for hole in map_set.holes.iter() {
+        sqlx::query(
+            "INSERT INTO map_set_hole (map_set_id, level, display_name, par, difficulty, author, tags, stroke_limit)
+             VALUES (UUID_TO_BIN(?), ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(map_set.map_set_id.to_string())
+        .bind(hole.level)
+        .bind(&hole.display_name)
+        .bind(hole.par)
+        .bind(hole.difficulty)
+        .bind(&hole.author)
+        .bind(serde_json::to_string(&hole.tags).unwrap_or_else(|_| String::from("[]")))
+        .bind(hole.stroke_limit)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+// Rebuilds the stored manifest from the files on disk and records a revision if they changed
+pub async fn refresh_map_set_assets(pool: &MySqlPool, map_set: &MapSet) -> Result<(), Error> {
+    let entries = scan_map_set(&map_assets_dir(), map_set).await;
+    store_manifest(pool, &map_set.map_set_id, &entries).await?;
+    record_revision(pool, map_set, &entries).await?;
+    Ok(())
+}
+
+pub async fn save_map_set_async(
+    command: String,
+    map_set: MapSet,
+    insert: bool,
+    player_id: Uuid,
+    peer: PeerId,
+    pool: MySqlPool,
+    ctx: TaskContext,
+) {
+    let result = match save_map_set(&pool, &map_set, insert).await {
+        Ok(()) => fetch_map_set(&pool, &map_set.map_set_id).await,
+        Err(err) => Err(err),
+    };
+    apply_map_set_change(command, result, player_id, peer, ctx).await;
+}
+
+async fn retire_map_set(pool: &MySqlPool, map_set_id: &Uuid, retired_by: Option<&Uuid>) -> Result<(), Error> {
+    let mut tx = pool.begin().await?;
+    match retired_by {
+        Some(retired_by) => {
+            sqlx::query("INSERT INTO map_set_retired (map_set_id, retired_by) VALUES (UUID_TO_BIN(?), UUID_TO_BIN(?))")
+                .bind(map_set_id.to_string())
+                .bind(retired_by.to_string())
+                .execute(&mut *tx)
+                .await?;
+        }
+        None => {
+            sqlx::query("DELETE FROM map_set_retired WHERE map_set_id = UUID_TO_BIN(?)")
+                .bind(map_set_id.to_string())
+                .execute(&mut *tx)
+                .await?;
+        }
+    }
+    sqlx::query("UPDATE map_set_table SET last_updated = NOW() WHERE map_set_id = UUID_TO_BIN(?)")
+        .bind(map_set_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await
+}
+
+pub async fn retire_map_set_async(
+    command: String,
+    map_set_id: Uuid,
+    retire: bool,
+    player_id: Uuid,
+    peer: PeerId,
+    pool: MySqlPool,
+    ctx: TaskContext,
+) {
+    let result = match retire_map_set(&pool, &map_set_id, retire.then_some(&player_id)).await {
+        Ok(()) => fetch_map_set(&pool, &map_set_id).await,
+        Err(err) => Err(err),
+    };
+    apply_map_set_change(command, result, player_id, peer, ctx).await;
+}
 
-            // Wait for a request from the client to send the full map sets
-            // This can be handled when we receive a specific request message from a client
+// Puts the saved set into MapSets, pushes it to synced players and answers the admin
+pub async fn apply_map_set_change(
+    command: String,
+    result: Result<Option<MapSet>, Error>,
+    player_id: Uuid,
+    peer: PeerId,
+    mut ctx: TaskContext,
+) {
+    let map_set = match result {
+        Ok(map_set) => map_set,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to save map set: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to save map set in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            None
+        }
+    };
+
+    ctx.run_on_main_thread(move |ctx| {
+        let Some(connected_players) = ctx.world.get_resource::<ConnectedPlayers>().cloned() else {
+            return;
+        };
+        let Some(mut map_sets) = ctx.world.remove_resource::<MapSets>() else {
+            info!("Failed to access map_sets_resource");
+            return;
+        };
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            match map_set {
+                Some(map_set) => {
+                    info!("{} applied to map set {:?} by admin {}", command, map_set.map_set_name, player_id);
+                    let map_set_id = map_set.map_set_id.to_string();
+                    map_sets.upsert(map_set.clone());
+                    broadcast_map_set_changes(&mut socket, &connected_players, &map_sets, vec![map_set], Vec::new());
+                    send_admin_result(&mut socket, peer, &player_id, &command, Some(map_set_id), Ok(()));
+                }
+                None => {
+                    send_admin_result(&mut socket, peer, &player_id, &command, None, Err(String::from("Failed to save the map set")));
+                }
+            }
         } else {
             info!("Failed to access matchbox resource");
         }
+        ctx.world.insert_resource(map_sets);
     })
     .await;
 }
-*/
-// pub async fn send_existing_map_sets_async(
-//     pool: MySqlPool,
-//     mut ctx: TaskContext
-// ) {
-//     let query = "SELECT * FROM map_set_table";
-
-//     let map_sets: Vec<MapSet> = match query_as::<_, MapSet>(query).fetch_all(&pool).await {
-//         Ok(map_sets) => {
-//             // info!("Retrieved map sets: {:?}", map_sets);
-//             map_sets
-//         }
-//         Err(err) => {
-//             error!("Failed to execute query: {:?}", err);
-
-//             // Run a callback on the main thread to handle the error properly
-//             ctx.run_on_main_thread(move |_ctx| {
-//                 info!("Failed to execute query in the task: {:?}", err);
-//             })
-//             .await;
-//             return; // Exit early since the query failed.
-//         }
-//     };
-
-//     ctx.run_on_main_thread(move |ctx| {
-//         if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
-//             let peers: Vec<_> = socket.connected_peers().collect();
-
-//             let summary: Vec<(Uuid, OffsetDateTime)> = map_sets
-//                 .iter()
-//                 .map(|ms| (ms.map_set_id.clone(), ms.last_updated)) // Use timestamp or version
-//                 .collect();
-
-//             match encode::to_vec(&summary) {
-//                 Ok(serialized_summary) => {
-//                     // info!("Serialization successful, attempting to send to peers...");
-//                     for peer in peers {
-//                         // info!("Sending map_sets update to {peer}");
-//                         socket.send(serialized_summary.clone().into(), peer);
-//                     }
-//                     info!("Finished sending updates.");
-//                 }
-//                 Err(ser_err) => {
-//                     error!("Failed to serialize map sets summary for sending: {:?}", ser_err);
-//                 }
-//             }
-
-//             let peers: Vec<_> = socket.connected_peers().collect();
-//             match encode::to_vec(&map_sets) {
-//                 Ok(serialized_map_sets) => {
-//                     // info!("Serialization successful, attempting to send to peers...");
-//                     for peer in peers {
-//                         // info!("Sending map_sets update to {peer}");
-//                         socket.send(serialized_map_sets.clone().into(), peer);
-//                     }
-//                     info!("Finished sending updates.");
-//                 }
-//                 Err(ser_err) => {
-//                     error!("Failed to serialize map sets for sending: {:?}", ser_err);
-//                 }
-//             }
-//         } else {
-//             info!("Failed to access matchbox resource");
-//         }
-//     })
-//     .await;
-// }
\ No newline at end of file
@@ -0,0 +1,352 @@
+use bevy::prelude::*;
+use bevy_matchbox::prelude::*;
+use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+
+use sqlx::{MySqlPool, Error};
+use std::collections::HashMap;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    Admins,
+    ClientRequestEvent,
+    ConnectedPlayers,
+    DatabasePool,
+    MapAssetEntry,
+    MapSet,
+    MapSetRevision,
+    MapSets,
+    PacketMapSetRevisions,
+    PacketMapSetRevisionsRequest,
+    PacketMapSetRollback,
+};
+
+use crate::handlers::{
+    map_asset_handler::{map_assets_dir, scan_map_set},
+    map_set_handler::{apply_map_set_change, existing_map_set, fetch_map_set, save_map_set, send_admin_result, verify_admin},
+    signaling_server_handler::send_peer_message,
+};
+
+const MAX_REVISIONS_LISTED: i64 = 50;
+
+impl MapSetRevision {
+    pub fn from_map_set(map_set: &MapSet, assets: &[MapAssetEntry], revision: i32) -> Self {
+        Self {
+            map_set_id: map_set.map_set_id,
+            revision,
+            created: OffsetDateTime::now_utc(),
+            map_set_name: map_set.map_set_name.clone(),
+            hole_range_start: map_set.hole_range_start,
+            hole_range_end: map_set.hole_range_end,
+            file_paths: map_set.file_paths().into_iter().cloned().collect(),
+            holes: map_set.holes.clone(),
+            assets: assets.to_vec(),
+        }
+    }
+
+    // Whether both describe the same course, ignoring numbering and time
+    fn same_course(&self, other: &MapSetRevision) -> bool {
+        self.map_set_name == other.map_set_name
+            && self.hole_range_start == other.hole_range_start
+            && self.hole_range_end == other.hole_range_end
+            && self.file_paths == other.file_paths
+            && self.holes == other.holes
+            && self.assets == other.assets
+    }
+
+    // The revision laid over `map_set`, which keeps its id, creation time and catalog extras
+    pub fn apply_to(&self, map_set: &MapSet) -> Result<MapSet, String> {
+        let mut restored = map_set.clone();
+        restored.map_set_name = self.map_set_name.clone();
+        restored.hole_range_start = self.hole_range_start;
+        restored.hole_range_end = self.hole_range_end;
+        restored.set_file_paths(&self.file_paths)?;
+        restored.set_holes(self.holes.clone());
+        Ok(restored)
+    }
+}
+
+type RevisionRow = (Uuid, i32, OffsetDateTime, String, i32, i32, String, String, String);
+
+const REVISION_COLUMNS: &str = "map_set_id, revision, created, map_set_name, hole_range_start, hole_range_end, file_paths, holes, assets";
+
+fn revision_from_row(row: RevisionRow) -> Option<MapSetRevision> {
+    let (map_set_id, revision, created, map_set_name, hole_range_start, hole_range_end, file_paths, holes, assets) = row;
+    let parsed = serde_json::from_str(&file_paths).and_then(|file_paths| {
+        Ok((file_paths, serde_json::from_str(&holes)?, serde_json::from_str(&assets)?))
+    });
+    match parsed {
+        Ok((file_paths, holes, assets)) => Some(MapSetRevision {
+            map_set_id,
+            revision,
+            created,
+            map_set_name,
+            hole_range_start,
+            hole_range_end,
+            file_paths,
+            holes,
+            assets,
+        }),
+        Err(err) => {
+            warn!("Map set {} revision {} is unreadable: {:?}", map_set_id, revision, err);
+            None
+        }
+    }
+}
+
+// Newest first
+pub async fn fetch_revisions(pool: &MySqlPool, map_set_id: &Uuid, limit: i64) -> Result<Vec<MapSetRevision>, Error> {
+    let rows: Vec<RevisionRow> = sqlx::query_as(&format!(
+        "SELECT {} FROM map_set_revision WHERE map_set_id = UUID_TO_BIN(?) ORDER BY revision DESC LIMIT ?",
+        REVISION_COLUMNS,
+    ))
+    .bind(map_set_id.to_string())
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().filter_map(revision_from_row).collect())
+}
+
+async fn fetch_revision(pool: &MySqlPool, map_set_id: &Uuid, revision: i32) -> Result<Option<MapSetRevision>, Error> {
+    let row: Option<RevisionRow> = sqlx::query_as(&format!(
+        "SELECT {} FROM map_set_revision WHERE map_set_id = UUID_TO_BIN(?) AND revision = ?",
+        REVISION_COLUMNS,
+    ))
+    .bind(map_set_id.to_string())
+    .bind(revision)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.and_then(revision_from_row))
+}
+
+// Latest revision number per map set
+pub async fn fetch_current_revisions(pool: &MySqlPool) -> Result<HashMap<Uuid, i32>, Error> {
+    let rows: Vec<(Uuid, i32)> = sqlx::query_as("SELECT map_set_id, MAX(revision) FROM map_set_revision GROUP BY map_set_id")
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.into_iter().collect())
+}
+
+// Stores the set as the next revision unless the latest one is the same course, returning the
+// current revision number. Rows are never updated, a rollback is recorded as a new revision too
+pub async fn record_revision(pool: &MySqlPool, map_set: &MapSet, assets: &[MapAssetEntry]) -> Result<i32, Error> {
+    let mut tx = pool.begin().await?;
+    // Locking the set's row serialises concurrent saves and scans, so each number is taken once
+    sqlx::query("SELECT map_set_id FROM map_set_table WHERE map_set_id = UUID_TO_BIN(?) FOR UPDATE")
+        .bind(map_set.map_set_id.to_string())
+        .fetch_optional(&mut *tx)
+        .await?;
+    let latest: Option<RevisionRow> = sqlx::query_as(&format!(
+        "SELECT {} FROM map_set_revision WHERE map_set_id = UUID_TO_BIN(?) ORDER BY revision DESC LIMIT 1 FOR UPDATE",
+        REVISION_COLUMNS,
+    ))
+    .bind(map_set.map_set_id.to_string())
+    .fetch_optional(&mut *tx)
+    .await?;
+    let latest_number = latest.as_ref().map(|row| row.1);
+    let latest = latest.and_then(revision_from_row);
+    let next = latest_number.map_or(1, |latest| latest + 1);
+    let revision = MapSetRevision::from_map_set(map_set, assets, next);
+    if let Some(latest) = latest.filter(|latest| latest.same_course(&revision)) {
+        return Ok(latest.revision);
+    }
+    sqlx::query(
+        "INSERT INTO map_set_revision (map_set_id, revision, created, map_set_name, hole_range_start,
+            hole_range_end, file_paths, holes, assets)
+         VALUES (UUID_TO_BIN(?), ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(map_set.map_set_id.to_string())
+    .bind(revision.revision)
+    .bind(revision.created)
+    .bind(&revision.map_set_name)
+    .bind(revision.hole_range_start)
+    .bind(revision.hole_range_end)
+    .bind(serde_json::to_string(&revision.file_paths).unwrap_or_else(|_| String::from("[]")))
+    .bind(serde_json::to_string(&revision.holes).unwrap_or_else(|_| String::from("[]")))
+    .bind(serde_json::to_string(&revision.assets).unwrap_or_else(|_| String::from("[]")))
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    info!("Map set {:?} is now at revision {}", map_set.map_set_name, revision.revision);
+    Ok(revision.revision)
+}
+
+pub fn map_set_revision_request_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    admins: Res<Admins>,
+    map_sets: Res<MapSets>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    for event in event_reader.read() {
+        let command = event.command.clone();
+        match event.command.as_str() {
+            "MapSetRevisionsRequest" => {
+                let packet = match serde_json::from_str::<PacketMapSetRevisionsRequest>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketMapSetRevisionsRequest from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = verify_admin(&mut socket, &connected_players, &admins, event, &packet.player_id) else {
+                    continue;
+                };
+                match existing_map_set(&map_sets, &packet.map_set_id) {
+                    Ok(map_set) => {
+                        let pool = pool.0.clone();
+                        let peer = event.peer;
+                        // Spawn the background task using bevy_tokio_tasks
+                        runtime.spawn_background_task(move |ctx| {
+                            send_map_set_revisions_async(map_set.map_set_id, player_id, peer, pool, ctx)
+                        });
+                    }
+                    Err(reason) => send_admin_result(&mut socket, event.peer, &player_id, &command, Some(packet.map_set_id), Err(reason)),
+                }
+            }
+            "MapSetRollback" => {
+                let packet = match serde_json::from_str::<PacketMapSetRollback>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketMapSetRollback from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = verify_admin(&mut socket, &connected_players, &admins, event, &packet.player_id) else {
+                    continue;
+                };
+                let result = existing_map_set(&map_sets, &packet.map_set_id).and_then(|map_set| {
+                    if map_set.revision == Some(packet.revision) {
+                        Err(format!("Map set is already at revision {}", packet.revision))
+                    } else {
+                        Ok(map_set)
+                    }
+                });
+                match result {
+                    Ok(map_set) => {
+                        info!("Admin {} rolling map set {:?} back to revision {}", player_id, map_set.map_set_name, packet.revision);
+                        let pool = pool.0.clone();
+                        let peer = event.peer;
+                        // Spawn the background task using bevy_tokio_tasks
+                        runtime.spawn_background_task(move |ctx| {
+                            rollback_map_set_async(command, map_set, packet.revision, player_id, peer, pool, ctx)
+                        });
+                    }
+                    Err(reason) => send_admin_result(&mut socket, event.peer, &player_id, &command, Some(packet.map_set_id), Err(reason)),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub async fn send_map_set_revisions_async(
+    map_set_id: Uuid,
+    player_id: Uuid,
+    peer: PeerId,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let revisions = match fetch_revisions(&pool, &map_set_id, MAX_REVISIONS_LISTED).await {
+        Ok(revisions) => revisions,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to execute query: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to execute query in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            return;
+        }
+    };
+    let packet = PacketMapSetRevisions {
+        map_set_id: map_set_id.to_string(),
+        revisions,
+    };
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            send_peer_message(&mut socket, peer, &player_id, "MapSetRevisions", &packet);
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+    })
+    .await;
+}
+
+// Files whose contents on disk no longer match the hash the revision recorded for them
+async fn changed_files(revision: &MapSetRevision, restored: &MapSet) -> Vec<String> {
+    let current: HashMap<String, String> = scan_map_set(&map_assets_dir(), restored)
+        .await
+        .into_iter()
+        .map(|entry| (entry.file_path, entry.hash))
+        .collect();
+    revision
+        .assets
+        .iter()
+        .filter(|asset| current.get(&asset.file_path) != Some(&asset.hash))
+        .map(|asset| asset.file_path.clone())
+        .collect()
+}
+
+// Ok(Err(reason)) when the revision cannot be restored, e.g. its files are gone or were edited in
+// place, or its name was taken by another set since
+async fn rollback_map_set(pool: &MySqlPool, map_set: &MapSet, revision: i32) -> Result<Result<MapSet, String>, Error> {
+    let Some(revision) = fetch_revision(pool, &map_set.map_set_id, revision).await? else {
+        return Ok(Err(String::from("Unknown revision")));
+    };
+    let restored = match revision.apply_to(map_set).and_then(|restored| restored.validate(&map_assets_dir()).map(|_| restored)) {
+        Ok(restored) => restored,
+        Err(reason) => return Ok(Err(reason)),
+    };
+    let changed = changed_files(&revision, &restored).await;
+    if !changed.is_empty() {
+        return Ok(Err(format!(
+            "Files changed since revision {}: {}",
+            revision.revision,
+            changed.join(", "),
+        )));
+    }
+    let (taken,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM map_set_table WHERE map_set_name = ? AND map_set_id <> UUID_TO_BIN(?)",
+    )
+    .bind(&restored.map_set_name)
+    .bind(restored.map_set_id.to_string())
+    .fetch_one(pool)
+    .await?;
+    if taken > 0 {
+        return Ok(Err(format!("Another map set is now named {:?}", restored.map_set_name)));
+    }
+    save_map_set(pool, &restored, false).await?;
+    Ok(Ok(restored))
+}
+
+pub async fn rollback_map_set_async(
+    command: String,
+    map_set: MapSet,
+    revision: i32,
+    player_id: Uuid,
+    peer: PeerId,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    match rollback_map_set(&pool, &map_set, revision).await {
+        Ok(Ok(restored)) => {
+            let result = fetch_map_set(&pool, &restored.map_set_id).await;
+            apply_map_set_change(command, result, player_id, peer, ctx).await;
+        }
+        Ok(Err(reason)) => {
+            let map_set_id = map_set.map_set_id.to_string();
+            ctx.run_on_main_thread(move |ctx| {
+                if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+                    send_admin_result(&mut socket, peer, &player_id, &command, Some(map_set_id), Err(reason));
+                } else {
+                    info!("Failed to access matchbox resource");
+                }
+            })
+            .await;
+        }
+        Err(err) => apply_map_set_change(command, Err(err), player_id, peer, ctx).await,
+    }
+}
@@ -0,0 +1,163 @@
+use bevy::prelude::*;
+
+use sqlx::{MySqlPool, Error};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+use uuid::Uuid;
+
+use crate::{
+    HoleMetadata,
+    MapSet,
+    MapSetSeed,
+};
+
+use crate::handlers::map_set_handler::{fetch_map_sets, save_map_set};
+
+const DEFAULT_MAP_SET_SEEDS_DIR: &str = "assets/map_sets";
+const MAX_SEED_KEY_LEN: usize = 64;
+
+pub fn map_set_seeds_dir() -> PathBuf {
+    PathBuf::from(env::var("MAP_SET_SEEDS_DIR").unwrap_or_else(|_| String::from(DEFAULT_MAP_SET_SEEDS_DIR)))
+}
+
+impl MapSetSeed {
+    // The seed laid over `map_set`, which keeps its id and creation time
+    pub fn apply_to(&self, map_set: &MapSet) -> Result<MapSet, String> {
+        let mut seeded = map_set.clone();
+        seeded.map_set_name = self.map_set_name.trim().to_string();
+        seeded.hole_range_start = self.hole_range_start;
+        seeded.hole_range_end = self.hole_range_end;
+        let mut file_paths: Vec<Option<String>> = vec![None; 18];
+        for level in self.levels.iter() {
+            let Some(slot) = usize::try_from(level.level - 1).ok().and_then(|idx| file_paths.get_mut(idx)) else {
+                return Err(format!("Level {} is not between 1 and 18", level.level));
+            };
+            if slot.is_some() {
+                return Err(format!("Level {} is listed twice", level.level));
+            }
+            *slot = Some(level.file_path.clone());
+        }
+        seeded.set_file_paths(&file_paths)?;
+        let holes = self
+            .levels
+            .iter()
+            .filter_map(|level| {
+                level.par.map(|par| HoleMetadata {
+                    level: level.level,
+                    display_name: level.display_name.clone(),
+                    par,
+                    difficulty: level.difficulty,
+                    author: level.author.clone(),
+                    tags: level.tags.clone(),
+                    stroke_limit: level.stroke_limit,
+                })
+            })
+            .collect();
+        seeded.set_holes(holes);
+        seeded.validate_levels()?;
+        Ok(seeded)
+    }
+}
+
+// Reads every .json file in the directory in name order, each holding a list of seeds. Broken files
+// and entries are skipped, so one bad edit does not hold back the rest
+pub fn load_map_set_seeds(dir: &Path) -> Vec<MapSetSeed> {
+    let mut paths: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+            .collect(),
+        Err(err) => {
+            warn!("Failed to read map set seeds directory {:?}: {}", dir, err);
+            return Vec::new();
+        }
+    };
+    paths.sort();
+
+    let mut keys = HashSet::new();
+    let mut names = HashSet::new();
+    let mut seeds = Vec::new();
+    for path in paths {
+        let file_seeds: Vec<MapSetSeed> = match fs::read_to_string(&path) {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(file_seeds) => file_seeds,
+                Err(err) => {
+                    error!("Failed to parse map set seed file {:?}: {:?}", path, err);
+                    continue;
+                }
+            },
+            Err(err) => {
+                warn!("Failed to read map set seed file {:?}: {}", path, err);
+                continue;
+            }
+        };
+        for seed in file_seeds {
+            if seed.key.is_empty() || seed.key.len() > MAX_SEED_KEY_LEN {
+                warn!("Ignoring map set seed with invalid key {:?} in {:?}", seed.key, path);
+            } else if !keys.insert(seed.key.clone()) {
+                warn!("Ignoring duplicate map set seed key {:?} in {:?}", seed.key, path);
+            } else if !names.insert(seed.map_set_name.trim().to_lowercase()) {
+                warn!("Ignoring map set seed {:?}, its name {:?} is already seeded", seed.key, seed.map_set_name);
+            } else {
+                seeds.push(seed);
+            }
+        }
+    }
+    info!("Loaded {} map set seed(s) from {:?}", seeds.len(), dir);
+    seeds
+}
+
+// Inserts missing sets and updates changed ones, returning (inserted, updated). Sets are matched by
+// seed key, falling back to the name for sets created before the key was recorded. Sets whose seed
+// was removed stay as they are, retiring them is up to an admin
+pub async fn apply_map_set_seeds(pool: &MySqlPool, seeds: &[MapSetSeed]) -> Result<(usize, usize), Error> {
+    let map_sets = fetch_map_sets(pool).await?;
+    let seed_keys: HashMap<String, Uuid> = sqlx::query_as::<_, (String, Uuid)>("SELECT seed_key, map_set_id FROM map_set_seed")
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .collect();
+    let keyed: HashSet<Uuid> = seed_keys.values().copied().collect();
+
+    let mut inserted = 0;
+    let mut updated = 0;
+    for seed in seeds.iter() {
+        let current = match seed_keys.get(&seed.key) {
+            Some(map_set_id) => map_sets.iter().find(|map_set| map_set.map_set_id == *map_set_id),
+            None => map_sets.iter().find(|map_set| {
+                !keyed.contains(&map_set.map_set_id) && map_set.map_set_name.eq_ignore_ascii_case(seed.map_set_name.trim())
+            }),
+        };
+        let insert = current.is_none();
+        let base = current
+            .cloned()
+            .unwrap_or_else(|| MapSet::new(Uuid::now_v7(), String::new(), 1, 1));
+        let seeded = match seed.apply_to(&base) {
+            Ok(seeded) => seeded,
+            Err(reason) => {
+                warn!("Skipping map set seed {:?}: {}", seed.key, reason);
+                continue;
+            }
+        };
+
+        if insert {
+            save_map_set(pool, &seeded, true).await?;
+            inserted += 1;
+        } else if !seeded.same_definition(&base) {
+            save_map_set(pool, &seeded, false).await?;
+            updated += 1;
+        }
+        if seed_keys.get(&seed.key) != Some(&seeded.map_set_id) {
+            sqlx::query(
+                "INSERT INTO map_set_seed (seed_key, map_set_id) VALUES (?, UUID_TO_BIN(?))
+                 ON DUPLICATE KEY UPDATE map_set_id = VALUES(map_set_id)",
+            )
+            .bind(&seed.key)
+            .bind(seeded.map_set_id.to_string())
+            .execute(pool)
+            .await?;
+        }
+    }
+    Ok((inserted, updated))
+}
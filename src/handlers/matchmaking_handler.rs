@@ -0,0 +1,346 @@
+use bevy::prelude::*;
+
+use bevy_matchbox::prelude::*;
+use bevy_tokio_tasks::TokioTasksRuntime;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::{
+    ClientRequestEvent,
+    ConnectedPlayers,
+    DatabasePool,
+    GameSession,
+    GameSessions,
+    MapSets,
+    MatchmakingEntry,
+    MatchmakingQueue,
+    PacketMatchFound,
+    PacketMatchmakingEnqueue,
+    PacketMatchmakingLeave,
+    PacketMatchmakingStatus,
+    Parties,
+    PlayerDisconnectedEvent,
+    PlayerRatings,
+    Rooms,
+};
+
+use crate::handlers::{
+    game_session_handler::broadcast_session_state,
+    party_handler::broadcast_party_state,
+    rating_handler::refresh_matchmaking_rating_async,
+    signaling_server_handler::send_player_message,
+};
+
+impl MatchmakingEntry {
+    pub fn size(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn contains(&self, player_id: &Uuid) -> bool {
+        self.members.contains(player_id)
+    }
+}
+
+impl MatchmakingQueue {
+    pub fn new(relax_after: Duration, rating_window: f64) -> Self {
+        Self {
+            entries: Vec::new(),
+            relax_after,
+            rating_window,
+        }
+    }
+
+    pub fn is_queued(&self, player_id: &Uuid) -> bool {
+        self.entries.iter().any(|entry| entry.contains(player_id))
+    }
+
+    pub fn enqueue(&mut self, entry: MatchmakingEntry) -> Result<(), String> {
+        if let Some(player_id) = entry.members.iter().find(|player_id| self.is_queued(player_id)) {
+            return Err(format!("Player {} is already queued", player_id));
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    pub fn remove_player(&mut self, player_id: &Uuid) -> Option<MatchmakingEntry> {
+        let idx = self.entries.iter().position(|entry| entry.contains(player_id))?;
+        Some(self.entries.remove(idx))
+    }
+
+    // How many relaxation steps the entry has earned by waiting:
+    // 0 = exact map set and party size, 1 = any map set, 2+ = smaller parties allowed.
+    // Every step also widens the allowed rating gap by another rating_window. Two entries
+    // are only matched on the terms both of them have waited long enough to accept.
+    fn relax_level(&self, entry: &MatchmakingEntry, now: Instant) -> u32 {
+        if self.relax_after.is_zero() {
+            return u32::MAX;
+        }
+        (now.duration_since(entry.enqueued).as_secs_f32() / self.relax_after.as_secs_f32()) as u32
+    }
+
+    // Pulls every group that can be matched right now out of the queue, oldest entries first,
+    // returning each group with the map set preference it should play on.
+    pub fn find_matches(&mut self, now: Instant) -> Vec<(Vec<MatchmakingEntry>, Option<Uuid>)> {
+        let mut matches = Vec::new();
+        self.entries.sort_by_key(|entry| entry.enqueued);
+
+        let mut idx = 0;
+        while idx < self.entries.len() {
+            let anchor = &self.entries[idx];
+            let anchor_relax_level = self.relax_level(anchor, now);
+            let mut group_relax_level = anchor_relax_level;
+            let mut group = vec![idx];
+            let mut size = anchor.size();
+            let mut map_set_id = anchor.map_set_id;
+
+            for other in idx + 1..self.entries.len() {
+                if size == anchor.party_size {
+                    break;
+                }
+                let candidate = &self.entries[other];
+                if candidate.play_style != anchor.play_style {
+                    continue;
+                }
+                let relax_level = anchor_relax_level.min(self.relax_level(candidate, now));
+                let rating_window = self.rating_window * relax_level.saturating_add(1) as f64;
+                if candidate.party_size != anchor.party_size && relax_level < 2 {
+                    continue;
+                }
+                if size + candidate.size() > anchor.party_size {
+                    continue;
+                }
+                // Provisional ratings are still unreliable, so they get twice the room
+                let allowed_gap = if anchor.provisional || candidate.provisional { rating_window * 2.0 } else { rating_window };
+                if (candidate.rating - anchor.rating).abs() > allowed_gap {
+                    continue;
+                }
+                let map_set_compatible = match (map_set_id, candidate.map_set_id) {
+                    (Some(wanted), Some(offered)) => wanted == offered || relax_level >= 1,
+                    _ => true,
+                };
+                if !map_set_compatible {
+                    continue;
+                }
+                if map_set_id.is_none() {
+                    map_set_id = candidate.map_set_id;
+                }
+                group.push(other);
+                group_relax_level = group_relax_level.min(relax_level);
+                size += candidate.size();
+            }
+
+            let minimum_size = if group_relax_level >= 2 { anchor.party_size.min(2) } else { anchor.party_size };
+            if size >= minimum_size {
+                let mut entries = Vec::new();
+                for other in group.into_iter().rev() {
+                    entries.push(self.entries.remove(other));
+                }
+                entries.reverse();
+                matches.push((entries, map_set_id));
+            } else {
+                idx += 1;
+            }
+        }
+        matches
+    }
+}
+
+fn send_matchmaking_status(
+    socket: &mut MatchboxSocket<SingleChannel>,
+    connected_players: &ConnectedPlayers,
+    players: &[Uuid],
+    queued: bool,
+    reason: Option<String>,
+) {
+    let status = PacketMatchmakingStatus {
+        queued,
+        reason,
+    };
+    for player_id in players.iter() {
+        send_player_message(socket, connected_players, player_id, "MatchmakingStatus", &status);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn matchmaking_request_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    map_sets: Res<MapSets>,
+    parties: Res<Parties>,
+    game_sessions: Res<GameSessions>,
+    player_ratings: Res<PlayerRatings>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+    mut matchmaking_queue: ResMut<MatchmakingQueue>,
+) {
+    for event in event_reader.read() {
+        match event.command.as_str() {
+            "MatchmakingEnqueue" => {
+                let packet = match serde_json::from_str::<PacketMatchmakingEnqueue>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketMatchmakingEnqueue from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+
+                let (party_id, members) = match parties.party_for_player(&player_id) {
+                    Some(party) if party.host_id != player_id => {
+                        let reason = String::from("Only the party host can enter matchmaking");
+                        send_matchmaking_status(&mut socket, &connected_players, &[player_id], false, Some(reason));
+                        continue;
+                    }
+                    Some(party) => (Some(party.party_id), party.members.clone()),
+                    None => (None, vec![player_id]),
+                };
+
+                let map_set_id = match packet.map_set_id.as_deref().map(Uuid::parse_str) {
+                    Some(Ok(map_set_id)) if map_sets.playable(&map_set_id).is_some() => Some(map_set_id),
+                    Some(_) => {
+                        let reason = String::from("Unknown map set");
+                        send_matchmaking_status(&mut socket, &connected_players, &[player_id], false, Some(reason));
+                        continue;
+                    }
+                    None => None,
+                };
+
+                let reason = if packet.party_size < members.len() || packet.party_size > parties.max_size {
+                    Some(format!("Party size must be between {} and {}", members.len(), parties.max_size))
+                } else if members.iter().any(|member| game_sessions.session_for_player(member).is_some()) {
+                    Some(String::from("A party member is already in a game session"))
+                } else {
+                    None
+                };
+                if let Some(reason) = reason {
+                    send_matchmaking_status(&mut socket, &connected_players, &[player_id], false, Some(reason));
+                    continue;
+                }
+
+                let Some(play_style) = packet.play_style.or_else(|| connected_players.get_play_style(&player_id)) else {
+                    let reason = String::from("No play style reported yet");
+                    send_matchmaking_status(&mut socket, &connected_players, &[player_id], false, Some(reason));
+                    continue;
+                };
+
+                // Cached ratings are used right away and refreshed from the database once loaded
+                let (rating, provisional) = player_ratings.group_rating(&members, &play_style);
+                let entry = MatchmakingEntry {
+                    host_id: player_id,
+                    party_id,
+                    members: members.clone(),
+                    map_set_id,
+                    party_size: packet.party_size,
+                    play_style: play_style.clone(),
+                    rating,
+                    provisional,
+                    enqueued: Instant::now(),
+                };
+                match matchmaking_queue.enqueue(entry) {
+                    Ok(()) => {
+                        info!("Player {} entered matchmaking with {} player(s)", player_id, members.len());
+                        send_matchmaking_status(&mut socket, &connected_players, &members, true, None);
+                        let generation = player_ratings.generation;
+                        let pool = pool.0.clone();
+                        runtime.spawn_background_task(move |ctx| {
+                            refresh_matchmaking_rating_async(player_id, members, play_style, generation, pool, ctx)
+                        });
+                    }
+                    Err(reason) => {
+                        send_matchmaking_status(&mut socket, &connected_players, &[player_id], false, Some(reason));
+                    }
+                }
+            }
+            "MatchmakingLeave" => {
+                let packet = match serde_json::from_str::<PacketMatchmakingLeave>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketMatchmakingLeave from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                if let Some(entry) = matchmaking_queue.remove_player(&player_id) {
+                    info!("Player {} left matchmaking", player_id);
+                    let reason = format!("Player {} left the queue", player_id);
+                    send_matchmaking_status(&mut socket, &connected_players, &entry.members, false, Some(reason));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn matchmaking_system(
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    map_sets: Res<MapSets>,
+    mut parties: ResMut<Parties>,
+    mut game_sessions: ResMut<GameSessions>,
+    mut rooms: ResMut<Rooms>,
+    mut matchmaking_queue: ResMut<MatchmakingQueue>,
+) {
+    let matches = matchmaking_queue.find_matches(Instant::now());
+    for (entries, map_set_id) in matches {
+        let map_set = map_set_id
+            .and_then(|map_set_id| map_sets.playable(&map_set_id))
+            .or_else(|| map_sets.map_sets.iter().find(|map_set| map_set.retired.is_none()));
+        let Some(map_set) = map_set else {
+            warn!("matchmaking_system: no map sets loaded, keeping players queued");
+            matchmaking_queue.entries.extend(entries);
+            continue;
+        };
+
+        let host_id = entries[0].host_id;
+        let members: Vec<Uuid> = entries.iter().flat_map(|entry| entry.members.clone()).collect();
+        // The session is validated before the party and room are touched, so a match that
+        // cannot start leaves every player's party and room as they were
+        let play_style = Some(entries[0].play_style.clone());
+        let result = GameSession::new(map_set, members.clone(), String::new(), play_style).and_then(|session| {
+            game_sessions.check_players_free(&members)?;
+            let party = parties.form_party(host_id, &members)?;
+            game_sessions.insert_in_new_room(session, &mut rooms).map(|session| (party, session))
+        });
+
+        match result {
+            Ok((party, session)) => {
+                info!("Matched {} player(s) into game session [{}]", members.len(), session.session_id);
+                let match_found = PacketMatchFound {
+                    session_id: session.session_id.to_string(),
+                    party_id: party.party_id.to_string(),
+                    map_set_id: session.map_set_id.to_string(),
+                    player_ids: members.iter().map(|player_id| player_id.to_string()).collect(),
+                };
+                for player_id in members.iter() {
+                    send_player_message(&mut socket, &connected_players, player_id, "MatchFound", &match_found);
+                }
+                broadcast_party_state(&mut socket, &connected_players, &party, &[]);
+                broadcast_session_state(&mut socket, &connected_players, &session);
+            }
+            Err(reason) => {
+                error!("matchmaking_system: failed to set up match: {}", reason);
+                send_matchmaking_status(&mut socket, &connected_players, &members, false, Some(reason));
+            }
+        }
+    }
+}
+
+pub fn matchmaking_disconnect_system(
+    mut event_reader: EventReader<PlayerDisconnectedEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    mut matchmaking_queue: ResMut<MatchmakingQueue>,
+) {
+    for event in event_reader.read() {
+        if let Some(entry) = matchmaking_queue.remove_player(&event.player_id) {
+            let remaining: Vec<Uuid> = entry.members.into_iter().filter(|player_id| player_id != &event.player_id).collect();
+            let reason = format!("Player {} disconnected", event.player_id);
+            send_matchmaking_status(&mut socket, &connected_players, &remaining, false, Some(reason));
+        }
+    }
+}
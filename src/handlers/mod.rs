@@ -1,6 +1,29 @@
+pub mod achievement_handler;
+pub mod admin_handler;
+pub mod challenge_handler;
+pub mod chat_handler;
+pub mod client_state_handler;
 pub mod database_handler;
+pub mod emote_handler;
+pub mod friend_handler;
+pub mod game_session_handler;
 pub mod heartbeat_handler;
+pub mod leader_board_handler;
+pub mod map_asset_handler;
+pub mod map_set_engagement_handler;
 pub mod map_set_handler;
+pub mod map_set_revision_handler;
+pub mod map_set_seed_handler;
+pub mod matchmaking_handler;
+pub mod party_handler;
+pub mod player_stats_handler;
+pub mod playlist_handler;
+pub mod rating_handler;
+pub mod room_handler;
 pub mod run_trigger_handler;
+pub mod schema_handler;
+pub mod season_handler;
 pub mod signaling_server_handler;
+pub mod tournament_handler;
+pub mod ugc_handler;
 pub mod player_handler;
\ No newline at end of file
@@ -0,0 +1,471 @@
+use bevy::prelude::*;
+
+use bevy_matchbox::prelude::*;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+use crate::{
+    ClientRequestEvent,
+    ConnectedPlayers,
+    Friends,
+    PacketPartyCreate,
+    PacketPartyInvite,
+    PacketPartyInviteNotice,
+    PacketPartyInviteResponse,
+    PacketPartyJoinCode,
+    PacketPartyKick,
+    PacketPartyLeave,
+    PacketPartyRejected,
+    PacketPartyRemoved,
+    Parties,
+    Party,
+    PlayerDisconnectedEvent,
+};
+
+use crate::handlers::signaling_server_handler::send_player_message;
+
+// Ambiguous characters (0/O, 1/I) are left out so codes can be read aloud
+const JOIN_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const JOIN_CODE_LENGTH: usize = 6;
+
+impl Party {
+    pub fn is_member(&self, player_id: &Uuid) -> bool {
+        self.members.contains(player_id)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.members.len() >= self.max_size
+    }
+
+    fn add_member(&mut self, player_id: Uuid) -> Result<(), String> {
+        if self.is_member(&player_id) {
+            return Err(String::from("Player is already in this party"));
+        }
+        if self.is_full() {
+            return Err(String::from("Party is full"));
+        }
+        self.invites.remove(&player_id);
+        self.members.push(player_id);
+        Ok(())
+    }
+
+    // Removes a member, handing host to the longest standing member when the host leaves
+    fn remove_member(&mut self, player_id: &Uuid) -> bool {
+        let Some(idx) = self.members.iter().position(|id| id == player_id) else {
+            return false;
+        };
+        self.members.remove(idx);
+        if &self.host_id == player_id {
+            if let Some(new_host) = self.members.first() {
+                info!("Party [{}] host transferred to {}", self.party_id, new_host);
+                self.host_id = *new_host;
+            }
+        }
+        true
+    }
+}
+
+impl Parties {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            parties: HashMap::new(),
+            max_size,
+        }
+    }
+
+    pub fn get(&self, party_id: &Uuid) -> Option<&Party> {
+        self.parties.get(party_id)
+    }
+
+    pub fn party_for_player(&self, player_id: &Uuid) -> Option<&Party> {
+        self.parties.values().find(|party| party.is_member(player_id))
+    }
+
+    fn generate_join_code(&self) -> String {
+        loop {
+            let code: String = Uuid::new_v4()
+                .as_bytes()
+                .iter()
+                .take(JOIN_CODE_LENGTH)
+                .map(|byte| JOIN_CODE_ALPHABET[*byte as usize % JOIN_CODE_ALPHABET.len()] as char)
+                .collect();
+            if !self.parties.values().any(|party| party.join_code == code) {
+                return code;
+            }
+        }
+    }
+
+    pub fn create(&mut self, host_id: Uuid) -> Result<Party, String> {
+        if self.party_for_player(&host_id).is_some() {
+            return Err(String::from("Player is already in a party"));
+        }
+        let party = Party {
+            party_id: Uuid::now_v7(),
+            host_id,
+            members: vec![host_id],
+            join_code: self.generate_join_code(),
+            max_size: self.max_size,
+            invites: HashSet::new(),
+            kicked: HashSet::new(),
+        };
+        self.parties.insert(party.party_id, party.clone());
+        Ok(party)
+    }
+
+    // Builds a party for a set of players, pulling them out of whatever parties they were in
+    pub fn form_party(&mut self, host_id: Uuid, members: &[Uuid]) -> Result<Party, String> {
+        if !members.contains(&host_id) {
+            return Err(String::from("Host must be one of the party members"));
+        }
+        if members.len() > self.max_size {
+            return Err(String::from("Too many players for one party"));
+        }
+        for player_id in members.iter() {
+            if let Some(party_id) = self.party_for_player(player_id).map(|party| party.party_id) {
+                self.leave(&party_id, player_id)?;
+            }
+        }
+        let mut party = self.create(host_id)?;
+        for player_id in members.iter().filter(|player_id| **player_id != host_id) {
+            party.add_member(*player_id)?;
+        }
+        self.parties.insert(party.party_id, party.clone());
+        Ok(party)
+    }
+
+    pub fn invite(&mut self, party_id: &Uuid, host_id: &Uuid, invitee_id: Uuid) -> Result<Party, String> {
+        let invitee_in_party = self.party_for_player(&invitee_id).is_some();
+        let party = self.parties.get_mut(party_id).ok_or(String::from("Unknown party"))?;
+        if &party.host_id != host_id {
+            return Err(String::from("Only the party host can invite players"));
+        }
+        if party.is_member(&invitee_id) || invitee_in_party {
+            return Err(String::from("Player is already in a party"));
+        }
+        if party.is_full() {
+            return Err(String::from("Party is full"));
+        }
+        party.kicked.remove(&invitee_id);
+        party.invites.insert(invitee_id);
+        Ok(party.clone())
+    }
+
+    pub fn accept_invite(&mut self, party_id: &Uuid, player_id: Uuid) -> Result<Party, String> {
+        if self.party_for_player(&player_id).is_some() {
+            return Err(String::from("Player is already in a party"));
+        }
+        let party = self.parties.get_mut(party_id).ok_or(String::from("Unknown party"))?;
+        if !party.invites.contains(&player_id) {
+            return Err(String::from("No pending invite for this party"));
+        }
+        party.add_member(player_id)?;
+        Ok(party.clone())
+    }
+
+    pub fn decline_invite(&mut self, party_id: &Uuid, player_id: &Uuid) -> Result<Party, String> {
+        let party = self.parties.get_mut(party_id).ok_or(String::from("Unknown party"))?;
+        if !party.invites.remove(player_id) {
+            return Err(String::from("No pending invite for this party"));
+        }
+        Ok(party.clone())
+    }
+
+    pub fn join_by_code(&mut self, join_code: &str, player_id: Uuid) -> Result<Party, String> {
+        if self.party_for_player(&player_id).is_some() {
+            return Err(String::from("Player is already in a party"));
+        }
+        let join_code = join_code.trim().to_uppercase();
+        let party = self
+            .parties
+            .values_mut()
+            .find(|party| party.join_code == join_code)
+            .ok_or(String::from("No party with that join code"))?;
+        if party.kicked.contains(&player_id) {
+            return Err(String::from("Player was kicked from this party"));
+        }
+        party.add_member(player_id)?;
+        Ok(party.clone())
+    }
+
+    // Returns the party after the player left; an empty member list means it was disbanded
+    pub fn leave(&mut self, party_id: &Uuid, player_id: &Uuid) -> Result<Party, String> {
+        let party = self.parties.get_mut(party_id).ok_or(String::from("Unknown party"))?;
+        if !party.remove_member(player_id) {
+            return Err(String::from("Player is not in this party"));
+        }
+        let party = party.clone();
+        if party.members.is_empty() {
+            info!("Party [{}] disbanded", party_id);
+            self.parties.remove(party_id);
+        }
+        Ok(party)
+    }
+
+    pub fn kick(&mut self, party_id: &Uuid, host_id: &Uuid, target_id: &Uuid) -> Result<Party, String> {
+        let party = self.parties.get_mut(party_id).ok_or(String::from("Unknown party"))?;
+        if &party.host_id != host_id {
+            return Err(String::from("Only the party host can kick players"));
+        }
+        if host_id == target_id {
+            return Err(String::from("The host cannot kick themselves"));
+        }
+        if !party.remove_member(target_id) {
+            return Err(String::from("Player is not in this party"));
+        }
+        party.kicked.insert(*target_id);
+        Ok(party.clone())
+    }
+}
+
+// Sends the party state to every member plus any extra players that need to learn they were removed
+pub fn broadcast_party_state(
+    socket: &mut MatchboxSocket<SingleChannel>,
+    connected_players: &ConnectedPlayers,
+    party: &Party,
+    extra_recipients: &[Uuid],
+) {
+    for player_id in party.members.iter().chain(extra_recipients.iter()) {
+        send_player_message(socket, connected_players, player_id, "PartyState", party);
+    }
+}
+
+pub fn send_party_rejection(
+    socket: &mut MatchboxSocket<SingleChannel>,
+    connected_players: &ConnectedPlayers,
+    player_id: &Uuid,
+    party_id: Option<&str>,
+    reason: String,
+) {
+    warn!("Rejected party request from {}: {}", player_id, reason);
+    let rejection = PacketPartyRejected {
+        party_id: party_id.map(String::from),
+        reason,
+    };
+    send_player_message(socket, connected_players, player_id, "PartyRejected", &rejection);
+}
+
+fn parse_party_id(party_id: &str) -> Result<Uuid, String> {
+    Uuid::parse_str(party_id).map_err(|_| String::from("Malformed party id"))
+}
+
+// Nobody joins a party where a block stands between them and any member
+fn check_not_blocked(friends: &Friends, party: Option<&Party>, player_id: &Uuid) -> Result<(), String> {
+    if party.is_some_and(|party| party.members.iter().any(|member| friends.is_blocked(member, player_id))) {
+        return Err(String::from("Player cannot join this party"));
+    }
+    Ok(())
+}
+
+pub fn party_request_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    friends: Res<Friends>,
+    mut parties: ResMut<Parties>,
+) {
+    for event in event_reader.read() {
+        match event.command.as_str() {
+            "PartyCreate" => {
+                let packet = match serde_json::from_str::<PacketPartyCreate>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketPartyCreate from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                match parties.create(player_id) {
+                    Ok(party) => {
+                        info!("Player {} created party [{}] with code {}", player_id, party.party_id, party.join_code);
+                        broadcast_party_state(&mut socket, &connected_players, &party, &[]);
+                    }
+                    Err(reason) => send_party_rejection(&mut socket, &connected_players, &player_id, None, reason),
+                }
+            }
+            "PartyInvite" => {
+                let packet = match serde_json::from_str::<PacketPartyInvite>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketPartyInvite from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let result = parse_party_id(&packet.party_id).and_then(|party_id| {
+                    let invitee_id = Uuid::parse_str(&packet.invitee_id).map_err(|_| String::from("Malformed invitee id"))?;
+                    if connected_players.get_peer(&invitee_id).is_none() {
+                        return Err(String::from("Invited player is not connected"));
+                    }
+                    check_not_blocked(&friends, parties.parties.get(&party_id), &invitee_id)?;
+                    parties.invite(&party_id, &player_id, invitee_id).map(|party| (party, invitee_id))
+                });
+                match result {
+                    Ok((party, invitee_id)) => {
+                        let notice = PacketPartyInviteNotice {
+                            party_id: party.party_id.to_string(),
+                            host_id: party.host_id.to_string(),
+                            join_code: party.join_code.clone(),
+                        };
+                        send_player_message(&mut socket, &connected_players, &invitee_id, "PartyInvite", &notice);
+                    }
+                    Err(reason) => send_party_rejection(&mut socket, &connected_players, &player_id, Some(packet.party_id.as_str()), reason),
+                }
+            }
+            "PartyInviteResponse" => {
+                let packet = match serde_json::from_str::<PacketPartyInviteResponse>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketPartyInviteResponse from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let result = parse_party_id(&packet.party_id).and_then(|party_id| {
+                    if packet.accept {
+                        // A block may have been placed after the invite went out
+                        check_not_blocked(&friends, parties.parties.get(&party_id), &player_id)?;
+                        parties.accept_invite(&party_id, player_id)
+                    } else {
+                        parties.decline_invite(&party_id, &player_id)
+                    }
+                });
+                match result {
+                    Ok(party) if packet.accept => {
+                        info!("Player {} joined party [{}]", player_id, party.party_id);
+                        broadcast_party_state(&mut socket, &connected_players, &party, &[]);
+                    }
+                    Ok(party) => {
+                        info!("Player {} declined party [{}]", player_id, party.party_id);
+                        let notice = PacketPartyInviteResponse {
+                            player_id: player_id.to_string(),
+                            party_id: party.party_id.to_string(),
+                            accept: false,
+                        };
+                        send_player_message(&mut socket, &connected_players, &party.host_id, "PartyInviteDeclined", &notice);
+                    }
+                    Err(reason) => send_party_rejection(&mut socket, &connected_players, &player_id, Some(packet.party_id.as_str()), reason),
+                }
+            }
+            "PartyJoinCode" => {
+                let packet = match serde_json::from_str::<PacketPartyJoinCode>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketPartyJoinCode from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let join_code = packet.join_code.trim().to_uppercase();
+                let party = parties.parties.values().find(|party| party.join_code == join_code);
+                let result = check_not_blocked(&friends, party, &player_id)
+                    .and_then(|_| parties.join_by_code(&join_code, player_id));
+                match result {
+                    Ok(party) => {
+                        info!("Player {} joined party [{}] by code", player_id, party.party_id);
+                        broadcast_party_state(&mut socket, &connected_players, &party, &[]);
+                    }
+                    Err(reason) => send_party_rejection(&mut socket, &connected_players, &player_id, None, reason),
+                }
+            }
+            "PartyLeave" => {
+                let packet = match serde_json::from_str::<PacketPartyLeave>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketPartyLeave from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                match parse_party_id(&packet.party_id).and_then(|party_id| parties.leave(&party_id, &player_id)) {
+                    Ok(party) => {
+                        info!("Player {} left party [{}]", player_id, party.party_id);
+                        broadcast_party_state(&mut socket, &connected_players, &party, &[player_id]);
+                    }
+                    Err(reason) => send_party_rejection(&mut socket, &connected_players, &player_id, Some(packet.party_id.as_str()), reason),
+                }
+            }
+            "PartyKick" => {
+                let packet = match serde_json::from_str::<PacketPartyKick>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketPartyKick from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let result = parse_party_id(&packet.party_id).and_then(|party_id| {
+                    let target_id = Uuid::parse_str(&packet.target_id).map_err(|_| String::from("Malformed target id"))?;
+                    parties.kick(&party_id, &player_id, &target_id).map(|party| (party, target_id))
+                });
+                match result {
+                    Ok((party, target_id)) => {
+                        info!("Player {} was kicked from party [{}]", target_id, party.party_id);
+                        broadcast_party_state(&mut socket, &connected_players, &party, &[]);
+                        // The kicked player learns only that they are out, not the party's join code
+                        let notice = PacketPartyRemoved {
+                            party_id: party.party_id.to_string(),
+                            host_id: party.host_id.to_string(),
+                        };
+                        send_player_message(&mut socket, &connected_players, &target_id, "PartyRemoved", &notice);
+                    }
+                    Err(reason) => send_party_rejection(&mut socket, &connected_players, &player_id, Some(packet.party_id.as_str()), reason),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn party_disconnect_system(
+    mut event_reader: EventReader<PlayerDisconnectedEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    mut parties: ResMut<Parties>,
+) {
+    for event in event_reader.read() {
+        for party in parties.parties.values_mut() {
+            party.invites.remove(&event.player_id);
+        }
+        let Some(party_id) = parties.party_for_player(&event.player_id).map(|party| party.party_id) else {
+            continue;
+        };
+        if let Ok(party) = parties.leave(&party_id, &event.player_id) {
+            info!("Player {} dropped from party [{}] after disconnecting", event.player_id, party_id);
+            broadcast_party_state(&mut socket, &connected_players, &party, &[]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kicked_player_cannot_rejoin_by_code() {
+        let mut parties = Parties::new(4);
+        let host_id = Uuid::now_v7();
+        let target_id = Uuid::now_v7();
+        let party = parties.create(host_id).unwrap();
+        parties.join_by_code(&party.join_code, target_id).unwrap();
+        parties.kick(&party.party_id, &host_id, &target_id).unwrap();
+
+        assert!(parties.join_by_code(&party.join_code, target_id).is_err());
+        assert!(!parties.get(&party.party_id).unwrap().is_member(&target_id));
+
+        // A fresh invite from the host still lets them back in
+        parties.invite(&party.party_id, &host_id, target_id).unwrap();
+        assert!(parties.accept_invite(&party.party_id, target_id).is_ok());
+    }
+}
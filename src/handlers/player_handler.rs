@@ -1,5 +1,6 @@
 use bevy::prelude::*;
 
+use bevy_matchbox::prelude::PeerId;
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::Mutex;
@@ -9,6 +10,7 @@ use uuid::Uuid;
 
 use crate::{
     ConnectedPlayers,
+    PacketAllStates,
     PlayerInfo,
     PlayerInfoStorage,
     PlayerHeartBeatStatus,
@@ -26,6 +28,8 @@ impl ConnectedPlayers {
         let mut players = self.players.lock().unwrap();
         players.insert(player_id, PlayerHeartBeatStatus {
             last_heartbeat: Instant::now(),
+            peer_id: None,
+            last_states: None,
         });
     }
 
@@ -36,6 +40,8 @@ impl ConnectedPlayers {
                 let mut players = self.players.lock().unwrap();
                 players.insert(uuid, PlayerHeartBeatStatus {
                     last_heartbeat: Instant::now(),
+                    peer_id: None,
+                    last_states: None,
                 });
                 println!("Player {} added.", uuid);
             }
@@ -60,6 +66,72 @@ impl ConnectedPlayers {
         }
     }
 
+    // Remember which matchbox peer a player is talking through so replies can be addressed. A player
+    // still bound to another live peer keeps it, so nobody can take over an id by claiming it
+    pub fn bind_peer(&self, player_id: Uuid, peer_id: PeerId, live_peers: &[PeerId]) -> bool {
+        let mut players = self.players.lock().unwrap();
+        let player_info = players.entry(player_id).or_insert_with(|| PlayerHeartBeatStatus {
+            last_heartbeat: Instant::now(),
+            peer_id: None,
+            last_states: None,
+        });
+        if let Some(bound) = player_info.peer_id.filter(|bound| *bound != peer_id && live_peers.contains(bound)) {
+            warn!("Player {} is already bound to peer {}, refusing peer {}", player_id, bound, peer_id);
+            return false;
+        }
+        player_info.peer_id = Some(peer_id);
+        player_info.last_heartbeat = Instant::now();
+        true
+    }
+
+    // Look up the matchbox peer for a player, if one has been recorded
+    pub fn get_peer(&self, player_id: &Uuid) -> Option<PeerId> {
+        let players = self.players.lock().unwrap();
+        players.get(player_id).and_then(|player_info| player_info.peer_id)
+    }
+
+    // Keep the latest reported client states around for features that read them between queries
+    pub fn set_states(&self, all_states: &PacketAllStates) {
+        let Ok(player_id) = Uuid::parse_str(&all_states.player_id) else {
+            return;
+        };
+        let mut players = self.players.lock().unwrap();
+        if let Some(player_info) = players.get_mut(&player_id) {
+            player_info.last_states = Some(all_states.clone());
+        }
+    }
+
+    pub fn get_states(&self, player_id: &Uuid) -> Option<PacketAllStates> {
+        let players = self.players.lock().unwrap();
+        players.get(player_id).and_then(|player_info| player_info.last_states.clone())
+    }
+
+    pub fn get_play_style(&self, player_id: &Uuid) -> Option<String> {
+        self.get_states(player_id).map(|all_states| all_states.state_game_play_style)
+    }
+
+    // Parse a player id sent by a client and make sure it arrived from that player's own peer
+    pub fn verify_peer(&self, player_id: &str, peer_id: PeerId) -> Option<Uuid> {
+        let uuid = Uuid::parse_str(player_id).ok()?;
+        if self.get_peer(&uuid) == Some(peer_id) {
+            Some(uuid)
+        } else {
+            warn!("Player {} is not registered on peer {}", player_id, peer_id);
+            None
+        }
+    }
+
+    pub fn is_connected(&self, player_id: &Uuid) -> bool {
+        let players = self.players.lock().unwrap();
+        players.contains_key(player_id)
+    }
+
+    // Snapshot of every connected player id
+    pub fn player_ids(&self) -> Vec<Uuid> {
+        let players = self.players.lock().unwrap();
+        players.keys().copied().collect()
+    }
+
     // Check for players that have timed out and return their UUIDs
     pub fn check_timeouts(&self, timeout_duration: Duration) -> Vec<Uuid> {
         let players = self.players.lock().unwrap();
@@ -72,6 +144,12 @@ impl ConnectedPlayers {
     }
 }
 
+impl Default for ConnectedPlayers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PlayerInfo {
     pub fn new(player_id: String, player_email: String, player_username: String) -> Self {
         PlayerInfo {
@@ -179,3 +257,9 @@ impl PlayerInfoStorage {
     }
 }
 
+impl Default for PlayerInfoStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
@@ -0,0 +1,452 @@
+use bevy::prelude::*;
+
+use bevy_matchbox::prelude::*;
+use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+use sqlx::{MySqlConnection, MySqlPool, Error};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::{
+    ClientRequestEvent,
+    ConnectedPlayers,
+    DatabasePool,
+    GameResult,
+    GameResultRecordedEvent,
+    MapSetStats,
+    PacketGetPlayerStats,
+    PacketPlayerStats,
+    PlayerStats,
+    PlayerStatsCache,
+    RunTrigger,
+};
+
+use crate::handlers::signaling_server_handler::send_peer_message;
+
+fn average(total_strokes: i32, holes_played: i32) -> f32 {
+    if holes_played == 0 {
+        0.0
+    } else {
+        total_strokes as f32 / holes_played as f32
+    }
+}
+
+impl PlayerStats {
+    pub fn new(player_id: Uuid) -> Self {
+        Self {
+            player_id,
+            ..Default::default()
+        }
+    }
+
+    // Folds one finished game into the totals; results must be applied in the order they were played
+    pub fn record(&mut self, result: &GameResult) {
+        let holes_played = result.hole_strokes.len() as i32;
+        self.games_played += 1;
+        self.holes_played += holes_played;
+        self.total_strokes += result.total_strokes;
+        self.holes_in_one += result.hole_strokes.iter().filter(|(_, strokes)| *strokes == 1).count() as i32;
+        for (level, strokes) in result.hole_strokes.iter() {
+            if let Some(par) = result.par(*level) {
+                self.par_holes_played += 1;
+                self.strokes_to_par += strokes - par;
+                if *strokes < par {
+                    self.under_par_holes += 1;
+                }
+            }
+        }
+
+        // Solo games neither extend nor break a win streak
+        if result.player_count > 1 {
+            if result.placement == 1 {
+                self.games_won += 1;
+                self.current_win_streak += 1;
+                self.best_win_streak = self.best_win_streak.max(self.current_win_streak);
+            } else {
+                self.current_win_streak = 0;
+            }
+        }
+
+        let idx = match self.map_sets.iter().position(|map_set| map_set.map_set_id == result.map_set_id) {
+            Some(idx) => idx,
+            None => {
+                self.map_sets.push(MapSetStats {
+                    map_set_id: result.map_set_id,
+                    ..Default::default()
+                });
+                self.map_sets.len() - 1
+            }
+        };
+        let map_set = &mut self.map_sets[idx];
+        map_set.games_played += 1;
+        map_set.holes_played += holes_played;
+        map_set.total_strokes += result.total_strokes;
+        map_set.best_round = Some(map_set.best_round.map_or(result.total_strokes, |best| best.min(result.total_strokes)));
+        if let Some(to_par) = result.to_par() {
+            map_set.best_round_to_par = Some(map_set.best_round_to_par.map_or(to_par, |best| best.min(to_par)));
+        }
+        self.refresh_averages();
+    }
+
+    pub fn refresh_averages(&mut self) {
+        self.average_strokes_per_hole = average(self.total_strokes, self.holes_played);
+        self.average_to_par = average(self.strokes_to_par, self.par_holes_played);
+        for map_set in self.map_sets.iter_mut() {
+            map_set.average_strokes_per_hole = average(map_set.total_strokes, map_set.holes_played);
+        }
+    }
+}
+
+impl PlayerStatsCache {
+    pub fn new() -> Self {
+        Self {
+            stats: HashMap::new(),
+        }
+    }
+}
+
+impl Default for PlayerStatsCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Reads the stored totals; `for_update` locks the rows for the rest of the surrounding transaction
+async fn fetch_player_stats(
+    conn: &mut MySqlConnection,
+    player_id: &Uuid,
+    for_update: bool,
+) -> Result<PlayerStats, Error> {
+    let lock = if for_update { " FOR UPDATE" } else { "" };
+    let mut stats = PlayerStats::new(*player_id);
+
+    type StatsRow = (i32, i32, i32, i32, i32, i32, i32, i32, i32, i32);
+    let row: Option<StatsRow> = sqlx::query_as(&format!(
+        "SELECT games_played, games_won, holes_played, holes_in_one, total_strokes, current_win_streak, best_win_streak,
+            par_holes_played, strokes_to_par, under_par_holes
+         FROM player_stats WHERE player_id = UUID_TO_BIN(?){}",
+        lock,
+    ))
+    .bind(player_id.to_string())
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some(row) = row {
+        (
+            stats.games_played,
+            stats.games_won,
+            stats.holes_played,
+            stats.holes_in_one,
+            stats.total_strokes,
+            stats.current_win_streak,
+            stats.best_win_streak,
+            stats.par_holes_played,
+            stats.strokes_to_par,
+            stats.under_par_holes,
+        ) = row;
+    }
+
+    type MapSetStatsRow = (Uuid, i32, i32, i32, Option<i32>, Option<i32>);
+    let rows: Vec<MapSetStatsRow> = sqlx::query_as(&format!(
+        "SELECT map_set_id, games_played, holes_played, total_strokes, best_round, best_round_to_par
+         FROM player_map_set_stats WHERE player_id = UUID_TO_BIN(?){}",
+        lock,
+    ))
+    .bind(player_id.to_string())
+    .fetch_all(&mut *conn)
+    .await?;
+    stats.map_sets = rows
+        .into_iter()
+        .map(|(map_set_id, games_played, holes_played, total_strokes, best_round, best_round_to_par)| MapSetStats {
+            map_set_id,
+            games_played,
+            holes_played,
+            total_strokes,
+            best_round,
+            best_round_to_par,
+            average_strokes_per_hole: 0.0,
+        })
+        .collect();
+    stats.refresh_averages();
+    Ok(stats)
+}
+
+async fn save_player_stats(
+    conn: &mut MySqlConnection,
+    stats: &PlayerStats,
+) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT INTO player_stats (player_id, games_played, games_won, holes_played, holes_in_one,
+            total_strokes, current_win_streak, best_win_streak, par_holes_played, strokes_to_par, under_par_holes)
+         VALUES (UUID_TO_BIN(?), ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+         ON DUPLICATE KEY UPDATE games_played = VALUES(games_played), games_won = VALUES(games_won),
+            holes_played = VALUES(holes_played), holes_in_one = VALUES(holes_in_one),
+            total_strokes = VALUES(total_strokes), current_win_streak = VALUES(current_win_streak),
+            best_win_streak = VALUES(best_win_streak), par_holes_played = VALUES(par_holes_played),
+            strokes_to_par = VALUES(strokes_to_par), under_par_holes = VALUES(under_par_holes)",
+    )
+    .bind(stats.player_id.to_string())
+    .bind(stats.games_played)
+    .bind(stats.games_won)
+    .bind(stats.holes_played)
+    .bind(stats.holes_in_one)
+    .bind(stats.total_strokes)
+    .bind(stats.current_win_streak)
+    .bind(stats.best_win_streak)
+    .bind(stats.par_holes_played)
+    .bind(stats.strokes_to_par)
+    .bind(stats.under_par_holes)
+    .execute(&mut *conn)
+    .await?;
+
+    for map_set in stats.map_sets.iter() {
+        sqlx::query(
+            "INSERT INTO player_map_set_stats (player_id, map_set_id, games_played, holes_played, total_strokes,
+                best_round, best_round_to_par)
+             VALUES (UUID_TO_BIN(?), UUID_TO_BIN(?), ?, ?, ?, ?, ?)
+             ON DUPLICATE KEY UPDATE games_played = VALUES(games_played), holes_played = VALUES(holes_played),
+                total_strokes = VALUES(total_strokes), best_round = VALUES(best_round),
+                best_round_to_par = VALUES(best_round_to_par)",
+        )
+        .bind(stats.player_id.to_string())
+        .bind(map_set.map_set_id.to_string())
+        .bind(map_set.games_played)
+        .bind(map_set.holes_played)
+        .bind(map_set.total_strokes)
+        .bind(map_set.best_round)
+        .bind(map_set.best_round_to_par)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+async fn record_game_result(
+    pool: &MySqlPool,
+    result: &GameResult,
+) -> Result<PlayerStats, Error> {
+    let mut tx = pool.begin().await?;
+    // Locking a row that does not exist yet locks nothing, so a player's first two results
+    // could both start from empty totals; create the row first so there is one to lock
+    sqlx::query("INSERT IGNORE INTO player_stats (player_id) VALUES (UUID_TO_BIN(?))")
+        .bind(result.player_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+    let mut stats = fetch_player_stats(&mut tx, &result.player_id, true).await?;
+    stats.record(result);
+    save_player_stats(&mut tx, &stats).await?;
+    tx.commit().await?;
+    Ok(stats)
+}
+
+// Recomputes a player's stats from scratch out of every validated game result
+async fn rebuild_player_stats(
+    pool: &MySqlPool,
+    player_id: &Uuid,
+) -> Result<PlayerStats, Error> {
+    type ResultRow = (Uuid, Option<Uuid>, Uuid, Option<i32>, i32, i32, i32, bool);
+    let rows: Vec<ResultRow> = sqlx::query_as(
+        "SELECT game_result_id, session_id, map_set_id, map_set_revision, total_strokes, placement, player_count, validated
+         FROM game_result WHERE player_id = UUID_TO_BIN(?) AND validated = 1
+         ORDER BY completed ASC",
+    )
+    .bind(player_id.to_string())
+    .fetch_all(pool)
+    .await?;
+
+    let hole_rows: Vec<(Uuid, i32, i32, Option<i32>)> = sqlx::query_as(
+        "SELECT h.game_result_id, h.level, h.strokes, h.par
+         FROM game_result_hole h JOIN game_result r ON r.game_result_id = h.game_result_id
+         WHERE r.player_id = UUID_TO_BIN(?) AND r.validated = 1
+         ORDER BY h.level ASC",
+    )
+    .bind(player_id.to_string())
+    .fetch_all(pool)
+    .await?;
+    let mut hole_strokes: HashMap<Uuid, Vec<(i32, i32)>> = HashMap::new();
+    let mut hole_pars: HashMap<Uuid, Vec<(i32, i32)>> = HashMap::new();
+    for (game_result_id, level, strokes, par) in hole_rows {
+        hole_strokes.entry(game_result_id).or_default().push((level, strokes));
+        if let Some(par) = par {
+            hole_pars.entry(game_result_id).or_default().push((level, par));
+        }
+    }
+
+    let mut stats = PlayerStats::new(*player_id);
+    for (game_result_id, session_id, map_set_id, map_set_revision, total_strokes, placement, player_count, validated) in rows {
+        stats.record(&GameResult {
+            game_result_id,
+            session_id,
+            map_set_id,
+            map_set_revision,
+            player_id: *player_id,
+            total_strokes,
+            hole_strokes: hole_strokes.remove(&game_result_id).unwrap_or_default(),
+            hole_pars: hole_pars.remove(&game_result_id).unwrap_or_default(),
+            placement,
+            player_count,
+            validated,
+        });
+    }
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM player_map_set_stats WHERE player_id = UUID_TO_BIN(?)")
+        .bind(player_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+    save_player_stats(&mut tx, &stats).await?;
+    tx.commit().await?;
+    Ok(stats)
+}
+
+fn cache_player_stats(world: &mut World, stats: Vec<PlayerStats>) {
+    if let Some(mut cache) = world.get_resource_mut::<PlayerStatsCache>() {
+        for stats in stats {
+            cache.stats.insert(stats.player_id, stats);
+        }
+    } else {
+        info!("Failed to access player_stats_cache resource");
+    }
+}
+
+pub fn player_stats_record_system(
+    mut event_reader: EventReader<GameResultRecordedEvent>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    for event in event_reader.read() {
+        let results = event.results.clone();
+        let pool = pool.0.clone();
+        // Spawn the background task using bevy_tokio_tasks
+        runtime.spawn_background_task(move |ctx| {
+            record_player_stats_async(results, pool, ctx)
+        });
+    }
+}
+
+pub async fn record_player_stats_async(
+    results: Vec<GameResult>,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let mut updated = Vec::new();
+    // Unvalidated self-reported scores are stored but never counted
+    for result in results.iter().filter(|result| result.validated) {
+        match record_game_result(&pool, result).await {
+            Ok(stats) => updated.push(stats),
+            Err(err) => {
+                let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+                eprintln!("Failed to update player stats: {:?}", err_for_ctx);
+                ctx.run_on_main_thread(move |_ctx| {
+                    info!("Failed to update player stats in the task: {:?}", err_for_ctx);
+                })
+                .await;
+            }
+        }
+    }
+    ctx.run_on_main_thread(move |ctx| {
+        cache_player_stats(ctx.world, updated);
+    })
+    .await;
+}
+
+pub fn player_stats_rebuild(
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+    connected_players: Res<ConnectedPlayers>,
+    mut run_trigger: ResMut<RunTrigger>,
+) {
+    let player_ids = connected_players.player_ids();
+    info!("player_stats_rebuild: {} players", player_ids.len());
+    let pool = pool.0.clone();
+    runtime.spawn_background_task(move |ctx| {
+        player_stats_rebuild_async(player_ids, pool, ctx)
+    });
+    run_trigger.set_target("player_stats_rebuild", false);
+}
+
+pub async fn player_stats_rebuild_async(
+    player_ids: Vec<Uuid>,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let mut rebuilt = Vec::new();
+    for player_id in player_ids.iter() {
+        match rebuild_player_stats(&pool, player_id).await {
+            Ok(stats) => rebuilt.push(stats),
+            Err(err) => {
+                let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+                eprintln!("Failed to rebuild player stats: {:?}", err_for_ctx);
+                ctx.run_on_main_thread(move |_ctx| {
+                    info!("Failed to rebuild player stats in the task: {:?}", err_for_ctx);
+                })
+                .await;
+            }
+        }
+    }
+    ctx.run_on_main_thread(move |ctx| {
+        cache_player_stats(ctx.world, rebuilt);
+    })
+    .await;
+}
+
+pub async fn send_player_stats_async(
+    player_id: Uuid,
+    peer: PeerId,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let stats = match pool.acquire().await {
+        Ok(mut conn) => fetch_player_stats(&mut conn, &player_id, false).await,
+        Err(err) => Err(err),
+    };
+    let stats = match stats {
+        Ok(stats) => stats,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to execute query: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to execute query in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            return;
+        }
+    };
+
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            let packet = PacketPlayerStats { stats: stats.clone() };
+            send_peer_message(&mut socket, peer, &player_id, "PlayerStats", &packet);
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+        cache_player_stats(ctx.world, vec![stats]);
+    })
+    .await;
+}
+
+pub fn player_stats_request_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    for event in event_reader.read() {
+        if event.command != "GetPlayerStats" {
+            continue;
+        }
+        let packet = match serde_json::from_str::<PacketGetPlayerStats>(&event.payload) {
+            Ok(packet) => packet,
+            Err(err) => {
+                error!("Failed to deserialize PacketGetPlayerStats from JSON: {:?}", err);
+                continue;
+            }
+        };
+        let Ok(player_id) = Uuid::parse_str(&packet.player_id) else {
+            warn!("GetPlayerStats carried an invalid player_id {:?}", packet.player_id);
+            continue;
+        };
+        let pool = pool.0.clone();
+        let peer = event.peer;
+        runtime.spawn_background_task(move |ctx| {
+            send_player_stats_async(player_id, peer, pool, ctx)
+        });
+    }
+}
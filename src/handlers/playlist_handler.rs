@@ -0,0 +1,342 @@
+use bevy::prelude::*;
+
+use bevy_matchbox::prelude::*;
+use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+use sqlx::{MySqlPool, Error};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    ClientRequestEvent,
+    ConnectedPlayers,
+    DatabasePool,
+    GameSession,
+    GameSessions,
+    MapSet,
+    MapSets,
+    PacketPartyPlaylistStart,
+    PacketPersonalMapSetDelete,
+    PacketPersonalMapSets,
+    PacketPersonalMapSetsRequest,
+    Parties,
+    PersonalMapSet,
+    PlaylistHole,
+    Rooms,
+};
+
+use crate::handlers::{
+    game_session_handler::broadcast_session_state,
+    party_handler::send_party_rejection,
+    signaling_server_handler::send_player_message,
+};
+
+const MAX_PLAYLIST_HOLES: usize = 18; // A map set has room for 18 levels
+const MAX_PERSONAL_MAP_SETS: i64 = 20;
+const MAX_PLAYLIST_NAME_LEN: usize = 64;
+const DEFAULT_PLAYLIST_NAME: &str = "Party Playlist";
+
+// An unsaved map set holding the playlist as levels 1 to n. Files, hole metadata and manifest entries
+// are copied from the published source sets, so repeats simply take another level
+pub fn build_playlist_map_set(map_sets: &MapSets, map_set_name: String, holes: &[PlaylistHole]) -> Result<MapSet, String> {
+    if holes.is_empty() || holes.len() > MAX_PLAYLIST_HOLES {
+        return Err(format!("Playlists hold 1 to {} holes", MAX_PLAYLIST_HOLES));
+    }
+    let mut map_set = MapSet::new(Uuid::now_v7(), map_set_name, 1, holes.len() as i32);
+    let mut file_paths: Vec<Option<String>> = vec![None; MAX_PLAYLIST_HOLES];
+    let mut metadata = Vec::new();
+    for (idx, hole) in holes.iter().enumerate() {
+        let level = idx as i32 + 1;
+        let Some(source) = map_sets.playable(&hole.map_set_id) else {
+            return Err(format!("Map set {} is not available", hole.map_set_id));
+        };
+        let Some((_, file_path)) = source.levels().into_iter().find(|(source_level, _)| *source_level == hole.level) else {
+            return Err(format!("Map set {:?} has no level {}", source.map_set_name, hole.level));
+        };
+        file_paths[idx] = Some(file_path);
+        if let Some(source_hole) = source.hole(hole.level) {
+            let mut source_hole = source_hole.clone();
+            source_hole.level = level;
+            metadata.push(source_hole);
+        }
+        if let Some(asset) = source.assets.iter().find(|asset| asset.level == hole.level) {
+            let mut asset = asset.clone();
+            asset.level = level;
+            map_set.assets.push(asset);
+        }
+    }
+    map_set.set_file_paths(&file_paths)?;
+    map_set.set_holes(metadata);
+    map_set.validate_levels()?;
+    Ok(map_set)
+}
+
+fn check_playlist_name(map_set_name: &str) -> Result<String, String> {
+    let map_set_name = map_set_name.trim();
+    if map_set_name.is_empty() || map_set_name.chars().count() > MAX_PLAYLIST_NAME_LEN {
+        return Err(format!("Map set names must be 1 to {} characters", MAX_PLAYLIST_NAME_LEN));
+    }
+    Ok(String::from(map_set_name))
+}
+
+fn send_personal_map_sets(
+    socket: &mut MatchboxSocket<SingleChannel>,
+    connected_players: &ConnectedPlayers,
+    player_id: &Uuid,
+    map_sets: Vec<PersonalMapSet>,
+    reason: Option<String>,
+) {
+    if let Some(reason) = reason.as_ref() {
+        info!("Personal map set change from player {} refused: {}", player_id, reason);
+    }
+    let packet = PacketPersonalMapSets {
+        map_sets,
+        reason,
+    };
+    send_player_message(socket, connected_players, player_id, "PersonalMapSets", &packet);
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn playlist_request_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    map_sets: Res<MapSets>,
+    parties: Res<Parties>,
+    mut game_sessions: ResMut<GameSessions>,
+    mut rooms: ResMut<Rooms>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    for event in event_reader.read() {
+        match event.command.as_str() {
+            "PartyPlaylistStart" => {
+                let packet = match serde_json::from_str::<PacketPartyPlaylistStart>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketPartyPlaylistStart from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let party = Uuid::parse_str(&packet.party_id).ok().and_then(|party_id| parties.get(&party_id));
+                let result = match party {
+                    None => Err(String::from("Unknown party")),
+                    Some(party) if party.host_id != player_id => Err(String::from("Only the party host can start a playlist")),
+                    Some(party) => {
+                        let map_set_name = packet.save_as.as_deref().map_or(Ok(String::from(DEFAULT_PLAYLIST_NAME)), check_playlist_name);
+                        map_set_name
+                            .and_then(|map_set_name| build_playlist_map_set(&map_sets, map_set_name, &packet.holes))
+                            .and_then(|map_set| {
+                                if let Some(missing) = party.members.iter().find(|id| connected_players.get_peer(id).is_none()) {
+                                    return Err(format!("Player {} is not connected", missing));
+                                }
+                                if party.members.iter().any(|id| game_sessions.session_for_player(id).is_some()) {
+                                    return Err(String::from("A party member is already in a game session"));
+                                }
+                                let play_style = connected_players.get_play_style(&player_id);
+                                let mut session = GameSession::new(&map_set, party.members.clone(), String::new(), play_style)?;
+                                session.playlist = packet.holes.clone();
+                                game_sessions.insert_in_new_room(session, &mut rooms).map(|session| (map_set, session))
+                            })
+                    }
+                };
+                let (map_set, session) = match result {
+                    Ok(started) => started,
+                    Err(reason) => {
+                        send_party_rejection(&mut socket, &connected_players, &player_id, Some(packet.party_id.as_str()), reason);
+                        continue;
+                    }
+                };
+                info!("Party [{}] started a {} hole playlist as game session [{}]", packet.party_id, session.levels.len(), session.session_id);
+                broadcast_session_state(&mut socket, &connected_players, &session);
+
+                if packet.save_as.is_some() {
+                    let personal = PersonalMapSet {
+                        map_set_id: map_set.map_set_id,
+                        player_id,
+                        map_set_name: map_set.map_set_name,
+                        holes: packet.holes,
+                        created: OffsetDateTime::now_utc(),
+                    };
+                    let pool = pool.0.clone();
+                    // Spawn the background task using bevy_tokio_tasks
+                    runtime.spawn_background_task(move |ctx| {
+                        save_personal_map_set_async(personal, pool, ctx)
+                    });
+                }
+            }
+            "PersonalMapSetsRequest" => {
+                let packet = match serde_json::from_str::<PacketPersonalMapSetsRequest>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketPersonalMapSetsRequest from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let pool = pool.0.clone();
+                // Spawn the background task using bevy_tokio_tasks
+                runtime.spawn_background_task(move |ctx| {
+                    send_personal_map_sets_async(player_id, None, pool, ctx)
+                });
+            }
+            "PersonalMapSetDelete" => {
+                let packet = match serde_json::from_str::<PacketPersonalMapSetDelete>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketPersonalMapSetDelete from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let Ok(map_set_id) = Uuid::parse_str(&packet.map_set_id) else {
+                    send_personal_map_sets(&mut socket, &connected_players, &player_id, Vec::new(), Some(String::from("Malformed map set id")));
+                    continue;
+                };
+                let pool = pool.0.clone();
+                // Spawn the background task using bevy_tokio_tasks
+                runtime.spawn_background_task(move |ctx| {
+                    delete_personal_map_set_async(player_id, map_set_id, pool, ctx)
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn fetch_personal_map_sets(pool: &MySqlPool, player_id: &Uuid) -> Result<Vec<PersonalMapSet>, Error> {
+    let rows: Vec<(Uuid, String, String, OffsetDateTime)> = sqlx::query_as(
+        "SELECT map_set_id, map_set_name, holes, created FROM personal_map_set
+         WHERE player_id = UUID_TO_BIN(?) ORDER BY map_set_name ASC",
+    )
+    .bind(player_id.to_string())
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(map_set_id, map_set_name, holes, created)| match serde_json::from_str(&holes) {
+            Ok(holes) => Some(PersonalMapSet {
+                map_set_id,
+                player_id: *player_id,
+                map_set_name,
+                holes,
+                created,
+            }),
+            Err(err) => {
+                warn!("Personal map set {} has unreadable holes: {:?}", map_set_id, err);
+                None
+            }
+        })
+        .collect())
+}
+
+// Ok(Some(reason)) when the player is at the limit; saving under an existing name replaces that set
+async fn save_personal_map_set(pool: &MySqlPool, personal: &PersonalMapSet) -> Result<Option<String>, Error> {
+    let (others,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM personal_map_set WHERE player_id = UUID_TO_BIN(?) AND map_set_name <> ?",
+    )
+    .bind(personal.player_id.to_string())
+    .bind(&personal.map_set_name)
+    .fetch_one(pool)
+    .await?;
+    if others >= MAX_PERSONAL_MAP_SETS {
+        return Ok(Some(format!("You can keep up to {} personal map sets", MAX_PERSONAL_MAP_SETS)));
+    }
+    sqlx::query(
+        "INSERT INTO personal_map_set (map_set_id, player_id, map_set_name, holes, created)
+         VALUES (UUID_TO_BIN(?), UUID_TO_BIN(?), ?, ?, ?)
+         ON DUPLICATE KEY UPDATE holes = VALUES(holes), created = VALUES(created)",
+    )
+    .bind(personal.map_set_id.to_string())
+    .bind(personal.player_id.to_string())
+    .bind(&personal.map_set_name)
+    .bind(serde_json::to_string(&personal.holes).unwrap_or_else(|_| String::from("[]")))
+    .bind(personal.created)
+    .execute(pool)
+    .await?;
+    Ok(None)
+}
+
+pub async fn save_personal_map_set_async(
+    personal: PersonalMapSet,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let reason = match save_personal_map_set(&pool, &personal).await {
+        Ok(reason) => reason,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to save personal map set: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to save personal map set in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            Some(String::from("Failed to save the map set"))
+        }
+    };
+    send_personal_map_sets_async(personal.player_id, reason, pool, ctx).await;
+}
+
+pub async fn delete_personal_map_set_async(
+    player_id: Uuid,
+    map_set_id: Uuid,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let result = sqlx::query("DELETE FROM personal_map_set WHERE map_set_id = UUID_TO_BIN(?) AND player_id = UUID_TO_BIN(?)")
+        .bind(map_set_id.to_string())
+        .bind(player_id.to_string())
+        .execute(&pool)
+        .await;
+    let reason = match result {
+        Ok(done) if done.rows_affected() == 0 => Some(String::from("Unknown map set")),
+        Ok(_) => None,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to delete personal map set: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to delete personal map set in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            Some(String::from("Failed to delete the map set"))
+        }
+    };
+    send_personal_map_sets_async(player_id, reason, pool, ctx).await;
+}
+
+pub async fn send_personal_map_sets_async(
+    player_id: Uuid,
+    reason: Option<String>,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let map_sets = match fetch_personal_map_sets(&pool, &player_id).await {
+        Ok(map_sets) => map_sets,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to execute query: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to execute query in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            return;
+        }
+    };
+    ctx.run_on_main_thread(move |ctx| {
+        let Some(connected_players) = ctx.world.get_resource::<ConnectedPlayers>().cloned() else {
+            return;
+        };
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            send_personal_map_sets(&mut socket, &connected_players, &player_id, map_sets, reason);
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+    })
+    .await;
+}
@@ -0,0 +1,915 @@
+use bevy::prelude::*;
+
+use bevy_matchbox::prelude::*;
+use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+use sqlx::{MySqlConnection, MySqlPool, Error};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    ClientRequestEvent,
+    ConnectedPlayers,
+    DatabasePool,
+    GameResult,
+    GameResultRecordedEvent,
+    GameSessions,
+    MatchmakingQueue,
+    PacketGetPlayerRating,
+    PacketPlayerRating,
+    PacketRankedLeaderBoard,
+    PacketRankedLeaderBoardRequest,
+    PlayerRating,
+    PlayerRatings,
+    RankedLeaderBoardEntry,
+    RatingHistoryEntry,
+    Seasons,
+};
+
+use crate::handlers::signaling_server_handler::send_peer_message;
+
+// Glicko-2 constants, ratings are stored on the familiar 1500 scale
+const GLICKO_SCALE: f64 = 173.7178;
+pub const DEFAULT_RATING: f64 = 1500.0;
+const DEFAULT_DEVIATION: f64 = 350.0;
+const DEFAULT_VOLATILITY: f64 = 0.06;
+const MIN_DEVIATION: f64 = 30.0;
+const SYSTEM_TAU: f64 = 0.5;
+const CONVERGENCE_TOLERANCE: f64 = 0.000001;
+
+// Accounts created by insert_new_player have no rating rows yet, so every play style they
+// pick up starts here and stays provisional (kept off the ranked board) for this many games
+pub const PROVISIONAL_GAMES: i32 = 10;
+// Games needed in the current season before a player shows up on the ranked board
+pub const SEASON_MIN_GAMES: i32 = 3;
+
+// Inactive players lose confidence and, above the default rating, a few points per interval
+const DECAY_AFTER_DAYS: i64 = 14;
+const DECAY_INTERVAL_DAYS: i64 = 7;
+const DECAY_POINTS: f64 = 15.0;
+
+const HISTORY_LIMIT: i64 = 20;
+const DEFAULT_PAGE_SIZE: u32 = 10;
+const MAX_PAGE_SIZE: u32 = 50;
+
+fn g(phi: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt()
+}
+
+fn expected_score(mu: f64, opponent_mu: f64, opponent_phi: f64) -> f64 {
+    1.0 / (1.0 + (-g(opponent_phi) * (mu - opponent_mu)).exp())
+}
+
+impl PlayerRating {
+    pub fn new(player_id: Uuid, play_style: &str) -> Self {
+        Self {
+            player_id,
+            play_style: String::from(play_style),
+            rating: DEFAULT_RATING,
+            deviation: DEFAULT_DEVIATION,
+            volatility: DEFAULT_VOLATILITY,
+            games_played: 0,
+            provisional: true,
+        }
+    }
+
+    fn mu(&self) -> f64 {
+        (self.rating - DEFAULT_RATING) / GLICKO_SCALE
+    }
+
+    fn phi(&self) -> f64 {
+        self.deviation / GLICKO_SCALE
+    }
+
+    // Glicko-2 update for one rating period; each outcome is (opponent, score, weight)
+    fn updated(&self, outcomes: &[(&PlayerRating, f64, f64)]) -> PlayerRating {
+        let mu = self.mu();
+        let phi = self.phi();
+
+        let mut variance_inv = 0.0;
+        let mut improvement = 0.0;
+        for (opponent, score, weight) in outcomes.iter() {
+            let g_phi = g(opponent.phi());
+            let expected = expected_score(mu, opponent.mu(), opponent.phi());
+            variance_inv += weight * g_phi * g_phi * expected * (1.0 - expected);
+            improvement += weight * g_phi * (score - expected);
+        }
+        if variance_inv == 0.0 {
+            return self.clone();
+        }
+        let v = 1.0 / variance_inv;
+        let delta = v * improvement;
+
+        // New volatility through the Illinois algorithm from the Glicko-2 paper
+        let a = (self.volatility * self.volatility).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            ex * (delta * delta - phi * phi - v - ex) / (2.0 * (phi * phi + v + ex).powi(2))
+                - (x - a) / (SYSTEM_TAU * SYSTEM_TAU)
+        };
+        let mut lower = a;
+        let mut upper = if delta * delta > phi * phi + v {
+            (delta * delta - phi * phi - v).ln()
+        } else {
+            let mut k = 1.0;
+            while f(a - k * SYSTEM_TAU) < 0.0 {
+                k += 1.0;
+            }
+            a - k * SYSTEM_TAU
+        };
+        let mut f_lower = f(lower);
+        let mut f_upper = f(upper);
+        while (upper - lower).abs() > CONVERGENCE_TOLERANCE {
+            let next = lower + (lower - upper) * f_lower / (f_upper - f_lower);
+            let f_next = f(next);
+            if f_next * f_upper <= 0.0 {
+                lower = upper;
+                f_lower = f_upper;
+            } else {
+                f_lower /= 2.0;
+            }
+            upper = next;
+            f_upper = f_next;
+        }
+        let volatility = (lower / 2.0).exp();
+
+        let phi_star = (phi * phi + volatility * volatility).sqrt();
+        let new_phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+        let new_mu = mu + new_phi * new_phi * improvement;
+
+        let games_played = self.games_played + 1;
+        PlayerRating {
+            player_id: self.player_id,
+            play_style: self.play_style.clone(),
+            rating: new_mu * GLICKO_SCALE + DEFAULT_RATING,
+            deviation: (new_phi * GLICKO_SCALE).clamp(MIN_DEVIATION, DEFAULT_DEVIATION),
+            volatility,
+            games_played,
+            provisional: games_played < PROVISIONAL_GAMES,
+        }
+    }
+
+    // One idle interval: the deviation grows as for a period without games and the rating
+    // drifts back towards the default
+    fn decayed(&self) -> PlayerRating {
+        let phi = self.phi();
+        let deviation = ((phi * phi + self.volatility * self.volatility).sqrt() * GLICKO_SCALE).min(DEFAULT_DEVIATION);
+        let rating = if self.rating > DEFAULT_RATING {
+            (self.rating - DECAY_POINTS).max(DEFAULT_RATING)
+        } else {
+            self.rating
+        };
+        PlayerRating {
+            rating,
+            deviation,
+            ..self.clone()
+        }
+    }
+}
+
+// Rates a free-for-all game as a round robin: every pair of players counts as a win, loss or
+// draw by placement, weighted so the whole game is worth one game per player
+pub fn rate_free_for_all(players: &[(PlayerRating, i32)]) -> Vec<PlayerRating> {
+    if players.len() < 2 {
+        return players.iter().map(|(rating, _)| rating.clone()).collect();
+    }
+    let weight = 1.0 / (players.len() - 1) as f64;
+    players
+        .iter()
+        .enumerate()
+        .map(|(idx, (rating, placement))| {
+            let outcomes: Vec<(&PlayerRating, f64, f64)> = players
+                .iter()
+                .enumerate()
+                .filter(|(other_idx, _)| *other_idx != idx)
+                .map(|(_, (opponent, opponent_placement))| {
+                    let score = if placement < opponent_placement {
+                        1.0
+                    } else if placement == opponent_placement {
+                        0.5
+                    } else {
+                        0.0
+                    };
+                    (opponent, score, weight)
+                })
+                .collect();
+            rating.updated(&outcomes)
+        })
+        .collect()
+}
+
+impl PlayerRatings {
+    pub fn new() -> Self {
+        Self {
+            ratings: HashMap::new(),
+            generation: 0,
+        }
+    }
+
+    pub fn get_or_default(&self, player_id: &Uuid, play_style: &str) -> PlayerRating {
+        self.ratings
+            .get(&(*player_id, String::from(play_style)))
+            .cloned()
+            .unwrap_or_else(|| PlayerRating::new(*player_id, play_style))
+    }
+
+    // Average rating of a group and whether any member is still provisional
+    pub fn group_rating(&self, player_ids: &[Uuid], play_style: &str) -> (f64, bool) {
+        if player_ids.is_empty() {
+            return (DEFAULT_RATING, true);
+        }
+        let ratings: Vec<PlayerRating> = player_ids
+            .iter()
+            .map(|player_id| self.get_or_default(player_id, play_style))
+            .collect();
+        let average = ratings.iter().map(|rating| rating.rating).sum::<f64>() / ratings.len() as f64;
+        (average, ratings.iter().any(|rating| rating.provisional))
+    }
+}
+
+impl Default for PlayerRatings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn fetch_ratings(
+    conn: &mut MySqlConnection,
+    player_id: &Uuid,
+    play_style: Option<&str>,
+    for_update: bool,
+) -> Result<Vec<PlayerRating>, Error> {
+    let style_filter = if play_style.is_some() { " AND play_style = ?" } else { "" };
+    let lock = if for_update { " FOR UPDATE" } else { "" };
+    let sql = format!(
+        "SELECT play_style, rating, deviation, volatility, games_played
+         FROM player_rating WHERE player_id = UUID_TO_BIN(?){}{}",
+        style_filter, lock,
+    );
+    let mut statement = sqlx::query_as::<_, (String, f64, f64, f64, i32)>(&sql).bind(player_id.to_string());
+    if let Some(play_style) = play_style {
+        statement = statement.bind(play_style);
+    }
+    let rows = statement.fetch_all(&mut *conn).await?;
+    Ok(rows
+        .into_iter()
+        .map(|(play_style, rating, deviation, volatility, games_played)| PlayerRating {
+            player_id: *player_id,
+            play_style,
+            rating,
+            deviation,
+            volatility,
+            games_played,
+            provisional: games_played < PROVISIONAL_GAMES,
+        })
+        .collect())
+}
+
+async fn save_rating(
+    conn: &mut MySqlConnection,
+    rating: &PlayerRating,
+) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT INTO player_rating (player_id, play_style, rating, deviation, volatility, games_played, season_games, last_played)
+         VALUES (UUID_TO_BIN(?), ?, ?, ?, ?, ?, 1, NOW())
+         ON DUPLICATE KEY UPDATE rating = VALUES(rating), deviation = VALUES(deviation),
+            volatility = VALUES(volatility), games_played = VALUES(games_played),
+            season_games = season_games + 1, last_played = NOW()",
+    )
+    .bind(rating.player_id.to_string())
+    .bind(&rating.play_style)
+    .bind(rating.rating)
+    .bind(rating.deviation)
+    .bind(rating.volatility)
+    .bind(rating.games_played)
+    .execute(&mut *conn)
+    .await
+    .map(|_| ())
+}
+
+async fn insert_rating_history(
+    conn: &mut MySqlConnection,
+    before: &PlayerRating,
+    after: &PlayerRating,
+    game_result_id: Option<Uuid>,
+) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT INTO player_rating_history (rating_history_id, player_id, play_style, game_result_id,
+            rating_before, rating_after, deviation_after)
+         VALUES (UUID_TO_BIN(?), UUID_TO_BIN(?), ?, UUID_TO_BIN(?), ?, ?, ?)",
+    )
+    .bind(Uuid::now_v7().to_string())
+    .bind(after.player_id.to_string())
+    .bind(&after.play_style)
+    .bind(game_result_id.map(|game_result_id| game_result_id.to_string()))
+    .bind(before.rating)
+    .bind(after.rating)
+    .bind(after.deviation)
+    .execute(&mut *conn)
+    .await
+    .map(|_| ())
+}
+
+// Applies one finished game to every participant's rating in a single transaction. Players who
+// left before the end have no result and are rated as sharing last place.
+async fn rate_game_results(
+    pool: &MySqlPool,
+    play_style: &str,
+    results: Vec<GameResult>,
+    departed: Vec<Uuid>,
+) -> Result<Vec<PlayerRating>, Error> {
+    let last_place = results.len() as i32 + 1;
+    let mut participants: Vec<(Uuid, i32, Option<Uuid>)> = results
+        .iter()
+        .map(|result| (result.player_id, result.placement, Some(result.game_result_id)))
+        .chain(departed.into_iter().map(|player_id| (player_id, last_place, None)))
+        .collect();
+    // Lock rows in a stable order so concurrent games never deadlock each other
+    participants.sort_by_key(|(player_id, _, _)| *player_id);
+    let mut tx = pool.begin().await?;
+    let mut players = Vec::new();
+    for (player_id, placement, _) in participants.iter() {
+        let rating = fetch_ratings(&mut tx, player_id, Some(play_style), true)
+            .await?
+            .pop()
+            .unwrap_or_else(|| PlayerRating::new(*player_id, play_style));
+        players.push((rating, *placement));
+    }
+
+    let updated = rate_free_for_all(&players);
+    for ((before, _), (after, (_, _, game_result_id))) in players.iter().zip(updated.iter().zip(participants.iter())) {
+        save_rating(&mut tx, after).await?;
+        insert_rating_history(&mut tx, before, after, *game_result_id).await?;
+    }
+    tx.commit().await?;
+    Ok(updated)
+}
+
+// Ratings read before a season reset committed would bring back the pre-reset values
+fn cache_player_ratings(world: &mut World, generation: u64, ratings: Vec<PlayerRating>) {
+    if let Some(mut cache) = world.get_resource_mut::<PlayerRatings>() {
+        if cache.generation != generation {
+            return;
+        }
+        for rating in ratings {
+            cache.ratings.insert((rating.player_id, rating.play_style.clone()), rating);
+        }
+    } else {
+        info!("Failed to access player_ratings resource");
+    }
+}
+
+pub fn rating_record_system(
+    mut event_reader: EventReader<GameResultRecordedEvent>,
+    game_sessions: Res<GameSessions>,
+    player_ratings: Res<PlayerRatings>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    for event in event_reader.read() {
+        // Only validated multiplayer games run by the server count towards ratings
+        let mut sessions: HashMap<Uuid, Vec<GameResult>> = HashMap::new();
+        for result in event.results.iter().filter(|result| result.validated) {
+            if let Some(session_id) = result.session_id {
+                sessions.entry(session_id).or_default().push(result.clone());
+            }
+        }
+
+        for (session_id, results) in sessions {
+            // The session holds what the results do not: the play style and who quit early
+            let Some(session) = game_sessions.get_recently_finished(&session_id) else {
+                warn!("rating_record_system: session [{}] is no longer known, skipping ratings", session_id);
+                continue;
+            };
+            if results.len() + session.departed.len() < 2 {
+                continue;
+            }
+            let Some(play_style) = session.play_style.clone() else {
+                warn!("rating_record_system: no play style recorded for session [{}], skipping ratings", session_id);
+                continue;
+            };
+            let departed = session.departed.clone();
+            let generation = player_ratings.generation;
+            let pool = pool.0.clone();
+            // Spawn the background task using bevy_tokio_tasks
+            runtime.spawn_background_task(move |ctx| {
+                rate_game_results_async(play_style, results, departed, generation, pool, ctx)
+            });
+        }
+    }
+}
+
+pub async fn rate_game_results_async(
+    play_style: String,
+    results: Vec<GameResult>,
+    departed: Vec<Uuid>,
+    generation: u64,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    match rate_game_results(&pool, &play_style, results, departed).await {
+        Ok(updated) => {
+            ctx.run_on_main_thread(move |ctx| {
+                cache_player_ratings(ctx.world, generation, updated);
+            })
+            .await;
+        }
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to update player ratings: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to update player ratings in the task: {:?}", err_for_ctx);
+            })
+            .await;
+        }
+    }
+}
+
+async fn decay_inactive_ratings(pool: &MySqlPool) -> Result<Vec<PlayerRating>, Error> {
+    let mut tx = pool.begin().await?;
+    let rows: Vec<(Uuid, String, f64, f64, f64, i32)> = sqlx::query_as(
+        "SELECT player_id, play_style, rating, deviation, volatility, games_played
+         FROM player_rating
+         WHERE last_played < NOW() - INTERVAL ? DAY AND last_decayed < NOW() - INTERVAL ? DAY
+         FOR UPDATE",
+    )
+    .bind(DECAY_AFTER_DAYS)
+    .bind(DECAY_INTERVAL_DAYS)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut decayed = Vec::new();
+    for (player_id, play_style, rating, deviation, volatility, games_played) in rows {
+        let before = PlayerRating {
+            player_id,
+            play_style,
+            rating,
+            deviation,
+            volatility,
+            games_played,
+            provisional: games_played < PROVISIONAL_GAMES,
+        };
+        let after = before.decayed();
+        sqlx::query(
+            "UPDATE player_rating SET rating = ?, deviation = ?, last_decayed = NOW()
+             WHERE player_id = UUID_TO_BIN(?) AND play_style = ?",
+        )
+        .bind(after.rating)
+        .bind(after.deviation)
+        .bind(after.player_id.to_string())
+        .bind(&after.play_style)
+        .execute(&mut *tx)
+        .await?;
+        insert_rating_history(&mut tx, &before, &after, None).await?;
+        decayed.push(after);
+    }
+    tx.commit().await?;
+    Ok(decayed)
+}
+
+pub fn rating_decay_system(
+    player_ratings: Res<PlayerRatings>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    let generation = player_ratings.generation;
+    let pool = pool.0.clone();
+    runtime.spawn_background_task(move |ctx| {
+        rating_decay_async(generation, pool, ctx)
+    });
+}
+
+pub async fn rating_decay_async(
+    generation: u64,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    match decay_inactive_ratings(&pool).await {
+        Ok(decayed) => {
+            if decayed.is_empty() {
+                return;
+            }
+            println!("Decayed {} inactive ratings", decayed.len());
+            ctx.run_on_main_thread(move |ctx| {
+                cache_player_ratings(ctx.world, generation, decayed);
+            })
+            .await;
+        }
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to decay player ratings: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to decay player ratings in the task: {:?}", err_for_ctx);
+            })
+            .await;
+        }
+    }
+}
+
+// Loads a queued group's ratings and refreshes their matchmaking entry once they arrive
+pub async fn refresh_matchmaking_rating_async(
+    host_id: Uuid,
+    members: Vec<Uuid>,
+    play_style: String,
+    generation: u64,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let mut loaded = Vec::new();
+    let mut conn = match pool.acquire().await {
+        Ok(conn) => conn,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to acquire connection: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to acquire connection in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            return;
+        }
+    };
+    for player_id in members.iter() {
+        match fetch_ratings(&mut conn, player_id, Some(&play_style), false).await {
+            Ok(ratings) => loaded.extend(ratings),
+            Err(err) => {
+                let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+                eprintln!("Failed to execute query: {:?}", err_for_ctx);
+                ctx.run_on_main_thread(move |_ctx| {
+                    info!("Failed to execute query in the task: {:?}", err_for_ctx);
+                })
+                .await;
+                return;
+            }
+        }
+    }
+
+    ctx.run_on_main_thread(move |ctx| {
+        cache_player_ratings(ctx.world, generation, loaded);
+        let Some((rating, provisional)) = ctx
+            .world
+            .get_resource::<PlayerRatings>()
+            .map(|ratings| ratings.group_rating(&members, &play_style))
+        else {
+            return;
+        };
+        if let Some(mut matchmaking_queue) = ctx.world.get_resource_mut::<MatchmakingQueue>() {
+            if let Some(entry) = matchmaking_queue.entries.iter_mut().find(|entry| entry.host_id == host_id) {
+                entry.rating = rating;
+                entry.provisional = provisional;
+            }
+        }
+    })
+    .await;
+}
+
+async fn fetch_rating_history(
+    pool: &MySqlPool,
+    player_id: &Uuid,
+    play_style: Option<&str>,
+) -> Result<Vec<RatingHistoryEntry>, Error> {
+    let style_filter = if play_style.is_some() { " AND play_style = ?" } else { "" };
+    let sql = format!(
+        "SELECT play_style, game_result_id, rating_before, rating_after, deviation_after, recorded
+         FROM player_rating_history WHERE player_id = UUID_TO_BIN(?){}
+         ORDER BY recorded DESC
+         LIMIT ?",
+        style_filter,
+    );
+    let mut statement = sqlx::query_as::<_, (String, Option<Uuid>, f64, f64, f64, OffsetDateTime)>(&sql)
+        .bind(player_id.to_string());
+    if let Some(play_style) = play_style {
+        statement = statement.bind(play_style);
+    }
+    let rows = statement.bind(HISTORY_LIMIT).fetch_all(pool).await?;
+    Ok(rows
+        .into_iter()
+        .map(|(play_style, game_result_id, rating_before, rating_after, deviation_after, recorded)| RatingHistoryEntry {
+            play_style,
+            game_result_id,
+            rating_before,
+            rating_after,
+            deviation_after,
+            recorded,
+        })
+        .collect())
+}
+
+pub async fn send_player_rating_async(
+    player_id: Uuid,
+    play_style: Option<String>,
+    peer: PeerId,
+    generation: u64,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let ratings = match pool.acquire().await {
+        Ok(mut conn) => fetch_ratings(&mut conn, &player_id, play_style.as_deref(), false).await,
+        Err(err) => Err(err),
+    };
+    let result = match ratings {
+        Ok(ratings) => fetch_rating_history(&pool, &player_id, play_style.as_deref())
+            .await
+            .map(|history| (ratings, history)),
+        Err(err) => Err(err),
+    };
+    let (ratings, history) = match result {
+        Ok(result) => result,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to execute query: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to execute query in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            return;
+        }
+    };
+
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            let packet = PacketPlayerRating {
+                player_id: player_id.to_string(),
+                ratings: ratings.clone(),
+                history,
+            };
+            send_peer_message(&mut socket, peer, &player_id, "PlayerRating", &packet);
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+        cache_player_ratings(ctx.world, generation, ratings);
+    })
+    .await;
+}
+
+pub struct RankedLeaderBoardQuery {
+    pub season_id: Option<Uuid>, // Archived standings of a past season when set
+    pub play_style: String,
+    pub page: u32,
+    pub page_size: u32,
+}
+
+// Provisional players and players without enough games this season are left off the live board
+async fn fetch_ranked_leader_board(
+    pool: &MySqlPool,
+    play_style: &str,
+    page: u32,
+    page_size: u32,
+) -> Result<(i64, Vec<RankedLeaderBoardEntry>), Error> {
+    let (total_entries,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM player_rating WHERE play_style = ? AND games_played >= ? AND season_games >= ?",
+    )
+    .bind(play_style)
+    .bind(PROVISIONAL_GAMES)
+    .bind(SEASON_MIN_GAMES)
+    .fetch_one(pool)
+    .await?;
+
+    let offset = page as i64 * page_size as i64;
+    let rows: Vec<(Uuid, String, f64, f64, i32)> = sqlx::query_as(
+        "SELECT r.player_id, p.username, r.rating, r.deviation, r.season_games
+         FROM player_rating r JOIN player_table p ON p.player_id = r.player_id
+         WHERE r.play_style = ? AND r.games_played >= ? AND r.season_games >= ?
+         ORDER BY r.rating DESC, r.deviation ASC, p.username ASC
+         LIMIT ? OFFSET ?",
+    )
+    .bind(play_style)
+    .bind(PROVISIONAL_GAMES)
+    .bind(SEASON_MIN_GAMES)
+    .bind(page_size as i64)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+
+    let entries = rows
+        .into_iter()
+        .enumerate()
+        .map(|(idx, (player_id, username, rating, deviation, games))| RankedLeaderBoardEntry {
+            rank: offset + idx as i64 + 1,
+            player_id: player_id.to_string(),
+            username,
+            rating,
+            deviation,
+            games,
+        })
+        .collect();
+    Ok((total_entries, entries))
+}
+
+// Final standings of an archived season, ranked when the season rolled over
+async fn fetch_archived_leader_board(
+    pool: &MySqlPool,
+    season_id: &Uuid,
+    play_style: &str,
+    page: u32,
+    page_size: u32,
+) -> Result<(i64, Vec<RankedLeaderBoardEntry>), Error> {
+    let (total_entries,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM season_standing WHERE season_id = UUID_TO_BIN(?) AND play_style = ?",
+    )
+    .bind(season_id.to_string())
+    .bind(play_style)
+    .fetch_one(pool)
+    .await?;
+
+    let rows: Vec<(i32, Uuid, String, f64, f64, i32)> = sqlx::query_as(
+        "SELECT s.`rank`, s.player_id, p.username, s.rating, s.deviation, s.season_games
+         FROM season_standing s JOIN player_table p ON p.player_id = s.player_id
+         WHERE s.season_id = UUID_TO_BIN(?) AND s.play_style = ?
+         ORDER BY s.`rank` ASC
+         LIMIT ? OFFSET ?",
+    )
+    .bind(season_id.to_string())
+    .bind(play_style)
+    .bind(page_size as i64)
+    .bind(page as i64 * page_size as i64)
+    .fetch_all(pool)
+    .await?;
+
+    let entries = rows
+        .into_iter()
+        .map(|(rank, player_id, username, rating, deviation, games)| RankedLeaderBoardEntry {
+            rank: rank as i64,
+            player_id: player_id.to_string(),
+            username,
+            rating,
+            deviation,
+            games,
+        })
+        .collect();
+    Ok((total_entries, entries))
+}
+
+pub async fn send_ranked_leader_board_async(
+    query: RankedLeaderBoardQuery,
+    peer: PeerId,
+    player_id: Uuid,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let board = match query.season_id {
+        Some(season_id) => fetch_archived_leader_board(&pool, &season_id, &query.play_style, query.page, query.page_size).await,
+        None => fetch_ranked_leader_board(&pool, &query.play_style, query.page, query.page_size).await,
+    };
+    let (total_entries, entries) = match board {
+        Ok(board) => board,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to execute query: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to execute query in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            return;
+        }
+    };
+
+    let leader_board = PacketRankedLeaderBoard {
+        season_id: query.season_id.map(|season_id| season_id.to_string()),
+        play_style: query.play_style,
+        page: query.page,
+        page_size: query.page_size,
+        total_entries,
+        entries,
+    };
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            send_peer_message(&mut socket, peer, &player_id, "RankedLeaderBoard", &leader_board);
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+    })
+    .await;
+}
+
+pub fn rating_request_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    connected_players: Res<ConnectedPlayers>,
+    seasons: Res<Seasons>,
+    player_ratings: Res<PlayerRatings>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    for event in event_reader.read() {
+        match event.command.as_str() {
+            "GetPlayerRating" => {
+                let packet = match serde_json::from_str::<PacketGetPlayerRating>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketGetPlayerRating from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Ok(player_id) = Uuid::parse_str(&packet.player_id) else {
+                    warn!("GetPlayerRating carried an invalid player_id {:?}", packet.player_id);
+                    continue;
+                };
+                let generation = player_ratings.generation;
+                let pool = pool.0.clone();
+                let peer = event.peer;
+                runtime.spawn_background_task(move |ctx| {
+                    send_player_rating_async(player_id, packet.play_style, peer, generation, pool, ctx)
+                });
+            }
+            "RankedLeaderBoardRequest" => {
+                let packet = match serde_json::from_str::<PacketRankedLeaderBoardRequest>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketRankedLeaderBoardRequest from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let Some(play_style) = packet.play_style.or_else(|| connected_players.get_play_style(&player_id)) else {
+                    warn!("RankedLeaderBoardRequest from {} has no play style", player_id);
+                    continue;
+                };
+                // The current season is served from the live ratings
+                let season_id = match packet.season_id.as_deref().map(Uuid::parse_str) {
+                    Some(Ok(season_id)) if seasons.current.as_ref().is_some_and(|season| season.season_id == season_id) => None,
+                    Some(Ok(season_id)) => Some(season_id),
+                    Some(Err(_)) => {
+                        warn!("RankedLeaderBoardRequest from {} carried a malformed season id", player_id);
+                        continue;
+                    }
+                    None => None,
+                };
+                let page_size = match packet.page_size {
+                    0 => DEFAULT_PAGE_SIZE,
+                    page_size => page_size.min(MAX_PAGE_SIZE),
+                };
+                let query = RankedLeaderBoardQuery {
+                    season_id,
+                    play_style,
+                    page: packet.page,
+                    page_size,
+                };
+                let pool = pool.0.clone();
+                let peer = event.peer;
+                runtime.spawn_background_task(move |ctx| {
+                    send_ranked_leader_board_async(query, peer, player_id, pool, ctx)
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rating(rating: f64, deviation: f64) -> PlayerRating {
+        PlayerRating {
+            rating,
+            deviation,
+            ..PlayerRating::new(Uuid::now_v7(), "test")
+        }
+    }
+
+    // Worked example from Glickman, "Example of the Glicko-2 system"
+    #[test]
+    fn glicko2_matches_reference_example() {
+        let player = rating(1500.0, 200.0);
+        let opponents = [rating(1400.0, 30.0), rating(1550.0, 100.0), rating(1700.0, 300.0)];
+        let outcomes: Vec<(&PlayerRating, f64, f64)> = opponents
+            .iter()
+            .zip([1.0, 0.0, 0.0])
+            .map(|(opponent, score)| (opponent, score, 1.0))
+            .collect();
+
+        let updated = player.updated(&outcomes);
+        assert!((updated.rating - 1464.06).abs() < 0.01, "rating {}", updated.rating);
+        assert!((updated.deviation - 151.52).abs() < 0.01, "deviation {}", updated.deviation);
+        assert!((updated.volatility - 0.05999).abs() < 0.00001, "volatility {}", updated.volatility);
+        assert_eq!(updated.games_played, 1);
+    }
+
+    #[test]
+    fn glicko2_without_outcomes_keeps_rating() {
+        let player = rating(1620.0, 80.0);
+        let updated = player.updated(&[]);
+        assert_eq!(updated.rating, player.rating);
+        assert_eq!(updated.deviation, player.deviation);
+    }
+
+    #[test]
+    fn free_for_all_orders_by_placement() {
+        let players = vec![(rating(1500.0, 200.0), 2), (rating(1500.0, 200.0), 1), (rating(1500.0, 200.0), 3)];
+        let updated = rate_free_for_all(&players);
+        assert!(updated[1].rating > DEFAULT_RATING);
+        assert!((updated[0].rating - DEFAULT_RATING).abs() < 0.01);
+        assert!(updated[2].rating < DEFAULT_RATING);
+        assert!((updated[1].rating - DEFAULT_RATING - (DEFAULT_RATING - updated[2].rating)).abs() < 0.01);
+    }
+
+    #[test]
+    fn free_for_all_ties_share_the_result() {
+        let players = vec![(rating(1500.0, 200.0), 1), (rating(1500.0, 200.0), 1)];
+        let updated = rate_free_for_all(&players);
+        assert!((updated[0].rating - DEFAULT_RATING).abs() < 0.01);
+        assert!((updated[0].rating - updated[1].rating).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,321 @@
+use bevy::prelude::*;
+
+use bevy_matchbox::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::{
+    ClientRequestEvent,
+    ConnectedPlayers,
+    GameSessions,
+    PacketRoomCreate,
+    PacketRoomJoin,
+    PacketRoomLeave,
+    PacketRoomRejected,
+    PlayerDisconnectedEvent,
+    Room,
+    Rooms,
+};
+
+use crate::handlers::signaling_server_handler::send_player_message;
+
+// Every client connects through this signaling room and starts out in it
+pub const DEFAULT_ROOM_ID: &str = "minigolf";
+const ROOM_ID_MAX_LENGTH: usize = 32;
+
+impl Room {
+    pub fn new(room_id: String) -> Self {
+        Self {
+            room_id,
+            members: HashSet::new(),
+            created: Instant::now(),
+            empty_since: Some(Instant::now()),
+            private: false,
+        }
+    }
+
+    fn add_member(&mut self, player_id: Uuid) {
+        self.members.insert(player_id);
+        self.empty_since = None;
+    }
+
+    fn remove_member(&mut self, player_id: &Uuid) -> bool {
+        let removed = self.members.remove(player_id);
+        if removed && self.members.is_empty() {
+            self.empty_since = Some(Instant::now());
+        }
+        removed
+    }
+}
+
+impl Rooms {
+    pub fn new(empty_room_timeout: Duration) -> Self {
+        Self {
+            rooms: HashMap::new(),
+            empty_room_timeout,
+        }
+    }
+
+    pub fn room_of(&self, player_id: &Uuid) -> &str {
+        self.rooms
+            .values()
+            .find(|room| room.members.contains(player_id))
+            .map(|room| room.room_id.as_str())
+            .unwrap_or(DEFAULT_ROOM_ID)
+    }
+
+    pub fn same_room(&self, player_ids: &[Uuid]) -> bool {
+        match player_ids.first() {
+            Some(first) => {
+                let room_id = self.room_of(first);
+                player_ids.iter().all(|player_id| self.room_of(player_id) == room_id)
+            }
+            None => true,
+        }
+    }
+
+    // Members of a room; the lobby holds every connected player not placed in another room
+    pub fn members_of(&self, room_id: &str, connected_players: &ConnectedPlayers) -> Vec<Uuid> {
+        if room_id == DEFAULT_ROOM_ID {
+            connected_players
+                .player_ids()
+                .into_iter()
+                .filter(|player_id| self.room_of(player_id) == DEFAULT_ROOM_ID)
+                .collect()
+        } else {
+            self.rooms
+                .get(room_id)
+                .map(|room| room.members.iter().copied().collect())
+                .unwrap_or_default()
+        }
+    }
+
+    // Peers sharing a room with the player, the player's own peer included
+    pub fn room_peers(&self, player_id: &Uuid, connected_players: &ConnectedPlayers) -> Vec<PeerId> {
+        self.members_of(self.room_of(player_id), connected_players)
+            .iter()
+            .filter_map(|member| connected_players.get_peer(member))
+            .collect()
+    }
+
+    fn generate_room_id(&self) -> String {
+        loop {
+            let room_id = format!("room-{}", &Uuid::new_v4().simple().to_string()[..8]);
+            if !self.rooms.contains_key(&room_id) {
+                return room_id;
+            }
+        }
+    }
+
+    fn validate_room_id(room_id: &str) -> Result<(), String> {
+        if room_id.is_empty() || room_id.len() > ROOM_ID_MAX_LENGTH {
+            return Err(format!("Room id must be between 1 and {} characters", ROOM_ID_MAX_LENGTH));
+        }
+        if !room_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(String::from("Room id may only contain letters, numbers, '-' and '_'"));
+        }
+        Ok(())
+    }
+
+    pub fn create(&mut self, room_id: Option<String>, player_id: Uuid) -> Result<Room, String> {
+        let room_id = match room_id {
+            Some(room_id) => {
+                Self::validate_room_id(&room_id)?;
+                if room_id == DEFAULT_ROOM_ID || self.rooms.contains_key(&room_id) {
+                    return Err(format!("Room {} already exists", room_id));
+                }
+                room_id
+            }
+            None => self.generate_room_id(),
+        };
+        self.leave(&player_id);
+        let mut room = Room::new(room_id.clone());
+        room.add_member(player_id);
+        self.rooms.insert(room_id, room.clone());
+        Ok(room)
+    }
+
+    pub fn join(&mut self, room_id: &str, player_id: Uuid) -> Result<Room, String> {
+        match self.rooms.get(room_id) {
+            None => return Err(format!("Unknown room {}", room_id)),
+            Some(room) if room.private && !room.members.contains(&player_id) => {
+                return Err(format!("Room {} is private", room_id));
+            }
+            Some(_) => {}
+        }
+        self.leave(&player_id);
+        let room = self.rooms.get_mut(room_id).ok_or(String::from("Unknown room"))?;
+        room.add_member(player_id);
+        Ok(room.clone())
+    }
+
+    // Moves the player back to the lobby, returning the room they left
+    pub fn leave(&mut self, player_id: &Uuid) -> Option<Room> {
+        let room = self.rooms.values_mut().find(|room| room.members.contains(player_id))?;
+        room.remove_member(player_id);
+        Some(room.clone())
+    }
+
+    // Creates a fresh room for a group, used when a game instance is set up for them
+    pub fn create_for_players(&mut self, player_ids: &[Uuid]) -> Room {
+        for player_id in player_ids.iter() {
+            self.leave(player_id);
+        }
+        let room_id = self.generate_room_id();
+        let mut room = Room::new(room_id.clone());
+        room.private = true;
+        for player_id in player_ids.iter() {
+            room.add_member(*player_id);
+        }
+        self.rooms.insert(room_id, room.clone());
+        room
+    }
+
+    pub fn remove_expired(&mut self, now: Instant) -> Vec<String> {
+        let timeout = self.empty_room_timeout;
+        let expired: Vec<String> = self
+            .rooms
+            .values()
+            .filter(|room| room.empty_since.is_some_and(|empty_since| now.duration_since(empty_since) >= timeout))
+            .map(|room| room.room_id.clone())
+            .collect();
+        for room_id in expired.iter() {
+            self.rooms.remove(room_id);
+        }
+        expired
+    }
+}
+
+fn broadcast_room_state(
+    socket: &mut MatchboxSocket<SingleChannel>,
+    connected_players: &ConnectedPlayers,
+    room: &Room,
+    extra_recipients: &[Uuid],
+) {
+    for player_id in room.members.iter().chain(extra_recipients.iter()) {
+        send_player_message(socket, connected_players, player_id, "RoomState", room);
+    }
+}
+
+fn send_room_rejection(
+    socket: &mut MatchboxSocket<SingleChannel>,
+    connected_players: &ConnectedPlayers,
+    player_id: &Uuid,
+    room_id: Option<String>,
+    reason: String,
+) {
+    warn!("Rejected room request from {}: {}", player_id, reason);
+    let rejection = PacketRoomRejected {
+        room_id,
+        reason,
+    };
+    send_player_message(socket, connected_players, player_id, "RoomRejected", &rejection);
+}
+
+pub fn room_request_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    game_sessions: Res<GameSessions>,
+    mut rooms: ResMut<Rooms>,
+) {
+    for event in event_reader.read() {
+        match event.command.as_str() {
+            "RoomCreate" | "RoomJoin" => {
+                let request = if event.command == "RoomCreate" {
+                    serde_json::from_str::<PacketRoomCreate>(&event.payload)
+                        .map(|packet| (packet.player_id, packet.room_id))
+                } else {
+                    serde_json::from_str::<PacketRoomJoin>(&event.payload)
+                        .map(|packet| (packet.player_id, Some(packet.room_id)))
+                };
+                let (player_id, room_id) = match request {
+                    Ok(request) => request,
+                    Err(err) => {
+                        error!("Failed to deserialize {} packet from JSON: {:?}", event.command, err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&player_id, event.peer) else {
+                    continue;
+                };
+                if game_sessions.session_for_player(&player_id).is_some() {
+                    let reason = String::from("Cannot change rooms during a game session");
+                    send_room_rejection(&mut socket, &connected_players, &player_id, room_id, reason);
+                    continue;
+                }
+
+                let previous_room = rooms.rooms.values().find(|room| room.members.contains(&player_id)).cloned();
+                let result = match (event.command.as_str(), room_id.clone()) {
+                    ("RoomJoin", Some(room_id)) => rooms.join(&room_id, player_id),
+                    _ => rooms.create(room_id.clone(), player_id),
+                };
+                match result {
+                    Ok(room) => {
+                        info!("Player {} is now in room [{}]", player_id, room.room_id);
+                        if let Some(mut previous_room) = previous_room {
+                            previous_room.members.remove(&player_id);
+                            broadcast_room_state(&mut socket, &connected_players, &previous_room, &[]);
+                        }
+                        broadcast_room_state(&mut socket, &connected_players, &room, &[]);
+                    }
+                    Err(reason) => send_room_rejection(&mut socket, &connected_players, &player_id, room_id, reason),
+                }
+            }
+            "RoomLeave" => {
+                let packet = match serde_json::from_str::<PacketRoomLeave>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketRoomLeave from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                if game_sessions.session_for_player(&player_id).is_some() {
+                    let reason = String::from("Cannot change rooms during a game session");
+                    send_room_rejection(&mut socket, &connected_players, &player_id, None, reason);
+                    continue;
+                }
+                if let Some(room) = rooms.leave(&player_id) {
+                    info!("Player {} left room [{}]", player_id, room.room_id);
+                    broadcast_room_state(&mut socket, &connected_players, &room, &[player_id]);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn room_disconnect_system(
+    mut event_reader: EventReader<PlayerDisconnectedEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    mut rooms: ResMut<Rooms>,
+) {
+    for event in event_reader.read() {
+        if let Some(room) = rooms.leave(&event.player_id) {
+            broadcast_room_state(&mut socket, &connected_players, &room, &[]);
+        }
+    }
+}
+
+pub fn room_cleanup_system(
+    mut rooms: ResMut<Rooms>,
+    game_sessions: Res<GameSessions>,
+) {
+    // Rooms that still host a game session are kept even if every player dropped
+    let now = Instant::now();
+    for room in rooms.rooms.values_mut() {
+        if game_sessions.sessions.values().any(|session| session.room_id == room.room_id) {
+            room.empty_since = None;
+        } else if room.members.is_empty() && room.empty_since.is_none() {
+            room.empty_since = Some(now);
+        }
+    }
+    for room_id in rooms.remove_expired(now) {
+        info!("Removed empty room [{}]", room_id);
+    }
+}
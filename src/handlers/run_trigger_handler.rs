@@ -3,6 +3,8 @@ use bevy::prelude::*;
 use bevy_matchbox::prelude::*;
 
 use crate::{
+    ConnectedPlayers,
+    Rooms,
     RunTrigger,
     SyncTriggerIndexEvent,
 };
@@ -37,6 +39,7 @@ impl RunTrigger {
             triggers,
             db_pipeline_player_init: false,
             network_get_client_state_game: false,
+            player_stats_rebuild: false,
         }
     }
 
@@ -78,6 +81,9 @@ impl RunTrigger {
             "db_pipeline_player_init" => {
                 self.db_pipeline_player_init
             },
+            "player_stats_rebuild" => {
+                self.player_stats_rebuild
+            },
             _ => {false},
         }
     }
@@ -92,6 +98,10 @@ impl RunTrigger {
                 self.network_get_client_state_game = state;
                 info!("response: network_get_client_state_game: {}", self.get("network_get_client_state_game"));  
             },
+            "player_stats_rebuild" => {
+                self.player_stats_rebuild = state;
+                info!("player_stats_rebuild: {}", self.get("player_stats_rebuild"));  
+            },
             _ => {},
         }
     }
@@ -103,19 +113,32 @@ impl RunTrigger {
     pub fn network_get_client_state_game(&self) -> bool {
         self.network_get_client_state_game
     }
+
+    pub fn player_stats_rebuild(&self) -> bool {
+        self.player_stats_rebuild
+    }
+}
+
+impl Default for RunTrigger {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 pub fn client_run_trigger(
     trigger: ResMut<RunTrigger>,
     mut event_reader: EventReader<SyncTriggerIndexEvent>,
     mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    rooms: Res<Rooms>,
 ) {
     for event in event_reader.read() {
         info!("client_run_trigger:{:?}", event.player_id.clone());
         let target_idx =  trigger.get_trigger_idx();
         let triggers = trigger.get_triggers_ref();
         let trigger = triggers[target_idx].as_str();
-        let peers: Vec<_> = socket.connected_peers().collect();
+        // Triggers only reach the peers sharing the player's room
+        let peers = rooms.room_peers(&event.player_id, &connected_players);
         for peer in peers {
             let message = format!("({}, RunTrigger({:?}))", event.player_id.clone(), trigger);
             info!("Sending sync_player_id_init_system update: {message:?} to {peer}");
@@ -0,0 +1,385 @@
+use bevy::prelude::*;
+
+use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+use sqlx::MySqlPool;
+
+use crate::DatabasePool;
+
+use crate::handlers::map_set_handler::first_time_boot_setup_map_set_async;
+
+// Tables owned by the server beyond player_table and map_set_table, created on boot when missing
+const SCHEMA_STATEMENTS: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS game_result (
+        game_result_id BINARY(16) NOT NULL PRIMARY KEY,
+        session_id BINARY(16) NULL,
+        map_set_id BINARY(16) NOT NULL,
+        map_set_revision INT NULL,
+        player_id BINARY(16) NOT NULL,
+        total_strokes INT NOT NULL,
+        to_par INT NULL,
+        holes_played INT NOT NULL,
+        placement INT NOT NULL,
+        player_count INT NOT NULL,
+        validated BOOLEAN NOT NULL DEFAULT FALSE,
+        completed TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        INDEX idx_game_result_map_set (map_set_id, completed),
+        INDEX idx_game_result_player (player_id, completed),
+        INDEX idx_game_result_session (session_id)
+    )",
+    "CREATE TABLE IF NOT EXISTS game_result_hole (
+        game_result_id BINARY(16) NOT NULL,
+        level INT NOT NULL,
+        strokes INT NOT NULL,
+        par INT NULL,
+        PRIMARY KEY (game_result_id, level),
+        INDEX idx_game_result_hole_level (level, strokes)
+    )",
+    "CREATE TABLE IF NOT EXISTS player_stats (
+        player_id BINARY(16) NOT NULL PRIMARY KEY,
+        games_played INT NOT NULL DEFAULT 0,
+        games_won INT NOT NULL DEFAULT 0,
+        holes_played INT NOT NULL DEFAULT 0,
+        holes_in_one INT NOT NULL DEFAULT 0,
+        total_strokes INT NOT NULL DEFAULT 0,
+        current_win_streak INT NOT NULL DEFAULT 0,
+        best_win_streak INT NOT NULL DEFAULT 0,
+        par_holes_played INT NOT NULL DEFAULT 0,
+        strokes_to_par INT NOT NULL DEFAULT 0,
+        under_par_holes INT NOT NULL DEFAULT 0,
+        updated TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP
+    )",
+    "CREATE TABLE IF NOT EXISTS player_map_set_stats (
+        player_id BINARY(16) NOT NULL,
+        map_set_id BINARY(16) NOT NULL,
+        games_played INT NOT NULL DEFAULT 0,
+        holes_played INT NOT NULL DEFAULT 0,
+        total_strokes INT NOT NULL DEFAULT 0,
+        best_round INT NULL,
+        best_round_to_par INT NULL,
+        PRIMARY KEY (player_id, map_set_id)
+    )",
+    "CREATE TABLE IF NOT EXISTS player_rating (
+        player_id BINARY(16) NOT NULL,
+        play_style VARCHAR(64) NOT NULL,
+        rating DOUBLE NOT NULL,
+        deviation DOUBLE NOT NULL,
+        volatility DOUBLE NOT NULL,
+        games_played INT NOT NULL DEFAULT 0,
+        season_games INT NOT NULL DEFAULT 0,
+        last_played TIMESTAMP NULL,
+        last_decayed TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        PRIMARY KEY (player_id, play_style),
+        INDEX idx_player_rating_style (play_style, rating)
+    )",
+    "CREATE TABLE IF NOT EXISTS player_rating_history (
+        rating_history_id BINARY(16) NOT NULL PRIMARY KEY,
+        player_id BINARY(16) NOT NULL,
+        play_style VARCHAR(64) NOT NULL,
+        game_result_id BINARY(16) NULL,
+        rating_before DOUBLE NOT NULL,
+        rating_after DOUBLE NOT NULL,
+        deviation_after DOUBLE NOT NULL,
+        recorded TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        INDEX idx_player_rating_history_player (player_id, play_style, recorded)
+    )",
+    "CREATE TABLE IF NOT EXISTS challenge (
+        challenge_id BINARY(16) NOT NULL PRIMARY KEY,
+        period VARCHAR(16) NOT NULL,
+        starts DATE NOT NULL,
+        ends DATE NOT NULL,
+        seed BIGINT UNSIGNED NOT NULL,
+        holes TEXT NOT NULL,
+        stroke_limit INT NULL,
+        created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        UNIQUE KEY uq_challenge_period (period, starts)
+    )",
+    "CREATE TABLE IF NOT EXISTS challenge_attempt (
+        challenge_id BINARY(16) NOT NULL,
+        player_id BINARY(16) NOT NULL,
+        total_strokes INT NOT NULL,
+        hole_strokes TEXT NOT NULL,
+        submitted TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        PRIMARY KEY (challenge_id, player_id),
+        INDEX idx_challenge_attempt_strokes (challenge_id, total_strokes)
+    )",
+    "CREATE TABLE IF NOT EXISTS tournament (
+        tournament_id BINARY(16) NOT NULL PRIMARY KEY,
+        name VARCHAR(64) NOT NULL,
+        map_set_id BINARY(16) NOT NULL,
+        format VARCHAR(16) NOT NULL,
+        state VARCHAR(16) NOT NULL,
+        registration_opens TIMESTAMP NOT NULL,
+        registration_closes TIMESTAMP NOT NULL,
+        rounds INT NOT NULL,
+        group_size INT NOT NULL,
+        advance_count INT NOT NULL,
+        current_round INT NOT NULL DEFAULT 0,
+        created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        INDEX idx_tournament_state (state)
+    )",
+    "CREATE TABLE IF NOT EXISTS tournament_entrant (
+        tournament_id BINARY(16) NOT NULL,
+        player_id BINARY(16) NOT NULL,
+        seed INT NOT NULL,
+        total_strokes INT NOT NULL DEFAULT 0,
+        eliminated_round INT NULL,
+        final_rank INT NULL,
+        PRIMARY KEY (tournament_id, player_id)
+    )",
+    "CREATE TABLE IF NOT EXISTS tournament_match (
+        match_id BINARY(16) NOT NULL PRIMARY KEY,
+        tournament_id BINARY(16) NOT NULL,
+        round INT NOT NULL,
+        player_ids TEXT NOT NULL,
+        session_id BINARY(16) NULL,
+        state VARCHAR(16) NOT NULL,
+        results TEXT NOT NULL,
+        INDEX idx_tournament_match_round (tournament_id, round)
+    )",
+    "CREATE TABLE IF NOT EXISTS season (
+        season_id BINARY(16) NOT NULL PRIMARY KEY,
+        name VARCHAR(64) NOT NULL,
+        starts TIMESTAMP NOT NULL,
+        ends TIMESTAMP NOT NULL,
+        archived TIMESTAMP NULL,
+        INDEX idx_season_archived (archived, starts)
+    )",
+    "CREATE TABLE IF NOT EXISTS season_standing (
+        season_id BINARY(16) NOT NULL,
+        play_style VARCHAR(64) NOT NULL,
+        player_id BINARY(16) NOT NULL,
+        `rank` INT NOT NULL,
+        rating DOUBLE NOT NULL,
+        deviation DOUBLE NOT NULL,
+        season_games INT NOT NULL,
+        PRIMARY KEY (season_id, play_style, player_id),
+        INDEX idx_season_standing_rank (season_id, play_style, `rank`)
+    )",
+    "CREATE TABLE IF NOT EXISTS season_reward (
+        season_id BINARY(16) NOT NULL,
+        player_id BINARY(16) NOT NULL,
+        play_style VARCHAR(64) NOT NULL,
+        reward VARCHAR(32) NOT NULL,
+        awarded TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        PRIMARY KEY (season_id, player_id, play_style)
+    )",
+    "CREATE TABLE IF NOT EXISTS player_achievement (
+        player_id BINARY(16) NOT NULL,
+        achievement_id VARCHAR(64) NOT NULL,
+        progress INT NOT NULL DEFAULT 0,
+        unlocked TIMESTAMP NULL,
+        updated TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+        PRIMARY KEY (player_id, achievement_id)
+    )",
+    "CREATE TABLE IF NOT EXISTS player_friend (
+        player_id BINARY(16) NOT NULL,
+        friend_id BINARY(16) NOT NULL,
+        since TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        PRIMARY KEY (player_id, friend_id)
+    )",
+    "CREATE TABLE IF NOT EXISTS player_friend_request (
+        from_player_id BINARY(16) NOT NULL,
+        to_player_id BINARY(16) NOT NULL,
+        created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        PRIMARY KEY (from_player_id, to_player_id),
+        INDEX idx_player_friend_request_to (to_player_id)
+    )",
+    "CREATE TABLE IF NOT EXISTS player_block (
+        player_id BINARY(16) NOT NULL,
+        blocked_id BINARY(16) NOT NULL,
+        created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        PRIMARY KEY (player_id, blocked_id)
+    )",
+    "CREATE TABLE IF NOT EXISTS player_emote_stats (
+        player_id BINARY(16) NOT NULL,
+        emote_id VARCHAR(32) NOT NULL,
+        uses INT NOT NULL DEFAULT 0,
+        last_used TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+        PRIMARY KEY (player_id, emote_id),
+        INDEX idx_player_emote_stats_emote (emote_id)
+    )",
+    "CREATE TABLE IF NOT EXISTS map_set_asset (
+        map_set_id BINARY(16) NOT NULL,
+        level INT NOT NULL,
+        file_path VARCHAR(255) NOT NULL,
+        size BIGINT UNSIGNED NOT NULL,
+        hash CHAR(64) NOT NULL,
+        scanned TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        PRIMARY KEY (map_set_id, level)
+    )",
+    "CREATE TABLE IF NOT EXISTS map_set_hole (
+        map_set_id BINARY(16) NOT NULL,
+        level INT NOT NULL,
+        display_name VARCHAR(64) NULL,
+        par INT NOT NULL,
+        difficulty INT NULL,
+        author VARCHAR(64) NULL,
+        tags TEXT NOT NULL,
+        stroke_limit INT NULL,
+        PRIMARY KEY (map_set_id, level)
+    )",
+    "CREATE TABLE IF NOT EXISTS map_set_retired (
+        map_set_id BINARY(16) NOT NULL PRIMARY KEY,
+        retired TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        retired_by BINARY(16) NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS map_set_seed (
+        seed_key VARCHAR(64) NOT NULL PRIMARY KEY,
+        map_set_id BINARY(16) NOT NULL,
+        UNIQUE KEY idx_map_set_seed_map_set (map_set_id)
+    )",
+    "CREATE TABLE IF NOT EXISTS ugc_file (
+        hash CHAR(64) NOT NULL,
+        player_id BINARY(16) NOT NULL,
+        file_name VARCHAR(128) NOT NULL,
+        size BIGINT UNSIGNED NOT NULL,
+        uploaded TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        PRIMARY KEY (hash, player_id),
+        INDEX idx_ugc_file_player (player_id)
+    )",
+    "CREATE TABLE IF NOT EXISTS ugc_submission (
+        submission_id BINARY(16) NOT NULL PRIMARY KEY,
+        player_id BINARY(16) NOT NULL,
+        map_set_name VARCHAR(64) NOT NULL,
+        definition TEXT NOT NULL,
+        status VARCHAR(16) NOT NULL,
+        reason VARCHAR(255) NULL,
+        map_set_id BINARY(16) NULL,
+        created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        reviewed TIMESTAMP NULL,
+        reviewed_by BINARY(16) NULL,
+        INDEX idx_ugc_submission_player (player_id),
+        INDEX idx_ugc_submission_status (status, created)
+    )",
+    "CREATE TABLE IF NOT EXISTS map_set_community (
+        map_set_id BINARY(16) NOT NULL PRIMARY KEY,
+        author_id BINARY(16) NOT NULL,
+        submission_id BINARY(16) NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS map_set_play (
+        map_set_id BINARY(16) NOT NULL PRIMARY KEY,
+        plays BIGINT NOT NULL DEFAULT 0,
+        completions BIGINT NOT NULL DEFAULT 0
+    )",
+    "CREATE TABLE IF NOT EXISTS map_set_rating (
+        map_set_id BINARY(16) NOT NULL,
+        level INT NOT NULL,
+        player_id BINARY(16) NOT NULL,
+        stars INT NOT NULL,
+        updated TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+        PRIMARY KEY (map_set_id, level, player_id)
+    )",
+    "CREATE TABLE IF NOT EXISTS map_set_hole_rating (
+        file_path VARCHAR(255) NOT NULL,
+        player_id BINARY(16) NOT NULL,
+        stars INT NOT NULL,
+        updated TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+        PRIMARY KEY (file_path, player_id)
+    )",
+    "CREATE TABLE IF NOT EXISTS map_set_revision (
+        map_set_id BINARY(16) NOT NULL,
+        revision INT NOT NULL,
+        created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        map_set_name VARCHAR(64) NOT NULL,
+        hole_range_start INT NOT NULL,
+        hole_range_end INT NOT NULL,
+        file_paths TEXT NOT NULL,
+        holes TEXT NOT NULL,
+        assets TEXT NOT NULL,
+        PRIMARY KEY (map_set_id, revision)
+    )",
+    "CREATE TABLE IF NOT EXISTS personal_map_set (
+        map_set_id BINARY(16) NOT NULL PRIMARY KEY,
+        player_id BINARY(16) NOT NULL,
+        map_set_name VARCHAR(64) NOT NULL,
+        holes TEXT NOT NULL,
+        created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        UNIQUE KEY uq_personal_map_set_name (player_id, map_set_name)
+    )",
+];
+
+// Columns added after their table first shipped; CREATE TABLE IF NOT EXISTS leaves existing
+// tables untouched, so each is added on boot when information_schema does not list it yet,
+// followed by its backfill statement if it has one
+const SCHEMA_COLUMNS: &[(&str, &str, &str, Option<&str>)] = &[
+    ("player_rating", "season_games", "INT NOT NULL DEFAULT 0 AFTER games_played", None),
+    ("game_result", "map_set_revision", "INT NULL AFTER map_set_id", None),
+    ("game_result_hole", "par", "INT NULL", None),
+    ("game_result", "to_par", "INT NULL AFTER total_strokes", Some(
+        "UPDATE game_result r SET r.to_par = r.total_strokes - (
+            SELECT CASE WHEN COUNT(h.par) = COUNT(*) THEN SUM(h.par) END
+            FROM game_result_hole h WHERE h.game_result_id = r.game_result_id)",
+    )),
+    ("player_stats", "par_holes_played", "INT NOT NULL DEFAULT 0 AFTER best_win_streak", None),
+    ("player_stats", "strokes_to_par", "INT NOT NULL DEFAULT 0 AFTER par_holes_played", None),
+    ("player_stats", "under_par_holes", "INT NOT NULL DEFAULT 0 AFTER strokes_to_par", None),
+    ("player_map_set_stats", "best_round_to_par", "INT NULL AFTER best_round", None),
+];
+
+async fn add_missing_columns(pool: &MySqlPool) -> Result<usize, sqlx::Error> {
+    let mut added = 0;
+    for (table, column, definition, backfill) in SCHEMA_COLUMNS {
+        let (present,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM information_schema.COLUMNS
+             WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = ? AND COLUMN_NAME = ?",
+        )
+        .bind(table)
+        .bind(column)
+        .fetch_one(pool)
+        .await?;
+        if present == 0 {
+            sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+                .execute(pool)
+                .await?;
+            if let Some(backfill) = backfill {
+                sqlx::query(backfill).execute(pool).await?;
+            }
+            added += 1;
+        }
+    }
+    Ok(added)
+}
+
+pub fn setup_schema(
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    let pool = pool.0.clone();
+
+    // Spawn the background task using bevy_tokio_tasks
+    runtime.spawn_background_task(move |ctx| {
+        setup_schema_async(pool, ctx)
+    });
+}
+
+pub async fn setup_schema_async(
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    for statement in SCHEMA_STATEMENTS {
+        if let Err(err) = sqlx::query(statement).execute(&pool).await {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to apply schema statement: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to apply schema statement in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            return;
+        }
+    }
+    match add_missing_columns(&pool).await {
+        Ok(added) => println!("Schema ready: {} statements applied, {} column(s) added", SCHEMA_STATEMENTS.len(), added),
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to add schema columns: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to add schema columns in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            return;
+        }
+    }
+
+    // Seeding and loading the catalog need the tables above
+    first_time_boot_setup_map_set_async(pool, ctx).await;
+}
@@ -0,0 +1,363 @@
+use bevy::prelude::*;
+
+use bevy_matchbox::prelude::*;
+use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+use sqlx::{MySqlConnection, MySqlPool, Error};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    ClientRequestEvent,
+    ConnectedPlayers,
+    DatabasePool,
+    PacketSeasonHistory,
+    PacketSeasonHistoryRequest,
+    PacketSeasonRequest,
+    PlayerRatings,
+    Season,
+    SeasonHistoryEntry,
+    Seasons,
+};
+
+use crate::handlers::{
+    rating_handler::{DEFAULT_RATING, PROVISIONAL_GAMES, SEASON_MIN_GAMES},
+    signaling_server_handler::{send_peer_message, send_player_message},
+};
+
+const SEASON_LENGTH_DAYS: i64 = 91;
+// Ratings keep this share of their distance from the default and lose some confidence
+const SOFT_RESET_FACTOR: f64 = 0.5;
+const SOFT_RESET_MIN_DEVIATION: f64 = 200.0;
+
+impl Season {
+    pub fn new(starts: OffsetDateTime) -> Self {
+        Self {
+            season_id: Uuid::now_v7(),
+            name: format!("Season {}", starts.date()),
+            starts,
+            ends: starts + time::Duration::days(SEASON_LENGTH_DAYS),
+        }
+    }
+
+    // Seasons follow each other back to back, unless the server was down past a whole season
+    fn next(&self, now: OffsetDateTime) -> Season {
+        let next = Season::new(self.ends);
+        if next.ends <= now {
+            Season::new(now)
+        } else {
+            next
+        }
+    }
+}
+
+impl Seasons {
+    pub fn new() -> Self {
+        Self {
+            current: None,
+            busy: false,
+        }
+    }
+}
+
+impl Default for Seasons {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn fetch_active_season(conn: &mut MySqlConnection) -> Result<Option<Season>, Error> {
+    let row: Option<(Uuid, String, OffsetDateTime, OffsetDateTime)> = sqlx::query_as(
+        "SELECT season_id, name, starts, ends FROM season
+         WHERE archived IS NULL
+         ORDER BY starts DESC
+         LIMIT 1
+         FOR UPDATE",
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    Ok(row.map(|(season_id, name, starts, ends)| Season {
+        season_id,
+        name,
+        starts,
+        ends,
+    }))
+}
+
+async fn insert_season(conn: &mut MySqlConnection, season: &Season) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT INTO season (season_id, name, starts, ends) VALUES (UUID_TO_BIN(?), ?, ?, ?)",
+    )
+    .bind(season.season_id.to_string())
+    .bind(&season.name)
+    .bind(season.starts)
+    .bind(season.ends)
+    .execute(&mut *conn)
+    .await
+    .map(|_| ())
+}
+
+// Archives the season's final standings, hands out rewards, soft resets every rating and opens
+// the next season, all in one transaction so a crash never leaves a half rolled season behind
+async fn archive_season(conn: &mut MySqlConnection, season: &Season) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT INTO season_standing (season_id, play_style, player_id, `rank`, rating, deviation, season_games)
+         SELECT UUID_TO_BIN(?), play_style, player_id,
+            ROW_NUMBER() OVER (PARTITION BY play_style ORDER BY rating DESC, deviation ASC),
+            rating, deviation, season_games
+         FROM player_rating
+         WHERE games_played >= ? AND season_games >= ?",
+    )
+    .bind(season.season_id.to_string())
+    .bind(PROVISIONAL_GAMES)
+    .bind(SEASON_MIN_GAMES)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        "INSERT INTO season_reward (season_id, player_id, play_style, reward)
+         SELECT season_id, player_id, play_style,
+            CASE WHEN `rank` = 1 THEN 'champion'
+                 WHEN `rank` <= 10 THEN 'top_ten'
+                 WHEN `rank` <= 100 THEN 'top_hundred'
+                 ELSE 'participant' END
+         FROM season_standing WHERE season_id = UUID_TO_BIN(?)",
+    )
+    .bind(season.season_id.to_string())
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        "INSERT INTO player_rating_history (rating_history_id, player_id, play_style, game_result_id,
+            rating_before, rating_after, deviation_after)
+         SELECT UUID_TO_BIN(UUID()), player_id, play_style, NULL,
+            rating, ? + (rating - ?) * ?, GREATEST(deviation, ?)
+         FROM player_rating",
+    )
+    .bind(DEFAULT_RATING)
+    .bind(DEFAULT_RATING)
+    .bind(SOFT_RESET_FACTOR)
+    .bind(SOFT_RESET_MIN_DEVIATION)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        "UPDATE player_rating
+         SET rating = ? + (rating - ?) * ?, deviation = GREATEST(deviation, ?), season_games = 0",
+    )
+    .bind(DEFAULT_RATING)
+    .bind(DEFAULT_RATING)
+    .bind(SOFT_RESET_FACTOR)
+    .bind(SOFT_RESET_MIN_DEVIATION)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query("UPDATE season SET archived = NOW() WHERE season_id = UUID_TO_BIN(?)")
+        .bind(season.season_id.to_string())
+        .execute(&mut *conn)
+        .await
+        .map(|_| ())
+}
+
+// Returns the season that is current afterwards and whether a rollover happened
+async fn ensure_current_season(pool: &MySqlPool) -> Result<(Season, bool), Error> {
+    let now = OffsetDateTime::now_utc();
+    let mut tx = pool.begin().await?;
+    let result = match fetch_active_season(&mut tx).await? {
+        Some(season) if now < season.ends => (season, false),
+        Some(season) => {
+            info!("Rolling over {} ({} - {})", season.name, season.starts, season.ends);
+            archive_season(&mut tx, &season).await?;
+            let next = season.next(now);
+            insert_season(&mut tx, &next).await?;
+            (next, true)
+        }
+        None => {
+            let season = Season::new(now);
+            insert_season(&mut tx, &season).await?;
+            (season, false)
+        }
+    };
+    tx.commit().await?;
+    Ok(result)
+}
+
+pub fn season_system(
+    mut seasons: ResMut<Seasons>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    if seasons.busy {
+        return;
+    }
+    let now = OffsetDateTime::now_utc();
+    if seasons.current.as_ref().is_some_and(|season| now < season.ends) {
+        return;
+    }
+    seasons.busy = true;
+    let pool = pool.0.clone();
+    // Spawn the background task using bevy_tokio_tasks
+    runtime.spawn_background_task(move |ctx| {
+        season_rollover_async(pool, ctx)
+    });
+}
+
+pub async fn season_rollover_async(
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let result = match ensure_current_season(&pool).await {
+        Ok(result) => Some(result),
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to update season: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to update season in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            None
+        }
+    };
+
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some(mut seasons) = ctx.world.get_resource_mut::<Seasons>() {
+            seasons.busy = false;
+            if let Some((season, _)) = result.as_ref() {
+                seasons.current = Some(season.clone());
+            }
+        }
+        let Some((season, rolled_over)) = result else {
+            return;
+        };
+        if !rolled_over {
+            return;
+        }
+        info!("{} started", season.name);
+        // Cached ratings predate the soft reset, as do the ones loading right now
+        if let Some(mut player_ratings) = ctx.world.get_resource_mut::<PlayerRatings>() {
+            player_ratings.ratings.clear();
+            player_ratings.generation += 1;
+        }
+        let Some(connected_players) = ctx.world.get_resource::<ConnectedPlayers>().cloned() else {
+            return;
+        };
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            for player_id in connected_players.player_ids() {
+                send_player_message(&mut socket, &connected_players, &player_id, "SeasonState", &season);
+            }
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+    })
+    .await;
+}
+
+async fn fetch_season_history(
+    pool: &MySqlPool,
+    player_id: &Uuid,
+) -> Result<Vec<SeasonHistoryEntry>, Error> {
+    let rows: Vec<(Uuid, String, String, i32, f64, Option<String>)> = sqlx::query_as(
+        "SELECT s.season_id, s.name, st.play_style, st.`rank`, st.rating, r.reward
+         FROM season_standing st
+         JOIN season s ON s.season_id = st.season_id
+         LEFT JOIN season_reward r ON r.season_id = st.season_id AND r.player_id = st.player_id
+            AND r.play_style = st.play_style
+         WHERE st.player_id = UUID_TO_BIN(?)
+         ORDER BY s.starts DESC, st.play_style ASC",
+    )
+    .bind(player_id.to_string())
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(season_id, name, play_style, rank, rating, reward)| SeasonHistoryEntry {
+            season_id,
+            name,
+            play_style,
+            rank,
+            rating,
+            reward,
+        })
+        .collect())
+}
+
+pub async fn send_season_history_async(
+    player_id: Uuid,
+    peer: PeerId,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let entries = match fetch_season_history(&pool, &player_id).await {
+        Ok(entries) => entries,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to execute query: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to execute query in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            return;
+        }
+    };
+
+    let history = PacketSeasonHistory {
+        player_id: player_id.to_string(),
+        entries,
+    };
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            send_peer_message(&mut socket, peer, &player_id, "SeasonHistory", &history);
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+    })
+    .await;
+}
+
+pub fn season_request_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    seasons: Res<Seasons>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    for event in event_reader.read() {
+        match event.command.as_str() {
+            "SeasonRequest" => {
+                let packet = match serde_json::from_str::<PacketSeasonRequest>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketSeasonRequest from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                match seasons.current.as_ref() {
+                    Some(season) => send_peer_message(&mut socket, event.peer, &player_id, "SeasonState", season),
+                    None => warn!("SeasonRequest from {} before the season was loaded", player_id),
+                }
+            }
+            "SeasonHistoryRequest" => {
+                let packet = match serde_json::from_str::<PacketSeasonHistoryRequest>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketSeasonHistoryRequest from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Ok(player_id) = Uuid::parse_str(&packet.player_id) else {
+                    warn!("SeasonHistoryRequest carried an invalid player_id {:?}", packet.player_id);
+                    continue;
+                };
+                let pool = pool.0.clone();
+                let peer = event.peer;
+                runtime.spawn_background_task(move |ctx| {
+                    send_season_history_async(player_id, peer, pool, ctx)
+                });
+            }
+            _ => {}
+        }
+    }
+}
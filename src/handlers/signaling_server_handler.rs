@@ -6,15 +6,20 @@ use std::{
         Ipv4Addr, 
         SocketAddrV4
     }, 
-    str::FromStr,
 };
 use regex::Regex;
-use rmp_serde::decode;
+use serde::Serialize;
+use rmp_serde::{
+    decode,
+    encode,
+};
 use serde_json;
 use uuid::Uuid;
 
 use crate::{
     ClientProtocol,
+    ClientRequestEvent,
+    ClientStateQuery,
     ConnectedPlayers,
     PacketAllStates,
     PacketHeartBeat,
@@ -23,6 +28,10 @@ use crate::{
     RunTrigger,
 };
 
+use crate::handlers::room_handler::DEFAULT_ROOM_ID;
+
+// The connection request callback's error type is set by matchbox
+#[allow(clippy::result_large_err)]
 pub fn start_signaling_server(mut commands: Commands) {
     info!("Starting signaling server");
     let addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 3536);
@@ -45,15 +54,18 @@ pub fn start_signaling_server(mut commands: Commands) {
 }
 
 pub fn start_host_socket(mut commands: Commands) {
-    let socket = MatchboxSocket::new_reliable("ws://localhost:3536/minigolf");
+    let socket = MatchboxSocket::new_reliable(format!("ws://localhost:3536/{}", DEFAULT_ROOM_ID));
     commands.insert_resource(socket);
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn receive_client_requests(
     mut socket: ResMut<MatchboxSocket<SingleChannel>>,
     connected_players: ResMut<ConnectedPlayers>,
     player_info_storage: ResMut<PlayerInfoStorage>,
     mut run_trigger: ResMut<RunTrigger>,
+    mut client_state_query: ResMut<ClientStateQuery>,
+    mut client_request_writer: EventWriter<ClientRequestEvent>,
     client_protocol: Res<State<ClientProtocol>>, 
     mut set_client_protocol: ResMut<NextState<ClientProtocol>>,
 ) {
@@ -91,9 +103,16 @@ pub fn receive_client_requests(
                                     player_id, username, email
                                 );
 
+                                let Ok(uid) = Uuid::parse_str(player_id) else {
+                                    warn!("InitPlayerConnection from peer {} carried a malformed player id", _id);
+                                    continue;
+                                };
+                                let live_peers: Vec<PeerId> = socket.connected_peers().collect();
+                                if !connected_players.bind_peer(uid, _id, &live_peers) {
+                                    continue;
+                                }
                                 let player = PlayerInfo::new(player_id.to_string(), username.to_string(), email.to_string());
                                 player_info_storage.add(player);
-                                connected_players.add_player_string(String::from(player_id));
                                 run_trigger.set_target("db_pipeline_player_init", true);
                                 send_client_state_update_bool = true;
                             };
@@ -106,7 +125,11 @@ pub fn receive_client_requests(
                             match serde_json::from_str::<PacketAllStates>(payload) {
                                 Ok(all_states) => {
                                     info!("Received PacketAllStates for peer {:?}: {:?}", _id, all_states);
-                                    // Handle the deserialized PacketAllStates data as needed
+                                    if connected_players.verify_peer(&all_states.player_id, _id).is_none() {
+                                        continue;
+                                    }
+                                    connected_players.set_states(&all_states);
+                                    client_state_query.record_response(all_states);
                                 }
                                 Err(err) => {
                                     error!("Failed to deserialize PacketAllStates from JSON: {:?}", err);
@@ -121,17 +144,24 @@ pub fn receive_client_requests(
                             match serde_json::from_str::<PacketHeartBeat>(payload) {
                                 Ok(heart_beat) => {
                                     info!("Received PacketHeartBeat for peer {:?}: {:?}", _id, heart_beat);
-                                    let id = heart_beat.player_id;
-                                    let uid = Uuid::from_str(&id);
-                                    connected_players.update_heartbeat(&uid.unwrap());
+                                    // Only the peer bound by InitPlayerConnection keeps a player alive
+                                    if let Some(uid) = connected_players.verify_peer(&heart_beat.player_id, _id) {
+                                        connected_players.update_heartbeat(&uid);
                                     }
+                                }
                                 Err(err) => {
                                     error!("Failed to deserialize PacketAllStates from JSON: {:?}", err);
                                 }
                             }
                         }
                         _ => {
-                            info!("Unknown command received: {}", command);
+                            // Everything else belongs to the feature handlers listening for ClientRequestEvent
+                            info!("Forwarding command: {}", command);
+                            client_request_writer.send(ClientRequestEvent {
+                                peer: _id,
+                                command: command.to_string(),
+                                payload: payload.to_string(),
+                            });
                         }
                     }
                 } else {
@@ -169,28 +199,54 @@ pub fn send_client_state_update(
     }
 }
 
-pub fn network_get_client_state_game(
-    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
-    mut run_trigger: ResMut<RunTrigger>,
+// Client bound messages share the "(player_id, Command(payload))" shape and are MessagePack encoded
+// the same way clients encode what they send to us.
+pub fn send_encoded_message(
+    socket: &mut MatchboxSocket<SingleChannel>,
+    peer: PeerId,
+    message: String,
 ) {
-    let peers: Vec<_> = socket.connected_peers().collect();
-
-    for peer in peers {
-        let trigger = "network_get_client_state_game";
-        info!("Sending message: {trigger:?} to {peer}");
-        socket.send(trigger.as_bytes().into(), peer);
+    match encode::to_vec(&message) {
+        Ok(serialized_message) => {
+            info!("Sending message: {message:?} to {peer}");
+            socket.send(serialized_message.into(), peer);
+        }
+        Err(err) => {
+            error!("Failed to serialize message for sending: {:?}", err);
+        }
     }
-    run_trigger.set_target("network_get_client_state_game", false);
 }
 
-                        // "REQUEST_FULL_MAP_SETS" => {
-                        //     match encode::to_vec(&*map_sets) {
-                        //         Ok(serialized_map_sets) => {
-                        //             socket.send(serialized_map_sets.into(), _id);
-                        //             info!("Sent full map sets to peer: {:?}", _id);
-                        //         }
-                        //         Err(ser_err) => {
-                        //             error!("Failed to serialize map sets for sending: {:?}", ser_err);
-                        //         }
-                        //     }
-                        // },
+// Serializes a packet as JSON and sends it to the peer recorded for the player
+pub fn send_player_message<T: Serialize>(
+    socket: &mut MatchboxSocket<SingleChannel>,
+    connected_players: &ConnectedPlayers,
+    player_id: &Uuid,
+    command: &str,
+    packet: &T,
+) {
+    let Some(peer) = connected_players.get_peer(player_id) else {
+        warn!("No peer recorded for player {}, dropping {}", player_id, command);
+        return;
+    };
+    send_peer_message(socket, peer, player_id, command, packet);
+}
+
+// Same as send_player_message for callers that already know the peer, e.g. replies to a request
+pub fn send_peer_message<T: Serialize>(
+    socket: &mut MatchboxSocket<SingleChannel>,
+    peer: PeerId,
+    player_id: &Uuid,
+    command: &str,
+    packet: &T,
+) {
+    match serde_json::to_string(packet) {
+        Ok(payload) => {
+            let message = format!("({}, {}({}))", player_id, command, payload);
+            send_encoded_message(socket, peer, message);
+        }
+        Err(err) => {
+            error!("Failed to serialize {} for sending: {:?}", command, err);
+        }
+    }
+}
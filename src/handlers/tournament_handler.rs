@@ -0,0 +1,1043 @@
+use bevy::prelude::*;
+
+use bevy_matchbox::prelude::*;
+use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+use sqlx::{MySqlConnection, MySqlPool, Error};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    Admins,
+    ClientRequestEvent,
+    ConnectedPlayers,
+    DatabasePool,
+    GameSession,
+    GameSessions,
+    MapSets,
+    PacketTournamentCancel,
+    PacketTournamentListRequest,
+    PacketTournamentRegister,
+    PacketTournamentRejected,
+    PacketTournamentSchedule,
+    PacketTournamentWithdraw,
+    PacketTournaments,
+    PlayerRatings,
+    Rooms,
+    Tournament,
+    TournamentEntrant,
+    TournamentFormat,
+    TournamentMatch,
+    TournamentMatchState,
+    TournamentState,
+    Tournaments,
+};
+
+use crate::handlers::{
+    game_session_handler::broadcast_session_state,
+    rating_handler::DEFAULT_RATING,
+    signaling_server_handler::send_player_message,
+};
+
+const MIN_ENTRANTS: usize = 2;
+const NAME_MAX_LENGTH: usize = 64;
+// How long a round waits for absent players before their match starts without them
+const FORFEIT_AFTER: Duration = Duration::from_secs(300);
+
+type TournamentRow = (Uuid, String, Uuid, String, String, OffsetDateTime, OffsetDateTime, i32, i32, i32, i32);
+type EntrantRow = (Uuid, i32, i32, Option<i32>, Option<i32>);
+
+impl TournamentFormat {
+    fn key(&self) -> &'static str {
+        match self {
+            TournamentFormat::StrokePlay => "stroke_play",
+            TournamentFormat::Elimination => "elimination",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        match key {
+            "stroke_play" => Some(TournamentFormat::StrokePlay),
+            "elimination" => Some(TournamentFormat::Elimination),
+            _ => None,
+        }
+    }
+}
+
+impl TournamentState {
+    fn key(&self) -> &'static str {
+        match self {
+            TournamentState::Scheduled => "scheduled",
+            TournamentState::Registration => "registration",
+            TournamentState::InProgress => "in_progress",
+            TournamentState::Finished => "finished",
+            TournamentState::Cancelled => "cancelled",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        match key {
+            "scheduled" => Some(TournamentState::Scheduled),
+            "registration" => Some(TournamentState::Registration),
+            "in_progress" => Some(TournamentState::InProgress),
+            "finished" => Some(TournamentState::Finished),
+            "cancelled" => Some(TournamentState::Cancelled),
+            _ => None,
+        }
+    }
+}
+
+impl TournamentMatchState {
+    fn key(&self) -> &'static str {
+        match self {
+            TournamentMatchState::Pending => "pending",
+            TournamentMatchState::InProgress => "in_progress",
+            TournamentMatchState::Completed => "completed",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        match key {
+            "pending" => Some(TournamentMatchState::Pending),
+            "in_progress" => Some(TournamentMatchState::InProgress),
+            "completed" => Some(TournamentMatchState::Completed),
+            _ => None,
+        }
+    }
+}
+
+impl Tournament {
+    pub fn new(packet: &PacketTournamentSchedule, map_set_id: Uuid) -> Result<Self, String> {
+        let name = packet.name.trim();
+        if name.is_empty() || name.len() > NAME_MAX_LENGTH {
+            return Err(format!("Tournament name must be between 1 and {} characters", NAME_MAX_LENGTH));
+        }
+        let registration_opens = OffsetDateTime::from_unix_timestamp(packet.registration_opens)
+            .map_err(|_| String::from("Invalid registration_opens"))?;
+        let registration_closes = OffsetDateTime::from_unix_timestamp(packet.registration_closes)
+            .map_err(|_| String::from("Invalid registration_closes"))?;
+        if registration_closes <= registration_opens {
+            return Err(String::from("Registration must close after it opens"));
+        }
+        if registration_closes <= OffsetDateTime::now_utc() {
+            return Err(String::from("Registration must close in the future"));
+        }
+        if packet.group_size < 2 {
+            return Err(String::from("Groups need at least two players"));
+        }
+        match packet.format {
+            TournamentFormat::StrokePlay if packet.rounds < 1 => {
+                return Err(String::from("Stroke play needs at least one round"));
+            }
+            TournamentFormat::Elimination if packet.advance_count < 1 || packet.advance_count >= packet.group_size => {
+                return Err(String::from("Elimination must advance at least one and fewer than the group size"));
+            }
+            _ => {}
+        }
+
+        Ok(Self {
+            tournament_id: Uuid::now_v7(),
+            name: String::from(name),
+            map_set_id,
+            format: packet.format,
+            state: TournamentState::Scheduled,
+            registration_opens,
+            registration_closes,
+            rounds: packet.rounds,
+            group_size: packet.group_size,
+            advance_count: packet.advance_count,
+            current_round: 0,
+            entrants: Vec::new(),
+            matches: Vec::new(),
+            round_started: None,
+        })
+    }
+
+    pub fn is_entrant(&self, player_id: &Uuid) -> bool {
+        self.entrants.iter().any(|entrant| &entrant.player_id == player_id)
+    }
+
+    pub fn register(&mut self, player_id: Uuid) -> Result<(), String> {
+        if self.state != TournamentState::Registration {
+            return Err(String::from("Registration is not open"));
+        }
+        if self.is_entrant(&player_id) {
+            return Err(String::from("Already registered"));
+        }
+        let seed = self.entrants.iter().map(|entrant| entrant.seed).max().unwrap_or(0) + 1;
+        self.entrants.push(TournamentEntrant {
+            player_id,
+            seed,
+            total_strokes: 0,
+            eliminated_round: None,
+            final_rank: None,
+        });
+        Ok(())
+    }
+
+    pub fn withdraw(&mut self, player_id: &Uuid) -> Result<(), String> {
+        if self.state != TournamentState::Registration {
+            return Err(String::from("Withdrawing is only possible during registration"));
+        }
+        let idx = self
+            .entrants
+            .iter()
+            .position(|entrant| &entrant.player_id == player_id)
+            .ok_or(String::from("Not registered"))?;
+        self.entrants.remove(idx);
+        Ok(())
+    }
+
+    pub fn active_players(&self) -> Vec<Uuid> {
+        self.entrants
+            .iter()
+            .filter(|entrant| entrant.eliminated_round.is_none())
+            .map(|entrant| entrant.player_id)
+            .collect()
+    }
+
+    // Seeds by the best cached rating in any play style, registration order breaking ties
+    fn seed_entrants(&mut self, player_ratings: &PlayerRatings) {
+        let best_rating = |player_id: &Uuid| {
+            player_ratings
+                .ratings
+                .values()
+                .filter(|rating| &rating.player_id == player_id)
+                .map(|rating| rating.rating)
+                .fold(DEFAULT_RATING, f64::max)
+        };
+        self.entrants.sort_by(|a, b| {
+            best_rating(&b.player_id)
+                .total_cmp(&best_rating(&a.player_id))
+                .then(a.seed.cmp(&b.seed))
+        });
+        for (idx, entrant) in self.entrants.iter_mut().enumerate() {
+            entrant.seed = idx as i32 + 1;
+        }
+    }
+
+    // Splits the remaining players into groups of at most group_size, snaking through the
+    // standings so the strongest players are spread across groups
+    fn start_round(&mut self) {
+        self.current_round += 1;
+        let players = self.active_players();
+        let group_count = players.len().div_ceil(self.group_size).max(1);
+        let mut groups: Vec<Vec<Uuid>> = vec![Vec::new(); group_count];
+        for (idx, player_id) in players.into_iter().enumerate() {
+            let lap = idx / group_count;
+            let position = idx % group_count;
+            let group = if lap.is_multiple_of(2) { position } else { group_count - 1 - position };
+            groups[group].push(player_id);
+        }
+        for player_ids in groups.into_iter().filter(|group| !group.is_empty()) {
+            self.matches.push(TournamentMatch {
+                match_id: Uuid::now_v7(),
+                round: self.current_round,
+                player_ids,
+                session_id: None,
+                state: TournamentMatchState::Pending,
+                results: Vec::new(),
+            });
+        }
+        self.round_started = Some(Instant::now());
+        info!("Tournament [{}] started round {}", self.tournament_id, self.current_round);
+    }
+
+    fn round_complete(&self) -> bool {
+        let mut matches = self.matches.iter().filter(|tournament_match| tournament_match.round == self.current_round).peekable();
+        matches.peek().is_some() && matches.all(|tournament_match| tournament_match.state == TournamentMatchState::Completed)
+    }
+
+    // Stores the outcome of a finished game session; players no longer in the session forfeited
+    fn record_session(&mut self, session: &GameSession) {
+        if let Some(tournament_match) = self.matches.iter_mut().find(|tournament_match| tournament_match.session_id == Some(session.session_id)) {
+            tournament_match.results = session
+                .player_order
+                .iter()
+                .map(|player_id| (*player_id, session.total_strokes(player_id)))
+                .collect();
+            tournament_match.state = TournamentMatchState::Completed;
+        }
+    }
+
+    fn eliminate(&mut self, player_id: &Uuid, round: i32) {
+        if let Some(entrant) = self.entrants.iter_mut().find(|entrant| &entrant.player_id == player_id) {
+            if entrant.eliminated_round.is_none() {
+                entrant.eliminated_round = Some(round);
+            }
+        }
+    }
+
+    fn complete_round(&mut self) {
+        let round = self.current_round;
+        let seeds: HashMap<Uuid, i32> = self.entrants.iter().map(|entrant| (entrant.player_id, entrant.seed)).collect();
+        let round_matches: Vec<TournamentMatch> = self
+            .matches
+            .iter()
+            .filter(|tournament_match| tournament_match.round == round)
+            .cloned()
+            .collect();
+
+        for tournament_match in round_matches.iter() {
+            for player_id in tournament_match.player_ids.iter() {
+                match tournament_match.results.iter().find(|(result_player, _)| result_player == player_id) {
+                    Some((_, strokes)) => {
+                        if let Some(entrant) = self.entrants.iter_mut().find(|entrant| &entrant.player_id == player_id) {
+                            entrant.total_strokes += strokes;
+                        }
+                    }
+                    None => self.eliminate(player_id, round),
+                }
+            }
+            if self.format == TournamentFormat::Elimination && round_matches.len() > 1 {
+                // Groups can be smaller than group_size, and every group of two or more must
+                // knock someone out or the bracket would replay the same round forever
+                let advancing = self.advance_count.min(tournament_match.player_ids.len().saturating_sub(1)).max(1);
+                let mut ranked = tournament_match.results.clone();
+                ranked.sort_by_key(|(player_id, strokes)| (*strokes, seeds.get(player_id).copied().unwrap_or(i32::MAX)));
+                for (player_id, _) in ranked.iter().skip(advancing) {
+                    self.eliminate(player_id, round);
+                }
+            }
+        }
+        self.sort_standings();
+
+        let remaining = self.active_players().len();
+        let finished = match self.format {
+            TournamentFormat::StrokePlay => round >= self.rounds || remaining == 0,
+            // A round played as a single group is the final
+            TournamentFormat::Elimination => round_matches.len() <= 1 || remaining <= 1,
+        };
+        if finished {
+            self.finish();
+        } else {
+            self.start_round();
+        }
+    }
+
+    fn sort_standings(&mut self) {
+        // Strokes in each player's most recent completed match, used to order elimination brackets
+        let mut last_strokes: HashMap<Uuid, i32> = HashMap::new();
+        for tournament_match in self.matches.iter().filter(|tournament_match| tournament_match.state == TournamentMatchState::Completed) {
+            for (player_id, strokes) in tournament_match.results.iter() {
+                last_strokes.insert(*player_id, *strokes);
+            }
+        }
+        match self.format {
+            TournamentFormat::StrokePlay => {
+                self.entrants.sort_by_key(|entrant| (entrant.eliminated_round.is_some(), entrant.total_strokes, entrant.seed));
+            }
+            TournamentFormat::Elimination => {
+                self.entrants.sort_by_key(|entrant| {
+                    (
+                        Reverse(entrant.eliminated_round.unwrap_or(i32::MAX)),
+                        last_strokes.get(&entrant.player_id).copied().unwrap_or(i32::MAX),
+                        entrant.seed,
+                    )
+                });
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        self.state = TournamentState::Finished;
+        for (idx, entrant) in self.entrants.iter_mut().enumerate() {
+            entrant.final_rank = Some(idx as i32 + 1);
+        }
+        info!("Tournament [{}] finished after {} round(s)", self.tournament_id, self.current_round);
+    }
+
+    // A restart loses every running game session, so interrupted matches are replayed
+    fn reset_interrupted_matches(&mut self) {
+        for tournament_match in self.matches.iter_mut() {
+            if tournament_match.state == TournamentMatchState::InProgress {
+                tournament_match.state = TournamentMatchState::Pending;
+                tournament_match.session_id = None;
+            }
+        }
+        if self.state == TournamentState::InProgress {
+            self.round_started = Some(Instant::now());
+        }
+    }
+}
+
+impl Tournaments {
+    pub fn new() -> Self {
+        Self {
+            tournaments: HashMap::new(),
+            dirty: HashSet::new(),
+            saving: false,
+            loaded: false,
+            loading: false,
+        }
+    }
+
+    pub fn list(&self) -> Vec<Tournament> {
+        let mut tournaments: Vec<Tournament> = self.tournaments.values().cloned().collect();
+        tournaments.sort_by_key(|tournament| tournament.registration_opens);
+        tournaments
+    }
+
+    // Drops finished and cancelled tournaments once their final state is stored; like a
+    // restart, which only loads unfinished ones, they then leave the list
+    fn evict_completed(&mut self) {
+        if self.saving {
+            return;
+        }
+        let dirty = &self.dirty;
+        self.tournaments.retain(|tournament_id, tournament| {
+            let completed = matches!(tournament.state, TournamentState::Finished | TournamentState::Cancelled);
+            !completed || dirty.contains(tournament_id)
+        });
+    }
+}
+
+impl Default for Tournaments {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn broadcast_tournament(
+    socket: &mut MatchboxSocket<SingleChannel>,
+    connected_players: &ConnectedPlayers,
+    tournament: &Tournament,
+    extra_recipients: &[Uuid],
+) {
+    for player_id in tournament.entrants.iter().map(|entrant| &entrant.player_id).chain(extra_recipients.iter()) {
+        send_player_message(socket, connected_players, player_id, "TournamentState", tournament);
+    }
+}
+
+fn send_tournament_rejection(
+    socket: &mut MatchboxSocket<SingleChannel>,
+    connected_players: &ConnectedPlayers,
+    player_id: &Uuid,
+    tournament_id: Option<String>,
+    reason: String,
+) {
+    warn!("Rejected tournament request from {}: {}", player_id, reason);
+    let rejection = PacketTournamentRejected {
+        tournament_id,
+        reason,
+    };
+    send_player_message(socket, connected_players, player_id, "TournamentRejected", &rejection);
+}
+
+async fn save_tournament(
+    conn: &mut MySqlConnection,
+    tournament: &Tournament,
+) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT INTO tournament (tournament_id, name, map_set_id, format, state, registration_opens,
+            registration_closes, rounds, group_size, advance_count, current_round)
+         VALUES (UUID_TO_BIN(?), ?, UUID_TO_BIN(?), ?, ?, ?, ?, ?, ?, ?, ?)
+         ON DUPLICATE KEY UPDATE state = VALUES(state), current_round = VALUES(current_round)",
+    )
+    .bind(tournament.tournament_id.to_string())
+    .bind(&tournament.name)
+    .bind(tournament.map_set_id.to_string())
+    .bind(tournament.format.key())
+    .bind(tournament.state.key())
+    .bind(tournament.registration_opens)
+    .bind(tournament.registration_closes)
+    .bind(tournament.rounds)
+    .bind(tournament.group_size as i32)
+    .bind(tournament.advance_count as i32)
+    .bind(tournament.current_round)
+    .execute(&mut *conn)
+    .await?;
+
+    // Entrants and matches are small, so they are rewritten as a whole
+    sqlx::query("DELETE FROM tournament_entrant WHERE tournament_id = UUID_TO_BIN(?)")
+        .bind(tournament.tournament_id.to_string())
+        .execute(&mut *conn)
+        .await?;
+    for entrant in tournament.entrants.iter() {
+        sqlx::query(
+            "INSERT INTO tournament_entrant (tournament_id, player_id, seed, total_strokes, eliminated_round, final_rank)
+             VALUES (UUID_TO_BIN(?), UUID_TO_BIN(?), ?, ?, ?, ?)",
+        )
+        .bind(tournament.tournament_id.to_string())
+        .bind(entrant.player_id.to_string())
+        .bind(entrant.seed)
+        .bind(entrant.total_strokes)
+        .bind(entrant.eliminated_round)
+        .bind(entrant.final_rank)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    sqlx::query("DELETE FROM tournament_match WHERE tournament_id = UUID_TO_BIN(?)")
+        .bind(tournament.tournament_id.to_string())
+        .execute(&mut *conn)
+        .await?;
+    for tournament_match in tournament.matches.iter() {
+        let player_ids = serde_json::to_string(&tournament_match.player_ids).map_err(|err| Error::Decode(Box::new(err)))?;
+        let results = serde_json::to_string(&tournament_match.results).map_err(|err| Error::Decode(Box::new(err)))?;
+        sqlx::query(
+            "INSERT INTO tournament_match (match_id, tournament_id, round, player_ids, session_id, state, results)
+             VALUES (UUID_TO_BIN(?), UUID_TO_BIN(?), ?, ?, UUID_TO_BIN(?), ?, ?)",
+        )
+        .bind(tournament_match.match_id.to_string())
+        .bind(tournament.tournament_id.to_string())
+        .bind(tournament_match.round)
+        .bind(player_ids)
+        .bind(tournament_match.session_id.map(|session_id| session_id.to_string()))
+        .bind(tournament_match.state.key())
+        .bind(results)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+async fn save_tournaments(
+    pool: &MySqlPool,
+    tournaments: &[Tournament],
+) -> Result<(), Error> {
+    let mut tx = pool.begin().await?;
+    for tournament in tournaments.iter() {
+        save_tournament(&mut tx, tournament).await?;
+    }
+    tx.commit().await
+}
+
+async fn load_unfinished_tournaments(pool: &MySqlPool) -> Result<Vec<Tournament>, Error> {
+    let rows: Vec<TournamentRow> = sqlx::query_as(
+        "SELECT tournament_id, name, map_set_id, format, state, registration_opens, registration_closes,
+            rounds, group_size, advance_count, current_round
+         FROM tournament WHERE state IN ('scheduled', 'registration', 'in_progress')",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut tournaments = Vec::new();
+    for (tournament_id, name, map_set_id, format, state, registration_opens, registration_closes, rounds, group_size, advance_count, current_round) in rows {
+        let (Some(format), Some(state)) = (TournamentFormat::from_key(&format), TournamentState::from_key(&state)) else {
+            warn!("Skipping tournament [{}] with unknown format or state", tournament_id);
+            continue;
+        };
+
+        let entrants: Vec<EntrantRow> = sqlx::query_as(
+            "SELECT player_id, seed, total_strokes, eliminated_round, final_rank
+             FROM tournament_entrant WHERE tournament_id = UUID_TO_BIN(?) ORDER BY seed ASC",
+        )
+        .bind(tournament_id.to_string())
+        .fetch_all(pool)
+        .await?;
+
+        let match_rows: Vec<(Uuid, i32, String, Option<Uuid>, String, String)> = sqlx::query_as(
+            "SELECT match_id, round, player_ids, session_id, state, results
+             FROM tournament_match WHERE tournament_id = UUID_TO_BIN(?) ORDER BY round ASC",
+        )
+        .bind(tournament_id.to_string())
+        .fetch_all(pool)
+        .await?;
+        let mut matches = Vec::new();
+        for (match_id, round, player_ids, session_id, match_state, results) in match_rows {
+            matches.push(TournamentMatch {
+                match_id,
+                round,
+                player_ids: serde_json::from_str(&player_ids).map_err(|err| Error::Decode(Box::new(err)))?,
+                session_id,
+                state: TournamentMatchState::from_key(&match_state).unwrap_or(TournamentMatchState::Pending),
+                results: serde_json::from_str(&results).map_err(|err| Error::Decode(Box::new(err)))?,
+            });
+        }
+
+        let mut tournament = Tournament {
+            tournament_id,
+            name,
+            map_set_id,
+            format,
+            state,
+            registration_opens,
+            registration_closes,
+            rounds,
+            group_size: group_size as usize,
+            advance_count: advance_count as usize,
+            current_round,
+            entrants: entrants
+                .into_iter()
+                .map(|(player_id, seed, total_strokes, eliminated_round, final_rank)| TournamentEntrant {
+                    player_id,
+                    seed,
+                    total_strokes,
+                    eliminated_round,
+                    final_rank,
+                })
+                .collect(),
+            matches,
+            round_started: None,
+        };
+        if tournament.state == TournamentState::InProgress {
+            tournament.sort_standings();
+        }
+        tournament.reset_interrupted_matches();
+        tournaments.push(tournament);
+    }
+    Ok(tournaments)
+}
+
+pub fn tournament_load_system(
+    mut tournaments: ResMut<Tournaments>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    if tournaments.loaded || tournaments.loading {
+        return;
+    }
+    tournaments.loading = true;
+    let pool = pool.0.clone();
+    // Spawn the background task using bevy_tokio_tasks
+    runtime.spawn_background_task(move |ctx| {
+        tournament_load_async(pool, ctx)
+    });
+}
+
+pub async fn tournament_load_async(
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let loaded = match load_unfinished_tournaments(&pool).await {
+        Ok(loaded) => Some(loaded),
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to load tournaments: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to load tournaments in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            None
+        }
+    };
+
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some(mut tournaments) = ctx.world.get_resource_mut::<Tournaments>() {
+            // On failure the load system tries again on its next tick
+            tournaments.loading = false;
+            if let Some(loaded) = loaded {
+                info!("Restored {} unfinished tournament(s)", loaded.len());
+                for tournament in loaded {
+                    tournaments.tournaments.entry(tournament.tournament_id).or_insert(tournament);
+                }
+                tournaments.loaded = true;
+            }
+        }
+    })
+    .await;
+}
+
+pub fn tournament_persist_system(
+    mut tournaments: ResMut<Tournaments>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    // Saves run one at a time so an older snapshot can never overwrite a newer one
+    if tournaments.saving {
+        return;
+    }
+    tournaments.evict_completed();
+    if tournaments.dirty.is_empty() {
+        return;
+    }
+    let dirty: Vec<Uuid> = tournaments.dirty.drain().collect();
+    let snapshots: Vec<Tournament> = dirty
+        .iter()
+        .filter_map(|tournament_id| tournaments.tournaments.get(tournament_id).cloned())
+        .collect();
+    tournaments.saving = true;
+    let pool = pool.0.clone();
+    runtime.spawn_background_task(move |ctx| {
+        tournament_persist_async(snapshots, pool, ctx)
+    });
+}
+
+pub async fn tournament_persist_async(
+    snapshots: Vec<Tournament>,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let failed = match save_tournaments(&pool, &snapshots).await {
+        Ok(()) => false,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to save tournaments: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to save tournaments in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            true
+        }
+    };
+
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some(mut tournaments) = ctx.world.get_resource_mut::<Tournaments>() {
+            tournaments.saving = false;
+            if failed {
+                // Retried with whatever state they have by the next save
+                tournaments.dirty.extend(snapshots.iter().map(|tournament| tournament.tournament_id));
+            }
+        }
+    })
+    .await;
+}
+
+// Drives every tournament through its schedule and rounds
+pub fn tournament_system(
+    mut tournaments: ResMut<Tournaments>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    map_sets: Res<MapSets>,
+    player_ratings: Res<PlayerRatings>,
+    mut game_sessions: ResMut<GameSessions>,
+    mut rooms: ResMut<Rooms>,
+) {
+    if !tournaments.loaded {
+        return;
+    }
+    let now = OffsetDateTime::now_utc();
+    let mut changed = Vec::new();
+
+    for tournament in tournaments.tournaments.values_mut() {
+        let mut is_changed = false;
+        match tournament.state {
+            TournamentState::Scheduled if now >= tournament.registration_opens => {
+                info!("Tournament [{}] registration open", tournament.tournament_id);
+                tournament.state = TournamentState::Registration;
+                is_changed = true;
+            }
+            TournamentState::Registration if now >= tournament.registration_closes => {
+                if tournament.entrants.len() < MIN_ENTRANTS {
+                    info!("Tournament [{}] cancelled, not enough entrants", tournament.tournament_id);
+                    tournament.state = TournamentState::Cancelled;
+                } else {
+                    tournament.seed_entrants(&player_ratings);
+                    tournament.state = TournamentState::InProgress;
+                    tournament.start_round();
+                }
+                is_changed = true;
+            }
+            TournamentState::InProgress => {
+                // Collect finished or abandoned game sessions
+                let mut finished_sessions = Vec::new();
+                for tournament_match in tournament.matches.iter_mut() {
+                    if tournament_match.state != TournamentMatchState::InProgress {
+                        continue;
+                    }
+                    let Some(session_id) = tournament_match.session_id else {
+                        continue;
+                    };
+                    if game_sessions.get(&session_id).is_some() {
+                        continue;
+                    }
+                    match game_sessions.get_recently_finished(&session_id) {
+                        Some(session) => finished_sessions.push(session.clone()),
+                        None => {
+                            // Everyone left the session, so everyone forfeits
+                            tournament_match.state = TournamentMatchState::Completed;
+                            is_changed = true;
+                        }
+                    }
+                }
+                for session in finished_sessions.iter() {
+                    tournament.record_session(session);
+                    is_changed = true;
+                }
+
+                // Start pending matches once their players are here or the grace period ran out
+                let round = tournament.current_round;
+                let grace_over = tournament.round_started.is_none_or(|started| started.elapsed() >= FORFEIT_AFTER);
+                if let Some(map_set) = map_sets.get(&tournament.map_set_id) {
+                    for tournament_match in tournament.matches.iter_mut() {
+                        if tournament_match.round != round || tournament_match.state != TournamentMatchState::Pending {
+                            continue;
+                        }
+                        let available: Vec<Uuid> = tournament_match
+                            .player_ids
+                            .iter()
+                            .filter(|player_id| connected_players.get_peer(player_id).is_some())
+                            .filter(|player_id| game_sessions.session_for_player(player_id).is_none())
+                            .copied()
+                            .collect();
+                        if available.len() < tournament_match.player_ids.len() && !grace_over {
+                            continue;
+                        }
+                        if available.is_empty() {
+                            tournament_match.state = TournamentMatchState::Completed;
+                            is_changed = true;
+                            continue;
+                        }
+                        let play_style = available.iter().find_map(|player_id| connected_players.get_play_style(player_id));
+                        let session = GameSession::new(map_set, available, String::new(), play_style)
+                            .and_then(|session| game_sessions.insert_in_new_room(session, &mut rooms));
+                        match session {
+                            Ok(session) => {
+                                info!("Tournament [{}] round {} match [{}] started", tournament.tournament_id, round, tournament_match.match_id);
+                                tournament_match.session_id = Some(session.session_id);
+                                tournament_match.state = TournamentMatchState::InProgress;
+                                broadcast_session_state(&mut socket, &connected_players, &session);
+                                is_changed = true;
+                            }
+                            Err(reason) => error!("tournament_system: failed to start match: {}", reason),
+                        }
+                    }
+                }
+
+                if tournament.round_complete() {
+                    tournament.complete_round();
+                    is_changed = true;
+                }
+            }
+            _ => {}
+        }
+        if is_changed {
+            broadcast_tournament(&mut socket, &connected_players, tournament, &[]);
+            changed.push(tournament.tournament_id);
+        }
+    }
+    tournaments.dirty.extend(changed);
+}
+
+pub fn tournament_request_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    admins: Res<Admins>,
+    map_sets: Res<MapSets>,
+    mut tournaments: ResMut<Tournaments>,
+) {
+    for event in event_reader.read() {
+        match event.command.as_str() {
+            "TournamentSchedule" => {
+                let packet = match serde_json::from_str::<PacketTournamentSchedule>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketTournamentSchedule from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                if !admins.is_admin(&player_id, event.peer) {
+                    send_tournament_rejection(&mut socket, &connected_players, &player_id, None, String::from("Only admins can schedule tournaments"));
+                    continue;
+                }
+                let result = match Uuid::parse_str(&packet.map_set_id) {
+                    Ok(map_set_id) if map_sets.playable(&map_set_id).is_some() => Tournament::new(&packet, map_set_id),
+                    _ => Err(String::from("Unknown map set")),
+                };
+                match result {
+                    Ok(tournament) => {
+                        info!("Admin {} scheduled tournament [{}] {:?}", player_id, tournament.tournament_id, tournament.name);
+                        send_player_message(&mut socket, &connected_players, &player_id, "TournamentState", &tournament);
+                        tournaments.dirty.insert(tournament.tournament_id);
+                        tournaments.tournaments.insert(tournament.tournament_id, tournament);
+                    }
+                    Err(reason) => send_tournament_rejection(&mut socket, &connected_players, &player_id, None, reason),
+                }
+            }
+            "TournamentCancel" => {
+                let packet = match serde_json::from_str::<PacketTournamentCancel>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketTournamentCancel from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                if !admins.is_admin(&player_id, event.peer) {
+                    send_tournament_rejection(&mut socket, &connected_players, &player_id, Some(packet.tournament_id), String::from("Only admins can cancel tournaments"));
+                    continue;
+                }
+                let tournament = Uuid::parse_str(&packet.tournament_id)
+                    .ok()
+                    .and_then(|tournament_id| tournaments.tournaments.get_mut(&tournament_id))
+                    .filter(|tournament| matches!(tournament.state, TournamentState::Scheduled | TournamentState::Registration | TournamentState::InProgress));
+                let Some(tournament) = tournament else {
+                    send_tournament_rejection(&mut socket, &connected_players, &player_id, Some(packet.tournament_id), String::from("No active tournament with that id"));
+                    continue;
+                };
+                info!("Admin {} cancelled tournament [{}]", player_id, tournament.tournament_id);
+                tournament.state = TournamentState::Cancelled;
+                let tournament = tournament.clone();
+                broadcast_tournament(&mut socket, &connected_players, &tournament, &[player_id]);
+                tournaments.dirty.insert(tournament.tournament_id);
+            }
+            "TournamentRegister" | "TournamentWithdraw" => {
+                let request = if event.command == "TournamentRegister" {
+                    serde_json::from_str::<PacketTournamentRegister>(&event.payload)
+                        .map(|packet| (packet.player_id, packet.tournament_id))
+                } else {
+                    serde_json::from_str::<PacketTournamentWithdraw>(&event.payload)
+                        .map(|packet| (packet.player_id, packet.tournament_id))
+                };
+                let (player_id, tournament_id) = match request {
+                    Ok(request) => request,
+                    Err(err) => {
+                        error!("Failed to deserialize {} packet from JSON: {:?}", event.command, err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&player_id, event.peer) else {
+                    continue;
+                };
+                let Some(tournament) = Uuid::parse_str(&tournament_id).ok().and_then(|id| tournaments.tournaments.get_mut(&id)) else {
+                    send_tournament_rejection(&mut socket, &connected_players, &player_id, Some(tournament_id), String::from("Unknown tournament"));
+                    continue;
+                };
+                let result = if event.command == "TournamentRegister" {
+                    tournament.register(player_id)
+                } else {
+                    tournament.withdraw(&player_id)
+                };
+                match result {
+                    Ok(()) => {
+                        let tournament = tournament.clone();
+                        broadcast_tournament(&mut socket, &connected_players, &tournament, &[player_id]);
+                        tournaments.dirty.insert(tournament.tournament_id);
+                    }
+                    Err(reason) => send_tournament_rejection(&mut socket, &connected_players, &player_id, Some(tournament_id), reason),
+                }
+            }
+            "TournamentListRequest" => {
+                let packet = match serde_json::from_str::<PacketTournamentListRequest>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketTournamentListRequest from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let packet = PacketTournaments {
+                    tournaments: tournaments.list(),
+                };
+                send_player_message(&mut socket, &connected_players, &player_id, "Tournaments", &packet);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PlayerRating;
+
+    fn tournament(format: TournamentFormat, group_size: usize, advance_count: usize, entrant_count: usize) -> Tournament {
+        let packet = PacketTournamentSchedule {
+            player_id: String::new(),
+            name: String::from("Test"),
+            map_set_id: String::new(),
+            format,
+            registration_opens: OffsetDateTime::now_utc().unix_timestamp(),
+            registration_closes: OffsetDateTime::now_utc().unix_timestamp() + 3600,
+            rounds: 2,
+            group_size,
+            advance_count,
+        };
+        let mut tournament = Tournament::new(&packet, Uuid::now_v7()).unwrap();
+        tournament.state = TournamentState::Registration;
+        for _ in 0..entrant_count {
+            tournament.register(Uuid::now_v7()).unwrap();
+        }
+        tournament.state = TournamentState::InProgress;
+        tournament
+    }
+
+    // Completes every match of the current round, the player with the better seed scoring lower
+    fn play_round(tournament: &mut Tournament) {
+        let seeds: HashMap<Uuid, i32> = tournament.entrants.iter().map(|entrant| (entrant.player_id, entrant.seed)).collect();
+        let round = tournament.current_round;
+        for tournament_match in tournament.matches.iter_mut().filter(|tournament_match| tournament_match.round == round) {
+            tournament_match.results = tournament_match
+                .player_ids
+                .iter()
+                .map(|player_id| (*player_id, 10 + seeds[player_id]))
+                .collect();
+            tournament_match.state = TournamentMatchState::Completed;
+        }
+        tournament.complete_round();
+    }
+
+    #[test]
+    fn seeds_by_best_rating_then_registration_order() {
+        let mut tournament = tournament(TournamentFormat::StrokePlay, 4, 0, 3);
+        let first = tournament.entrants[0].player_id;
+        let second = tournament.entrants[1].player_id;
+        let third = tournament.entrants[2].player_id;
+        let mut player_ratings = PlayerRatings::new();
+        for (player_id, play_style, value) in [(third, "casual", 1700.0), (third, "ranked", 1400.0), (second, "casual", 1300.0)] {
+            let mut rating = PlayerRating::new(player_id, play_style);
+            rating.rating = value;
+            player_ratings.ratings.insert((player_id, String::from(play_style)), rating);
+        }
+
+        tournament.seed_entrants(&player_ratings);
+
+        let order: Vec<(Uuid, i32)> = tournament.entrants.iter().map(|entrant| (entrant.player_id, entrant.seed)).collect();
+        assert_eq!(order, vec![(third, 1), (first, 2), (second, 3)]);
+    }
+
+    #[test]
+    fn groups_snake_through_the_standings() {
+        let mut tournament = tournament(TournamentFormat::StrokePlay, 3, 0, 7);
+        let players = tournament.active_players();
+
+        tournament.start_round();
+
+        let groups: Vec<Vec<Uuid>> = tournament.matches.iter().map(|tournament_match| tournament_match.player_ids.clone()).collect();
+        assert_eq!(groups, vec![
+            vec![players[0], players[5], players[6]],
+            vec![players[1], players[4]],
+            vec![players[2], players[3]],
+        ]);
+        assert!(groups.iter().all(|group| group.len() <= 3));
+    }
+
+    #[test]
+    fn elimination_knocks_someone_out_of_small_groups() {
+        // Four entrants in groups of three make two groups of two, where advancing two would eliminate nobody
+        let mut tournament = tournament(TournamentFormat::Elimination, 3, 2, 4);
+        let players = tournament.active_players();
+        tournament.start_round();
+        assert_eq!(tournament.matches.len(), 2);
+
+        play_round(&mut tournament);
+
+        assert_eq!(tournament.active_players(), vec![players[0], players[1]]);
+        assert_eq!(tournament.current_round, 2);
+        assert_eq!(tournament.state, TournamentState::InProgress);
+
+        play_round(&mut tournament);
+
+        assert_eq!(tournament.state, TournamentState::Finished);
+        let ranks: Vec<(Uuid, Option<i32>)> = tournament.entrants.iter().map(|entrant| (entrant.player_id, entrant.final_rank)).collect();
+        assert_eq!(ranks[0], (players[0], Some(1)));
+        assert_eq!(ranks[1], (players[1], Some(2)));
+    }
+
+    #[test]
+    fn elimination_keeps_advance_count_from_full_groups() {
+        let mut tournament = tournament(TournamentFormat::Elimination, 3, 2, 6);
+        let players = tournament.active_players();
+        tournament.start_round();
+
+        play_round(&mut tournament);
+
+        // Groups are {1, 4, 5} and {2, 3, 6}; the highest stroke total in each group is eliminated
+        let mut remaining = tournament.active_players();
+        remaining.sort();
+        let mut expected = vec![players[0], players[3], players[1], players[2]];
+        expected.sort();
+        assert_eq!(remaining, expected);
+    }
+}
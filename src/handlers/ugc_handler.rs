@@ -0,0 +1,1092 @@
+use bevy::prelude::*;
+use bevy_matchbox::prelude::*;
+use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sqlx::{MySqlConnection, MySqlPool, Error};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    Admins,
+    ClientRequestEvent,
+    ConnectedPlayers,
+    DatabasePool,
+    MapAssetDownloads,
+    MapAssetEntry,
+    MapAssetTransfer,
+    MapSet,
+    MapSets,
+    PacketUgcFileRequest,
+    PacketUgcReview,
+    PacketUgcSubmissionStatus,
+    PacketUgcSubmissions,
+    PacketUgcSubmissionsRequest,
+    PacketUgcSubmit,
+    PacketUgcUploadBegin,
+    PacketUgcUploadChunk,
+    PacketUgcUploadStatus,
+    Ugc,
+    UgcDefinition,
+    UgcStatus,
+    UgcSubmission,
+    UgcUpload,
+};
+
+use crate::handlers::{
+    map_asset_handler::{hash_file, hex_sha256, map_assets_dir, resolve_asset_path, resume_offset, send_asset_rejection, CHUNK_SIZE},
+    map_set_handler::{apply_map_set_change, fetch_map_set, refresh_map_set_assets, save_map_set_rows, send_admin_result},
+    signaling_server_handler::{send_peer_message, send_player_message},
+};
+
+// Uploaded files live under the assets directory so map sets can point at them like any other level
+const UGC_DIR: &str = "ugc";
+const MAX_UPLOAD_SIZE: u64 = 8 * 1024 * 1024;
+const MAX_UPLOADS_PER_PLAYER: usize = 2;
+const MAX_FILES_PER_DAY: i64 = 20; // Per player, uploading a file they already stored again is free
+const MAX_PENDING_SUBMISSIONS: i64 = 3; // Per player, so one author cannot flood the queue
+const MAX_FILE_NAME_LEN: usize = 128;
+const MAX_REASON_LEN: usize = 255;
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+// Uploads that no submission, map set or revision uses are deleted after this long
+const ORPHAN_AFTER_DAYS: i64 = 7;
+// Files on disk without a ugc_file row, e.g. after a failed insert, are deleted after this long
+const UNRECORDED_AFTER: Duration = Duration::from_secs(60 * 60);
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_HEADER_LEN: usize = 12;
+const GLB_CHUNK_JSON: u32 = 0x4E4F534A;
+const GLB_CHUNK_BIN: u32 = 0x004E4942;
+
+impl Ugc {
+    pub fn new() -> Self {
+        Self {
+            uploads: HashMap::new(),
+        }
+    }
+}
+
+impl Default for Ugc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UgcStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UgcStatus::Pending => "Pending",
+            UgcStatus::Approved => "Approved",
+            UgcStatus::Rejected => "Rejected",
+        }
+    }
+
+    fn parse(status: &str) -> Option<Self> {
+        match status {
+            "Pending" => Some(UgcStatus::Pending),
+            "Approved" => Some(UgcStatus::Approved),
+            "Rejected" => Some(UgcStatus::Rejected),
+            _ => None,
+        }
+    }
+}
+
+impl UgcDefinition {
+    // The set this definition would publish; only uploaded files are allowed, and they have to be on disk
+    pub fn to_map_set(&self, map_set_id: Uuid, root: &Path) -> Result<MapSet, String> {
+        let mut map_set = MapSet::new(map_set_id, self.map_set_name.trim().to_string(), self.hole_range_start, self.hole_range_end);
+        map_set.set_file_paths(&self.file_paths)?;
+        map_set.set_holes(self.holes.clone());
+        for (level, file_path) in map_set.levels() {
+            if ugc_file_hash(&file_path).is_none() {
+                return Err(format!("Level {} file {:?} is not an uploaded file", level, file_path));
+            }
+        }
+        map_set.validate(root)?;
+        Ok(map_set)
+    }
+}
+
+fn is_sha256_hex(hash: &str) -> bool {
+    hash.len() == 64 && hash.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+}
+
+// Uploads are stored by content hash, so the same file uploaded twice is kept once
+pub fn ugc_file_path(hash: &str) -> String {
+    format!("{}/{}.glb", UGC_DIR, hash)
+}
+
+fn ugc_file_hash(file_path: &str) -> Option<&str> {
+    let hash = file_path.strip_prefix(UGC_DIR)?.strip_prefix('/')?.strip_suffix(".glb")?;
+    is_sha256_hex(hash).then_some(hash)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4)?.try_into().ok().map(u32::from_le_bytes)
+}
+
+// Structural checks on a binary glTF: header, a JSON chunk describing at least one mesh, an optional
+// BIN chunk, and no references to files outside the upload
+pub fn validate_glb(data: &[u8]) -> Result<(), String> {
+    if data.len() < GLB_HEADER_LEN + 8 || &data[0..4] != GLB_MAGIC {
+        return Err(String::from("Not a binary glTF file"));
+    }
+    if read_u32(data, 4) != Some(2) {
+        return Err(String::from("Only glTF 2.0 is supported"));
+    }
+    if read_u32(data, 8).map(|length| length as usize) != Some(data.len()) {
+        return Err(String::from("Header length does not match the file size"));
+    }
+
+    let mut chunks = Vec::new();
+    let mut offset = GLB_HEADER_LEN;
+    while offset < data.len() {
+        let (Some(length), Some(chunk_type)) = (read_u32(data, offset), read_u32(data, offset + 4)) else {
+            return Err(String::from("Truncated chunk header"));
+        };
+        let start = offset + 8;
+        let end = start + length as usize;
+        if length % 4 != 0 || end > data.len() {
+            return Err(format!("Chunk at byte {} has an invalid length", offset));
+        }
+        chunks.push((chunk_type, &data[start..end]));
+        offset = end;
+    }
+    let Some((GLB_CHUNK_JSON, json)) = chunks.first().copied() else {
+        return Err(String::from("The first chunk must be JSON"));
+    };
+    let bin = match chunks.get(1) {
+        Some((GLB_CHUNK_BIN, bin)) => Some(*bin),
+        Some(_) => return Err(String::from("The second chunk must be BIN")),
+        None => None,
+    };
+
+    let gltf: serde_json::Value = serde_json::from_slice(json).map_err(|err| format!("Invalid glTF JSON: {}", err))?;
+    if gltf["asset"]["version"].as_str() != Some("2.0") {
+        return Err(String::from("asset.version must be 2.0"));
+    }
+    if gltf["meshes"].as_array().is_none_or(|meshes| meshes.is_empty()) {
+        return Err(String::from("The file has no meshes"));
+    }
+    for key in ["buffers", "images"] {
+        for item in gltf[key].as_array().map(Vec::as_slice).unwrap_or_default() {
+            if item["uri"].as_str().is_some_and(|uri| !uri.starts_with("data:")) {
+                return Err(format!("{} may not reference external files", key));
+            }
+        }
+    }
+    // A buffer without a uri is the BIN chunk, which has to be large enough to hold it
+    if let Some(buffer) = gltf["buffers"].as_array().and_then(|buffers| buffers.first()) {
+        if buffer["uri"].is_null() {
+            let byte_length = buffer["byteLength"].as_u64().unwrap_or(0);
+            if bin.is_none_or(|bin| (bin.len() as u64) < byte_length) {
+                return Err(String::from("The BIN chunk is missing or shorter than its buffer"));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_upload_begin(packet: &PacketUgcUploadBegin) -> Result<(), String> {
+    let file_name = packet.file_name.trim();
+    if file_name.is_empty() || file_name.chars().count() > MAX_FILE_NAME_LEN {
+        return Err(format!("File name must be 1 to {} characters", MAX_FILE_NAME_LEN));
+    }
+    if !file_name.to_lowercase().ends_with(".glb") {
+        return Err(String::from("Only .glb files can be uploaded"));
+    }
+    if packet.size < (GLB_HEADER_LEN + 8) as u64 || packet.size > MAX_UPLOAD_SIZE {
+        return Err(format!("File size must be {} to {} bytes", GLB_HEADER_LEN + 8, MAX_UPLOAD_SIZE));
+    }
+    if !is_sha256_hex(&packet.hash) {
+        return Err(String::from("Hash must be a lowercase hex SHA-256"));
+    }
+    Ok(())
+}
+
+fn upload_status(upload: &UgcUpload, error: Option<String>) -> PacketUgcUploadStatus {
+    PacketUgcUploadStatus {
+        upload_id: Some(upload.upload_id.to_string()),
+        file_name: upload.file_name.clone(),
+        received: upload.data.len() as u64,
+        size: upload.size,
+        file_path: None,
+        error,
+    }
+}
+
+pub fn ugc_upload_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    mut ugc: ResMut<Ugc>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    for event in event_reader.read() {
+        match event.command.as_str() {
+            "UgcUploadBegin" => {
+                let packet = match serde_json::from_str::<PacketUgcUploadBegin>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketUgcUploadBegin from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                // Beginning the same file again resumes it, e.g. after a reconnect
+                if let Some(upload) = ugc.uploads.values_mut().find(|upload| upload.player_id == player_id && upload.hash == packet.hash) {
+                    upload.last_activity = Instant::now();
+                    send_peer_message(&mut socket, event.peer, &player_id, "UgcUploadStatus", &upload_status(upload, None));
+                    continue;
+                }
+                let running = ugc.uploads.values().filter(|upload| upload.player_id == player_id).count();
+                let result = check_upload_begin(&packet).and_then(|_| {
+                    if running >= MAX_UPLOADS_PER_PLAYER {
+                        Err(String::from("Too many uploads in progress"))
+                    } else {
+                        Ok(())
+                    }
+                });
+                if let Err(reason) = result {
+                    let status = PacketUgcUploadStatus {
+                        upload_id: None,
+                        file_name: packet.file_name,
+                        received: 0,
+                        size: packet.size,
+                        file_path: None,
+                        error: Some(reason),
+                    };
+                    send_peer_message(&mut socket, event.peer, &player_id, "UgcUploadStatus", &status);
+                    continue;
+                }
+                let upload = UgcUpload {
+                    upload_id: Uuid::now_v7(),
+                    player_id,
+                    file_name: packet.file_name.trim().to_string(),
+                    size: packet.size,
+                    hash: packet.hash,
+                    data: Vec::new(),
+                    last_activity: Instant::now(),
+                };
+                info!("Player {} uploading {:?} ({} bytes)", player_id, upload.file_name, upload.size);
+                send_peer_message(&mut socket, event.peer, &player_id, "UgcUploadStatus", &upload_status(&upload, None));
+                ugc.uploads.insert(upload.upload_id, upload);
+            }
+            "UgcUploadChunk" => {
+                let packet = match serde_json::from_str::<PacketUgcUploadChunk>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketUgcUploadChunk from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let Some(upload) = Uuid::parse_str(&packet.upload_id)
+                    .ok()
+                    .and_then(|upload_id| ugc.uploads.get_mut(&upload_id))
+                    .filter(|upload| upload.player_id == player_id)
+                else {
+                    warn!("Player {} sent a chunk for unknown upload {:?}", player_id, packet.upload_id);
+                    continue;
+                };
+                // Out of order chunks are dropped, the status tells the client where to continue
+                if packet.offset != upload.data.len() as u64 {
+                    let reason = String::from("Unexpected offset");
+                    send_peer_message(&mut socket, event.peer, &player_id, "UgcUploadStatus", &upload_status(upload, Some(reason)));
+                    continue;
+                }
+                let data = match STANDARD.decode(&packet.data) {
+                    Ok(data) if data.len() <= CHUNK_SIZE && hex_sha256(&data) == packet.chunk_hash => data,
+                    _ => {
+                        let reason = String::from("Chunk failed its integrity check");
+                        send_peer_message(&mut socket, event.peer, &player_id, "UgcUploadStatus", &upload_status(upload, Some(reason)));
+                        continue;
+                    }
+                };
+                if upload.data.len() as u64 + data.len() as u64 > upload.size {
+                    let reason = String::from("Chunk runs past the announced size");
+                    send_peer_message(&mut socket, event.peer, &player_id, "UgcUploadStatus", &upload_status(upload, Some(reason)));
+                    continue;
+                }
+                upload.data.extend_from_slice(&data);
+                upload.last_activity = Instant::now();
+                if (upload.data.len() as u64) < upload.size {
+                    send_peer_message(&mut socket, event.peer, &player_id, "UgcUploadStatus", &upload_status(upload, None));
+                    continue;
+                }
+                if hex_sha256(&upload.data) != upload.hash {
+                    upload.data.clear();
+                    let reason = String::from("File hash does not match, upload restarted");
+                    send_peer_message(&mut socket, event.peer, &player_id, "UgcUploadStatus", &upload_status(upload, Some(reason)));
+                    continue;
+                }
+                let upload_id = upload.upload_id;
+                let Some(upload) = ugc.uploads.remove(&upload_id) else {
+                    continue;
+                };
+                let pool = pool.0.clone();
+                // Spawn the background task using bevy_tokio_tasks
+                runtime.spawn_background_task(move |ctx| {
+                    store_ugc_file_async(upload, pool, ctx)
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+// Abandoned uploads would otherwise hold their data forever
+pub fn ugc_upload_cleanup_system(
+    mut ugc: ResMut<Ugc>,
+) {
+    let now = Instant::now();
+    ugc.uploads.retain(|upload_id, upload| {
+        let active = now.duration_since(upload.last_activity) < UPLOAD_TIMEOUT;
+        if !active {
+            info!("Dropping stale upload {} of {:?} from player {}", upload_id, upload.file_name, upload.player_id);
+        }
+        active
+    });
+}
+
+// Writes the file next to its final name first so a half written file is never picked up
+async fn write_ugc_file(root: &Path, upload: &UgcUpload) -> std::io::Result<String> {
+    let file_path = ugc_file_path(&upload.hash);
+    let path = root.join(&file_path);
+    if tokio::fs::metadata(&path).await.is_ok_and(|metadata| metadata.len() == upload.size) {
+        return Ok(file_path);
+    }
+    tokio::fs::create_dir_all(root.join(UGC_DIR)).await?;
+    let temp_path = root.join(UGC_DIR).join(format!("{}.tmp", upload.upload_id));
+    tokio::fs::write(&temp_path, &upload.data).await?;
+    if let Err(err) = tokio::fs::rename(&temp_path, &path).await {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+        return Err(err);
+    }
+    Ok(file_path)
+}
+
+async fn store_ugc_file(pool: &MySqlPool, upload: &UgcUpload) -> Result<String, String> {
+    validate_glb(&upload.data)?;
+    let uploaded_today: Result<(i64,), Error> = sqlx::query_as(
+        "SELECT COUNT(*) FROM ugc_file
+         WHERE player_id = UUID_TO_BIN(?) AND hash <> ? AND uploaded > NOW() - INTERVAL 1 DAY",
+    )
+    .bind(upload.player_id.to_string())
+    .bind(&upload.hash)
+    .fetch_one(pool)
+    .await;
+    match uploaded_today {
+        Ok((count,)) if count >= MAX_FILES_PER_DAY => {
+            return Err(format!("You can upload {} files per day, try again later", MAX_FILES_PER_DAY));
+        }
+        Ok(_) => {}
+        Err(err) => {
+            eprintln!("Failed to count uploads of player {}: {:?}", upload.player_id, err.to_string());
+            return Err(String::from("Failed to store the file"));
+        }
+    }
+    let file_path = write_ugc_file(&map_assets_dir(), upload).await.map_err(|err| {
+        eprintln!("Failed to write uploaded file {:?}: {:?}", upload.file_name, err);
+        String::from("Failed to store the file")
+    })?;
+    sqlx::query(
+        "INSERT INTO ugc_file (hash, player_id, file_name, size) VALUES (?, UUID_TO_BIN(?), ?, ?)
+         ON DUPLICATE KEY UPDATE file_name = VALUES(file_name), uploaded = NOW()",
+    )
+    .bind(&upload.hash)
+    .bind(upload.player_id.to_string())
+    .bind(&upload.file_name)
+    .bind(upload.size)
+    .execute(pool)
+    .await
+    .map_err(|err| {
+        eprintln!("Failed to record uploaded file {:?}: {:?}", upload.file_name, err.to_string());
+        String::from("Failed to store the file")
+    })?;
+    Ok(file_path)
+}
+
+pub async fn store_ugc_file_async(
+    upload: UgcUpload,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let result = store_ugc_file(&pool, &upload).await;
+    ctx.run_on_main_thread(move |ctx| {
+        let Some(connected_players) = ctx.world.get_resource::<ConnectedPlayers>().cloned() else {
+            return;
+        };
+        let mut status = upload_status(&upload, None);
+        match result {
+            Ok(file_path) => {
+                info!("Player {} uploaded {:?} as {:?}", upload.player_id, upload.file_name, file_path);
+                status.file_path = Some(file_path);
+            }
+            Err(reason) => {
+                info!("Upload {:?} from player {} rejected: {}", upload.file_name, upload.player_id, reason);
+                status.error = Some(reason);
+            }
+        }
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            send_player_message(&mut socket, &connected_players, &upload.player_id, "UgcUploadStatus", &status);
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+    })
+    .await;
+}
+
+fn submission_status(submission: &UgcSubmission) -> PacketUgcSubmissionStatus {
+    PacketUgcSubmissionStatus {
+        submission_id: Some(submission.submission_id.to_string()),
+        status: Some(submission.status),
+        map_set_id: submission.map_set_id.map(|map_set_id| map_set_id.to_string()),
+        reason: submission.reason.clone(),
+    }
+}
+
+fn send_submission_refused(
+    socket: &mut MatchboxSocket<SingleChannel>,
+    peer: PeerId,
+    player_id: &Uuid,
+    reason: String,
+) {
+    info!("UgcSubmit from player {} refused: {}", player_id, reason);
+    let status = PacketUgcSubmissionStatus {
+        submission_id: None,
+        status: None,
+        map_set_id: None,
+        reason: Some(reason),
+    };
+    send_peer_message(socket, peer, player_id, "UgcSubmissionStatus", &status);
+}
+
+pub fn ugc_submission_system(
+    mut event_reader: EventReader<ClientRequestEvent>,
+    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    connected_players: Res<ConnectedPlayers>,
+    admins: Res<Admins>,
+    map_sets: Res<MapSets>,
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    for event in event_reader.read() {
+        match event.command.as_str() {
+            "UgcSubmit" => {
+                let packet = match serde_json::from_str::<PacketUgcSubmit>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketUgcSubmit from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let mut definition = packet.definition;
+                let result = map_sets.check_name(&definition.map_set_name, None).and_then(|map_set_name| {
+                    definition.map_set_name = map_set_name;
+                    definition.to_map_set(Uuid::nil(), &map_assets_dir())
+                });
+                if let Err(reason) = result {
+                    send_submission_refused(&mut socket, event.peer, &player_id, reason);
+                    continue;
+                }
+                let pool = pool.0.clone();
+                let peer = event.peer;
+                // Spawn the background task using bevy_tokio_tasks
+                runtime.spawn_background_task(move |ctx| {
+                    submit_ugc_async(definition, player_id, peer, pool, ctx)
+                });
+            }
+            "UgcSubmissionsRequest" => {
+                let packet = match serde_json::from_str::<PacketUgcSubmissionsRequest>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketUgcSubmissionsRequest from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let queue = admins.is_admin(&player_id, event.peer);
+                let pool = pool.0.clone();
+                let peer = event.peer;
+                // Spawn the background task using bevy_tokio_tasks
+                runtime.spawn_background_task(move |ctx| {
+                    send_ugc_submissions_async(player_id, queue, peer, pool, ctx)
+                });
+            }
+            "UgcReview" => {
+                let packet = match serde_json::from_str::<PacketUgcReview>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketUgcReview from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let result = if !admins.is_admin(&player_id, event.peer) {
+                    Err(String::from("Only admins can review community map sets"))
+                } else if packet.reason.as_ref().is_some_and(|reason| reason.chars().count() > MAX_REASON_LEN) {
+                    Err(format!("Reason is limited to {} characters", MAX_REASON_LEN))
+                } else {
+                    Uuid::parse_str(&packet.submission_id).map_err(|_| String::from("Unknown submission"))
+                };
+                match result {
+                    Ok(submission_id) => {
+                        info!("Admin {} {} submission {}", player_id, if packet.approve { "approving" } else { "rejecting" }, submission_id);
+                        let pool = pool.0.clone();
+                        let peer = event.peer;
+                        // Spawn the background task using bevy_tokio_tasks
+                        runtime.spawn_background_task(move |ctx| {
+                            review_ugc_submission_async(submission_id, packet, player_id, peer, pool, ctx)
+                        });
+                    }
+                    Err(reason) => send_admin_result(&mut socket, event.peer, &player_id, &event.command, None, Err(reason)),
+                }
+            }
+            "UgcFileRequest" => {
+                let packet = match serde_json::from_str::<PacketUgcFileRequest>(&event.payload) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to deserialize PacketUgcFileRequest from JSON: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(player_id) = connected_players.verify_peer(&packet.player_id, event.peer) else {
+                    continue;
+                };
+                let result = if !admins.is_admin(&player_id, event.peer) {
+                    Err("Only admins can download submitted files")
+                } else {
+                    Uuid::parse_str(&packet.submission_id).map_err(|_| "Unknown submission")
+                };
+                match result {
+                    Ok(submission_id) => {
+                        let pool = pool.0.clone();
+                        let peer = event.peer;
+                        // Spawn the background task using bevy_tokio_tasks
+                        runtime.spawn_background_task(move |ctx| {
+                            send_ugc_file_async(submission_id, packet, player_id, peer, pool, ctx)
+                        });
+                    }
+                    Err(reason) => send_asset_rejection(&mut socket, event.peer, &player_id, packet.submission_id, packet.level, reason),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Ok(Err(reason)) when the submission is refused rather than the database failing
+async fn submit_ugc(pool: &MySqlPool, player_id: &Uuid, definition: &UgcDefinition) -> Result<Result<UgcSubmission, String>, Error> {
+    let (pending,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM ugc_submission WHERE player_id = UUID_TO_BIN(?) AND status = ?",
+    )
+    .bind(player_id.to_string())
+    .bind(UgcStatus::Pending.as_str())
+    .fetch_one(pool)
+    .await?;
+    if pending >= MAX_PENDING_SUBMISSIONS {
+        return Ok(Err(format!("You already have {} submissions waiting for review", pending)));
+    }
+    // Players can only publish files they uploaded themselves
+    let hashes: HashSet<&str> = definition.file_paths.iter().flatten().filter_map(|file_path| ugc_file_hash(file_path)).collect();
+    for hash in hashes {
+        let (owned,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM ugc_file WHERE hash = ? AND player_id = UUID_TO_BIN(?)",
+        )
+        .bind(hash)
+        .bind(player_id.to_string())
+        .fetch_one(pool)
+        .await?;
+        if owned == 0 {
+            return Ok(Err(format!("{} was not uploaded by you", ugc_file_path(hash))));
+        }
+    }
+
+    let submission = UgcSubmission {
+        submission_id: Uuid::now_v7(),
+        player_id: *player_id,
+        definition: definition.clone(),
+        status: UgcStatus::Pending,
+        reason: None,
+        map_set_id: None,
+        created: OffsetDateTime::now_utc(),
+        reviewed: None,
+    };
+    sqlx::query(
+        "INSERT INTO ugc_submission (submission_id, player_id, map_set_name, definition, status, created)
+         VALUES (UUID_TO_BIN(?), UUID_TO_BIN(?), ?, ?, ?, ?)",
+    )
+    .bind(submission.submission_id.to_string())
+    .bind(player_id.to_string())
+    .bind(&definition.map_set_name)
+    .bind(serde_json::to_string(definition).unwrap_or_default())
+    .bind(submission.status.as_str())
+    .bind(submission.created)
+    .execute(pool)
+    .await?;
+    Ok(Ok(submission))
+}
+
+pub async fn submit_ugc_async(
+    definition: UgcDefinition,
+    player_id: Uuid,
+    peer: PeerId,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let result = match submit_ugc(&pool, &player_id, &definition).await {
+        Ok(result) => result,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to save submission: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to save submission in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            Err(String::from("Failed to save the submission"))
+        }
+    };
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            match result {
+                Ok(submission) => {
+                    info!("Player {} submitted map set {:?} for review", player_id, submission.definition.map_set_name);
+                    send_peer_message(&mut socket, peer, &player_id, "UgcSubmissionStatus", &submission_status(&submission));
+                }
+                Err(reason) => send_submission_refused(&mut socket, peer, &player_id, reason),
+            }
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+    })
+    .await;
+}
+
+type SubmissionRow = (Uuid, Uuid, String, String, Option<String>, Option<Uuid>, OffsetDateTime, Option<OffsetDateTime>);
+
+const SUBMISSION_COLUMNS: &str = "submission_id, player_id, definition, status, reason, map_set_id, created, reviewed";
+
+fn submission_from_row(row: SubmissionRow) -> Option<UgcSubmission> {
+    let (submission_id, player_id, definition, status, reason, map_set_id, created, reviewed) = row;
+    let definition = match serde_json::from_str(&definition) {
+        Ok(definition) => definition,
+        Err(err) => {
+            warn!("Submission {} has an unreadable definition: {:?}", submission_id, err);
+            return None;
+        }
+    };
+    Some(UgcSubmission {
+        submission_id,
+        player_id,
+        definition,
+        status: UgcStatus::parse(&status)?,
+        reason,
+        map_set_id,
+        created,
+        reviewed,
+    })
+}
+
+// The oldest pending submissions for moderators, or the player's own submissions newest first
+async fn fetch_ugc_submissions(pool: &MySqlPool, player_id: &Uuid, queue: bool) -> Result<Vec<UgcSubmission>, Error> {
+    let rows: Vec<SubmissionRow> = if queue {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM ugc_submission WHERE status = ? ORDER BY created ASC LIMIT 100",
+            SUBMISSION_COLUMNS,
+        ))
+        .bind(UgcStatus::Pending.as_str())
+        .fetch_all(pool)
+        .await?
+    } else {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM ugc_submission WHERE player_id = UUID_TO_BIN(?) ORDER BY created DESC LIMIT 100",
+            SUBMISSION_COLUMNS,
+        ))
+        .bind(player_id.to_string())
+        .fetch_all(pool)
+        .await?
+    };
+    Ok(rows.into_iter().filter_map(submission_from_row).collect())
+}
+
+pub async fn send_ugc_submissions_async(
+    player_id: Uuid,
+    queue: bool,
+    peer: PeerId,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let submissions = match fetch_ugc_submissions(&pool, &player_id, queue).await {
+        Ok(submissions) => submissions,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to execute query: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to execute query in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            return;
+        }
+    };
+    ctx.run_on_main_thread(move |ctx| {
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            send_peer_message(&mut socket, peer, &player_id, "UgcSubmissions", &PacketUgcSubmissions { submissions });
+        } else {
+            info!("Failed to access matchbox resource");
+        }
+    })
+    .await;
+}
+
+// Approving publishes the set as community content credited to the author. Ok(Err(reason)) when the
+// review cannot be applied, e.g. the submission was already reviewed
+async fn review_ugc_submission(
+    pool: &MySqlPool,
+    submission_id: &Uuid,
+    packet: &PacketUgcReview,
+    reviewer: &Uuid,
+) -> Result<Result<UgcSubmission, String>, Error> {
+    // The row stays locked until the decision is stored, so two moderators cannot both publish it
+    let mut tx = pool.begin().await?;
+    let (submission, published) = match apply_ugc_review(&mut tx, submission_id, packet, reviewer).await? {
+        Ok(review) => review,
+        Err(reason) => return Ok(Err(reason)),
+    };
+    tx.commit().await?;
+
+    if let Some(map_set) = published {
+        refresh_map_set_assets(pool, &map_set).await?;
+    }
+    Ok(Ok(submission))
+}
+
+// Returns the reviewed submission and, when approved, the map set it published
+async fn apply_ugc_review(
+    conn: &mut MySqlConnection,
+    submission_id: &Uuid,
+    packet: &PacketUgcReview,
+    reviewer: &Uuid,
+) -> Result<Result<(UgcSubmission, Option<MapSet>), String>, Error> {
+    let row: Option<SubmissionRow> = sqlx::query_as(&format!(
+        "SELECT {} FROM ugc_submission WHERE submission_id = UUID_TO_BIN(?) FOR UPDATE",
+        SUBMISSION_COLUMNS,
+    ))
+    .bind(submission_id.to_string())
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some(mut submission) = row.and_then(submission_from_row) else {
+        return Ok(Err(String::from("Unknown submission")));
+    };
+    if submission.status != UgcStatus::Pending {
+        return Ok(Err(String::from("Submission was already reviewed")));
+    }
+
+    let mut published = None;
+    if packet.approve {
+        let map_set = match submission.definition.to_map_set(Uuid::now_v7(), &map_assets_dir()) {
+            Ok(map_set) => map_set,
+            Err(reason) => return Ok(Err(reason)),
+        };
+        let (taken,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM map_set_table WHERE map_set_name = ?")
+            .bind(&map_set.map_set_name)
+            .fetch_one(&mut *conn)
+            .await?;
+        if taken > 0 {
+            return Ok(Err(String::from("A map set with this name was published in the meantime")));
+        }
+        save_map_set_rows(&mut *conn, &map_set, true).await?;
+        sqlx::query(
+            "INSERT INTO map_set_community (map_set_id, author_id, submission_id)
+             VALUES (UUID_TO_BIN(?), UUID_TO_BIN(?), UUID_TO_BIN(?))",
+        )
+        .bind(map_set.map_set_id.to_string())
+        .bind(submission.player_id.to_string())
+        .bind(submission_id.to_string())
+        .execute(&mut *conn)
+        .await?;
+        submission.status = UgcStatus::Approved;
+        submission.map_set_id = Some(map_set.map_set_id);
+        published = Some(map_set);
+    } else {
+        submission.status = UgcStatus::Rejected;
+    }
+    submission.reason = packet.reason.as_ref().map(|reason| reason.trim().to_string()).filter(|reason| !reason.is_empty());
+    submission.reviewed = Some(OffsetDateTime::now_utc());
+
+    sqlx::query(
+        "UPDATE ugc_submission SET status = ?, reason = ?, map_set_id = UUID_TO_BIN(?), reviewed = ?,
+            reviewed_by = UUID_TO_BIN(?)
+         WHERE submission_id = UUID_TO_BIN(?)",
+    )
+    .bind(submission.status.as_str())
+    .bind(&submission.reason)
+    .bind(submission.map_set_id.map(|map_set_id| map_set_id.to_string()))
+    .bind(submission.reviewed)
+    .bind(reviewer.to_string())
+    .bind(submission_id.to_string())
+    .execute(&mut *conn)
+    .await?;
+    Ok(Ok((submission, published)))
+}
+
+pub async fn review_ugc_submission_async(
+    submission_id: Uuid,
+    packet: PacketUgcReview,
+    player_id: Uuid,
+    peer: PeerId,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let command = String::from("UgcReview");
+    let result = match review_ugc_submission(&pool, &submission_id, &packet, &player_id).await {
+        Ok(result) => result,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to review submission: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to review submission in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            Err(String::from("Failed to review the submission"))
+        }
+    };
+    let submission = match result {
+        Ok(submission) => submission,
+        Err(reason) => {
+            ctx.run_on_main_thread(move |ctx| {
+                if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+                    send_admin_result(&mut socket, peer, &player_id, &command, None, Err(reason));
+                }
+            })
+            .await;
+            return;
+        }
+    };
+
+    // The author hears about the decision if they are online, otherwise from their submission list
+    let status = submission_status(&submission);
+    let author_id = submission.player_id;
+    ctx.run_on_main_thread(move |ctx| {
+        let Some(connected_players) = ctx.world.get_resource::<ConnectedPlayers>().cloned() else {
+            return;
+        };
+        if !connected_players.is_connected(&author_id) {
+            return;
+        }
+        if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+            send_player_message(&mut socket, &connected_players, &author_id, "UgcSubmissionStatus", &status);
+        }
+    })
+    .await;
+
+    match submission.map_set_id {
+        Some(map_set_id) => {
+            let result = fetch_map_set(&pool, &map_set_id).await;
+            apply_map_set_change(command, result, player_id, peer, ctx).await;
+        }
+        None => {
+            ctx.run_on_main_thread(move |ctx| {
+                if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+                    send_admin_result(&mut socket, peer, &player_id, &command, None, Ok(()));
+                }
+            })
+            .await;
+        }
+    }
+}
+
+// The level file of a submission as it is on disk, for moderators to try before deciding
+async fn find_submission_file(pool: &MySqlPool, submission_id: &Uuid, level: i32) -> Result<Result<MapAssetEntry, &'static str>, Error> {
+    let row: Option<SubmissionRow> = sqlx::query_as(&format!(
+        "SELECT {} FROM ugc_submission WHERE submission_id = UUID_TO_BIN(?)",
+        SUBMISSION_COLUMNS,
+    ))
+    .bind(submission_id.to_string())
+    .fetch_optional(pool)
+    .await?;
+    let Some(submission) = row.and_then(submission_from_row) else {
+        return Ok(Err("Unknown submission"));
+    };
+    let definition = &submission.definition;
+    let mut map_set = MapSet::new(*submission_id, definition.map_set_name.clone(), definition.hole_range_start, definition.hole_range_end);
+    if map_set.set_file_paths(&definition.file_paths).is_err() {
+        return Ok(Err("Submission has no valid file list"));
+    }
+    let Some((_, file_path)) = map_set.levels().into_iter().find(|(hole, file_path)| *hole == level && ugc_file_hash(file_path).is_some()) else {
+        return Ok(Err("Level is not an uploaded file of this submission"));
+    };
+    let Some(path) = resolve_asset_path(&map_assets_dir(), &file_path) else {
+        return Ok(Err("Level file is unavailable"));
+    };
+    Ok(match hash_file(&path).await {
+        Ok((size, hash)) => Ok(MapAssetEntry {
+            level,
+            file_path,
+            size,
+            hash,
+        }),
+        Err(_) => Err("Level file is unavailable"),
+    })
+}
+
+pub async fn send_ugc_file_async(
+    submission_id: Uuid,
+    packet: PacketUgcFileRequest,
+    player_id: Uuid,
+    peer: PeerId,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    let result = match find_submission_file(&pool, &submission_id, packet.level).await {
+        Ok(result) => result,
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to execute query: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to execute query in the task: {:?}", err_for_ctx);
+            })
+            .await;
+            Err("Failed to look up the submission")
+        }
+    };
+    ctx.run_on_main_thread(move |ctx| {
+        // The regular asset transfer streams the file, with the submission id in place of a map set id
+        let result = result.and_then(|asset| {
+            let Some(mut downloads) = ctx.world.get_resource_mut::<MapAssetDownloads>() else {
+                info!("Failed to access map asset downloads resource");
+                return Err("Downloads are unavailable");
+            };
+            let offset = resume_offset(&asset, packet.hash.as_deref(), packet.offset);
+            downloads.queue(MapAssetTransfer {
+                player_id,
+                peer,
+                map_set_id: submission_id,
+                asset,
+                offset,
+            })
+        });
+        if let Err(reason) = result {
+            if let Some(mut socket) = ctx.world.get_resource_mut::<MatchboxSocket<SingleChannel>>() {
+                send_asset_rejection(&mut socket, peer, &player_id, packet.submission_id, packet.level, reason);
+            } else {
+                info!("Failed to access matchbox resource");
+            }
+        }
+    })
+    .await;
+}
+
+// A file is in use while a submission that is not rejected, a map set manifest, a stored revision or
+// a personal map set lists it; ugc_file.hash is the file being checked
+const UGC_FILE_UNUSED: &str = "ugc_file.uploaded < NOW() - INTERVAL ? DAY
+    AND NOT EXISTS (SELECT 1 FROM ugc_submission s WHERE s.status <> ? AND s.definition LIKE CONCAT('%', ugc_file.hash, '%'))
+    AND NOT EXISTS (SELECT 1 FROM map_set_asset a WHERE a.hash = ugc_file.hash)
+    AND NOT EXISTS (SELECT 1 FROM map_set_revision r WHERE r.assets LIKE CONCAT('%', ugc_file.hash, '%'))
+    AND NOT EXISTS (SELECT 1 FROM personal_map_set p WHERE p.holes LIKE CONCAT('%', ugc_file.hash, '%'))";
+
+// Deletes uploads nothing uses anymore, including those only rejected submissions listed, and files
+// left on disk without a record; returns how many files were removed
+async fn clean_up_ugc_files(pool: &MySqlPool, root: &Path) -> Result<usize, Error> {
+    let unused: Vec<(String,)> = sqlx::query_as(&format!("SELECT DISTINCT hash FROM ugc_file WHERE {}", UGC_FILE_UNUSED))
+        .bind(ORPHAN_AFTER_DAYS)
+        .bind(UgcStatus::Rejected.as_str())
+        .fetch_all(pool)
+        .await?;
+    let mut removed = 0;
+    for (hash,) in unused {
+        // Checked again under the delete, a submission may have picked the file up since
+        let mut tx = pool.begin().await?;
+        sqlx::query(&format!("DELETE FROM ugc_file WHERE hash = ? AND {}", UGC_FILE_UNUSED))
+            .bind(&hash)
+            .bind(ORPHAN_AFTER_DAYS)
+            .bind(UgcStatus::Rejected.as_str())
+            .execute(&mut *tx)
+            .await?;
+        let (owners,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM ugc_file WHERE hash = ?")
+            .bind(&hash)
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        if owners == 0 && tokio::fs::remove_file(root.join(ugc_file_path(&hash))).await.is_ok() {
+            removed += 1;
+        }
+    }
+
+    let recorded: HashSet<String> = sqlx::query_as::<_, (String,)>("SELECT DISTINCT hash FROM ugc_file")
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|(hash,)| hash)
+        .collect();
+    let Ok(mut entries) = tokio::fs::read_dir(root.join(UGC_DIR)).await else {
+        return Ok(removed);
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        let unrecorded = match file_name.strip_suffix(".glb") {
+            Some(hash) => !recorded.contains(hash),
+            None => file_name.ends_with(".tmp"),
+        };
+        let old = entry
+            .metadata()
+            .await
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age >= UNRECORDED_AFTER));
+        if unrecorded && old && tokio::fs::remove_file(entry.path()).await.is_ok() {
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+pub fn ugc_file_cleanup_system(
+    pool: Res<DatabasePool>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    let root = map_assets_dir();
+    let pool = pool.0.clone();
+    // Spawn the background task using bevy_tokio_tasks
+    runtime.spawn_background_task(move |ctx| {
+        clean_up_ugc_files_async(root, pool, ctx)
+    });
+}
+
+pub async fn clean_up_ugc_files_async(
+    root: PathBuf,
+    pool: MySqlPool,
+    mut ctx: TaskContext,
+) {
+    match clean_up_ugc_files(&pool, &root).await {
+        Ok(0) => {}
+        Ok(removed) => {
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Removed {} unused uploaded file(s)", removed);
+            })
+            .await;
+        }
+        Err(err) => {
+            let err_for_ctx = err.to_string(); // Convert error to string or clone it before moving it
+            eprintln!("Failed to clean up uploaded files: {:?}", err_for_ctx);
+            ctx.run_on_main_thread(move |_ctx| {
+                info!("Failed to clean up uploaded files in the task: {:?}", err_for_ctx);
+            })
+            .await;
+        }
+    }
+}
@@ -1,10 +1,11 @@
 use bevy::prelude::*;
+use bevy_matchbox::prelude::PeerId;
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
-use std::time::Instant;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
 use sqlx::MySqlPool;
 use sqlx::FromRow;  
-use time::OffsetDateTime;
+use time::{Date, OffsetDateTime};
 use uuid::Uuid;
 
 pub mod handlers;
@@ -13,9 +14,123 @@ pub mod user_interface;
 use std::sync::Arc;
 use std::sync::Mutex;
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AchievementCriteria {
+    GamesPlayed,
+    GamesWon, // Multiplayer games finished in first place
+    HoleInOne { level: Option<i32>, map_set_name: Option<String> },
+    PlayedWith { players: i32 }, // Games with at least this many players
+    RoundUnder { map_set_name: Option<String>, strokes: i32 }, // Rounds finished in fewer strokes
+    RoundToPar { map_set_name: Option<String>, to_par: i32 }, // Rounds finished at or below this score to par
+    WinStreak, // Progress falls back to zero on a multiplayer loss
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AchievementDefinition {
+    pub achievement_id: String, // Stable key, progress is stored against it
+    pub name: String,
+    pub description: String,
+    pub criteria: AchievementCriteria,
+    pub target: i32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AchievementProgress {
+    pub achievement_id: String,
+    pub name: String,
+    pub description: String,
+    pub progress: i32,
+    pub target: i32,
+    pub unlocked: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Resource)]
+pub struct Achievements {
+    pub definitions: Vec<AchievementDefinition>, // Loaded from ACHIEVEMENTS_FILE at startup
+}
+
+#[derive(Debug, Resource)]
+pub struct Admins {
+    // Players allowed to run admin commands, read from ADMIN_PLAYER_IDS
+    pub player_ids: HashSet<Uuid>,
+    pub token: Option<String>, // ADMIN_TOKEN, admins send it with AdminLogin before any admin command
+    pub sessions: HashMap<Uuid, PeerId>, // The peer each admin logged in from
+}
+
 #[derive(Asset, Component, TypePath)]
 pub struct CameraUi;
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Challenge {
+    pub challenge_id: Uuid,
+    pub period: ChallengePeriod,
+    pub starts: Date,
+    pub ends: Date, // Exclusive
+    pub seed: u64,
+    pub holes: Vec<ChallengeHole>,
+    pub stroke_limit: Option<i32>, // Per hole cap, strokes above it count as the cap
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChallengeHistoryEntry {
+    pub challenge: Challenge,
+    pub attempts: i64,
+    pub best_strokes: Option<i32>,
+    pub player_strokes: Option<i32>, // The requesting player's attempt, if any
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChallengeHole {
+    pub map_set_id: Uuid,
+    pub level: i32,
+    pub file_path: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ChallengePeriod {
+    Daily,
+    Weekly,
+}
+
+#[derive(Debug, Resource)]
+pub struct Challenges {
+    pub current: HashMap<ChallengePeriod, Challenge>,
+    pub loading: HashSet<ChallengePeriod>, // Periods whose challenge is being fetched or generated
+}
+
+#[derive(Debug, Resource)]
+pub struct Chat {
+    pub history: HashMap<ChatChannel, VecDeque<ChatMessage>>, // Recent messages per channel
+    pub seen_members: HashMap<ChatChannel, HashSet<Uuid>>, // Members already holding the history
+    pub recent_sends: HashMap<Uuid, VecDeque<Instant>>, // Per player, for rate limiting
+    pub muted_by: HashMap<Uuid, HashSet<Uuid>>, // Players each player has muted for this connection
+    pub silenced: HashMap<Uuid, Instant>, // Admin mutes, until the given instant
+    pub word_filter: Option<regex::Regex>, // Loaded from CHAT_WORD_FILTER_FILE at startup
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ChatChannel {
+    Party(Uuid),
+    Room(String),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub message_id: Uuid,
+    pub scope: ChatScope,
+    pub channel_id: String, // Party id or room id
+    pub sender_id: String,
+    pub text: String, // Already passed through the word filter
+    pub sent: OffsetDateTime,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChatScope {
+    Party,
+    Room,
+}
+
 #[derive(States, Clone, PartialEq, Eq, Hash, Debug, Default)]
 pub enum ClientProtocol{
     #[default]
@@ -25,6 +140,41 @@ pub enum ClientProtocol{
     RunTrigger,
 }
 
+#[derive(Event)]
+pub struct ClientRequestEvent {
+    pub peer: PeerId,
+    pub command: String,
+    pub payload: String,
+}
+
+#[derive(Debug, Resource)]
+pub struct ClientStateQuery {
+    request_id: Option<Uuid>,
+    started: Option<Instant>,
+    timeout: Duration,
+    pending: HashSet<Uuid>,
+    responses: HashMap<Uuid, PacketAllStates>,
+    last_result: Option<ClientStateQueryResult>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ClientStateQueryResult {
+    pub request_id: Uuid,
+    pub responses: Vec<(Uuid, PacketAllStates)>,
+    pub non_responders: Vec<Uuid>,
+}
+
+#[derive(Event)]
+pub struct ClientStateQueryCompleteEvent {
+    pub result: ClientStateQueryResult,
+}
+
+#[derive(Event)]
+pub struct ClientStateQueryEvent {
+    // Empty targets every connected player
+    pub player_ids: Vec<Uuid>,
+}
+
 #[derive(Clone, Debug, Resource)]
 pub struct ConnectedPlayers {
     // Using a HashMap to map player UUIDs to metadata about their connection
@@ -34,12 +184,184 @@ pub struct ConnectedPlayers {
 #[derive(Resource)]
 pub struct DatabasePool(pub MySqlPool);
 
+#[derive(Debug, Resource)]
+pub struct Emotes {
+    pub last_sent: HashMap<Uuid, Instant>, // Per player, for throttling
+    pub pending: HashMap<(Uuid, String), i32>, // Uses not yet written to player_emote_stats
+    pub totals: HashMap<String, i64>, // Uses since startup, for the server UI
+    pub flushing: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FriendAction {
+    Request, // Accepts instead when the other player already asked
+    Accept,
+    Decline,
+    Remove, // Also withdraws pending requests in either direction
+    Block,
+    Unblock,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FriendEntry {
+    pub player_id: String,
+    pub username: String,
+    pub presence: Presence,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FriendRequestEntry {
+    pub player_id: String,
+    pub username: String,
+    pub created: OffsetDateTime,
+}
+
+#[derive(Debug, Resource)]
+pub struct Friends {
+    // Friend and block lists of connected players, loaded on connect and reloaded after changes
+    pub friends: HashMap<Uuid, HashSet<Uuid>>,
+    pub blocked: HashMap<Uuid, HashSet<Uuid>>, // Players each player has blocked
+    pub loading: HashSet<Uuid>,
+    pub presence: HashMap<Uuid, Presence>, // Last presence pushed to friends
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GameResult {
+    pub game_result_id: Uuid,
+    pub session_id: Option<Uuid>, // None for games the server did not run
+    pub map_set_id: Uuid,
+    pub map_set_revision: Option<i32>, // The course as played, None for results recorded before revisions
+    pub player_id: Uuid,
+    pub total_strokes: i32,
+    pub hole_strokes: Vec<(i32, i32)>, // (level, strokes)
+    pub hole_pars: Vec<(i32, i32)>, // (level, par) for the holes that had a par when played
+    pub placement: i32,
+    pub player_count: i32,
+    pub validated: bool,
+}
+
+#[derive(Event)]
+pub struct GameResultRecordedEvent {
+    pub results: Vec<GameResult>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GameSession {
+    pub session_id: Uuid,
+    pub room_id: String,
+    pub map_set_id: Uuid,
+    pub map_set_revision: Option<i32>, // Pinned at start, later edits do not change the course being played
+    pub levels: Vec<i32>, // Level numbers from the map set, in play order
+    pub current_level: usize, // Index into levels
+    pub player_order: Vec<Uuid>,
+    #[serde(default)]
+    pub departed: Vec<Uuid>, // Players who left before the end, rated as finishing last
+    pub play_style: Option<String>, // Recorded at start, the style the game is rated under
+    pub turn: usize, // Index into player_order
+    pub strokes: HashMap<Uuid, Vec<i32>>, // Per player, one entry per level
+    pub hole_completed: HashMap<Uuid, Vec<bool>>, // Per player, one entry per level
+    pub pars: Vec<Option<i32>>, // One entry per level
+    pub stroke_limits: Vec<i32>, // One entry per level, a player's hole ends on reaching it
+    #[serde(default)]
+    pub playlist: Vec<PlaylistHole>, // Source of each level for party playlists, empty for regular map sets
+    pub state: GameSessionState,
+    #[serde(skip)]
+    pub turn_started: Option<Instant>, // When the active player got the turn, drives the turn timeout
+}
+
+#[derive(Event)]
+pub struct GameSessionFinishedEvent {
+    pub session: GameSession,
+}
+
+#[derive(Debug, Default, Resource)]
+pub struct GameResultSubmissions {
+    pub recent: HashMap<Uuid, Vec<(Uuid, Instant)>>, // Unvalidated (map_set_id, submitted) per player, for throttling
+}
+
+#[derive(Debug, Resource)]
+pub struct GameSessions {
+    pub sessions: HashMap<Uuid, GameSession>,
+    pub recently_finished: VecDeque<GameSession>, // Kept to validate late result submissions
+    pub turn_timeout: Duration, // Idle players are charged a stroke and passed over after this long
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameSessionState {
+    InProgress,
+    Finished,
+}
+
 #[derive(Resource)]
 pub struct HeartBeatMonitorTimer(pub Timer);
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HoleMetadata {
+    pub level: i32,
+    pub display_name: Option<String>,
+    pub par: i32,
+    pub difficulty: Option<i32>, // 1 (easiest) to 5
+    pub author: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub stroke_limit: Option<i32>, // At least par, defaults to the server wide cap
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HoleRating {
+    pub level: i32,
+    pub rating: f64, // Average stars
+    pub rating_count: i64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LeaderBoardEntry {
+    pub rank: i64,
+    pub player_id: String,
+    pub username: String,
+    pub strokes: i64, // Of the player's best game, the one lowest to par where any game has a par
+    pub games: i64,
+    pub to_par: Option<i64>, // Of that same game, None unless every hole in it had a par
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LeaderBoardPeriod {
+    AllTime,
+    Weekly,
+    Season, // Since the current season started
+}
+
+#[derive(Debug, Resource)]
+pub struct MapAssetDownloads {
+    pub transfers: VecDeque<MapAssetTransfer>, // Served round robin, one chunk per turn
+    pub files: HashMap<String, Arc<Vec<u8>>>, // File contents by hash, while a transfer needs them
+    pub loading: HashSet<String>, // Hashes being read from disk
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MapAssetEntry {
+    pub level: i32,
+    pub file_path: String, // Relative to MAP_ASSETS_DIR, same as the map set's file_path_level_N
+    pub size: u64,
+    pub hash: String, // Hex encoded SHA-256 of the file contents
+}
+
+#[derive(Clone, Debug)]
+pub struct MapAssetTransfer {
+    pub player_id: Uuid,
+    pub peer: PeerId,
+    pub map_set_id: Uuid,
+    pub asset: MapAssetEntry,
+    pub offset: u64, // Next byte to send
+}
+
 #[derive(Debug, Resource, Serialize, Deserialize)]
 pub struct MapSets{
     pub map_sets: Vec<MapSet>,
+    #[serde(skip)]
+    pub loaded: bool, // Set once the catalog was read from map_set_table
+    #[serde(skip)]
+    pub synced: HashSet<Uuid>, // Connected players that were sent the catalog summary
 }
 
 #[derive(Clone, Debug, FromRow, Serialize, Deserialize)]
@@ -68,6 +390,18 @@ pub struct MapSet {
     pub file_path_level_16: Option<String>,
     pub file_path_level_17: Option<String>,
     pub file_path_level_18: Option<String>,
+    #[sqlx(skip)]
+    pub assets: Vec<MapAssetEntry>, // Manifest from map_set_asset, one entry per level file found
+    #[sqlx(skip)]
+    pub holes: Vec<HoleMetadata>, // From map_set_hole, only for levels that have metadata
+    #[sqlx(skip)]
+    pub community_author: Option<MapSetAuthor>, // Set for player made sets approved through moderation
+    #[sqlx(skip)]
+    pub revision: Option<i32>, // Latest row in map_set_revision
+    #[sqlx(skip)]
+    pub engagement: MapSetEngagement, // Also refreshed through the summary, changes here do not bump last_updated
+    #[sqlx(skip)]
+    pub retired: Option<OffsetDateTime>, // From map_set_retired, retired sets stay for history but cannot be picked for new games
 }
 
 impl MapSets {
@@ -75,13 +409,143 @@ impl MapSets {
         let map_sets: Vec<MapSet> = Vec::new();
         MapSets { 
             map_sets,
+            loaded: false,
+            synced: HashSet::new(),
         }
     }
+
+    pub fn get(&self, map_set_id: &Uuid) -> Option<&MapSet> {
+        self.map_sets.iter().find(|map_set| &map_set.map_set_id == map_set_id)
+    }
+
+    // Same as get, but only for sets new games may use
+    pub fn playable(&self, map_set_id: &Uuid) -> Option<&MapSet> {
+        self.get(map_set_id).filter(|map_set| map_set.retired.is_none())
+    }
+}
+
+impl Default for MapSets {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MapSetAuthor {
+    pub player_id: Uuid,
+    pub username: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MapSetEngagement {
+    pub plays: i64, // Player rounds started on the set
+    pub completions: i64, // Player rounds still in the session when it finished
+    pub completion_rate: Option<f64>, // completions / plays, None before the first play
+    pub rating: Option<f64>, // Average stars for the whole set
+    pub rating_count: i64,
+    pub holes: Vec<HoleRating>, // Only holes that were rated
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MapSetRevision {
+    pub map_set_id: Uuid,
+    pub revision: i32,
+    pub created: OffsetDateTime,
+    pub map_set_name: String,
+    pub hole_range_start: i32,
+    pub hole_range_end: i32,
+    pub file_paths: Vec<Option<String>>, // One per level 1 to 18
+    pub holes: Vec<HoleMetadata>,
+    pub assets: Vec<MapAssetEntry>, // File hashes at the time, files themselves are not kept
+}
+
+#[derive(Debug, Resource)]
+pub struct MapSetPlays {
+    pub seen_sessions: HashSet<Uuid>, // Sessions whose start was already counted
+    pub pending: HashMap<Uuid, (i64, i64)>, // map_set_id -> (plays, completions) not yet written
+    pub flushing: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MapSetSeed {
+    pub key: String, // Stable across boots, the map set id is looked up by it in map_set_seed
+    pub map_set_name: String,
+    pub hole_range_start: i32,
+    pub hole_range_end: i32,
+    pub levels: Vec<MapSetSeedLevel>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MapSetSeedLevel {
+    pub level: i32,
+    pub file_path: String, // Relative to MAP_ASSETS_DIR
+    pub par: Option<i32>, // The remaining fields are only stored for levels with a par
+    pub display_name: Option<String>,
+    pub difficulty: Option<i32>,
+    pub author: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub stroke_limit: Option<i32>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MapSetStats {
+    pub map_set_id: Uuid,
+    pub games_played: i32,
+    pub holes_played: i32,
+    pub total_strokes: i32,
+    pub best_round: Option<i32>,
+    pub best_round_to_par: Option<i32>, // Over rounds with a par for every hole
+    pub average_strokes_per_hole: f32, // Derived, not stored
+}
+
+#[derive(Clone, Debug)]
+pub struct MatchmakingEntry {
+    pub host_id: Uuid,
+    pub party_id: Option<Uuid>,
+    pub members: Vec<Uuid>,
+    pub map_set_id: Option<Uuid>, // None accepts any map set
+    pub party_size: usize, // Desired size of the matched party
+    pub play_style: String,
+    pub rating: f64, // Average rating of the members for the play style
+    pub provisional: bool, // Any member still in their provisional period
+    pub enqueued: Instant,
+}
+
+#[derive(Debug, Resource)]
+pub struct MatchmakingQueue {
+    pub entries: Vec<MatchmakingEntry>,
+    pub relax_after: Duration, // Wait before each relaxation step of the match rules
+    pub rating_window: f64, // Allowed rating gap at relax level 0, widened with each step
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketAchievementUnlocked {
+    pub achievement: AchievementProgress,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketAchievements {
+    pub player_id: String,
+    pub achievements: Vec<AchievementProgress>, // Every defined achievement, locked ones included
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketAchievementsRequest {
+    pub player_id: String, // Player whose progress is requested
+}
 
 #[derive(Serialize, Deserialize, Debug)]
+pub struct PacketAdminLogin {
+    pub player_id: String,
+    pub token: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct PacketAllStates {
+    #[serde(default)]
+    request_id: String, // Echo of PacketStateRequest.request_id, empty for unsolicited updates
     player_id: String,
     state_game: String,
     state_cam_orbit_entity: String,
@@ -93,44 +557,990 @@ pub struct PacketAllStates {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-pub struct PacketHeartBeat {
-    player_id: String,
+pub struct PacketChallengeHistory {
+    pub period: ChallengePeriod,
+    pub entries: Vec<ChallengeHistoryEntry>, // Most recent first
 }
 
-#[derive(Clone, Debug)]
-pub struct PlayerHeartBeatStatus {
-    pub last_heartbeat: Instant,
-    // Additional fields can be added here, e.g., player status, connection info, etc.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketChallengeHistoryRequest {
+    pub player_id: String,
+    pub period: ChallengePeriod,
 }
 
-#[derive(Clone, Debug, Resource)]
-pub struct PlayerInfo {
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketChallengeLeaderBoard {
+    pub challenge_id: String,
+    pub page: u32,
+    pub page_size: u32,
+    pub total_entries: i64,
+    pub entries: Vec<LeaderBoardEntry>,
+    pub validated: bool, // False while attempts are client reported rather than checked against a server-run session
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketChallengeLeaderBoardRequest {
+    pub player_id: String,
+    pub challenge_id: Option<String>, // The current challenge of the period when unset
+    pub period: ChallengePeriod,
+    pub page: u32,
+    pub page_size: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketChallengeRequest {
+    pub player_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketChallengeStatus {
+    pub challenge_id: String,
+    pub recorded: bool,
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketChallengeSubmit {
+    pub player_id: String,
+    pub challenge_id: String,
+    pub strokes: Vec<i32>, // One entry per challenge hole, in order
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketChallenges {
+    pub challenges: Vec<Challenge>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketChatHistory {
+    pub scope: ChatScope,
+    pub channel_id: String,
+    pub messages: Vec<ChatMessage>, // Oldest first
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketChatMute {
+    pub player_id: String,
+    pub target_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketChatRejected {
+    pub scope: ChatScope,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketChatSend {
+    pub player_id: String,
+    pub scope: ChatScope, // The server picks the sender's current party or room
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketChatSilence {
+    pub player_id: String, // Must be an admin
+    pub target_id: String,
+    pub minutes: u32, // 0 lifts the mute
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketEmote {
+    pub session_id: String,
+    pub sender_id: String,
+    pub emote_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketEmoteRejected {
+    pub emote_id: String,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketEmoteSend {
+    pub player_id: String,
+    pub emote_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketFriendAction {
+    pub player_id: String,
+    pub target_id: String, // Player the request, block or removal is about
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketFriendList {
+    pub player_id: String,
+    pub friends: Vec<FriendEntry>,
+    pub incoming: Vec<FriendRequestEntry>,
+    pub outgoing: Vec<FriendRequestEntry>,
+    pub blocked: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketFriendListRequest {
+    pub player_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketFriendPresence {
+    pub player_id: String,
+    pub presence: Presence,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketFriendStatus {
+    pub command: String,
+    pub target_id: String,
+    pub ok: bool,
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketGameResultStatus {
+    pub recorded: bool,
+    pub validated: bool,
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketGameResultSubmit {
+    pub player_id: String,
+    pub session_id: Option<String>,
+    pub map_set_id: String,
+    pub strokes: Vec<i32>, // One entry per level of the map set, in play order
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketGameSessionLeave {
+    pub session_id: String,
+    pub player_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketGameSessionRejected {
+    pub session_id: Option<String>,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketGameSessionStart {
+    pub player_id: String,
+    pub map_set_id: String,
+    pub player_ids: Vec<String>, // Play order, must include player_id
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketGameSessionStateRequest {
+    pub session_id: String,
+    pub player_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketGameSessionStroke {
+    pub session_id: String,
+    pub player_id: String,
+    pub hole_completed: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketHeartBeat {
     player_id: String,
-    player_email: String,
-    player_username: String,
 }
 
-#[derive(Clone, Debug, Resource)]
-pub struct PlayerInfoStorage {
-    pub players: Arc<Mutex<Vec<Arc<Mutex<PlayerInfo>>>>>,
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketGetPlayerStats {
+    pub player_id: String, // Player whose stats are requested
 }
 
-#[derive(Debug, Resource)]
-pub struct RunTrigger{
-    trigger_idx: i32,
-    triggers: Vec<String>,
-    db_pipeline_player_init: bool,
-    network_get_client_state_game: bool,
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketGetPlayerRating {
+    pub player_id: String, // Player whose ratings are requested
+    pub play_style: Option<String>, // All play styles when unset
 }
 
-#[derive(Event)]
-pub struct SyncPlayerIdEvent {
-    pub player_id_host: String,
-    pub player_id_client: String,
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketLastGame {
+    pub results: Vec<GameResult>,
 }
 
-#[derive(Event)]
-pub struct SyncTriggerIndexEvent {
-    pub player_id: Uuid,
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketLastGameRequest {
+    pub player_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketLeaderBoard {
+    pub map_set_id: Option<String>,
+    pub level: Option<i32>,
+    pub map_set_revision: Option<i32>,
+    pub period: LeaderBoardPeriod,
+    pub friends_only: bool,
+    pub page: u32,
+    pub page_size: u32,
+    pub total_entries: i64,
+    pub entries: Vec<LeaderBoardEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketLeaderBoardRequest {
+    pub player_id: String,
+    pub map_set_id: Option<String>, // Required unless a level is given
+    pub level: Option<i32>, // Per hole board when set
+    #[serde(default)]
+    pub map_set_revision: Option<i32>, // Only results played on this revision of the map set, the current one when omitted
+    pub period: LeaderBoardPeriod,
+    pub friends_only: bool,
+    pub page: u32,
+    pub page_size: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMapAssetCancel {
+    pub player_id: String,
+    pub map_set_id: String,
+    pub level: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMapAssetChunk {
+    pub map_set_id: String,
+    pub level: i32,
+    pub hash: String, // Of the whole file, as listed in the manifest
+    pub size: u64,
+    pub offset: u64,
+    pub data: String, // Base64 encoded
+    pub chunk_hash: String, // Hex encoded SHA-256 of the decoded data
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMapAssetRejected {
+    pub map_set_id: String,
+    pub level: i32,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMapAssetRequest {
+    pub player_id: String,
+    pub map_set_id: String,
+    pub level: i32,
+    pub hash: Option<String>, // Hash of the partial download being resumed
+    pub offset: u64, // Bytes already received, ignored unless the hash still matches
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMapSetAdminResult {
+    pub command: String,
+    pub map_set_id: Option<String>, // The new id after a MapSetCreate
+    pub accepted: bool,
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMapSetCreate {
+    pub player_id: String, // Must be an admin
+    pub map_set_name: String,
+    pub hole_range_start: i32,
+    pub hole_range_end: i32,
+    pub file_paths: Vec<Option<String>>, // One per level 1 to 18
+    #[serde(default)]
+    pub holes: Vec<HoleMetadata>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMapSetRate {
+    pub player_id: String,
+    pub map_set_id: String,
+    pub level: Option<i32>, // None rates the whole set
+    pub stars: i32, // 1 to 5, clients offering thumbs send 1 or 5
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMapSetRateResult {
+    pub map_set_id: String,
+    pub level: Option<i32>,
+    pub stars: i32,
+    pub accepted: bool,
+    pub reason: Option<String>,
+    pub engagement: Option<MapSetEngagement>, // The set's figures including this rating
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMapSetReorder {
+    pub player_id: String, // Must be an admin
+    pub map_set_id: String,
+    pub order: Vec<i32>, // Current levels in their new order, covering the hole range
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMapSetRequest {
+    pub player_id: String,
+    pub map_set_ids: Vec<String>, // Sets the client found changed in the summary, empty for all
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMapSetRetire {
+    pub player_id: String, // Must be an admin
+    pub map_set_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMapSetRevisions {
+    pub map_set_id: String,
+    pub revisions: Vec<MapSetRevision>, // Newest first
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMapSetRevisionsRequest {
+    pub player_id: String, // Must be an admin
+    pub map_set_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMapSetRollback {
+    pub player_id: String, // Must be an admin
+    pub map_set_id: String,
+    pub revision: i32, // Its contents become a new revision, history is never rewritten
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMapSetSummary {
+    pub map_sets: Vec<(Uuid, OffsetDateTime)>, // (map_set_id, last_updated)
+    pub engagement: Vec<(Uuid, MapSetEngagement)>, // Current plays and ratings, for sorting by popularity and quality
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMapSetSummaryRequest {
+    pub player_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMapSetUpdate {
+    pub player_id: String, // Must be an admin
+    pub map_set_id: String,
+    pub map_set_name: Option<String>, // Fields left out keep their current value
+    pub hole_range_start: Option<i32>,
+    pub hole_range_end: Option<i32>,
+    pub file_paths: Option<Vec<Option<String>>>,
+    pub holes: Option<Vec<HoleMetadata>>, // Replaces all hole metadata when set
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMapSets {
+    pub map_sets: Vec<MapSet>,
+    pub removed: Vec<Uuid>, // Only set on pushed updates
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMatchFound {
+    pub session_id: String,
+    pub party_id: String,
+    pub map_set_id: String,
+    pub player_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMatchmakingEnqueue {
+    pub player_id: String,
+    pub map_set_id: Option<String>,
+    pub party_size: usize,
+    pub play_style: Option<String>, // Falls back to the last reported state_game_play_style
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMatchmakingLeave {
+    pub player_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketMatchmakingStatus {
+    pub queued: bool,
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketPartyCreate {
+    pub player_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketPartyInvite {
+    pub player_id: String,
+    pub party_id: String,
+    pub invitee_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketPartyInviteNotice {
+    pub party_id: String,
+    pub host_id: String,
+    pub join_code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketPartyInviteResponse {
+    pub player_id: String,
+    pub party_id: String,
+    pub accept: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketPartyJoinCode {
+    pub player_id: String,
+    pub join_code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketPartyKick {
+    pub player_id: String,
+    pub party_id: String,
+    pub target_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketPartyLeave {
+    pub player_id: String,
+    pub party_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketPartyPlaylistStart {
+    pub player_id: String, // Must be the party host
+    pub party_id: String,
+    pub holes: Vec<PlaylistHole>, // Play order, the same hole may appear more than once
+    pub save_as: Option<String>, // Also keep the playlist as a personal map set under this name
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketPartyRemoved {
+    pub party_id: String,
+    pub host_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketPartyRejected {
+    pub party_id: Option<String>,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketPersonalMapSetDelete {
+    pub player_id: String,
+    pub map_set_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketPersonalMapSets {
+    pub map_sets: Vec<PersonalMapSet>,
+    pub reason: Option<String>, // Set when a save or delete was refused
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketPersonalMapSetsRequest {
+    pub player_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketPlayerRating {
+    pub player_id: String,
+    pub ratings: Vec<PlayerRating>,
+    pub history: Vec<RatingHistoryEntry>, // Most recent first
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketPlayerStats {
+    pub stats: PlayerStats,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketRankedLeaderBoard {
+    pub season_id: Option<String>, // Set for archived standings of a past season
+    pub play_style: String,
+    pub page: u32,
+    pub page_size: u32,
+    pub total_entries: i64,
+    pub entries: Vec<RankedLeaderBoardEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketRankedLeaderBoardRequest {
+    pub player_id: String,
+    pub play_style: Option<String>, // Falls back to the last reported state_game_play_style
+    pub season_id: Option<String>, // A past season's archived standings, the live board when unset
+    pub page: u32,
+    pub page_size: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketRoomCreate {
+    pub player_id: String,
+    pub room_id: Option<String>, // Generated when not provided
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketRoomJoin {
+    pub player_id: String,
+    pub room_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketRoomLeave {
+    pub player_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketRoomRejected {
+    pub room_id: Option<String>,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketTournamentCancel {
+    pub player_id: String,
+    pub tournament_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketTournamentListRequest {
+    pub player_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketTournamentRegister {
+    pub player_id: String,
+    pub tournament_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketTournamentRejected {
+    pub tournament_id: Option<String>,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketTournamentSchedule {
+    pub player_id: String, // Must be an admin
+    pub name: String,
+    pub map_set_id: String,
+    pub format: TournamentFormat,
+    pub registration_opens: i64, // Unix timestamp
+    pub registration_closes: i64, // Unix timestamp, play starts once registration closes
+    pub rounds: i32, // Stroke play only
+    pub group_size: usize, // Players per game session
+    pub advance_count: usize, // Elimination only, players advancing from each group
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketTournamentWithdraw {
+    pub player_id: String,
+    pub tournament_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketTournaments {
+    pub tournaments: Vec<Tournament>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketSeasonHistory {
+    pub player_id: String,
+    pub entries: Vec<SeasonHistoryEntry>, // Most recent season first
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketSeasonHistoryRequest {
+    pub player_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketSeasonRequest {
+    pub player_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketStateRequest {
+    pub request_id: String,
+    pub player_id: String,
+}
+
+// Streamed back as MapAssetChunk packets carrying the submission id in place of a map set id
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketUgcFileRequest {
+    pub player_id: String, // Must be an admin
+    pub submission_id: String,
+    pub level: i32,
+    pub hash: Option<String>, // Hash of the partial download being resumed
+    pub offset: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketUgcReview {
+    pub player_id: String, // Must be an admin
+    pub submission_id: String,
+    pub approve: bool,
+    pub reason: Option<String>, // Shown to the author, expected when rejecting
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketUgcSubmissionStatus {
+    pub submission_id: Option<String>, // None when the submission was refused outright
+    pub status: Option<UgcStatus>,
+    pub map_set_id: Option<String>, // Set once approved
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketUgcSubmissions {
+    pub submissions: Vec<UgcSubmission>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketUgcSubmissionsRequest {
+    pub player_id: String, // Own submissions, or the moderation queue for admins
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketUgcSubmit {
+    pub player_id: String,
+    pub definition: UgcDefinition,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketUgcUploadBegin {
+    pub player_id: String,
+    pub file_name: String, // Shown to moderators, the stored name is the hash
+    pub size: u64,
+    pub hash: String, // Hex encoded SHA-256 of the whole file
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketUgcUploadChunk {
+    pub player_id: String,
+    pub upload_id: String,
+    pub offset: u64,
+    pub data: String, // Base64 encoded
+    pub chunk_hash: String, // Hex encoded SHA-256 of the decoded data
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketUgcUploadStatus {
+    pub upload_id: Option<String>,
+    pub file_name: String,
+    pub received: u64, // Resume from here after a lost chunk or reconnect
+    pub size: u64,
+    pub file_path: Option<String>, // Set once stored, for use in a UgcDefinition
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Resource)]
+pub struct Parties {
+    pub parties: HashMap<Uuid, Party>,
+    pub max_size: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Party {
+    pub party_id: Uuid,
+    pub host_id: Uuid,
+    pub members: Vec<Uuid>, // Join order, used for host transfer
+    pub join_code: String,
+    pub max_size: usize,
+    #[serde(skip)]
+    pub invites: HashSet<Uuid>,
+    #[serde(skip)]
+    pub kicked: HashSet<Uuid>, // Cannot come back by join code, only by a fresh invite from the host
+}
+
+#[derive(Event)]
+pub struct PlayerDisconnectedEvent {
+    pub player_id: Uuid,
+}
+
+#[derive(Clone, Debug)]
+pub struct PlayerHeartBeatStatus {
+    pub last_heartbeat: Instant,
+    pub peer_id: Option<PeerId>,
+    pub last_states: Option<PacketAllStates>,
+    // Additional fields can be added here, e.g., player status, connection info, etc.
+}
+
+#[derive(Clone, Debug, Resource)]
+pub struct PlayerInfo {
+    player_id: String,
+    player_email: String,
+    player_username: String,
+}
+
+#[derive(Clone, Debug, Resource)]
+pub struct PlayerInfoStorage {
+    pub players: Arc<Mutex<Vec<Arc<Mutex<PlayerInfo>>>>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlayerRating {
+    pub player_id: Uuid,
+    pub play_style: String,
+    pub rating: f64,
+    pub deviation: f64, // Glicko rating deviation, grows while a player is inactive
+    pub volatility: f64,
+    pub games_played: i32,
+    pub provisional: bool, // Derived from games_played, not stored
+}
+
+#[derive(Debug, Resource)]
+pub struct PlayerRatings {
+    // Keyed by player and play style, filled as ratings are loaded or updated
+    pub ratings: HashMap<(Uuid, String), PlayerRating>,
+    pub generation: u64, // Bumped by every season reset, ratings loaded under an older one are dropped
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PlayerStats {
+    pub player_id: Uuid,
+    pub games_played: i32,
+    pub games_won: i32, // Multiplayer games finished in first place
+    pub holes_played: i32,
+    pub holes_in_one: i32,
+    pub total_strokes: i32,
+    pub current_win_streak: i32,
+    pub best_win_streak: i32,
+    pub par_holes_played: i32, // Holes played that had a par
+    pub strokes_to_par: i32, // Summed over par_holes_played
+    pub under_par_holes: i32,
+    pub average_strokes_per_hole: f32, // Derived, not stored
+    pub average_to_par: f32, // Derived, not stored
+    pub map_sets: Vec<MapSetStats>,
+}
+
+#[derive(Debug, Resource)]
+pub struct PlayerStatsCache {
+    pub stats: HashMap<Uuid, PlayerStats>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PersonalMapSet {
+    pub map_set_id: Uuid,
+    pub player_id: Uuid,
+    pub map_set_name: String, // Unique per player, saving under the same name replaces the set
+    pub holes: Vec<PlaylistHole>,
+    pub created: OffsetDateTime,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlaylistHole {
+    pub map_set_id: Uuid,
+    pub level: i32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Presence {
+    pub status: PresenceStatus,
+    pub menu: Option<String>, // Client menu state while online
+    pub map_set: Option<String>, // Client map set state while in a game
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PresenceStatus {
+    Offline,
+    InMenu,
+    InGame,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RankedLeaderBoardEntry {
+    pub rank: i64,
+    pub player_id: String,
+    pub username: String,
+    pub rating: f64,
+    pub deviation: f64,
+    pub games: i32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RatingHistoryEntry {
+    pub play_style: String,
+    pub game_result_id: Option<Uuid>, // None for inactivity decay
+    pub rating_before: f64,
+    pub rating_after: f64,
+    pub deviation_after: f64,
+    pub recorded: OffsetDateTime,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Room {
+    pub room_id: String,
+    pub members: HashSet<Uuid>,
+    #[serde(skip)]
+    pub created: Instant,
+    #[serde(skip)]
+    pub empty_since: Option<Instant>,
+    pub private: bool, // Set up for a game session, only its players are put in
+}
+
+#[derive(Debug, Resource)]
+pub struct Rooms {
+    // Players not listed in any room are in the default lobby room
+    pub rooms: HashMap<String, Room>,
+    pub empty_room_timeout: Duration,
+}
+
+#[derive(Debug, Resource)]
+pub struct RunTrigger{
+    trigger_idx: i32,
+    triggers: Vec<String>,
+    db_pipeline_player_init: bool,
+    network_get_client_state_game: bool,
+    player_stats_rebuild: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Tournament {
+    pub tournament_id: Uuid,
+    pub name: String,
+    pub map_set_id: Uuid,
+    pub format: TournamentFormat,
+    pub state: TournamentState,
+    pub registration_opens: OffsetDateTime,
+    pub registration_closes: OffsetDateTime,
+    pub rounds: i32,
+    pub group_size: usize,
+    pub advance_count: usize,
+    pub current_round: i32, // 0 until play starts
+    pub entrants: Vec<TournamentEntrant>, // Kept in standings order once play starts
+    pub matches: Vec<TournamentMatch>,
+    #[serde(skip)]
+    pub round_started: Option<Instant>, // Not persisted, absent players get a fresh grace period after a restart
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TournamentEntrant {
+    pub player_id: Uuid,
+    pub seed: i32, // Registration order until seeded
+    pub total_strokes: i32,
+    pub eliminated_round: Option<i32>, // Knocked out or forfeited in this round
+    pub final_rank: Option<i32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TournamentFormat {
+    StrokePlay, // Every round is played by everyone, lowest total wins
+    Elimination, // The best advance_count of every group move on
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TournamentMatch {
+    pub match_id: Uuid,
+    pub round: i32,
+    pub player_ids: Vec<Uuid>,
+    pub session_id: Option<Uuid>,
+    pub state: TournamentMatchState,
+    pub results: Vec<(Uuid, i32)>, // (player, total strokes), players missing here forfeited
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TournamentMatchState {
+    Pending,
+    InProgress,
+    Completed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TournamentState {
+    Scheduled,
+    Registration,
+    InProgress,
+    Finished,
+    Cancelled,
+}
+
+#[derive(Debug, Resource)]
+pub struct Tournaments {
+    pub tournaments: HashMap<Uuid, Tournament>,
+    pub dirty: HashSet<Uuid>, // Changed since the last save
+    pub saving: bool,
+    pub loaded: bool, // Unfinished tournaments restored from the database
+    pub loading: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Season {
+    pub season_id: Uuid,
+    pub name: String,
+    pub starts: OffsetDateTime,
+    pub ends: OffsetDateTime, // Rollover happens at the first check past this
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SeasonHistoryEntry {
+    pub season_id: Uuid,
+    pub name: String,
+    pub play_style: String,
+    pub rank: i32,
+    pub rating: f64,
+    pub reward: Option<String>,
+}
+
+#[derive(Debug, Resource)]
+pub struct Seasons {
+    pub current: Option<Season>,
+    pub busy: bool, // A load or rollover is running
+}
+
+#[derive(Event)]
+pub struct SyncPlayerIdEvent {
+    pub player_id_host: String,
+    pub player_id_client: String,
+}
+
+#[derive(Event)]
+pub struct SyncTriggerIndexEvent {
+    pub player_id: Uuid,
     pub trigger_idx: usize,
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Resource)]
+pub struct Ugc {
+    pub uploads: HashMap<Uuid, UgcUpload>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UgcDefinition {
+    pub map_set_name: String,
+    pub hole_range_start: i32,
+    pub hole_range_end: i32,
+    pub file_paths: Vec<Option<String>>, // One per level 1 to 18, each an uploaded file
+    #[serde(default)]
+    pub holes: Vec<HoleMetadata>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UgcStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UgcSubmission {
+    pub submission_id: Uuid,
+    pub player_id: Uuid,
+    pub definition: UgcDefinition,
+    pub status: UgcStatus,
+    pub reason: Option<String>,
+    pub map_set_id: Option<Uuid>, // The published set once approved
+    pub created: OffsetDateTime,
+    pub reviewed: Option<OffsetDateTime>,
+}
+
+#[derive(Debug)]
+pub struct UgcUpload {
+    pub upload_id: Uuid,
+    pub player_id: Uuid,
+    pub file_name: String,
+    pub size: u64,
+    pub hash: String,
+    pub data: Vec<u8>, // Received so far
+    pub last_activity: Instant,
+}
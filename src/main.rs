@@ -2,6 +2,7 @@
 
 use bevy::{prelude::*, 
     input::common_conditions::*,
+    time::common_conditions::on_timer,
 };
 
 use bevy_easy_vec_ui::BevyEasyVecUiPlugin;
@@ -16,15 +17,40 @@ use sqlx::mysql::MySqlPoolOptions;
 use tokio::runtime::Runtime;
 
 use minigolf_backend_server::{
+    Achievements,
+    Admins,
+    Challenges,
+    Chat,
     ClientProtocol,
+    ClientRequestEvent,
+    ClientStateQuery,
+    ClientStateQueryCompleteEvent,
+    ClientStateQueryEvent,
     ConnectedPlayers,
     DatabasePool,
+    Emotes,
+    Friends,
+    GameResultRecordedEvent,
+    GameResultSubmissions,
+    GameSessionFinishedEvent,
+    GameSessions,
     HeartBeatMonitorTimer,
+    MapAssetDownloads,
+    MapSetPlays,
     MapSets,
+    MatchmakingQueue,
+    Parties,
+    PlayerDisconnectedEvent,
     PlayerInfoStorage,
+    PlayerRatings,
+    PlayerStatsCache,
+    Rooms,
     RunTrigger,
+    Seasons,
     SyncPlayerIdEvent,
     SyncTriggerIndexEvent,
+    Tournaments,
+    Ugc,
 };
 
 use minigolf_backend_server::user_interface::{
@@ -35,19 +61,118 @@ use minigolf_backend_server::user_interface::{
 };
 
 use minigolf_backend_server::handlers::{
+    achievement_handler::{
+        achievement_record_system,
+        achievement_request_system,
+    },
+    admin_handler::{
+        admin_disconnect_system,
+        admin_request_system,
+    },
+    challenge_handler::{
+        challenge_request_system,
+        challenge_rollover_system,
+    },
+    chat_handler::{
+        chat_disconnect_system,
+        chat_history_system,
+        chat_request_system,
+    },
+    client_state_handler::{
+        client_state_query_request_system,
+        client_state_query_timeout_system,
+        network_get_client_state_game,
+    },
     database_handler::{
         db_pipeline_player_init,
         sync_player_id_init_system,
     },
+    emote_handler::{
+        emote_persist_system,
+        emote_request_system,
+    },
+    friend_handler::{
+        friend_request_system,
+        friends_load_system,
+        friends_presence_system,
+    },
+    game_session_handler::{
+        game_session_disconnect_system,
+        game_session_request_system,
+        game_session_turn_timeout_system,
+    },
     heartbeat_handler::heartbeat_monitor_system,
-    map_set_handler::first_time_boot_setup_map_set,
+    leader_board_handler::{
+        leader_board_log_game,
+        leader_board_request_system,
+    },
+    map_asset_handler::{
+        map_asset_disconnect_system,
+        map_asset_request_system,
+        map_asset_scan_system,
+        map_asset_transfer_system,
+    },
+    map_set_engagement_handler::{
+        map_set_play_persist_system,
+        map_set_play_system,
+        map_set_rating_request_system,
+    },
+    map_set_handler::{
+        client_sync_protocol_send_existing_map_sets,
+        first_time_boot_setup_map_set,
+        map_set_admin_system,
+        map_set_request_system,
+        map_set_sync_system,
+    },
+    map_set_revision_handler::map_set_revision_request_system,
+    matchmaking_handler::{
+        matchmaking_disconnect_system,
+        matchmaking_request_system,
+        matchmaking_system,
+    },
+    party_handler::{
+        party_disconnect_system,
+        party_request_system,
+    },
+    player_stats_handler::{
+        player_stats_rebuild,
+        player_stats_record_system,
+        player_stats_request_system,
+    },
+    playlist_handler::playlist_request_system,
+    rating_handler::{
+        rating_decay_system,
+        rating_record_system,
+        rating_request_system,
+    },
+    room_handler::{
+        room_cleanup_system,
+        room_disconnect_system,
+        room_request_system,
+    },
     run_trigger_handler::client_run_trigger,
+    schema_handler::setup_schema,
+    season_handler::{
+        season_request_system,
+        season_system,
+    },
     signaling_server_handler::{
-        network_get_client_state_game,
         receive_client_requests,
         start_host_socket,
         start_signaling_server,
     },
+    tournament_handler::{
+        tournament_load_system,
+        tournament_persist_system,
+        tournament_request_system,
+        tournament_system,
+    },
+    ugc_handler::{
+        ugc_file_cleanup_system,
+        ugc_submission_system,
+        ugc_upload_cleanup_system,
+        ugc_upload_system,
+    },
 };
 
 async fn establish_connection() -> sqlx::Result<sqlx::Pool<sqlx::MySql>> {
@@ -85,19 +210,45 @@ fn main() {
 
         .insert_state(ClientProtocol::Idle)
         
+        .add_event::<ClientRequestEvent>() 
+        .add_event::<ClientStateQueryCompleteEvent>() 
+        .add_event::<ClientStateQueryEvent>() 
+        .add_event::<GameResultRecordedEvent>() 
+        .add_event::<GameSessionFinishedEvent>() 
+        .add_event::<PlayerDisconnectedEvent>() 
         .add_event::<SyncPlayerIdEvent>() 
         .add_event::<SyncTriggerIndexEvent>() 
 
+        .insert_resource(Achievements::load())
+        .insert_resource(Admins::from_env())
+        .insert_resource(Challenges::new())
+        .insert_resource(Chat::load())
+        .insert_resource(ClientStateQuery::new(Duration::from_secs(5)))
         .insert_resource(ConnectedPlayers::new())
         .insert_resource(DatabasePool(pool))
+        .insert_resource(Emotes::new())
+        .insert_resource(Friends::new())
+        .insert_resource(GameResultSubmissions::default())
+        .insert_resource(GameSessions::new(Duration::from_secs(60)))
+        .insert_resource(MapAssetDownloads::new())
+        .insert_resource(MapSetPlays::new())
         .insert_resource(MapSets::new())
+        .insert_resource(MatchmakingQueue::new(Duration::from_secs(30), 150.0))
+        .insert_resource(Parties::new(4))
         .insert_resource(PlayerInfoStorage::new())
+        .insert_resource(PlayerRatings::new())
+        .insert_resource(PlayerStatsCache::new())
+        .insert_resource(Rooms::new(Duration::from_secs(60)))
         .insert_resource(RunTrigger::new())
+        .insert_resource(Seasons::new())
+        .insert_resource(Tournaments::new())
+        .insert_resource(Ugc::new())
 
         .insert_resource(HeartBeatMonitorTimer(Timer::new(Duration::from_secs(5), TimerMode::Repeating)))
         
         // .add_systems(Update, send_message.run_if(on_timer(Duration::from_secs(5))))
         .add_systems(Startup, (start_signaling_server, start_host_socket).chain())
+        .add_systems(Startup, setup_schema)
         // .add_systems(Startup, setup_ui)
 
         .add_systems(Update, interface)
@@ -106,10 +257,68 @@ fn main() {
         .add_systems(Update, heartbeat_monitor_system)
         .add_systems(Update, client_run_trigger)
         .add_systems(Update, first_time_boot_setup_map_set.run_if(input_just_released(KeyCode::Space)))
+        .add_systems(Update, client_sync_protocol_send_existing_map_sets.run_if(input_just_released(KeyCode::KeyZ)))
+        .add_systems(Update, client_sync_protocol_send_existing_map_sets.run_if(on_timer(Duration::from_secs(30))))
+        .add_systems(Update, map_asset_scan_system.run_if(input_just_released(KeyCode::KeyX)))
+        .add_systems(Update, map_asset_request_system)
+        .add_systems(Update, map_asset_transfer_system.run_if(on_timer(Duration::from_millis(50))))
+        .add_systems(Update, map_asset_disconnect_system)
+        .add_systems(Update, map_set_request_system)
+        .add_systems(Update, map_set_admin_system)
+        .add_systems(Update, map_set_revision_request_system)
+        .add_systems(Update, map_set_sync_system.run_if(on_timer(Duration::from_secs(1))))
+        .add_systems(Update, map_set_play_system)
+        .add_systems(Update, map_set_play_persist_system.run_if(on_timer(Duration::from_secs(10))))
+        .add_systems(Update, map_set_rating_request_system)
         .add_systems(Update, db_pipeline_player_init.run_if(|run_trigger: Res<RunTrigger>|run_trigger.db_pipeline_player_init()))
         .add_systems(Update, network_get_client_state_game.run_if(|run_trigger: Res<RunTrigger>|run_trigger.network_get_client_state_game()))
+        .add_systems(Update, client_state_query_request_system)
+        .add_systems(Update, client_state_query_timeout_system)
+        .add_systems(Update, game_session_request_system)
+        .add_systems(Update, game_session_disconnect_system)
+        .add_systems(Update, game_session_turn_timeout_system.run_if(on_timer(Duration::from_secs(1))))
+        .add_systems(Update, party_request_system)
+        .add_systems(Update, party_disconnect_system)
+        .add_systems(Update, playlist_request_system)
+        .add_systems(Update, matchmaking_request_system)
+        .add_systems(Update, matchmaking_system.run_if(on_timer(Duration::from_secs(1))))
+        .add_systems(Update, matchmaking_disconnect_system)
+        .add_systems(Update, room_request_system)
+        .add_systems(Update, room_disconnect_system)
+        .add_systems(Update, leader_board_log_game)
+        .add_systems(Update, leader_board_request_system)
+        .add_systems(Update, player_stats_record_system)
+        .add_systems(Update, player_stats_request_system)
+        .add_systems(Update, player_stats_rebuild.run_if(|run_trigger: Res<RunTrigger>|run_trigger.player_stats_rebuild()))
+        .add_systems(Update, rating_record_system)
+        .add_systems(Update, rating_request_system)
+        .add_systems(Update, rating_decay_system.run_if(on_timer(Duration::from_secs(3600))))
+        .add_systems(Update, challenge_request_system)
+        .add_systems(Update, challenge_rollover_system.run_if(on_timer(Duration::from_secs(10))))
+        .add_systems(Update, achievement_record_system)
+        .add_systems(Update, achievement_request_system)
+        .add_systems(Update, admin_request_system)
+        .add_systems(Update, admin_disconnect_system)
+        .add_systems(Update, chat_disconnect_system)
+        .add_systems(Update, chat_history_system.run_if(on_timer(Duration::from_secs(1))))
+        .add_systems(Update, chat_request_system)
+        .add_systems(Update, emote_persist_system.run_if(on_timer(Duration::from_secs(10))))
+        .add_systems(Update, emote_request_system)
+        .add_systems(Update, friend_request_system)
+        .add_systems(Update, friends_load_system.run_if(on_timer(Duration::from_secs(1))))
+        .add_systems(Update, friends_presence_system.run_if(on_timer(Duration::from_secs(1))))
+        .add_systems(Update, season_request_system)
+        .add_systems(Update, season_system.run_if(on_timer(Duration::from_secs(60))))
+        .add_systems(Update, tournament_load_system.run_if(on_timer(Duration::from_secs(5))))
+        .add_systems(Update, tournament_request_system)
+        .add_systems(Update, tournament_system.run_if(on_timer(Duration::from_secs(2))))
+        .add_systems(Update, tournament_persist_system.run_if(on_timer(Duration::from_secs(1))))
+        .add_systems(Update, room_cleanup_system.run_if(on_timer(Duration::from_secs(5))))
+        .add_systems(Update, ugc_upload_system)
+        .add_systems(Update, ugc_upload_cleanup_system.run_if(on_timer(Duration::from_secs(30))))
+        .add_systems(Update, ugc_file_cleanup_system.run_if(on_timer(Duration::from_secs(60 * 60))))
+        .add_systems(Update, ugc_submission_system)
         .add_systems(Update, easy_vec_ui)                
-        // .add_systems(Update, client_sync_protocol_send_existing_map_sets.run_if(input_just_released(KeyCode::KeyZ)))
 
         .run();
 }
\ No newline at end of file
@@ -3,9 +3,20 @@ use bevy::prelude::*;
 use bevy_easy_vec_ui::EasyVecUi;
 
 use crate::{
+    ChallengePeriod,
+    Challenges,
+    ClientStateQuery,
     ConnectedPlayers, 
+    Emotes,
+    GameSessions,
+    MatchmakingQueue,
+    Parties,
+    PlayerStatsCache,
+    Rooms,
     RunTrigger, 
+    Seasons,
     SyncTriggerIndexEvent, 
+    Tournaments,
 };
 
 pub fn interface(
@@ -34,27 +45,143 @@ pub fn interface(
                 info!("post trigger:{:?}", &player_id);
             }
         }
+        if keys.just_released(KeyCode::KeyG) {
+            info!("pressed: KeyG");  
+            run_trigger.set_target("network_get_client_state_game", true);
+        }
+        if keys.just_released(KeyCode::KeyS) {
+            info!("pressed: KeyS");  
+            run_trigger.set_target("player_stats_rebuild", true);
+        }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn easy_vec_ui(
     mut easy_vec_ui_resource: ResMut<EasyVecUi>,
     connected_players: Res<ConnectedPlayers>,
     run_trigger: Res<RunTrigger>,
+    client_state_query: Res<ClientStateQuery>,
+    game_sessions: Res<GameSessions>,
+    parties: Res<Parties>,
+    matchmaking_queue: Res<MatchmakingQueue>,
+    rooms: Res<Rooms>,
+    player_stats_cache: Res<PlayerStatsCache>,
+    challenges: Res<Challenges>,
+    tournaments: Res<Tournaments>,
+    seasons: Res<Seasons>,
+    emotes: Res<Emotes>,
 ) {
 
-    let right_data_vec = vec![
-        String::from(format!("( Shift + E ) <--- Client Run Trigger Index [{}] ---> ( Shift + D )", run_trigger.get_trigger_idx())),
-        String::from(format!("( Shift + F ) All Clients Run Trigger: [{}]", run_trigger.get_triggers_ref()[run_trigger.get_trigger_idx()])),
+    let mut right_data_vec = vec![
+        format!("( Shift + E ) <--- Client Run Trigger Index [{}] ---> ( Shift + D )", run_trigger.get_trigger_idx()),
+        format!("( Shift + F ) All Clients Run Trigger: [{}]", run_trigger.get_triggers_ref()[run_trigger.get_trigger_idx()]),
+        String::from("( Shift + G ) Query All Client States"),
+        String::from("( Shift + S ) Rebuild Connected Player Stats"),
     ];
+    if client_state_query.is_active() {
+        right_data_vec.push(String::from("Client State Query: waiting for replies..."));
+    }
+    if let Some(result) = client_state_query.get_last_result() {
+        right_data_vec.push(format!("Last Client State Query: [{}]", result.request_id));
+        for (player_id, all_states) in result.responses.iter() {
+            right_data_vec.push(format!(
+                "Player [{}] Game: [{}] Menu: [{}] Map Set: [{}] Level: [{}] Turn: [{}]",
+                player_id,
+                all_states.state_game,
+                all_states.state_menu,
+                all_states.state_map_set,
+                all_states.state_level,
+                all_states.state_turn,
+            ));
+        }
+        for player_id in result.non_responders.iter() {
+            right_data_vec.push(format!("Player [{}] No Response", player_id));
+        }
+    }
+    right_data_vec.push(format!("Matchmaking Queue: [{}] entries", matchmaking_queue.entries.len()));
+    right_data_vec.push(format!("Rooms: [{}]", rooms.rooms.len()));
+    for room in rooms.rooms.values() {
+        right_data_vec.push(format!(
+            "Room [{}] Members: [{}] Age: [{}s]",
+            room.room_id,
+            room.members.len(),
+            room.created.elapsed().as_secs(),
+        ));
+    }
+    right_data_vec.push(format!("Game Sessions: [{}]", game_sessions.sessions.len()));
+    for session in game_sessions.sessions.values() {
+        right_data_vec.push(format!(
+            "Session [{}] Room: [{}] Level: [{:?}] ({}/{}) Active Player: [{:?}]",
+            session.session_id,
+            session.room_id,
+            session.current_level_number(),
+            session.current_level + 1,
+            session.levels.len(),
+            session.active_player(),
+        ));
+    }
+    for period in ChallengePeriod::ALL {
+        if let Some(challenge) = challenges.current.get(&period) {
+            right_data_vec.push(format!(
+                "{:?} Challenge [{}] {} - {} Holes: [{}] Stroke Limit: [{:?}]",
+                period,
+                challenge.challenge_id,
+                challenge.starts,
+                challenge.ends,
+                challenge.holes.len(),
+                challenge.stroke_limit,
+            ));
+        }
+    }
+    if let Some(season) = seasons.current.as_ref() {
+        right_data_vec.push(format!("{} [{}] Ends: [{}]", season.name, season.season_id, season.ends));
+    }
+    if !emotes.totals.is_empty() {
+        let mut totals: Vec<(&String, &i64)> = emotes.totals.iter().collect();
+        totals.sort_by(|a, b| b.1.cmp(a.1));
+        let totals: Vec<String> = totals.iter().map(|(emote_id, uses)| format!("{} [{}]", emote_id, uses)).collect();
+        right_data_vec.push(format!("Emotes Used: {}", totals.join(" ")));
+    }
+    for tournament in tournaments.list() {
+        right_data_vec.push(format!(
+            "Tournament [{}] {:?} State: [{:?}] Round: [{}] Entrants: [{}]",
+            tournament.name,
+            tournament.format,
+            tournament.state,
+            tournament.current_round,
+            tournament.entrants.len(),
+        ));
+    }
     easy_vec_ui_resource.inject_vec_right(right_data_vec);
 
     let mut left_data_vec: Vec<String> = Vec::new();
     let players_guard = connected_players.players.lock().unwrap(); // Lock the connected players to read player data
     for (uuid, player_status) in players_guard.iter() { // Iterate over each player and create a row for each one
-        left_data_vec.push(String::from(format!("Player ID: [{}] Last heartbeat: [{:?}]", uuid, player_status.last_heartbeat)));
+        left_data_vec.push(format!("Player ID: [{}] Last heartbeat: [{:?}]", uuid, player_status.last_heartbeat));
+        if let Some(stats) = player_stats_cache.stats.get(uuid) {
+            left_data_vec.push(format!(
+                "    Games: [{}] Wins: [{}] Holes-in-one: [{}] Avg/Hole: [{:.2}] Win Streak: [{}/{}]",
+                stats.games_played,
+                stats.games_won,
+                stats.holes_in_one,
+                stats.average_strokes_per_hole,
+                stats.current_win_streak,
+                stats.best_win_streak,
+            ));
+        }
     }
     left_data_vec.push(String::from("_____________________________________________"));
     left_data_vec.push(String::from("Heart Beat Interface: Connected Players Above"));
+    for party in parties.parties.values() {
+        left_data_vec.push(format!(
+            "Party [{}] Code: [{}] Host: [{}] Members: [{}/{}]",
+            party.party_id,
+            party.join_code,
+            party.host_id,
+            party.members.len(),
+            party.max_size,
+        ));
+    }
     easy_vec_ui_resource.inject_vec_left(left_data_vec);
 }
\ No newline at end of file